@@ -3,20 +3,30 @@ use core::{
     any::Any,
     ffi::c_int,
     hint::likely,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     task::Context,
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
+use axfs_ng::{FS_CONTEXT, FileBackend, FileFlags, FsContext};
 use axfs_ng_vfs::{Location, Metadata, NodeFlags};
-use axio::{IoEvents, Pollable};
+use axio::{Buf, IoEvents, Pollable, Seek, SeekFrom};
 use axsync::Mutex;
 use axtask::future::Poller;
-use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, O_DSYNC, O_SYNC};
 
-use super::{FileLike, Kstat, get_file_like};
-use crate::file::{SealedBuf, SealedBufMut};
+use super::{
+    FileLike, Kstat, MountFd, get_file_like,
+    readahead::{self, ReadAhead},
+};
+use crate::{
+    file::{SealedBuf, SealedBufMut},
+    vfs::{
+        acl,
+        mount::{self, MountFlags},
+        pagecache, tmp, writeback,
+    },
+};
 
 pub fn with_fs<R>(
     dirfd: c_int,
@@ -26,7 +36,14 @@ pub fn with_fs<R>(
     if dirfd == AT_FDCWD {
         f(&mut fs)
     } else {
-        let dir = Directory::from_fd(dirfd)?.inner.clone();
+        let file_like = get_file_like(dirfd)?.into_any();
+        let dir = if let Some(dir) = file_like.downcast_ref::<Directory>() {
+            dir.inner.clone()
+        } else if let Some(mount) = file_like.downcast_ref::<MountFd>() {
+            mount.root()
+        } else {
+            return Err(LinuxError::ENOTDIR);
+        };
         f(&mut fs.with_current_dir(dir)?)
     }
 }
@@ -64,11 +81,14 @@ pub fn resolve_at(dirfd: c_int, path: Option<&str>, flags: u32) -> LinuxResult<R
                 ResolveAtResult::File(file.inner().backend()?.location().clone())
             } else if let Some(dir) = f.downcast_ref::<Directory>() {
                 ResolveAtResult::File(dir.inner().clone())
+            } else if let Some(mount) = f.downcast_ref::<MountFd>() {
+                ResolveAtResult::File(mount.root())
             } else {
                 ResolveAtResult::Other(file_like)
             })
         }
         Some(path) => with_fs(dirfd, |fs| {
+            acl::check_search(fs, path)?;
             if flags & AT_SYMLINK_NOFOLLOW != 0 {
                 fs.resolve_no_follow(path)
             } else {
@@ -104,6 +124,11 @@ pub fn metadata_to_kstat(metadata: &Metadata) -> Kstat {
 pub struct File {
     inner: axfs_ng::File,
     nonblock: AtomicBool,
+    /// `O_SYNC` and `O_DSYNC` bits the file was opened with.
+    sync_flags: AtomicU32,
+    ra: ReadAhead,
+    /// Largest readahead window, looked up on the first read.
+    ra_window: AtomicU64,
 }
 
 impl File {
@@ -111,6 +136,9 @@ impl File {
         Self {
             inner,
             nonblock: AtomicBool::new(false),
+            sync_flags: AtomicU32::new(0),
+            ra: ReadAhead::default(),
+            ra_window: AtomicU64::new(u64::MAX),
         }
     }
 
@@ -118,6 +146,135 @@ impl File {
         &self.inner
     }
 
+    /// Returns the `O_SYNC`/`O_DSYNC` bits of the file.
+    pub fn sync_flags(&self) -> u32 {
+        self.sync_flags.load(Ordering::Relaxed)
+    }
+
+    /// Sets the `O_SYNC`/`O_DSYNC` bits of the file.
+    pub fn set_sync_flags(&self, flags: u32) {
+        self.sync_flags
+            .store(flags & (O_SYNC | O_DSYNC), Ordering::Relaxed);
+    }
+
+    /// Must be called before `len` bytes are written at `pos`: the pages
+    /// written to stop being holes of a tmpfs file.
+    fn prepare_write(&self, pos: u64, len: usize) -> LinuxResult<()> {
+        if len > 0
+            && let Some(node) = tmp::memory_node(self.inner.location())
+        {
+            node.populate(pos, pos + len as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `src` at `pos` without moving the file offset.
+    pub fn write_at(&self, src: &mut SealedBuf, pos: u64) -> LinuxResult<usize> {
+        self.prepare_write(pos, src.remaining())?;
+        let written = self.inner.write_at(src, pos)?;
+        self.finish_write(pos, written)?;
+        Ok(written)
+    }
+
+    /// Must be called after `written` bytes have been written to the file at
+    /// `pos`.
+    ///
+    /// Writes the data back at once for synchronous files, otherwise leaves
+    /// it to the writeback task, throttling the writer when there is too
+    /// much dirty data.
+    pub fn finish_write(&self, pos: u64, written: usize) -> LinuxResult<()> {
+        let backend = self.inner.backend()?;
+        pagecache::touch(backend, pos, written as u64);
+        if let Some(data_only) = self.write_sync() {
+            return writeback::sync_file(backend, data_only);
+        }
+        writeback::mark_dirty(backend, pos, written as u64);
+        writeback::balance_dirty();
+        Ok(())
+    }
+
+    /// Writes `src` at `pos` like [`Self::write_at`], for `RWF_NOWAIT`: the
+    /// writer is not throttled, and synchronous files fail with `EAGAIN`.
+    pub fn write_at_nowait(&self, src: &mut SealedBuf, pos: u64) -> LinuxResult<usize> {
+        if self.write_sync().is_some() {
+            return Err(LinuxError::EAGAIN);
+        }
+        self.prepare_write(pos, src.remaining())?;
+        let written = self.inner.write_at(src, pos)?;
+        let backend = self.inner.backend()?;
+        pagecache::touch(backend, pos, written as u64);
+        writeback::mark_dirty(backend, pos, written as u64);
+        Ok(written)
+    }
+
+    /// Returns whether writes to the file are written back at once, and if
+    /// so whether only the data is.
+    fn write_sync(&self) -> Option<bool> {
+        let flags = self.sync_flags();
+        if flags & O_SYNC == O_SYNC
+            || mount::mount_flags(self.inner.location()).contains(MountFlags::SYNCHRONOUS)
+        {
+            Some(false)
+        } else if flags & O_DSYNC != 0 {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the file back, as `fsync` and `fdatasync` do.
+    pub fn sync(&self, data_only: bool) -> LinuxResult<()> {
+        writeback::sync_file(self.inner.backend()?, data_only)
+    }
+
+    /// Returns the readahead state of the file.
+    pub fn readahead(&self) -> &ReadAhead {
+        &self.ra
+    }
+
+    /// Must be called after `len` bytes have been read at `pos`, to read
+    /// ahead of sequential readers.
+    pub fn finish_read(&self, pos: u64, len: usize) {
+        let Ok(backend) = self.inner.backend() else {
+            return;
+        };
+        pagecache::touch(backend, pos, len as u64);
+        let mut window = self.ra_window.load(Ordering::Relaxed);
+        if window == u64::MAX {
+            window = readahead::max_window(self.inner.location());
+            self.ra_window.store(window, Ordering::Relaxed);
+        }
+        if let Some(range) = self.ra.on_read(pos, len as u64, window) {
+            readahead::prefetch(backend, range);
+        }
+    }
+
+    /// Returns whether an I/O for `events` of `len` bytes at `pos` may have
+    /// to wait, see `RWF_NOWAIT`.
+    pub fn would_block(&self, events: IoEvents, pos: u64, len: usize) -> bool {
+        if self.is_blocking() {
+            match self.inner.backend() {
+                // Writes only dirty the page cache, unless the file is
+                // synchronous. Reads wait for the pages that are not cached
+                // yet, while tmpfs has all of its pages in the page cache.
+                Ok(backend @ FileBackend::Cached(_)) => {
+                    if events.contains(IoEvents::OUT) && self.write_sync().is_some() {
+                        return true;
+                    }
+                    let size = self.inner.location().len().unwrap_or_default();
+                    let len = pos.saturating_add(len as u64).min(size).saturating_sub(pos);
+                    events.contains(IoEvents::IN)
+                        && len > 0
+                        && tmp::memory_node(self.inner.location()).is_none()
+                        && !pagecache::is_cached(backend, pos, len)
+                }
+                _ => true,
+            }
+        } else {
+            !self.poll().intersects(events)
+        }
+    }
+
     fn is_blocking(&self) -> bool {
         self.inner.location().flags().contains(NodeFlags::BLOCKING)
     }
@@ -132,7 +289,12 @@ impl FileLike for File {
     fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
         let inner = self.inner();
         if likely(self.is_blocking()) {
-            inner.read(dst)
+            let read = inner.read(dst)?;
+            if read > 0 {
+                let pos = inner.seek(SeekFrom::Current(0))?;
+                self.finish_read(pos - read as u64, read);
+            }
+            Ok(read)
         } else {
             Poller::new(self, IoEvents::IN)
                 .non_blocking(self.nonblocking())
@@ -142,13 +304,21 @@ impl FileLike for File {
 
     fn write(&self, src: &mut SealedBuf) -> LinuxResult<usize> {
         let inner = self.inner();
-        if likely(self.is_blocking()) {
-            inner.write(src)
+        let pos = if inner.access(FileFlags::APPEND).is_ok() {
+            inner.location().len()?
+        } else {
+            inner.seek(SeekFrom::Current(0))?
+        };
+        self.prepare_write(pos, src.remaining())?;
+        let written = if likely(self.is_blocking()) {
+            inner.write(src)?
         } else {
             Poller::new(self, IoEvents::OUT)
                 .non_blocking(self.nonblocking())
-                .poll(|| inner.write(src))
-        }
+                .poll(|| inner.write(src))?
+        };
+        self.finish_write(pos, written)?;
+        Ok(written)
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
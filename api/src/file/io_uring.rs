@@ -0,0 +1,744 @@
+//! `io_uring` instances.
+//!
+//! The submission queue, the completion queue and the SQE array live in pages
+//! shared with user space through `mmap`. Requests are copied out of the
+//! submission queue by `io_uring_enter` and handed to worker tasks, which post
+//! their results straight into the completion queue.
+
+use alloc::{borrow::Cow, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    future::poll_fn,
+    mem,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::TimeValue;
+use axio::{IoEvents, PollSet, Pollable};
+use axmm::backend::SharedPages;
+use axsync::Mutex;
+use axtask::{
+    current,
+    future::{Poller, block_on},
+};
+use bitflags::bitflags;
+use bytemuck::AnyBitPattern;
+use starry_core::task::AsThread;
+use starry_process::Process;
+
+use crate::{
+    file::{FileLike, Kstat, SealedBuf, SealedBufMut, event::EventFd},
+    mm::SharedRegion,
+};
+
+/// `mmap` offset of the submission queue ring.
+pub const IORING_OFF_SQ_RING: usize = 0;
+/// `mmap` offset of the completion queue ring.
+pub const IORING_OFF_CQ_RING: usize = 0x800_0000;
+/// `mmap` offset of the SQE array.
+pub const IORING_OFF_SQES: usize = 0x1000_0000;
+
+/// Upper bound of the submission queue size.
+pub const IORING_MAX_ENTRIES: u32 = 32768;
+/// Upper bound of the completion queue size.
+pub const IORING_MAX_CQ_ENTRIES: u32 = 2 * IORING_MAX_ENTRIES;
+
+/// Set in the SQ ring flags while completions wait in the overflow list.
+const IORING_SQ_CQ_OVERFLOW: u32 = 1 << 1;
+
+// Layout of the submission queue ring.
+const SQ_HEAD: usize = 0;
+const SQ_TAIL: usize = 4;
+const SQ_RING_MASK: usize = 8;
+const SQ_RING_ENTRIES: usize = 12;
+const SQ_FLAGS: usize = 16;
+const SQ_DROPPED: usize = 20;
+const SQ_ARRAY: usize = 64;
+
+// Layout of the completion queue ring.
+const CQ_HEAD: usize = 0;
+const CQ_TAIL: usize = 4;
+const CQ_RING_MASK: usize = 8;
+const CQ_RING_ENTRIES: usize = 12;
+const CQ_OVERFLOW: usize = 16;
+const CQ_FLAGS: usize = 20;
+const CQ_CQES: usize = 64;
+
+/// Number of idle workers kept around per ring.
+const MAX_IDLE_WORKERS: usize = 4;
+/// Number of requests that may execute concurrently per ring.
+const MAX_WORKERS: usize = 64;
+
+bitflags! {
+    /// `IORING_SETUP_*` flags.
+    #[derive(Debug, Clone, Copy)]
+    pub struct SetupFlags: u32 {
+        const IOPOLL = 1 << 0;
+        const SQPOLL = 1 << 1;
+        const SQ_AFF = 1 << 2;
+        const CQSIZE = 1 << 3;
+        const CLAMP = 1 << 4;
+        const ATTACH_WQ = 1 << 5;
+        const R_DISABLED = 1 << 6;
+        const SUBMIT_ALL = 1 << 7;
+        const COOP_TASKRUN = 1 << 8;
+        const TASKRUN_FLAG = 1 << 9;
+        const SQE128 = 1 << 10;
+        const CQE32 = 1 << 11;
+        const SINGLE_ISSUER = 1 << 12;
+        const DEFER_TASKRUN = 1 << 13;
+        const NO_MMAP = 1 << 14;
+        const REGISTERED_FD_ONLY = 1 << 15;
+        const NO_SQARRAY = 1 << 16;
+    }
+}
+
+bitflags! {
+    /// `IOSQE_*` flags of a submission queue entry.
+    #[derive(Debug, Clone, Copy)]
+    pub struct SqeFlags: u8 {
+        const FIXED_FILE = 1 << 0;
+        const IO_DRAIN = 1 << 1;
+        const IO_LINK = 1 << 2;
+        const IO_HARDLINK = 1 << 3;
+        const ASYNC = 1 << 4;
+        const BUFFER_SELECT = 1 << 5;
+        const CQE_SKIP_SUCCESS = 1 << 6;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoSqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoCqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+/// `struct io_uring_params`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: IoSqringOffsets,
+    pub cq_off: IoCqringOffsets,
+}
+
+/// `struct io_uring_sqe`, with the unions flattened to their first member.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoUringSqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    /// Offset into the file, or `addr2`.
+    pub off: u64,
+    /// Buffer or iovec address.
+    pub addr: u64,
+    pub len: u32,
+    /// Opcode specific flags, e.g. `rw_flags` or `poll32_events`.
+    pub op_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub file_index: u32,
+    pub addr3: u64,
+    pub pad: u64,
+}
+
+impl IoUringSqe {
+    pub fn sqe_flags(&self) -> SqeFlags {
+        SqeFlags::from_bits_retain(self.flags)
+    }
+}
+
+/// `struct io_uring_cqe`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoUringCqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+/// Requests linked with `IOSQE_IO_LINK`, executed in order by one worker.
+pub type Chain = Vec<IoUringSqe>;
+
+struct RingState {
+    /// Whether the ring accepts submissions, see `IORING_SETUP_R_DISABLED`.
+    enabled: bool,
+    /// Chains waiting for a worker.
+    queue: VecDeque<Chain>,
+    /// Requests being executed, with their cancellation flags.
+    running: Vec<(u64, Arc<AtomicBool>)>,
+    workers: usize,
+    idle: usize,
+    /// Completions that did not fit into the completion queue.
+    overflow: VecDeque<IoUringCqe>,
+    eventfd: Option<Arc<EventFd>>,
+    /// Buffers registered with `IORING_REGISTER_BUFFERS`.
+    buffers: Option<Vec<(usize, usize)>>,
+}
+
+/// The state of an `io_uring` instance shared by its file and its workers.
+pub struct Ring {
+    sq: SharedRegion,
+    cq: SharedRegion,
+    sqes: SharedRegion,
+    sq_entries: u32,
+    cq_entries: u32,
+    state: Mutex<RingState>,
+    /// Number of completions posted so far.
+    completions: AtomicU64,
+    closed: AtomicBool,
+    /// Woken when a completion is posted.
+    cq_event: PollSet,
+    /// Woken when work is queued, a request is cancelled or the ring closes.
+    work_event: PollSet,
+    proc: Arc<Process>,
+    exit_event: Arc<PollSet>,
+}
+
+impl Ring {
+    fn new(sq_entries: u32, cq_entries: u32, flags: SetupFlags) -> LinuxResult<Self> {
+        let sq = SharedRegion::new(SQ_ARRAY + sq_entries as usize * size_of::<u32>())?;
+        let cq = SharedRegion::new(CQ_CQES + cq_entries as usize * size_of::<IoUringCqe>())?;
+        let sqes = SharedRegion::new(sq_entries as usize * size_of::<IoUringSqe>())?;
+        sq.write(SQ_RING_MASK, sq_entries - 1);
+        sq.write(SQ_RING_ENTRIES, sq_entries);
+        cq.write(CQ_RING_MASK, cq_entries - 1);
+        cq.write(CQ_RING_ENTRIES, cq_entries);
+
+        let proc_data = &current().as_thread().proc_data;
+        Ok(Self {
+            sq,
+            cq,
+            sqes,
+            sq_entries,
+            cq_entries,
+            state: Mutex::new(RingState {
+                enabled: !flags.contains(SetupFlags::R_DISABLED),
+                queue: VecDeque::new(),
+                running: Vec::new(),
+                workers: 0,
+                idle: 0,
+                overflow: VecDeque::new(),
+                eventfd: None,
+                buffers: None,
+            }),
+            completions: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            cq_event: PollSet::new(),
+            work_event: PollSet::new(),
+            proc: proc_data.proc.clone(),
+            exit_event: proc_data.exit_event.clone(),
+        })
+    }
+
+    pub fn sq_entries(&self) -> u32 {
+        self.sq_entries
+    }
+
+    pub fn cq_entries(&self) -> u32 {
+        self.cq_entries
+    }
+
+    /// Fills in the ring layout reported by `io_uring_setup`.
+    pub fn fill_params(&self, params: &mut IoUringParams) {
+        params.sq_entries = self.sq_entries;
+        params.cq_entries = self.cq_entries;
+        params.sq_off = IoSqringOffsets {
+            head: SQ_HEAD as _,
+            tail: SQ_TAIL as _,
+            ring_mask: SQ_RING_MASK as _,
+            ring_entries: SQ_RING_ENTRIES as _,
+            flags: SQ_FLAGS as _,
+            dropped: SQ_DROPPED as _,
+            array: SQ_ARRAY as _,
+            ..Default::default()
+        };
+        params.cq_off = IoCqringOffsets {
+            head: CQ_HEAD as _,
+            tail: CQ_TAIL as _,
+            ring_mask: CQ_RING_MASK as _,
+            ring_entries: CQ_RING_ENTRIES as _,
+            overflow: CQ_OVERFLOW as _,
+            cqes: CQ_CQES as _,
+            flags: CQ_FLAGS as _,
+            ..Default::default()
+        };
+    }
+
+    /// Returns the pages to map for an `mmap` of the ring file.
+    pub fn mmap_pages(&self, offset: usize, length: usize) -> LinuxResult<Arc<SharedPages>> {
+        let region = match offset {
+            IORING_OFF_SQ_RING => &self.sq,
+            IORING_OFF_CQ_RING => &self.cq,
+            IORING_OFF_SQES => &self.sqes,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        if length > region.size() {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(region.pages().clone())
+    }
+
+    fn is_dead(&self) -> bool {
+        self.closed.load(Ordering::Acquire) || self.proc.is_zombie()
+    }
+
+    /// Fails with `ECANCELED` once the request should stop waiting.
+    pub fn check_live(&self, cancel: &AtomicBool) -> LinuxResult<()> {
+        if self.is_dead() || cancel.load(Ordering::Acquire) {
+            return Err(LinuxError::ECANCELED);
+        }
+        Ok(())
+    }
+
+    pub fn check_enabled(&self) -> LinuxResult<()> {
+        if !self.state.lock().enabled {
+            return Err(LinuxError::EBADFD);
+        }
+        Ok(())
+    }
+
+    pub fn enable(&self) -> LinuxResult<()> {
+        let mut state = self.state.lock();
+        if state.enabled {
+            return Err(LinuxError::EBADFD);
+        }
+        state.enabled = true;
+        Ok(())
+    }
+
+    /// Consumes up to `max` entries from the submission queue and queues
+    /// them for execution.
+    ///
+    /// Returns the number of entries consumed and the number of workers
+    /// that need to be spawned.
+    pub fn submit(&self, max: u32) -> (u32, usize) {
+        let mut state = self.state.lock();
+        let head = self.sq.word(SQ_HEAD).load(Ordering::Relaxed);
+        let tail = self.sq.word(SQ_TAIL).load(Ordering::Acquire);
+        let count = tail.wrapping_sub(head).min(max).min(self.sq_entries);
+
+        let mut submitted = 0;
+        let mut chain = Chain::new();
+        for i in 0..count {
+            let slot = head.wrapping_add(i) & (self.sq_entries - 1);
+            let index: u32 = self.sq.read(SQ_ARRAY + slot as usize * size_of::<u32>());
+            if index >= self.sq_entries {
+                self.sq.word(SQ_DROPPED).fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let sqe: IoUringSqe = self.sqes.read(index as usize * size_of::<IoUringSqe>());
+            submitted += 1;
+            let linked = sqe
+                .sqe_flags()
+                .intersects(SqeFlags::IO_LINK | SqeFlags::IO_HARDLINK);
+            chain.push(sqe);
+            if !linked {
+                state.queue.push_back(mem::take(&mut chain));
+            }
+        }
+        if !chain.is_empty() {
+            state.queue.push_back(chain);
+        }
+        self.sq
+            .word(SQ_HEAD)
+            .store(head.wrapping_add(count), Ordering::Release);
+
+        // Newly spawned workers count as idle until they pick up work.
+        let spawn = state
+            .queue
+            .len()
+            .saturating_sub(state.idle)
+            .min(MAX_WORKERS - state.workers);
+        state.workers += spawn;
+        state.idle += spawn;
+        drop(state);
+
+        self.work_event.wake();
+        (submitted, spawn)
+    }
+
+    /// Waits for the next chain to execute. Returns `None` when the worker
+    /// should exit.
+    pub fn next_chain(&self) -> Option<Chain> {
+        block_on(poll_fn(|cx| {
+            self.work_event.register(cx.waker());
+            self.exit_event.register(cx.waker());
+            let mut state = self.state.lock();
+            if self.is_dead() {
+                state.idle -= 1;
+                state.workers -= 1;
+                return Poll::Ready(None);
+            }
+            if let Some(chain) = state.queue.pop_front() {
+                state.idle -= 1;
+                return Poll::Ready(Some(chain));
+            }
+            Poll::Pending
+        }))
+    }
+
+    /// Called by a worker after it has finished a chain. Returns whether the
+    /// worker should stay around for more work.
+    pub fn worker_idle(&self) -> bool {
+        let mut state = self.state.lock();
+        if state.idle >= MAX_IDLE_WORKERS {
+            state.workers -= 1;
+            false
+        } else {
+            state.idle += 1;
+            true
+        }
+    }
+
+    /// Records that the request `user_data` started executing and returns
+    /// its cancellation flag.
+    pub fn begin(&self, user_data: u64) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.state.lock().running.push((user_data, cancel.clone()));
+        cancel
+    }
+
+    /// Records that the request owning `cancel` has finished.
+    pub fn end(&self, cancel: &Arc<AtomicBool>) {
+        self.state
+            .lock()
+            .running
+            .retain(|(_, it)| !Arc::ptr_eq(it, cancel));
+    }
+
+    /// Cancels every request with the given `user_data`. Returns whether any
+    /// was found.
+    pub fn cancel(&self, user_data: u64) -> bool {
+        let mut cancelled = Vec::new();
+        let mut found = false;
+        {
+            let mut state = self.state.lock();
+            state.queue.retain_mut(|chain| {
+                if chain.iter().any(|sqe| sqe.user_data == user_data) {
+                    cancelled.push(mem::take(chain));
+                    false
+                } else {
+                    true
+                }
+            });
+            for (data, cancel) in &state.running {
+                if *data == user_data {
+                    cancel.store(true, Ordering::Release);
+                    found = true;
+                }
+            }
+        }
+        self.work_event.wake();
+
+        found |= !cancelled.is_empty();
+        for sqe in cancelled.iter().flatten() {
+            self.complete(sqe, -LinuxError::ECANCELED.code());
+        }
+        found
+    }
+
+    fn push_cqe(&self, cqe: IoUringCqe) -> bool {
+        let head = self.cq.word(CQ_HEAD).load(Ordering::Acquire);
+        let tail = self.cq.word(CQ_TAIL).load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) >= self.cq_entries {
+            return false;
+        }
+        let slot = (tail & (self.cq_entries - 1)) as usize;
+        self.cq.write(CQ_CQES + slot * size_of::<IoUringCqe>(), cqe);
+        self.cq
+            .word(CQ_TAIL)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn flush_overflow(&self, state: &mut RingState) {
+        while let Some(cqe) = state.overflow.front() {
+            if !self.push_cqe(*cqe) {
+                return;
+            }
+            state.overflow.pop_front();
+        }
+        self.sq
+            .word(SQ_FLAGS)
+            .fetch_and(!IORING_SQ_CQ_OVERFLOW, Ordering::Release);
+    }
+
+    /// Posts a completion event.
+    pub fn post(&self, user_data: u64, res: i32, flags: u32) {
+        let cqe = IoUringCqe {
+            user_data,
+            res,
+            flags,
+        };
+        let eventfd = {
+            let mut state = self.state.lock();
+            self.flush_overflow(&mut state);
+            if !state.overflow.is_empty() || !self.push_cqe(cqe) {
+                // Nothing is dropped, the entry is posted once there is room.
+                state.overflow.push_back(cqe);
+                self.sq
+                    .word(SQ_FLAGS)
+                    .fetch_or(IORING_SQ_CQ_OVERFLOW, Ordering::Release);
+            }
+            state.eventfd.clone()
+        };
+        self.completions.fetch_add(1, Ordering::AcqRel);
+        self.cq_event.wake();
+        if let Some(eventfd) = eventfd {
+            let _ = eventfd.write(&mut 1u64.to_ne_bytes().as_slice().into());
+        }
+    }
+
+    /// Posts the completion of `sqe`, honouring `IOSQE_CQE_SKIP_SUCCESS`.
+    pub fn complete(&self, sqe: &IoUringSqe, res: i32) {
+        if res >= 0 && sqe.sqe_flags().contains(SqeFlags::CQE_SKIP_SUCCESS) {
+            return;
+        }
+        self.post(sqe.user_data, res, 0);
+    }
+
+    /// Number of entries in the completion queue not consumed yet.
+    fn cq_ready(&self) -> u32 {
+        let head = self.cq.word(CQ_HEAD).load(Ordering::Acquire);
+        let tail = self.cq.word(CQ_TAIL).load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Waits until at least `min` completions are available.
+    pub fn wait_cqes(&self, min: u32, timeout: Option<TimeValue>) -> LinuxResult<()> {
+        Poller::new(&RingWaiter::new(self, None), IoEvents::IN)
+            .timeout(timeout)
+            .poll(|| {
+                self.flush_overflow(&mut self.state.lock());
+                if self.cq_ready() >= min {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            })
+            .map_err(|err| match err {
+                LinuxError::ETIMEDOUT => LinuxError::ETIME,
+                err => err,
+            })
+    }
+
+    /// Waits until `file` reports any of `events`.
+    pub fn wait_ready(
+        &self,
+        file: &dyn FileLike,
+        events: IoEvents,
+        cancel: &AtomicBool,
+    ) -> LinuxResult<IoEvents> {
+        let events = events | IoEvents::ALWAYS_POLL;
+        Poller::new(&RingWaiter::new(self, Some(file)), events).poll(|| {
+            self.check_live(cancel)?;
+            let ready = file.poll() & events;
+            if ready.is_empty() {
+                Err(LinuxError::EAGAIN)
+            } else {
+                Ok(ready)
+            }
+        })
+    }
+
+    /// Waits for `timeout`, or until `count` other requests have completed
+    /// if `count` is not zero. Fails with `ETIME` if the timeout expired.
+    pub fn wait_timeout(
+        &self,
+        count: u64,
+        timeout: TimeValue,
+        cancel: &AtomicBool,
+    ) -> LinuxResult<()> {
+        let start = self.completions.load(Ordering::Acquire);
+        Poller::new(&RingWaiter::new(self, None), IoEvents::IN)
+            .timeout(Some(timeout))
+            .poll(|| {
+                self.check_live(cancel)?;
+                if count > 0 && self.completions.load(Ordering::Acquire) - start >= count {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            })
+            .map_err(|err| match err {
+                LinuxError::ETIMEDOUT => LinuxError::ETIME,
+                err => err,
+            })
+    }
+
+    pub fn register_eventfd(&self, eventfd: Arc<EventFd>) -> LinuxResult<()> {
+        let mut state = self.state.lock();
+        if state.eventfd.is_some() {
+            return Err(LinuxError::EBUSY);
+        }
+        state.eventfd = Some(eventfd);
+        Ok(())
+    }
+
+    pub fn unregister_eventfd(&self) -> LinuxResult<()> {
+        self.state
+            .lock()
+            .eventfd
+            .take()
+            .map(|_| ())
+            .ok_or(LinuxError::ENXIO)
+    }
+
+    pub fn register_buffers(&self, buffers: Vec<(usize, usize)>) -> LinuxResult<()> {
+        let mut state = self.state.lock();
+        if state.buffers.is_some() {
+            return Err(LinuxError::EBUSY);
+        }
+        state.buffers = Some(buffers);
+        Ok(())
+    }
+
+    pub fn unregister_buffers(&self) -> LinuxResult<()> {
+        self.state
+            .lock()
+            .buffers
+            .take()
+            .map(|_| ())
+            .ok_or(LinuxError::ENXIO)
+    }
+
+    /// Checks that `[addr, addr + len)` lies in registered buffer `index`.
+    pub fn check_buffer(&self, index: u16, addr: usize, len: usize) -> LinuxResult<()> {
+        let state = self.state.lock();
+        let &(base, size) = state
+            .buffers
+            .as_ref()
+            .and_then(|it| it.get(index as usize))
+            .ok_or(LinuxError::EFAULT)?;
+        if addr < base || addr.saturating_add(len) > base + size {
+            return Err(LinuxError::EFAULT);
+        }
+        Ok(())
+    }
+}
+
+/// Wakes a waiter on `file` readiness, new completions, cancellation, ring
+/// closure or process exit.
+struct RingWaiter<'a> {
+    ring: &'a Ring,
+    file: Option<&'a dyn FileLike>,
+}
+
+impl<'a> RingWaiter<'a> {
+    fn new(ring: &'a Ring, file: Option<&'a dyn FileLike>) -> Self {
+        Self { ring, file }
+    }
+}
+
+impl Pollable for RingWaiter<'_> {
+    fn poll(&self) -> IoEvents {
+        unreachable!()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if let Some(file) = self.file {
+            file.register(context, events);
+        } else {
+            self.ring.cq_event.register(context.waker());
+        }
+        self.ring.work_event.register(context.waker());
+        self.ring.exit_event.register(context.waker());
+    }
+}
+
+/// An `io_uring` file descriptor.
+pub struct IoUring {
+    ring: Arc<Ring>,
+}
+
+impl IoUring {
+    pub fn new(sq_entries: u32, cq_entries: u32, flags: SetupFlags) -> LinuxResult<Self> {
+        Ok(Self {
+            ring: Arc::new(Ring::new(sq_entries, cq_entries, flags)?),
+        })
+    }
+
+    pub fn ring(&self) -> &Arc<Ring> {
+        &self.ring
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        // Workers hold the ring, tell them to cancel their requests and exit.
+        self.ring.closed.store(true, Ordering::Release);
+        self.ring.work_event.wake();
+    }
+}
+
+impl FileLike for IoUring {
+    fn read(&self, _dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:[io_uring]".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for IoUring {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        let overflow = !self.ring.state.lock().overflow.is_empty();
+        events.set(IoEvents::IN, overflow || self.ring.cq_ready() > 0);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.ring.cq_event.register(context.waker());
+        }
+    }
+}
@@ -1,9 +1,12 @@
 pub mod epoll;
 pub mod event;
 mod fs;
+pub mod io_uring;
+mod mount;
 mod net;
 mod pidfd;
 mod pipe;
+pub mod readahead;
 
 use alloc::{borrow::Cow, sync::Arc};
 use core::{any::Any, ffi::c_int, time::Duration};
@@ -22,9 +25,10 @@ use starry_vm::{VmBytes, VmBytesMut};
 
 pub use self::{
     fs::{Directory, File, ResolveAtResult, metadata_to_kstat, resolve_at, with_fs},
-    net::Socket,
+    mount::{DetachedMount, FsContextFd, FsContextPhase, FsContextState, MountFd, MountTree},
+    net::{AncillaryOptions, Socket, SocketInner, Timestamp},
     pidfd::PidFd,
-    pipe::Pipe,
+    pipe::{Pipe, raise_pipe},
 };
 use crate::io::IoVectorBufIo;
 
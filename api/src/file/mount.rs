@@ -0,0 +1,172 @@
+use alloc::{
+    borrow::Cow,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{any::Any, task::Context};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{Filesystem, Location, Mountpoint};
+use axio::{IoEvents, Pollable};
+use axsync::Mutex;
+
+use crate::{
+    file::{FileLike, Kstat, SealedBuf, SealedBufMut, metadata_to_kstat},
+    vfs::mount::MountSpec,
+};
+
+/// Lifecycle of a filesystem context.
+pub enum FsContextPhase {
+    /// Collecting parameters for a new filesystem (`fsopen`).
+    Create,
+    /// The filesystem has been created and can be mounted.
+    Created(Filesystem, MountSpec),
+    /// Reconfiguring an existing mount (`fspick`).
+    Reconfigure(Location),
+    /// `fsmount` has consumed the filesystem.
+    Mounted,
+    /// A previous command failed; the context is unusable.
+    Failed,
+}
+
+/// State of a filesystem context.
+pub struct FsContextState {
+    pub fs_type: String,
+    pub source: Option<String>,
+    pub source_loc: Option<Location>,
+    /// Options in `key` or `key=value` form, in the order they were given.
+    pub options: Vec<String>,
+    pub phase: FsContextPhase,
+}
+
+/// A filesystem context file descriptor, created by `fsopen` or `fspick`.
+pub struct FsContextFd {
+    pub state: Mutex<FsContextState>,
+}
+
+impl FsContextFd {
+    pub fn new(fs_type: String, phase: FsContextPhase) -> Self {
+        Self {
+            state: Mutex::new(FsContextState {
+                fs_type,
+                source: None,
+                source_loc: None,
+                options: Vec::new(),
+                phase,
+            }),
+        }
+    }
+}
+
+impl FileLike for FsContextFd {
+    fn read(&self, _dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        // Error messages are not collected.
+        Err(LinuxError::ENODATA)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:[fscontext]".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for FsContextFd {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+/// A mount that is not attached to the namespace yet.
+#[derive(Clone)]
+pub struct DetachedMount {
+    pub fs: Filesystem,
+    pub spec: MountSpec,
+    /// Mounts below the root, as paths relative to it, shallowest first.
+    pub children: Vec<(String, Filesystem, MountSpec)>,
+}
+
+/// What a mount file descriptor refers to.
+pub enum MountTree {
+    /// A mount not attached yet, with its root on a mountpoint of its own.
+    Detached(DetachedMount, Location),
+    /// The root of the mount, once attached.
+    Attached(Location),
+}
+
+impl MountTree {
+    /// Returns the root of the mount.
+    pub fn root(&self) -> &Location {
+        match self {
+            Self::Detached(_, root) | Self::Attached(root) => root,
+        }
+    }
+}
+
+/// A mount file descriptor, created by `fsmount` or `open_tree`.
+pub struct MountFd {
+    pub tree: Mutex<MountTree>,
+}
+
+impl MountFd {
+    pub fn new(mount: DetachedMount) -> Self {
+        let root = Mountpoint::new_root(&mount.fs).root_location();
+        Self {
+            tree: Mutex::new(MountTree::Detached(mount, root)),
+        }
+    }
+
+    /// Returns the root of the mount, which paths relative to the file
+    /// descriptor start from.
+    pub fn root(&self) -> Location {
+        self.tree.lock().root().clone()
+    }
+}
+
+impl FileLike for MountFd {
+    fn read(&self, _dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        Err(LinuxError::EBADF)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> LinuxResult<usize> {
+        Err(LinuxError::EBADF)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(metadata_to_kstat(&self.root().metadata()?))
+    }
+
+    fn path(&self) -> Cow<str> {
+        match &*self.tree.lock() {
+            MountTree::Detached(..) => "/".into(),
+            MountTree::Attached(root) => root
+                .absolute_path()
+                .map_or("/".into(), |path| Cow::Owned(path.to_string())),
+        }
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for MountFd {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
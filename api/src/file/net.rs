@@ -1,34 +1,671 @@
-use alloc::{borrow::Cow, format, sync::Arc};
-use core::{ffi::c_int, ops::Deref, task::Context};
+use alloc::{borrow::Cow, collections::BTreeSet, format, sync::Arc, vec::Vec};
+use core::{
+    ffi::c_int,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::atomic::{AtomicU16, Ordering},
+    task::Context,
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axio::{IoEvents, Pollable};
+use axhal::time::wall_time;
+use axio::{Buf, BufMut, IoEvents, Pollable};
 use axnet::{
-    SocketOps,
+    CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, Shutdown, SocketAddrEx, SocketOps,
     options::{Configurable, GetSocketOption, SetSocketOption},
+    unix::UnixSocketAddr,
+};
+use axsync::Mutex;
+use axtask::future::Poller;
+use linux_raw_sys::{
+    general::S_IFSOCK,
+    net::{AF_INET, AF_INET6, AF_UNIX, SOCK_STREAM},
 };
-use linux_raw_sys::general::S_IFSOCK;
 
 use super::{FileLike, Kstat};
-use crate::file::{SealedBuf, SealedBufMut, get_file_like};
+use crate::{
+    file::{SealedBuf, SealedBufMut, get_file_like, raise_pipe},
+    net::{
+        ioctl::interface_ioctl,
+        netlink::NetlinkSocket,
+        ns::{NetNamespace, current_net_ns},
+        packet::PacketSocket,
+        raw::{IcmpError, PacketInfo, RawSocket},
+    },
+    socket::{NetlinkAddr, SockAddr, ipv6_from_stack, ipv6_to_stack},
+};
+
+/// `IPPROTO_IPV6` options of an `AF_INET6` socket.
+pub struct Ipv6Options {
+    pub v6only: bool,
+    pub multicast_loop: bool,
+    pub recv_pktinfo: bool,
+}
+
+impl Default for Ipv6Options {
+    fn default() -> Self {
+        Self {
+            v6only: false,
+            multicast_loop: true,
+            recv_pktinfo: false,
+        }
+    }
+}
+
+/// Resolution of the receive timestamps of a socket.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    /// `SO_TIMESTAMP`, in microseconds.
+    Micro,
+    /// `SO_TIMESTAMPNS`, in nanoseconds.
+    Nano,
+}
+
+/// Which ancillary data a socket reports with the messages it receives.
+#[derive(Default, Clone, Copy)]
+pub struct AncillaryOptions {
+    pub timestamp: Option<Timestamp>,
+    /// `IP_PKTINFO`.
+    pub pktinfo: bool,
+    /// `IP_RECVTTL`.
+    pub recv_ttl: bool,
+    /// `IP_RECVTOS`.
+    pub recv_tos: bool,
+    /// `IP_RECVERR`, or `IPV6_RECVERR`.
+    pub recv_err: bool,
+}
+
+/// Ports taken by [`V6OnlyBinding`]s, by namespace and socket type.
+static V6ONLY_PORTS: Mutex<BTreeSet<(u64, u32, u16)>> = Mutex::new(BTreeSet::new());
+/// Where the search for a free port for a [`V6OnlyBinding`] starts.
+static NEXT_V6ONLY_PORT: AtomicU16 = AtomicU16::new(V6ONLY_EPHEMERAL.start);
+/// Ports given to [`V6OnlyBinding`]s bound to port 0.
+const V6ONLY_EPHEMERAL: core::ops::Range<u16> = 49152..65535;
+
+/// The address of an `IPV6_V6ONLY` socket bound to the unspecified or the
+/// loopback IPv6 address. The network stack only carries IPv4, so the stack
+/// socket is left unbound: no traffic ever comes in for the socket, and the
+/// port stays free for IPv4 sockets, as on Linux.
+struct V6OnlyBinding {
+    ns: u64,
+    ty: u32,
+    addr: SocketAddrV6,
+}
+
+impl V6OnlyBinding {
+    /// Takes the port of `addr`, or a free one if it is 0.
+    fn new(ns: u64, ty: u32, mut addr: SocketAddrV6) -> LinuxResult<Self> {
+        let mut ports = V6ONLY_PORTS.lock();
+        if addr.port() != 0 {
+            if !ports.insert((ns, ty, addr.port())) {
+                return Err(LinuxError::EADDRINUSE);
+            }
+            return Ok(Self { ns, ty, addr });
+        }
+        for _ in V6ONLY_EPHEMERAL {
+            let port = NEXT_V6ONLY_PORT.fetch_add(1, Ordering::Relaxed);
+            let port = V6ONLY_EPHEMERAL.start + port % V6ONLY_EPHEMERAL.len() as u16;
+            if ports.insert((ns, ty, port)) {
+                addr.set_port(port);
+                return Ok(Self { ns, ty, addr });
+            }
+        }
+        Err(LinuxError::EADDRINUSE)
+    }
+}
+
+impl Drop for V6OnlyBinding {
+    fn drop(&mut self) {
+        V6ONLY_PORTS
+            .lock()
+            .remove(&(self.ns, self.ty, self.addr.port()));
+    }
+}
+
+/// The protocol implementation behind a [`Socket`].
+pub enum SocketInner {
+    /// A socket of the network stack.
+    Stack(axnet::Socket),
+    Netlink(NetlinkSocket),
+    /// A raw IP or ping socket.
+    Raw(RawSocket),
+    /// An `AF_PACKET` socket.
+    Packet(PacketSocket),
+}
+
+impl From<axnet::Socket> for SocketInner {
+    fn from(socket: axnet::Socket) -> Self {
+        Self::Stack(socket)
+    }
+}
+
+impl From<NetlinkSocket> for SocketInner {
+    fn from(socket: NetlinkSocket) -> Self {
+        Self::Netlink(socket)
+    }
+}
+
+impl From<RawSocket> for SocketInner {
+    fn from(socket: RawSocket) -> Self {
+        Self::Raw(socket)
+    }
+}
+
+impl From<PacketSocket> for SocketInner {
+    fn from(socket: PacketSocket) -> Self {
+        Self::Packet(socket)
+    }
+}
+
+pub struct Socket {
+    inner: SocketInner,
+    /// Address family the socket was created with.
+    family: u32,
+    /// Type the socket was created with, `SOCK_STREAM` and so on.
+    ty: u32,
+    /// Network namespace the socket was created in.
+    ns: Arc<NetNamespace>,
+    ipv6: Mutex<Ipv6Options>,
+    ancillary: Mutex<AncillaryOptions>,
+    v6only_binding: Mutex<Option<V6OnlyBinding>>,
+    /// Error that ended a `MSG_WAITALL` receive after some data, reported by
+    /// the next call.
+    pending_error: Mutex<Option<LinuxError>>,
+    /// Held while the `O_NONBLOCK` flag of a stack socket is changed, see
+    /// [`Socket::without_waiting`].
+    nonblocking_lock: Mutex<()>,
+}
+
+impl Socket {
+    pub fn new(inner: impl Into<SocketInner>, family: u32, ty: u32) -> Self {
+        Self {
+            inner: inner.into(),
+            family,
+            ty,
+            ns: current_net_ns(),
+            ipv6: Mutex::default(),
+            ancillary: Mutex::default(),
+            v6only_binding: Mutex::default(),
+            pending_error: Mutex::default(),
+            nonblocking_lock: Mutex::default(),
+        }
+    }
+
+    /// Returns the address family of the socket.
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    /// Returns whether the socket is a byte stream.
+    pub fn is_stream(&self) -> bool {
+        self.ty == SOCK_STREAM
+    }
+
+    /// Returns whether the socket is a TCP one.
+    pub fn is_tcp(&self) -> bool {
+        matches!(self.inner, SocketInner::Stack(axnet::Socket::Tcp(_)))
+    }
+
+    /// Returns the `IPPROTO_IPV6` options, or `ENOPROTOOPT` for sockets other
+    /// than `AF_INET6` ones.
+    pub fn ipv6_options(&self) -> LinuxResult<&Mutex<Ipv6Options>> {
+        if self.family != AF_INET6 {
+            return Err(LinuxError::ENOPROTOOPT);
+        }
+        Ok(&self.ipv6)
+    }
+
+    /// Returns the options choosing the ancillary data received.
+    pub fn ancillary_options(&self) -> &Mutex<AncillaryOptions> {
+        &self.ancillary
+    }
+
+    /// Returns whether the IP headers and errors of the messages received
+    /// are known, for `IP_RECVTTL`, `IP_RECVTOS` and `IP_RECVERR`. The
+    /// network stack tells neither, only raw and ping sockets do.
+    pub fn knows_headers(&self) -> bool {
+        matches!(self.inner, SocketInner::Raw(_))
+    }
+
+    /// Sets whether errors are queued, `IP_RECVERR`, or fails with
+    /// `ENOPROTOOPT` for sockets that never learn of any.
+    pub fn set_recv_err(&self, recv_err: bool) -> LinuxResult<()> {
+        let SocketInner::Raw(socket) = &self.inner else {
+            return Err(LinuxError::ENOPROTOOPT);
+        };
+        self.ancillary.lock().recv_err = recv_err;
+        socket.set_recv_err(recv_err);
+        Ok(())
+    }
+
+    /// Works out where a datagram of the network stack from `from` came in,
+    /// which the stack does not tell. A socket bound to an address only
+    /// receives at that address; otherwise the datagram came in where the
+    /// route back to `from` goes out, at the address of that interface, with
+    /// local traffic coming in on the loopback interface at the address it
+    /// was sent from.
+    fn stack_packet_info(&self, local: IpAddr, from: IpAddr) -> PacketInfo {
+        let config = self.ns.config();
+        let address_of = |index: u32| {
+            config.interface(index).ok().and_then(|iface| {
+                iface
+                    .addrs
+                    .iter()
+                    .find(|it| it.addr.is_ipv4() == from.is_ipv4())
+                    .map(|it| it.addr)
+            })
+        };
+        let (ifindex, dst) = if !local.is_unspecified() {
+            let ifindex = if local.is_loopback() {
+                config.loopback_index()
+            } else {
+                config.interface_for(local).ok()
+            };
+            (ifindex, Some(local))
+        } else if config.is_local(from) {
+            (config.loopback_index(), Some(from))
+        } else {
+            let ifindex = config.lookup(from).ok().map(|route| route.oif);
+            (ifindex, ifindex.and_then(address_of))
+        };
+        let unspecified = if from.is_ipv4() {
+            Ipv4Addr::UNSPECIFIED.into()
+        } else {
+            Ipv6Addr::UNSPECIFIED.into()
+        };
+        PacketInfo {
+            dst: dst.unwrap_or(unspecified),
+            ifindex: ifindex.unwrap_or(0),
+            ttl: None,
+            tos: None,
+            time: wall_time(),
+        }
+    }
+
+    /// Converts an address given by user space to the one the network stack
+    /// uses. Abstract Unix names are prefixed with the namespace, which the
+    /// stack knows nothing about.
+    pub fn to_stack_addr(&self, addr: SockAddr) -> LinuxResult<SocketAddrEx> {
+        let SockAddr::Stack(addr) = addr else {
+            return Err(LinuxError::EAFNOSUPPORT);
+        };
+        match (self.family, addr) {
+            (AF_INET6, SocketAddrEx::Ip(SocketAddr::V6(addr))) => Ok(SocketAddrEx::Ip(
+                ipv6_to_stack(addr, self.ipv6.lock().v6only)?,
+            )),
+            (AF_UNIX, SocketAddrEx::Unix(UnixSocketAddr::Abstract(name))) => {
+                let name = [&self.ns.id().to_be_bytes(), &name[..]].concat();
+                Ok(SocketAddrEx::Unix(UnixSocketAddr::Abstract(name.into())))
+            }
+            (AF_INET, addr @ SocketAddrEx::Ip(SocketAddr::V4(_)))
+            | (AF_UNIX, addr @ SocketAddrEx::Unix(_)) => Ok(addr),
+            _ => Err(LinuxError::EAFNOSUPPORT),
+        }
+    }
+
+    /// Converts an address given by user space to an IP address of the
+    /// family of the socket, as raw sockets take them.
+    fn to_ip_addr(&self, addr: SockAddr) -> LinuxResult<SocketAddr> {
+        match (self.family, addr) {
+            (AF_INET, SockAddr::Stack(SocketAddrEx::Ip(addr @ SocketAddr::V4(_))))
+            | (AF_INET6, SockAddr::Stack(SocketAddrEx::Ip(addr @ SocketAddr::V6(_)))) => Ok(addr),
+            _ => Err(LinuxError::EAFNOSUPPORT),
+        }
+    }
+
+    /// Converts an address reported by the network stack to the family of
+    /// the socket, undoing [`Self::to_stack_addr`].
+    pub fn from_stack_addr(&self, addr: SocketAddrEx) -> SocketAddrEx {
+        match (self.family, addr) {
+            (AF_INET6, SocketAddrEx::Ip(addr)) => {
+                SocketAddrEx::Ip(SocketAddr::V6(ipv6_from_stack(addr)))
+            }
+            (AF_UNIX, SocketAddrEx::Unix(UnixSocketAddr::Abstract(name))) => {
+                let name = name.get(size_of::<u64>()..).unwrap_or_default();
+                SocketAddrEx::Unix(UnixSocketAddr::Abstract(name.into()))
+            }
+            (_, addr) => addr,
+        }
+    }
+
+    /// Returns the netlink socket for its `SOL_NETLINK` options, or
+    /// `ENOPROTOOPT` for other sockets.
+    pub fn netlink(&self) -> LinuxResult<&NetlinkSocket> {
+        match &self.inner {
+            SocketInner::Netlink(socket) => Ok(socket),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Returns the raw socket for its options, or `ENOPROTOOPT` for other
+    /// sockets.
+    pub fn raw(&self) -> LinuxResult<&RawSocket> {
+        match &self.inner {
+            SocketInner::Raw(socket) => Ok(socket),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Returns the packet socket for its options, or `ENOPROTOOPT` for other
+    /// sockets.
+    pub fn packet(&self) -> LinuxResult<&PacketSocket> {
+        match &self.inner {
+            SocketInner::Packet(socket) => Ok(socket),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Leaves `err` for the next receive or `SO_ERROR` to report.
+    pub fn set_pending_error(&self, err: LinuxError) {
+        *self.pending_error.lock() = Some(err);
+    }
+
+    /// Takes the error left by [`Self::set_pending_error`].
+    pub fn take_pending_error(&self) -> Option<LinuxError> {
+        self.pending_error.lock().take()
+    }
+
+    /// Binds an `IPV6_V6ONLY` stack socket to `addr` if it is one the
+    /// network stack cannot carry, see [`V6OnlyBinding`]. Returns whether it
+    /// was.
+    fn bind_v6only(&self, addr: &SockAddr) -> LinuxResult<bool> {
+        let SockAddr::Stack(SocketAddrEx::Ip(SocketAddr::V6(addr))) = addr else {
+            return Ok(false);
+        };
+        let ip = addr.ip();
+        if self.family != AF_INET6
+            || !self.ipv6.lock().v6only
+            || !(ip.is_unspecified() || ip.is_loopback())
+        {
+            return Ok(false);
+        }
+        if self.is_bound() {
+            return Err(LinuxError::EINVAL);
+        }
+        let binding = V6OnlyBinding::new(self.ns.id(), self.ty, *addr)?;
+        *self.v6only_binding.lock() = Some(binding);
+        Ok(true)
+    }
+
+    /// Returns whether the socket is bound by [`Self::bind_v6only`].
+    fn is_v6only_bound(&self) -> bool {
+        self.v6only_binding.lock().is_some()
+    }
+
+    /// Waits for traffic to a socket bound by [`Self::bind_v6only`], which
+    /// never comes, failing with `EAGAIN` instead if `dontwait` is set.
+    fn wait_v6only<T>(&self, dontwait: bool) -> LinuxResult<T> {
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(dontwait || self.nonblocking())
+            .poll(|| Err(LinuxError::EAGAIN))
+    }
+
+    /// Returns the local address as seen by user space.
+    pub fn sock_name(&self) -> LinuxResult<SockAddr> {
+        if let Some(binding) = &*self.v6only_binding.lock() {
+            return Ok(SocketAddrEx::Ip(binding.addr.into()).into());
+        }
+        match &self.inner {
+            SocketInner::Stack(socket) => Ok(self.from_stack_addr(socket.local_addr()?).into()),
+            SocketInner::Netlink(socket) => Ok(SockAddr::Netlink(socket.local_addr())),
+            SocketInner::Raw(socket) => Ok(SocketAddrEx::Ip(socket.local_addr()).into()),
+            SocketInner::Packet(socket) => Ok(SockAddr::Packet(socket.local_addr())),
+        }
+    }
+
+    /// Returns the peer address as seen by user space.
+    pub fn peer_name(&self) -> LinuxResult<SockAddr> {
+        match &self.inner {
+            SocketInner::Stack(socket) => Ok(self.from_stack_addr(socket.peer_addr()?).into()),
+            // Netlink sockets only ever talk to the kernel.
+            SocketInner::Netlink(_) => Ok(SockAddr::Netlink(NetlinkAddr::default())),
+            SocketInner::Raw(socket) => Ok(SocketAddrEx::Ip(socket.peer_addr()?).into()),
+            SocketInner::Packet(_) => Err(LinuxError::EOPNOTSUPP),
+        }
+    }
+
+    /// Returns whether the socket has been bound to a port.
+    pub fn is_bound(&self) -> bool {
+        if self.is_v6only_bound() {
+            return true;
+        }
+        match &self.inner {
+            SocketInner::Stack(socket) => {
+                matches!(socket.local_addr(), Ok(SocketAddrEx::Ip(addr)) if addr.port() != 0)
+            }
+            SocketInner::Netlink(socket) => socket.local_addr().pid != 0,
+            SocketInner::Raw(socket) => socket.is_bound(),
+            SocketInner::Packet(socket) => socket.local_addr().ifindex != 0,
+        }
+    }
+
+    pub fn bind(&self, addr: SockAddr) -> LinuxResult<()> {
+        match &self.inner {
+            SocketInner::Stack(_) if self.bind_v6only(&addr)? => Ok(()),
+            SocketInner::Stack(socket) => socket.bind(self.to_stack_addr(addr)?),
+            SocketInner::Netlink(socket) => socket.bind(addr.into_netlink()?),
+            SocketInner::Raw(socket) => socket.bind(self.to_ip_addr(addr)?),
+            SocketInner::Packet(socket) => socket.bind(addr.into_packet()?),
+        }
+    }
+
+    pub fn connect(&self, addr: SockAddr) -> LinuxResult<()> {
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.connect(self.to_stack_addr(addr)?),
+            SocketInner::Netlink(socket) => socket.connect(addr.into_netlink()?),
+            SocketInner::Raw(socket) => socket.connect(self.to_ip_addr(addr)?),
+            SocketInner::Packet(_) => Err(LinuxError::EOPNOTSUPP),
+        }
+    }
+
+    pub fn listen(&self) -> LinuxResult<()> {
+        match &self.inner {
+            // Nothing ever connects, see `V6OnlyBinding`.
+            SocketInner::Stack(_) if self.is_v6only_bound() => Ok(()),
+            SocketInner::Stack(socket) => socket.listen(),
+            SocketInner::Netlink(_) | SocketInner::Raw(_) | SocketInner::Packet(_) => {
+                Err(LinuxError::EOPNOTSUPP)
+            }
+        }
+    }
+
+    /// Accepts a connection, returning a socket of the same family.
+    pub fn accept(&self) -> LinuxResult<Socket> {
+        match &self.inner {
+            SocketInner::Stack(_) if self.is_v6only_bound() => self.wait_v6only(false),
+            // Connections stay in the namespace of the listener.
+            SocketInner::Stack(socket) => Ok(Socket {
+                ns: self.ns.clone(),
+                ..Socket::new(socket.accept()?, self.family, self.ty)
+            }),
+            SocketInner::Netlink(_) | SocketInner::Raw(_) | SocketInner::Packet(_) => {
+                Err(LinuxError::EOPNOTSUPP)
+            }
+        }
+    }
 
-pub struct Socket(pub axnet::Socket);
+    pub fn shutdown(&self, how: Shutdown) -> LinuxResult<()> {
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.shutdown(how),
+            SocketInner::Netlink(_) | SocketInner::Raw(_) | SocketInner::Packet(_) => {
+                Err(LinuxError::EOPNOTSUPP)
+            }
+        }
+    }
+
+    /// Runs `f` on a stack socket as if it were non-blocking, for
+    /// `MSG_DONTWAIT`.
+    ///
+    /// The stack only knows of a per-socket flag, so it is set for the
+    /// duration of the call. Other calls starting meanwhile do not wait
+    /// either.
+    fn without_waiting<T>(
+        &self,
+        socket: &axnet::Socket,
+        f: impl FnOnce() -> LinuxResult<T>,
+    ) -> LinuxResult<T> {
+        let _guard = self.nonblocking_lock.lock();
+        if self.nonblocking() {
+            return f();
+        }
+        socket.set_option(SetSocketOption::NonBlocking(&true))?;
+        let result = f();
+        socket.set_option(SetSocketOption::NonBlocking(&false))?;
+        result
+    }
 
-impl Deref for Socket {
-    type Target = axnet::Socket;
+    /// Sends data from `src`, to `to` if given. Fails with `EAGAIN` instead
+    /// of waiting if `dontwait` is set.
+    pub fn send(
+        &self,
+        src: &mut impl Buf,
+        to: Option<SockAddr>,
+        flags: SendFlags,
+        cmsg: Vec<CMsgData>,
+        dontwait: bool,
+    ) -> LinuxResult<usize> {
+        match &self.inner {
+            SocketInner::Stack(socket) => {
+                let to = to.map(|addr| self.to_stack_addr(addr)).transpose()?;
+                let send = || socket.send(src, SendOptions { to, flags, cmsg });
+                if dontwait {
+                    self.without_waiting(socket, send)
+                } else {
+                    send()
+                }
+            }
+            // The others never wait to send.
+            SocketInner::Netlink(socket) => {
+                let to = to.map(SockAddr::into_netlink).transpose()?;
+                socket.send(src, to)
+            }
+            SocketInner::Raw(socket) => {
+                let to = to.map(|addr| self.to_ip_addr(addr)).transpose()?;
+                socket.send(src, to)
+            }
+            SocketInner::Packet(socket) => {
+                let to = to.map(SockAddr::into_packet).transpose()?;
+                socket.send(src, to)
+            }
+        }
+    }
+
+    /// Receives data into `dst`, together with the source address if
+    /// `want_from` is set. IP datagrams also come with where and when they
+    /// came in. Fails with `EAGAIN` instead of waiting if
+    /// `dontwait` is set.
+    pub fn recv(
+        &self,
+        dst: &mut impl BufMut,
+        flags: RecvFlags,
+        cmsg: Option<&mut Vec<CMsgData>>,
+        want_from: bool,
+        dontwait: bool,
+    ) -> LinuxResult<(usize, Option<SockAddr>, Option<PacketInfo>)> {
+        if let Some(err) = self.take_pending_error() {
+            return Err(err);
+        }
+        match &self.inner {
+            SocketInner::Stack(_) if self.is_v6only_bound() => self.wait_v6only(dontwait),
+            SocketInner::Stack(socket) => {
+                // Where an IP datagram came in is worked out from its source.
+                let datagram = !self.is_stream() && self.family != AF_UNIX;
+                let mut from = (want_from || datagram)
+                    .then(|| SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into()));
+                let recv = || {
+                    socket.recv(
+                        dst,
+                        RecvOptions {
+                            from: from.as_mut(),
+                            flags,
+                            cmsg,
+                        },
+                    )
+                };
+                let recv = if dontwait {
+                    self.without_waiting(socket, recv)?
+                } else {
+                    recv()?
+                };
+                let local = socket.local_addr();
+                let info = match (&from, local) {
+                    (Some(SocketAddrEx::Ip(from)), Ok(SocketAddrEx::Ip(local))) if datagram => {
+                        let local = local.ip().to_canonical();
+                        Some(self.stack_packet_info(local, from.ip().to_canonical()))
+                    }
+                    _ => None,
+                };
+                Ok((
+                    recv,
+                    from.filter(|_| want_from)
+                        .map(|addr| self.from_stack_addr(addr).into()),
+                    info,
+                ))
+            }
+            SocketInner::Netlink(socket) => {
+                let recv = socket.recv(dst, flags, dontwait)?;
+                Ok((
+                    recv,
+                    want_from.then(|| SockAddr::Netlink(NetlinkAddr::default())),
+                    None,
+                ))
+            }
+            SocketInner::Raw(socket) => {
+                let (recv, from, info) = socket.recv(dst, flags, dontwait)?;
+                Ok((
+                    recv,
+                    want_from.then(|| SocketAddrEx::Ip(SocketAddr::new(from, 0)).into()),
+                    Some(info),
+                ))
+            }
+            SocketInner::Packet(socket) => {
+                let (recv, from) = socket.recv(dst, flags, dontwait)?;
+                Ok((recv, want_from.then_some(SockAddr::Packet(from)), None))
+            }
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Takes the oldest error queued for `IP_RECVERR`, see
+    /// [`RawSocket::recv_error`]. Only raw and ping sockets queue any: the
+    /// others refuse `IP_RECVERR`, so their queue is always empty.
+    pub fn recv_error(&self, dst: &mut impl BufMut) -> LinuxResult<(usize, IcmpError)> {
+        match &self.inner {
+            SocketInner::Raw(socket) => socket.recv_error(dst),
+            _ => Err(LinuxError::EAGAIN),
+        }
+    }
+
+    pub fn get_option(&self, option: GetSocketOption) -> LinuxResult<()> {
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.get_option(option),
+            SocketInner::Netlink(socket) => socket.get_option(option),
+            SocketInner::Raw(socket) => socket.get_option(option),
+            SocketInner::Packet(socket) => socket.get_option(option),
+        }
+    }
+
+    pub fn set_option(&self, option: SetSocketOption) -> LinuxResult<()> {
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.set_option(option),
+            SocketInner::Netlink(socket) => socket.set_option(option),
+            SocketInner::Raw(socket) => socket.set_option(option),
+            SocketInner::Packet(socket) => socket.set_option(option),
+        }
     }
 }
 
 impl FileLike for Socket {
     fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
-        self.recv(dst, axnet::RecvOptions::default())
+        self.recv(dst, RecvFlags::empty(), None, false, false)
+            .map(|(recv, ..)| recv)
     }
 
     fn write(&self, src: &mut SealedBuf) -> LinuxResult<usize> {
-        self.send(src, axnet::SendOptions::default())
+        self.send(src, None, SendFlags::default(), Vec::new(), false)
+            .inspect_err(|err| {
+                if *err == LinuxError::EPIPE {
+                    raise_pipe();
+                }
+            })
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
@@ -44,6 +681,10 @@ impl FileLike for Socket {
         self
     }
 
+    fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
+        interface_ioctl(cmd, arg)
+    }
+
     fn nonblocking(&self) -> bool {
         let mut result = false;
         self.get_option(GetSocketOption::NonBlocking(&mut result))
@@ -52,8 +693,8 @@ impl FileLike for Socket {
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult<()> {
-        self.0
-            .set_option(SetSocketOption::NonBlocking(&nonblocking))
+        let _guard = self.nonblocking_lock.lock();
+        self.set_option(SetSocketOption::NonBlocking(&nonblocking))
     }
 
     fn path(&self) -> Cow<str> {
@@ -72,10 +713,27 @@ impl FileLike for Socket {
 }
 impl Pollable for Socket {
     fn poll(&self) -> IoEvents {
-        self.0.poll()
+        if self.is_v6only_bound() {
+            return if self.is_stream() {
+                IoEvents::empty()
+            } else {
+                IoEvents::OUT
+            };
+        }
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.poll(),
+            SocketInner::Netlink(socket) => socket.poll(),
+            SocketInner::Raw(socket) => socket.poll(),
+            SocketInner::Packet(socket) => socket.poll(),
+        }
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        self.0.register(context, events);
+        match &self.inner {
+            SocketInner::Stack(socket) => socket.register(context, events),
+            SocketInner::Netlink(socket) => socket.register(context, events),
+            SocketInner::Raw(socket) => socket.register(context, events),
+            SocketInner::Packet(socket) => socket.register(context, events),
+        }
     }
 }
@@ -1,21 +1,16 @@
-use alloc::{borrow::Cow, format, sync::Arc};
+use alloc::{borrow::Cow, collections::vec_deque::VecDeque, format, sync::Arc, vec, vec::Vec};
 use core::{
     any::Any,
-    mem,
     sync::atomic::{AtomicBool, Ordering},
     task::Context,
 };
 
 use axerrno::{LinuxError, LinuxResult};
 use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
-use axsync::Mutex;
+use axsync::{Mutex, MutexGuard};
 use axtask::{current, future::Poller};
 use linux_raw_sys::{general::S_IFIFO, ioctl::FIONREAD};
 use memory_addr::PAGE_SIZE_4K;
-use ringbuf::{
-    HeapRb,
-    traits::{Consumer, Observer, Producer},
-};
 use starry_core::task::{AsThread, send_signal_to_process};
 use starry_signal::{SignalInfo, Signo};
 use starry_vm::VmMutPtr;
@@ -23,10 +18,205 @@ use starry_vm::VmMutPtr;
 use super::{FileLike, Kstat};
 use crate::file::{SealedBuf, SealedBufMut};
 
-const RING_BUFFER_INIT_SIZE: usize = 65536; // 64 KiB
+const PIPE_DEF_BUFFERS: usize = 16; // 64 KiB
+
+/// A chunk of data in a pipe.
+///
+/// Pages are reference counted so that `splice` and `tee` can move or
+/// duplicate them between pipes without copying. A page is only written to
+/// while no other buffer refers to it.
+#[derive(Clone)]
+struct PipeBuffer {
+    page: Arc<[u8]>,
+    offset: usize,
+    len: usize,
+}
+
+impl PipeBuffer {
+    fn new() -> Self {
+        Self {
+            page: Arc::from(vec![0; PAGE_SIZE_4K]),
+            offset: 0,
+            len: 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.page[self.offset..self.offset + self.len]
+    }
+
+    /// Returns whether data can be appended to the page.
+    fn has_room(&self) -> bool {
+        self.offset + self.len < self.page.len() && Arc::strong_count(&self.page) == 1
+    }
+
+    /// Returns the unused tail of the page, if it is not shared.
+    fn spare_mut(&mut self) -> Option<&mut [u8]> {
+        let end = self.offset + self.len;
+        Arc::get_mut(&mut self.page).map(|page| &mut page[end..])
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.offset += count;
+        self.len -= count;
+    }
+}
+
+struct PipeRing {
+    bufs: VecDeque<PipeBuffer>,
+    /// Maximum number of buffers, i.e. the capacity in pages.
+    max_bufs: usize,
+    /// Number of bytes in the pipe.
+    len: usize,
+}
+
+impl PipeRing {
+    fn new(max_bufs: usize) -> Self {
+        Self {
+            bufs: VecDeque::new(),
+            max_bufs,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bufs.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.bufs.len() >= self.max_bufs && !self.bufs.back().is_some_and(PipeBuffer::has_room)
+    }
+
+    /// Appends up to `max` bytes produced by `f`, which fills the slice it is
+    /// given and returns how many bytes it wrote.
+    fn fill(
+        &mut self,
+        mut max: usize,
+        mut f: impl FnMut(&mut [u8]) -> LinuxResult<usize>,
+    ) -> LinuxResult<usize> {
+        let mut total = 0;
+        while max > 0 {
+            if !self.bufs.back().is_some_and(PipeBuffer::has_room) {
+                if self.bufs.len() >= self.max_bufs {
+                    break;
+                }
+                self.bufs.push_back(PipeBuffer::new());
+            }
+            let buf = self.bufs.back_mut().unwrap();
+            let spare = buf.spare_mut().unwrap();
+            let room = spare.len().min(max);
+            let result = f(&mut spare[..room]);
+            let count = *result.as_ref().unwrap_or(&0);
+            buf.len += count;
+            if buf.len == 0 {
+                self.bufs.pop_back();
+            }
+            match result {
+                Ok(_) => {}
+                Err(_) if total > 0 => break,
+                Err(err) => return Err(err),
+            }
+            self.len += count;
+            total += count;
+            max -= count;
+            if count < room {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Returns the buffers holding the first `max` bytes, sharing their
+    /// pages.
+    fn peek(&self, mut max: usize) -> Vec<PipeBuffer> {
+        let mut bufs = Vec::new();
+        for buf in &self.bufs {
+            if max == 0 {
+                break;
+            }
+            let len = buf.len.min(max);
+            bufs.push(PipeBuffer { len, ..buf.clone() });
+            max -= len;
+        }
+        bufs
+    }
+
+    /// Drops the first `count` bytes.
+    fn consume(&mut self, mut count: usize) {
+        self.len -= count;
+        while count > 0 {
+            let buf = self.bufs.front_mut().unwrap();
+            let n = buf.len.min(count);
+            buf.consume(n);
+            if buf.len == 0 {
+                self.bufs.pop_front();
+            }
+            count -= n;
+        }
+    }
+
+    /// Number of pages that can still be added.
+    fn free_bufs(&self) -> usize {
+        self.max_bufs.saturating_sub(self.bufs.len())
+    }
+
+    fn push(&mut self, buf: PipeBuffer) {
+        self.len += buf.len;
+        self.bufs.push_back(buf);
+    }
+
+    /// Moves up to `max` bytes into `dst` without copying.
+    fn splice_to(&mut self, dst: &mut PipeRing, mut max: usize) -> usize {
+        let mut total = 0;
+        while max > 0
+            && dst.bufs.len() < dst.max_bufs
+            && let Some(buf) = self.bufs.front_mut()
+        {
+            let count = buf.len.min(max);
+            if count == buf.len {
+                dst.bufs.push_back(self.bufs.pop_front().unwrap());
+            } else {
+                dst.bufs.push_back(PipeBuffer {
+                    len: count,
+                    ..buf.clone()
+                });
+                buf.consume(count);
+            }
+            self.len -= count;
+            dst.len += count;
+            total += count;
+            max -= count;
+        }
+        total
+    }
+
+    /// Duplicates up to `max` bytes into `dst` without consuming or copying
+    /// them.
+    fn tee_to(&self, dst: &mut PipeRing, mut max: usize) -> usize {
+        let mut total = 0;
+        for buf in &self.bufs {
+            if max == 0 || dst.bufs.len() >= dst.max_bufs {
+                break;
+            }
+            let count = buf.len.min(max);
+            dst.bufs.push_back(PipeBuffer {
+                len: count,
+                ..buf.clone()
+            });
+            dst.len += count;
+            total += count;
+            max -= count;
+        }
+        total
+    }
+}
 
 struct Shared {
-    buffer: Mutex<HeapRb<u8>>,
+    buffer: Mutex<PipeRing>,
+    /// Taken by readers and writers for a whole transfer, so that `buffer`
+    /// is only locked briefly and never while waiting for a file or socket.
+    reader: Mutex<()>,
+    writer: Mutex<()>,
     poll_rx: PollSet,
     poll_tx: PollSet,
     poll_close: PollSet,
@@ -46,7 +236,9 @@ impl Drop for Pipe {
 impl Pipe {
     pub fn new() -> (Pipe, Pipe) {
         let shared = Arc::new(Shared {
-            buffer: Mutex::new(HeapRb::new(RING_BUFFER_INIT_SIZE)),
+            buffer: Mutex::new(PipeRing::new(PIPE_DEF_BUFFERS)),
+            reader: Mutex::new(()),
+            writer: Mutex::new(()),
             poll_rx: PollSet::new(),
             poll_tx: PollSet::new(),
             poll_close: PollSet::new(),
@@ -77,67 +269,268 @@ impl Pipe {
     }
 
     pub fn capacity(&self) -> usize {
-        self.shared.buffer.lock().capacity().get()
+        self.shared.buffer.lock().max_bufs * PAGE_SIZE_4K
     }
 
     pub fn resize(&self, new_size: usize) -> LinuxResult<()> {
-        let new_size = new_size.div_ceil(PAGE_SIZE_4K).max(1) * PAGE_SIZE_4K;
+        let max_bufs = new_size.div_ceil(PAGE_SIZE_4K).max(1);
 
+        let _writer = self.shared.writer.lock();
         let mut buffer = self.shared.buffer.lock();
-        if new_size == buffer.capacity().get() {
-            return Ok(());
-        }
-        if new_size < buffer.occupied_len() {
+        if max_bufs < buffer.bufs.len() {
             return Err(LinuxError::EBUSY);
         }
-        let old_buffer = mem::replace(&mut *buffer, HeapRb::new(new_size));
-        let (left, right) = old_buffer.as_slices();
-        buffer.push_slice(left);
-        buffer.push_slice(right);
+        buffer.max_bufs = max_bufs;
         Ok(())
     }
-}
 
-fn raise_pipe() {
-    let curr = current();
-    send_signal_to_process(
-        curr.as_thread().proc_data.proc.pid(),
-        Some(SignalInfo::new_kernel(Signo::SIGPIPE)),
-    )
-    .expect("Failed to send SIGPIPE");
-}
+    /// Returns whether both ends refer to the same pipe.
+    pub fn same_pipe(&self, other: &Pipe) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
 
-impl FileLike for Pipe {
-    fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
+    /// Waits for data and hands up to `len` bytes of it to `f` in place, one
+    /// page at a time, until `f` takes less than it is given. Returns 0 once
+    /// the write end is closed.
+    ///
+    /// `f` may block: it runs with the pipe unlocked, on pages that are not
+    /// written to meanwhile. Errors from `f` are returned as is instead of
+    /// waiting on the pipe, unless some data was taken already.
+    pub fn drain(
+        &self,
+        len: usize,
+        non_blocking: bool,
+        mut f: impl FnMut(&[u8]) -> LinuxResult<usize>,
+    ) -> LinuxResult<usize> {
         if !self.is_read() {
             return Err(LinuxError::EBADF);
         }
-        if dst.remaining_mut() == 0 {
+        if len == 0 {
             return Ok(0);
         }
-
-        Poller::new(self, IoEvents::IN)
-            .non_blocking(self.nonblocking())
+        let count = Poller::new(self, IoEvents::IN)
+            .non_blocking(non_blocking)
             .poll(|| {
-                let read = {
-                    let cons = self.shared.buffer.lock();
-                    let (left, right) = cons.as_slices();
-                    let mut count = dst.write(left)?;
-                    if count >= left.len() {
-                        count += dst.write(right)?;
+                // The data stays at the front of the pipe until it is
+                // consumed, as no other reader can get in between.
+                let _reader = self.shared.reader.lock();
+                let bufs = {
+                    let ring = self.shared.buffer.lock();
+                    if ring.is_empty() {
+                        return if self.closed() {
+                            Ok(Ok(0))
+                        } else {
+                            Err(LinuxError::EAGAIN)
+                        };
                     }
-                    unsafe { cons.advance_read_index(count) };
-                    count
+                    ring.peek(len)
                 };
-                if read > 0 {
-                    self.shared.poll_tx.wake();
-                    Ok(read)
-                } else if self.closed() {
-                    Ok(0)
+                let mut total = 0;
+                let mut result = Ok(());
+                for buf in &bufs {
+                    match f(buf.data()) {
+                        Ok(count) => {
+                            total += count;
+                            if count < buf.len {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            result = Err(err);
+                            break;
+                        }
+                    }
+                }
+                drop(bufs);
+                self.shared.buffer.lock().consume(total);
+                Ok(if total > 0 {
+                    Ok(total)
                 } else {
+                    result.map(|_| 0)
+                })
+            })??;
+        if count > 0 {
+            self.shared.poll_tx.wake();
+        }
+        Ok(count)
+    }
+
+    /// Waits for room and lets `f` write up to `len` bytes into new pipe
+    /// pages, one at a time, until `f` fills less than it is given.
+    ///
+    /// `f` may block: it runs with the pipe unlocked, and the pages are only
+    /// added once they are filled. Errors from `f` are returned as is
+    /// instead of waiting on the pipe, unless some data was written already.
+    pub fn fill(
+        &self,
+        len: usize,
+        non_blocking: bool,
+        mut f: impl FnMut(&mut [u8]) -> LinuxResult<usize>,
+    ) -> LinuxResult<usize> {
+        if !self.is_write() {
+            return Err(LinuxError::EBADF);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let count = Poller::new(self, IoEvents::OUT)
+            .non_blocking(non_blocking)
+            .poll(|| {
+                if self.closed() {
+                    raise_pipe();
+                    return Err(LinuxError::EPIPE);
+                }
+                // The room found here stays, as no other writer can get in
+                // between.
+                let _writer = self.shared.writer.lock();
+                let room = self.shared.buffer.lock().free_bufs();
+                if room == 0 {
+                    return Err(LinuxError::EAGAIN);
+                }
+                let mut total = 0;
+                let mut result = Ok(());
+                for _ in 0..room {
+                    if total == len {
+                        break;
+                    }
+                    let mut buf = PipeBuffer::new();
+                    let wanted = (len - total).min(PAGE_SIZE_4K);
+                    match f(&mut buf.spare_mut().unwrap()[..wanted]) {
+                        Ok(count) => {
+                            if count > 0 {
+                                buf.len = count;
+                                self.shared.buffer.lock().push(buf);
+                                self.shared.poll_rx.wake();
+                                total += count;
+                            }
+                            if count < wanted {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            result = Err(err);
+                            break;
+                        }
+                    }
+                }
+                Ok(if total > 0 {
+                    Ok(total)
+                } else {
+                    result.map(|_| 0)
+                })
+            })??;
+        Ok(count)
+    }
+
+    /// Locks the buffers of two different pipes in a fixed order.
+    fn lock_pair<'a>(
+        &'a self,
+        dst: &'a Pipe,
+    ) -> (MutexGuard<'a, PipeRing>, MutexGuard<'a, PipeRing>) {
+        if Arc::as_ptr(&self.shared) < Arc::as_ptr(&dst.shared) {
+            let src = self.shared.buffer.lock();
+            (src, dst.shared.buffer.lock())
+        } else {
+            let dst = dst.shared.buffer.lock();
+            (self.shared.buffer.lock(), dst)
+        }
+    }
+
+    /// Transfers up to `len` bytes to the pipe `dst`, moving or duplicating
+    /// the pages depending on `consume`.
+    fn transfer(
+        &self,
+        dst: &Pipe,
+        len: usize,
+        non_blocking: bool,
+        consume: bool,
+    ) -> LinuxResult<usize> {
+        if !self.is_read() || !dst.is_write() {
+            return Err(LinuxError::EBADF);
+        }
+        if self.same_pipe(dst) {
+            return Err(LinuxError::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let count = Poller::new(&PipePair(self, dst), IoEvents::IN | IoEvents::OUT)
+            .non_blocking(non_blocking)
+            .poll(|| {
+                let _reader = consume.then(|| self.shared.reader.lock());
+                let _writer = dst.shared.writer.lock();
+                let (mut src_ring, mut dst_ring) = self.lock_pair(dst);
+                if src_ring.is_empty() {
+                    return if self.closed() {
+                        Ok(0)
+                    } else {
+                        Err(LinuxError::EAGAIN)
+                    };
+                }
+                if dst.closed() {
+                    raise_pipe();
+                    return Err(LinuxError::EPIPE);
+                }
+                let count = if consume {
+                    src_ring.splice_to(&mut dst_ring, len)
+                } else {
+                    src_ring.tee_to(&mut dst_ring, len)
+                };
+                if count == 0 {
                     Err(LinuxError::EAGAIN)
+                } else {
+                    Ok(count)
                 }
-            })
+            })?;
+        if consume {
+            self.shared.poll_tx.wake();
+        }
+        dst.shared.poll_rx.wake();
+        Ok(count)
+    }
+
+    /// Moves up to `len` bytes to the pipe `dst` without copying.
+    pub fn splice_to(&self, dst: &Pipe, len: usize, non_blocking: bool) -> LinuxResult<usize> {
+        self.transfer(dst, len, non_blocking, true)
+    }
+
+    /// Duplicates up to `len` bytes into the pipe `dst` without consuming or
+    /// copying them.
+    pub fn tee_to(&self, dst: &Pipe, len: usize, non_blocking: bool) -> LinuxResult<usize> {
+        self.transfer(dst, len, non_blocking, false)
+    }
+}
+
+/// Waits for data in the first pipe and room in the second one.
+struct PipePair<'a>(&'a Pipe, &'a Pipe);
+
+impl Pollable for PipePair<'_> {
+    fn poll(&self) -> IoEvents {
+        unreachable!()
+    }
+
+    fn register(&self, context: &mut Context<'_>, _events: IoEvents) {
+        self.0.register(context, IoEvents::IN);
+        self.1.register(context, IoEvents::OUT);
+    }
+}
+
+/// Sends `SIGPIPE` to the current process, for writes to a pipe or socket
+/// whose reading end is closed.
+pub fn raise_pipe() {
+    let curr = current();
+    send_signal_to_process(
+        curr.as_thread().proc_data.proc.pid(),
+        Some(SignalInfo::new_kernel(Signo::SIGPIPE)),
+    )
+    .expect("Failed to send SIGPIPE");
+}
+
+impl FileLike for Pipe {
+    fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        self.drain(dst.remaining_mut(), self.nonblocking(), |data| {
+            dst.write(data)
+        })
     }
 
     fn write(&self, src: &mut SealedBuf) -> LinuxResult<usize> {
@@ -159,16 +552,12 @@ impl FileLike for Pipe {
                     return Err(LinuxError::EPIPE);
                 }
 
-                let written = {
-                    let mut prod = self.shared.buffer.lock();
-                    let (left, right) = prod.vacant_slices_mut();
-                    let mut count = src.read(unsafe { left.assume_init_mut() })?;
-                    if count >= left.len() {
-                        count += src.read(unsafe { right.assume_init_mut() })?;
-                    }
-                    unsafe { prod.advance_write_index(count) };
-                    count
-                };
+                let _writer = self.shared.writer.lock();
+                let written = self
+                    .shared
+                    .buffer
+                    .lock()
+                    .fill(size - total_written, |buf| src.read(buf))?;
                 if written > 0 {
                     self.shared.poll_rx.wake();
                     total_written += written;
@@ -207,7 +596,7 @@ impl FileLike for Pipe {
     fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
         match cmd {
             FIONREAD => {
-                (arg as *mut u32).vm_write(self.shared.buffer.lock().occupied_len() as u32)?;
+                (arg as *mut u32).vm_write(self.shared.buffer.lock().len as u32)?;
                 Ok(0)
             }
             _ => Err(LinuxError::ENOTTY),
@@ -220,10 +609,10 @@ impl Pollable for Pipe {
         let mut events = IoEvents::empty();
         let buf = self.shared.buffer.lock();
         if self.read_side {
-            events.set(IoEvents::IN, buf.occupied_len() > 0);
+            events.set(IoEvents::IN, !buf.is_empty());
             events.set(IoEvents::HUP, self.closed());
         } else {
-            events.set(IoEvents::OUT, buf.vacant_len() > 0);
+            events.set(IoEvents::OUT, !buf.is_full());
         }
         events
     }
@@ -0,0 +1,180 @@
+//! Readahead in front of the page cache.
+//!
+//! Every open file keeps a [`ReadAhead`] that watches where reads land. Once
+//! the reads turn out to be sequential, the pages past them are pulled into
+//! the page cache a window at a time, and the window doubles up to a limit
+//! while the pattern holds. Windows are read by a single kernel task, so the
+//! reader only waits for pages it actually asks for.
+
+use alloc::{collections::VecDeque, vec};
+use core::ops::Range;
+
+use axerrno::LinuxResult;
+use axfs_ng::{FS_CONTEXT, FileBackend};
+use axfs_ng_vfs::Location;
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::vfs::{Device, mount, pagecache};
+
+/// Window the first readahead of a sequential stream starts with.
+const INITIAL_WINDOW: u64 = 4 * PAGE_SIZE_4K as u64;
+/// Largest window for files without advice.
+pub const DEFAULT_MAX_WINDOW: u64 = 32 * PAGE_SIZE_4K as u64;
+
+/// Size of the scratch buffer used to pull pages into the cache.
+const PREFETCH_CHUNK: usize = 0x10000;
+
+/// Access pattern advice, from `posix_fadvise`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    #[default]
+    Normal,
+    Sequential,
+    Random,
+}
+
+#[derive(Default)]
+struct State {
+    advice: Advice,
+    /// End of the last read.
+    prev_end: u64,
+    /// End of what has been read ahead so far.
+    ahead_end: u64,
+    window: u64,
+}
+
+/// Per-open-file readahead state.
+#[derive(Default)]
+pub struct ReadAhead(Mutex<State>);
+
+impl ReadAhead {
+    /// Sets the access pattern advice, starting over the detection.
+    pub fn set_advice(&self, advice: Advice) {
+        *self.0.lock() = State {
+            advice,
+            ..State::default()
+        };
+    }
+
+    /// Records a read of `len` bytes at `pos` and returns the range to read
+    /// ahead, if any. `max_window` bounds the window; sequential advice
+    /// doubles it.
+    pub fn on_read(&self, pos: u64, len: u64, max_window: u64) -> Option<Range<u64>> {
+        let mut state = self.0.lock();
+        let end = pos.saturating_add(len);
+        let sequential = pos == state.prev_end || state.advice == Advice::Sequential;
+        state.prev_end = end;
+        if max_window == 0 || state.advice == Advice::Random {
+            return None;
+        }
+        let max_window = match state.advice {
+            Advice::Sequential => max_window * 2,
+            _ => max_window,
+        };
+        if !sequential {
+            state.window = 0;
+            state.ahead_end = end;
+            return None;
+        }
+        // Start the next window once the reader is halfway into the current
+        // one, so that it never catches up with the readahead.
+        if end.saturating_add(state.window / 2) < state.ahead_end {
+            return None;
+        }
+        state.window = if state.window == 0 {
+            INITIAL_WINDOW.min(max_window)
+        } else {
+            (state.window * 2).min(max_window)
+        };
+        let start = state.ahead_end.max(end);
+        state.ahead_end = start.saturating_add(state.window);
+        Some(start..state.ahead_end)
+    }
+}
+
+struct Queue {
+    pending: VecDeque<(FileBackend, Range<u64>)>,
+    /// Whether the worker task is alive.
+    running: bool,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    pending: VecDeque::new(),
+    running: false,
+});
+
+/// Most windows waiting for the worker; later ones are dropped.
+const MAX_PENDING: usize = 16;
+
+/// Queues `range` of a cached file to be pulled into the page cache in the
+/// background, stopping at the end of the file. Uncached backends are left
+/// alone.
+pub fn prefetch(backend: &FileBackend, range: Range<u64>) {
+    if !matches!(backend, FileBackend::Cached(_)) || range.is_empty() {
+        return;
+    }
+    let mut queue = QUEUE.lock();
+    if queue.pending.len() >= MAX_PENDING {
+        return;
+    }
+    queue.pending.push_back((backend.clone(), range));
+    if !queue.running {
+        queue.running = true;
+        axtask::spawn(worker, "readahead".into());
+    }
+}
+
+fn worker() {
+    loop {
+        let (backend, range) = {
+            let mut queue = QUEUE.lock();
+            match queue.pending.pop_front() {
+                Some(item) => item,
+                None => {
+                    queue.running = false;
+                    return;
+                }
+            }
+        };
+        // Readahead is only a hint, nobody waits for it to succeed.
+        if let Err(err) = read_into_cache(&backend, range) {
+            debug!("readahead failed: {:?}", err);
+        }
+    }
+}
+
+/// Returns the largest readahead window for the file at `loc`: that of the
+/// block device its filesystem is mounted from, if any.
+pub fn max_window(loc: &Location) -> u64 {
+    let Some(source) = mount::lookup(loc).map(|mount| mount.source) else {
+        return DEFAULT_MAX_WINDOW;
+    };
+    if !source.starts_with("/dev/") {
+        return DEFAULT_MAX_WINDOW;
+    }
+    let Ok(dev) = FS_CONTEXT.lock().resolve(&source) else {
+        return DEFAULT_MAX_WINDOW;
+    };
+    dev.entry()
+        .downcast::<Device>()
+        .ok()
+        .and_then(|dev| dev.inner().readahead())
+        .unwrap_or(DEFAULT_MAX_WINDOW)
+}
+
+fn read_into_cache(backend: &FileBackend, range: Range<u64>) -> LinuxResult<()> {
+    let end = range.end.min(backend.location().len()?);
+    let mut buf = vec![0; PREFETCH_CHUNK.min(end.saturating_sub(range.start) as usize)];
+    let mut pos = range.start;
+    while pos < end {
+        let chunk = ((end - pos) as usize).min(buf.len());
+        let read = backend.read_at(&mut &mut buf[..chunk], pos)?;
+        if read == 0 {
+            break;
+        }
+        pagecache::touch(backend, pos, read as u64);
+        pos += read as u64;
+    }
+    Ok(())
+}
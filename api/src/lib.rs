@@ -14,6 +14,7 @@ extern crate alloc;
 pub mod file;
 pub mod io;
 pub mod mm;
+pub mod net;
 pub mod signal;
 pub mod socket;
 pub mod syscall;
@@ -37,4 +38,13 @@ pub fn init() {
 
     info!("Initialize alarm...");
     starry_core::time::spawn_alarm_task();
+
+    info!("Initialize writeback...");
+    vfs::writeback::spawn_writeback_task();
+
+    info!("Initialize reclaim...");
+    starry_core::mm::register_elf_cache_shrinker();
+    vfs::writeback::register_shrinker();
+    vfs::pagecache::register_shrinker();
+    starry_core::shrink::spawn_reclaim_task();
 }
@@ -1,13 +1,18 @@
-use alloc::string::String;
-use core::{alloc::Layout, ffi::c_char, hint::unlikely, mem::transmute, ptr, slice, str};
+use alloc::{string::String, sync::Arc};
+use core::{
+    alloc::Layout, ffi::c_char, hint::unlikely, mem::transmute, ptr, slice, str,
+    sync::atomic::AtomicU32,
+};
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
-    paging::MappingFlags,
+    mem::phys_to_virt,
+    paging::{MappingFlags, PageSize},
     trap::{PAGE_FAULT, register_trap_handler},
 };
+use axmm::backend::SharedPages;
 use axtask::current;
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, align_up_4k};
 use starry_core::{
     mm::{access_user_memory, is_accessing_user_memory},
     task::AsThread,
@@ -256,3 +261,52 @@ pub fn vm_load_string(ptr: *const c_char) -> LinuxResult<String> {
     let bytes = vm_load_until_nul(ptr as *const u8)?;
     String::from_utf8(bytes).map_err(|_| LinuxError::EILSEQ)
 }
+
+/// Pages shared between the kernel and user space, accessed by the kernel
+/// through the linear mapping.
+pub struct SharedRegion(Arc<SharedPages>);
+
+impl SharedRegion {
+    pub fn new(size: usize) -> LinuxResult<Self> {
+        let region = Self(Arc::new(SharedPages::new(
+            align_up_4k(size),
+            PageSize::Size4K,
+        )?));
+        for page in region.0.iter() {
+            unsafe {
+                phys_to_virt(*page)
+                    .as_mut_ptr()
+                    .write_bytes(0, PAGE_SIZE_4K)
+            };
+        }
+        Ok(region)
+    }
+
+    pub fn pages(&self) -> &Arc<SharedPages> {
+        &self.0
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len() * PAGE_SIZE_4K
+    }
+
+    /// Returns a kernel pointer to the `T` at `offset`. The value must not
+    /// cross a page boundary.
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        let in_page = offset % PAGE_SIZE_4K;
+        debug_assert!(in_page + size_of::<T>() <= PAGE_SIZE_4K);
+        (phys_to_virt(self.0[offset / PAGE_SIZE_4K]).as_usize() + in_page) as *mut T
+    }
+
+    pub fn word(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*self.ptr::<AtomicU32>(offset) }
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    pub fn write<T>(&self, offset: usize, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) }
+    }
+}
@@ -0,0 +1,268 @@
+//! Classic BPF socket filters, `SO_ATTACH_FILTER`.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use axerrno::{LinuxError, LinuxResult};
+use bytemuck::AnyBitPattern;
+use starry_vm::vm_load;
+
+/// Longest program accepted.
+const BPF_MAXINSNS: usize = 4096;
+/// Number of scratch memory words.
+const BPF_MEMWORDS: usize = 16;
+
+// Instruction classes.
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+
+// Load sizes.
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+
+// Load modes.
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_MSH: u16 = 0xa0;
+
+// ALU operations.
+const BPF_ADD: u16 = 0x00;
+const BPF_SUB: u16 = 0x10;
+const BPF_MUL: u16 = 0x20;
+const BPF_DIV: u16 = 0x30;
+const BPF_OR: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_LSH: u16 = 0x60;
+const BPF_RSH: u16 = 0x70;
+const BPF_NEG: u16 = 0x80;
+const BPF_MOD: u16 = 0x90;
+const BPF_XOR: u16 = 0xa0;
+
+// Jumps.
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+
+// Operand sources.
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+const BPF_A: u16 = 0x10;
+
+// Register transfers.
+const BPF_TAX: u16 = 0x00;
+const BPF_TXA: u16 = 0x80;
+
+/// Offset of the ancillary data Linux lets filters load, `SKF_AD_OFF`.
+const SKF_AD_OFF: u32 = -0x1000i32 as u32;
+const SKF_AD_PROTOCOL: u32 = 0;
+const SKF_AD_PKTTYPE: u32 = 4;
+const SKF_AD_IFINDEX: u32 = 8;
+const SKF_AD_VLAN_TAG: u32 = 44;
+const SKF_AD_VLAN_TAG_PRESENT: u32 = 48;
+
+/// `struct sock_filter`, one instruction.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// `struct sock_fprog`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct SockFprog {
+    len: u16,
+    filter: usize,
+}
+
+/// What a filter can learn about a packet besides its data.
+pub struct PacketMeta {
+    /// Ethernet protocol, `ETH_P_*`.
+    pub protocol: u16,
+    /// Packet type, `PACKET_*`.
+    pub pkttype: u8,
+    pub ifindex: u32,
+}
+
+/// A checked filter program.
+pub struct BpfProgram(Vec<SockFilter>);
+
+impl BpfProgram {
+    /// Loads the program `fprog` points to from user space and checks it as
+    /// Linux does: every jump stays inside, memory words exist, no constant
+    /// division by zero, and the last instruction returns.
+    pub fn load(fprog: &SockFprog) -> LinuxResult<Self> {
+        let len = fprog.len as usize;
+        if len == 0 || len > BPF_MAXINSNS {
+            return Err(LinuxError::EINVAL);
+        }
+        let insns = vm_load(fprog.filter as *const u8, len * size_of::<SockFilter>())?
+            .chunks_exact(size_of::<SockFilter>())
+            .map(bytemuck::pod_read_unaligned::<SockFilter>)
+            .collect::<Vec<_>>();
+        for (pc, insn) in insns.iter().enumerate() {
+            let valid = match insn.code & 0x07 {
+                BPF_LD | BPF_LDX => match insn.code & 0xe0 {
+                    BPF_MEM => (insn.k as usize) < BPF_MEMWORDS,
+                    BPF_IMM | BPF_LEN => true,
+                    BPF_ABS | BPF_IND => {
+                        insn.code & 0x07 == BPF_LD
+                            && matches!(insn.code & 0x18, BPF_W | BPF_H | BPF_B)
+                    }
+                    BPF_MSH => insn.code == BPF_LDX | BPF_B | BPF_MSH,
+                    _ => false,
+                },
+                BPF_ST | BPF_STX => (insn.k as usize) < BPF_MEMWORDS,
+                BPF_ALU => match insn.code & 0xf0 {
+                    BPF_DIV | BPF_MOD => insn.code & BPF_X != 0 || insn.k != 0,
+                    BPF_LSH | BPF_RSH => insn.code & BPF_X != 0 || insn.k < 32,
+                    BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_NEG | BPF_XOR => true,
+                    _ => false,
+                },
+                BPF_JMP => {
+                    let rest = len - pc - 1;
+                    match insn.code & 0xf0 {
+                        BPF_JA => (insn.k as usize) < rest,
+                        BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                            (insn.jt as usize) < rest && (insn.jf as usize) < rest
+                        }
+                        _ => false,
+                    }
+                }
+                BPF_RET => matches!(insn.code & 0x18, BPF_K | BPF_A),
+                BPF_MISC => matches!(insn.code & 0xf8, BPF_TAX | BPF_TXA),
+                _ => unreachable!(),
+            };
+            if !valid {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        if insns[len - 1].code & 0x07 != BPF_RET {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(Self(insns))
+    }
+
+    /// Runs the program over `data` and returns how many bytes of it to
+    /// keep, 0 to drop it.
+    pub fn run(&self, data: &[u8], meta: &PacketMeta) -> u32 {
+        let load = |offset: u32, size: u16| -> Option<u32> {
+            if offset >= SKF_AD_OFF {
+                return match offset - SKF_AD_OFF {
+                    SKF_AD_PROTOCOL => Some(meta.protocol as u32),
+                    SKF_AD_PKTTYPE => Some(meta.pkttype as u32),
+                    SKF_AD_IFINDEX => Some(meta.ifindex),
+                    SKF_AD_VLAN_TAG | SKF_AD_VLAN_TAG_PRESENT => Some(0),
+                    _ => None,
+                };
+            }
+            let offset = offset as usize;
+            let bytes = match size {
+                BPF_W => data.get(offset..offset.checked_add(4)?)?,
+                BPF_H => data.get(offset..offset.checked_add(2)?)?,
+                _ => data.get(offset..offset.checked_add(1)?)?,
+            };
+            Some(bytes.iter().fold(0, |acc, &it| acc << 8 | it as u32))
+        };
+
+        let (mut a, mut x) = (0u32, 0u32);
+        let mut mem = [0u32; BPF_MEMWORDS];
+        let mut pc = 0;
+        loop {
+            let insn = self.0[pc];
+            pc += 1;
+            let src = if insn.code & BPF_X != 0 { x } else { insn.k };
+            match insn.code & 0x07 {
+                BPF_LD => {
+                    a = match insn.code & 0xe0 {
+                        BPF_IMM => insn.k,
+                        BPF_LEN => data.len() as u32,
+                        BPF_MEM => mem[insn.k as usize],
+                        mode => {
+                            let offset = if mode == BPF_IND {
+                                x.wrapping_add(insn.k)
+                            } else {
+                                insn.k
+                            };
+                            // Out of bounds drops the packet.
+                            let Some(val) = load(offset, insn.code & 0x18) else {
+                                return 0;
+                            };
+                            val
+                        }
+                    }
+                }
+                BPF_LDX => {
+                    x = match insn.code & 0xe0 {
+                        BPF_IMM => insn.k,
+                        BPF_LEN => data.len() as u32,
+                        BPF_MEM => mem[insn.k as usize],
+                        _ => {
+                            // The IP header length, for `BPF_MSH`.
+                            let Some(val) = load(insn.k, BPF_B) else {
+                                return 0;
+                            };
+                            (val & 0xf) << 2
+                        }
+                    }
+                }
+                BPF_ST => mem[insn.k as usize] = a,
+                BPF_STX => mem[insn.k as usize] = x,
+                BPF_ALU => {
+                    a = match insn.code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(src),
+                        BPF_SUB => a.wrapping_sub(src),
+                        BPF_MUL => a.wrapping_mul(src),
+                        BPF_DIV | BPF_MOD if src == 0 => return 0,
+                        BPF_DIV => a / src,
+                        BPF_MOD => a % src,
+                        BPF_OR => a | src,
+                        BPF_AND => a & src,
+                        BPF_LSH => a.checked_shl(src).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(src).unwrap_or(0),
+                        BPF_NEG => a.wrapping_neg(),
+                        _ => a ^ src,
+                    }
+                }
+                BPF_JMP => {
+                    let taken = match insn.code & 0xf0 {
+                        BPF_JA => {
+                            pc += insn.k as usize;
+                            continue;
+                        }
+                        BPF_JEQ => a == src,
+                        BPF_JGT => a > src,
+                        BPF_JGE => a >= src,
+                        _ => a & src != 0,
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+                BPF_RET => {
+                    return if insn.code & 0x18 == BPF_A { a } else { insn.k };
+                }
+                _ => {
+                    if insn.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,677 @@
+//! Network interfaces, addresses and routes.
+//!
+//! The network stack takes its configuration at build time and does not
+//! expose its interfaces, so the kernel keeps the table that user space
+//! inspects and edits through `rtnetlink`, one for every namespace. That of
+//! the initial namespace starts out describing what the stack was built
+//! with: the loopback interface and one Ethernet interface with the `AX_IP`
+//! address and a default route through `AX_GW`. These two belong to the
+//! stack, so changing them fails with `EOPNOTSUPP` rather than pretending to
+//! take effect; only virtual interfaces can be reconfigured.
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use bitflags::bitflags;
+
+use super::ns::current_net_ns;
+
+/// `ARPHRD_ETHER`, the hardware type of Ethernet interfaces.
+pub const ARPHRD_ETHER: u16 = 1;
+/// `ARPHRD_LOOPBACK`, the hardware type of the loopback interface.
+pub const ARPHRD_LOOPBACK: u16 = 772;
+/// `ARPHRD_NONE`, the hardware type of interfaces without a link layer.
+pub const ARPHRD_NONE: u16 = 0xfffe;
+
+/// Longest interface name, including the terminating nul.
+pub const IFNAMSIZ: usize = 16;
+/// Smallest MTU an interface can be given.
+pub const MIN_MTU: u32 = 68;
+
+/// Address of the Ethernet interface when none is given at build time.
+const DEFAULT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+/// Gateway of the Ethernet interface when none is given at build time.
+const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+/// Prefix length of the Ethernet interface address.
+const DEFAULT_PREFIX_LEN: u8 = 24;
+
+/// Routing table `ip route` shows by default.
+pub const RT_TABLE_MAIN: u32 = 254;
+
+/// `RT_SCOPE_UNIVERSE`, a global route or address.
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+/// `RT_SCOPE_LINK`, a route to a directly attached network.
+pub const RT_SCOPE_LINK: u8 = 253;
+/// `RT_SCOPE_HOST`, a route or address local to the host.
+pub const RT_SCOPE_HOST: u8 = 254;
+
+/// `RTPROT_KERNEL`, a route the kernel added for an address.
+pub const RTPROT_KERNEL: u8 = 2;
+/// `RTPROT_BOOT`, a route added by user space or at boot.
+pub const RTPROT_BOOT: u8 = 3;
+
+bitflags! {
+    /// Interface flags, `IFF_*`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InterfaceFlags: u32 {
+        const UP = 0x1;
+        const BROADCAST = 0x2;
+        const DEBUG = 0x4;
+        const LOOPBACK = 0x8;
+        const POINTOPOINT = 0x10;
+        const NOTRAILERS = 0x20;
+        const RUNNING = 0x40;
+        const NOARP = 0x80;
+        const PROMISC = 0x100;
+        const ALLMULTI = 0x200;
+        const MULTICAST = 0x1000;
+        const LOWER_UP = 0x10000;
+    }
+}
+
+impl InterfaceFlags {
+    /// Flags user space may change, the rest follow the link state.
+    pub const CHANGEABLE: Self = Self::UP
+        .union(Self::DEBUG)
+        .union(Self::NOARP)
+        .union(Self::PROMISC)
+        .union(Self::ALLMULTI)
+        .union(Self::MULTICAST);
+}
+
+/// An address assigned to an interface.
+#[derive(Debug, Clone)]
+pub struct InterfaceAddr {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    /// Broadcast address, IPv4 only.
+    pub broadcast: Option<Ipv4Addr>,
+    pub label: String,
+}
+
+impl InterfaceAddr {
+    /// Returns the scope of the address, `RT_SCOPE_*`.
+    pub fn scope(&self) -> u8 {
+        if self.addr.is_loopback() {
+            RT_SCOPE_HOST
+        } else {
+            RT_SCOPE_UNIVERSE
+        }
+    }
+}
+
+/// A network interface.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    /// Hardware type, `ARPHRD_*`.
+    pub hw_type: u16,
+    pub hw_addr: [u8; 6],
+    pub flags: InterfaceFlags,
+    pub mtu: u32,
+    pub tx_queue_len: u32,
+    /// Number of users that put the interface into promiscuous mode.
+    pub promiscuity: u32,
+    /// Kind of a virtual interface, `IFLA_INFO_KIND`.
+    pub kind: Option<&'static str>,
+    /// Index of the peer of a `veth` interface, 0 for none.
+    pub link: u32,
+    pub addrs: Vec<InterfaceAddr>,
+    /// Whether the interface belongs to the network stack, whose
+    /// configuration cannot be changed.
+    pub stack: bool,
+}
+
+impl Interface {
+    /// Fails with `EOPNOTSUPP` if the interface belongs to the network stack.
+    pub fn check_configurable(&self) -> LinuxResult<()> {
+        if self.stack {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the interface is up and its link is running.
+    pub fn is_running(&self) -> bool {
+        self.flags
+            .contains(InterfaceFlags::UP | InterfaceFlags::RUNNING)
+    }
+
+    /// Returns the flags reported to user space: a link that is down is not
+    /// running either, and packet sockets may have made it promiscuous.
+    pub fn reported_flags(&self) -> InterfaceFlags {
+        let mut flags = self.flags;
+        if !flags.contains(InterfaceFlags::UP) {
+            flags -= InterfaceFlags::RUNNING | InterfaceFlags::LOWER_UP;
+        }
+        if self.promiscuity > 0 {
+            flags |= InterfaceFlags::PROMISC;
+        }
+        flags
+    }
+
+    /// Returns the first IPv4 address with the label `label`.
+    pub fn ipv4_addr(&self, label: &str) -> Option<&InterfaceAddr> {
+        self.addrs
+            .iter()
+            .find(|it| it.addr.is_ipv4() && it.label == label)
+    }
+}
+
+/// A route.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub dst: IpAddr,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddr>,
+    /// Index of the outgoing interface.
+    pub oif: u32,
+    /// Preferred source address.
+    pub prefsrc: Option<IpAddr>,
+    pub metric: u32,
+    pub table: u32,
+    /// Origin of the route, `RTPROT_*`.
+    pub protocol: u8,
+    /// Scope of the route, `RT_SCOPE_*`.
+    pub scope: u8,
+}
+
+impl Route {
+    /// Returns whether `addr` falls into the destination of the route.
+    pub fn matches(&self, addr: IpAddr) -> bool {
+        prefix_matches(self.dst, addr, self.prefix_len)
+    }
+
+    /// Returns whether `other` has the same destination, table and metric,
+    /// i.e. one would replace the other.
+    pub fn same_key(&self, other: &Route) -> bool {
+        self.dst == other.dst
+            && self.prefix_len == other.prefix_len
+            && self.table == other.table
+            && self.metric == other.metric
+    }
+}
+
+/// Returns the maximal prefix length of addresses of the family of `addr`.
+pub fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Returns whether the first `prefix_len` bits of `a` and `b` are equal.
+pub fn prefix_matches(a: IpAddr, b: IpAddr, prefix_len: u8) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            a.to_bits() & mask == b.to_bits() & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            a.to_bits() & mask == b.to_bits() & mask
+        }
+        _ => false,
+    }
+}
+
+/// Returns `addr` with the bits past the prefix cleared.
+pub fn network_of(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from_bits(addr.to_bits() & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from_bits(addr.to_bits() & mask))
+        }
+    }
+}
+
+/// Interfaces and routes of the system.
+pub struct NetConfig {
+    pub interfaces: Vec<Interface>,
+    pub routes: Vec<Route>,
+    next_index: u32,
+}
+
+impl NetConfig {
+    /// Creates the configuration of the initial namespace.
+    pub fn initial() -> Self {
+        let ip = option_env!("AX_IP")
+            .and_then(|it| it.parse().ok())
+            .unwrap_or(DEFAULT_IP);
+        let gateway = option_env!("AX_GW")
+            .and_then(|it| it.parse().ok())
+            .unwrap_or(DEFAULT_GATEWAY);
+
+        let mut config = Self::new_loopback(true);
+        // The stack does not tell the address of the device, so none is
+        // reported.
+        let eth0 = config.add_interface(
+            "eth0",
+            ARPHRD_ETHER,
+            [0; 6],
+            InterfaceFlags::UP
+                | InterfaceFlags::BROADCAST
+                | InterfaceFlags::RUNNING
+                | InterfaceFlags::MULTICAST
+                | InterfaceFlags::LOWER_UP,
+            1500,
+        );
+        // Freshly built, nothing here can fail.
+        let broadcast = Ipv4Addr::from_bits(ip.to_bits() | u32::MAX >> DEFAULT_PREFIX_LEN);
+        let _ = config.add_addr(eth0, ip.into(), DEFAULT_PREFIX_LEN, Some(broadcast), None);
+        let _ = config.add_route(
+            Route {
+                dst: Ipv4Addr::UNSPECIFIED.into(),
+                prefix_len: 0,
+                gateway: Some(gateway.into()),
+                oif: eth0,
+                prefsrc: None,
+                metric: 0,
+                table: RT_TABLE_MAIN,
+                protocol: RTPROT_BOOT,
+                scope: RT_SCOPE_UNIVERSE,
+            },
+            false,
+        );
+        for iface in &mut config.interfaces {
+            iface.stack = true;
+        }
+        config
+    }
+
+    /// Creates a configuration with only the loopback interface, which is up
+    /// if `up` is set.
+    pub fn new_loopback(up: bool) -> Self {
+        let mut config = Self {
+            interfaces: Vec::new(),
+            routes: Vec::new(),
+            next_index: 1,
+        };
+        let mut flags = InterfaceFlags::LOOPBACK | InterfaceFlags::RUNNING;
+        flags.set(InterfaceFlags::UP, up);
+        let lo = config.add_interface("lo", ARPHRD_LOOPBACK, [0; 6], flags, 65536);
+        // Freshly built, nothing here can fail.
+        let _ = config.add_addr(lo, Ipv4Addr::LOCALHOST.into(), 8, None, None);
+        let _ = config.add_addr(lo, Ipv6Addr::LOCALHOST.into(), 128, None, None);
+        config
+    }
+
+    /// Adds an interface and returns its index.
+    pub fn add_interface(
+        &mut self,
+        name: &str,
+        hw_type: u16,
+        hw_addr: [u8; 6],
+        flags: InterfaceFlags,
+        mtu: u32,
+    ) -> u32 {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.interfaces.push(Interface {
+            index,
+            name: name.into(),
+            hw_type,
+            hw_addr,
+            flags,
+            mtu,
+            tx_queue_len: if hw_type == ARPHRD_LOOPBACK { 0 } else { 1000 },
+            promiscuity: 0,
+            kind: None,
+            link: 0,
+            addrs: Vec::new(),
+            stack: false,
+        });
+        index
+    }
+
+    /// Adds an interface moved from another namespace and returns its new
+    /// index. It comes without addresses and down, as on Linux.
+    pub fn adopt_interface(&mut self, mut iface: Interface) -> LinuxResult<u32> {
+        if self.interface_by_name(&iface.name).is_ok() {
+            return Err(LinuxError::EEXIST);
+        }
+        iface.index = self.next_index;
+        self.next_index += 1;
+        iface.flags -= InterfaceFlags::UP;
+        iface.promiscuity = 0;
+        iface.addrs.clear();
+        let index = iface.index;
+        self.interfaces.push(iface);
+        Ok(index)
+    }
+
+    /// Removes an interface together with its routes, and returns it.
+    pub fn remove_interface(&mut self, index: u32) -> LinuxResult<Interface> {
+        let pos = self
+            .interfaces
+            .iter()
+            .position(|it| it.index == index)
+            .ok_or(LinuxError::ENODEV)?;
+        self.interfaces[pos].check_configurable()?;
+        self.routes.retain(|it| it.oif != index);
+        Ok(self.interfaces.remove(pos))
+    }
+
+    /// Looks an interface up by index.
+    pub fn interface(&self, index: u32) -> LinuxResult<&Interface> {
+        self.interfaces
+            .iter()
+            .find(|it| it.index == index)
+            .ok_or(LinuxError::ENODEV)
+    }
+
+    /// Looks an interface up by index for modification.
+    pub fn interface_mut(&mut self, index: u32) -> LinuxResult<&mut Interface> {
+        self.interfaces
+            .iter_mut()
+            .find(|it| it.index == index)
+            .ok_or(LinuxError::ENODEV)
+    }
+
+    /// Looks an interface up by name.
+    pub fn interface_by_name(&self, name: &str) -> LinuxResult<&Interface> {
+        self.interfaces
+            .iter()
+            .find(|it| it.name == name)
+            .ok_or(LinuxError::ENODEV)
+    }
+
+    /// Assigns an address to an interface, together with the route to the
+    /// network it belongs to.
+    pub fn add_addr(
+        &mut self,
+        index: u32,
+        addr: IpAddr,
+        prefix_len: u8,
+        broadcast: Option<Ipv4Addr>,
+        label: Option<&str>,
+    ) -> LinuxResult<()> {
+        if prefix_len > max_prefix_len(addr) {
+            return Err(LinuxError::EINVAL);
+        }
+        let iface = self.interface_mut(index)?;
+        iface.check_configurable()?;
+        if iface
+            .addrs
+            .iter()
+            .any(|it| it.addr == addr && it.prefix_len == prefix_len)
+        {
+            return Err(LinuxError::EEXIST);
+        }
+        let label = label.map_or_else(|| iface.name.clone(), String::from);
+        iface.addrs.push(InterfaceAddr {
+            addr,
+            prefix_len,
+            broadcast,
+            label,
+        });
+
+        if prefix_len < max_prefix_len(addr) && !addr.is_loopback() {
+            let _ = self.add_route(
+                Route {
+                    dst: network_of(addr, prefix_len),
+                    prefix_len,
+                    gateway: None,
+                    oif: index,
+                    prefsrc: Some(addr),
+                    metric: 0,
+                    table: RT_TABLE_MAIN,
+                    protocol: RTPROT_KERNEL,
+                    scope: RT_SCOPE_LINK,
+                },
+                false,
+            );
+        }
+        Ok(())
+    }
+
+    /// Removes an address from an interface, together with the routes that
+    /// prefer it as source. A `prefix_len` of `None` matches any.
+    pub fn remove_addr(
+        &mut self,
+        index: u32,
+        addr: IpAddr,
+        prefix_len: Option<u8>,
+    ) -> LinuxResult<()> {
+        let iface = self.interface_mut(index)?;
+        iface.check_configurable()?;
+        let pos = iface
+            .addrs
+            .iter()
+            .position(|it| it.addr == addr && prefix_len.is_none_or(|len| len == it.prefix_len))
+            .ok_or(LinuxError::EADDRNOTAVAIL)?;
+        iface.addrs.remove(pos);
+        self.routes
+            .retain(|it| !(it.oif == index && it.prefsrc == Some(addr)));
+        Ok(())
+    }
+
+    /// Adds a route. An existing route with the same key is replaced if
+    /// `replace` is set, otherwise `EEXIST` is returned.
+    pub fn add_route(&mut self, route: Route, replace: bool) -> LinuxResult<()> {
+        if route.prefix_len > max_prefix_len(route.dst) {
+            return Err(LinuxError::EINVAL);
+        }
+        self.interface(route.oif)?.check_configurable()?;
+        if let Some(pos) = self.routes.iter().position(|it| it.same_key(&route)) {
+            if !replace {
+                return Err(LinuxError::EEXIST);
+            }
+            self.interface(self.routes[pos].oif)?.check_configurable()?;
+            self.routes[pos] = route;
+        } else {
+            self.routes.push(route);
+        }
+        Ok(())
+    }
+
+    /// Fills in the outgoing interface of a route from its gateway, and
+    /// checks that the gateway is on the link.
+    pub fn resolve_route(&self, route: &mut Route) -> LinuxResult<()> {
+        match (route.gateway, route.oif) {
+            (None, 0) => return Err(LinuxError::ENODEV),
+            (Some(gateway), 0) => route.oif = self.interface_for(gateway)?,
+            (Some(gateway), oif) => {
+                if !self
+                    .interface(oif)?
+                    .addrs
+                    .iter()
+                    .any(|it| prefix_matches(it.addr, gateway, it.prefix_len))
+                {
+                    return Err(LinuxError::ENETUNREACH);
+                }
+            }
+            (None, _) => {}
+        }
+        if route.gateway.is_some() && route.scope != RT_SCOPE_UNIVERSE {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Removes the first route matching `route` in destination and table,
+    /// and in gateway and interface where they are given.
+    pub fn remove_route(&mut self, route: &Route) -> LinuxResult<()> {
+        let pos = self
+            .routes
+            .iter()
+            .position(|it| {
+                it.dst == route.dst
+                    && it.prefix_len == route.prefix_len
+                    && it.table == route.table
+                    && (route.gateway.is_none() || it.gateway == route.gateway)
+                    && (route.oif == 0 || it.oif == route.oif)
+            })
+            .ok_or(LinuxError::ESRCH)?;
+        self.interface(self.routes[pos].oif)?.check_configurable()?;
+        self.routes.remove(pos);
+        Ok(())
+    }
+
+    /// Returns the index of the loopback interface.
+    pub fn loopback_index(&self) -> Option<u32> {
+        self.interfaces
+            .iter()
+            .find(|it| it.hw_type == ARPHRD_LOOPBACK)
+            .map(|it| it.index)
+    }
+
+    /// Finds the interface a route to `dst` has to go out through, from the
+    /// networks of the interface addresses.
+    pub fn interface_for(&self, dst: IpAddr) -> LinuxResult<u32> {
+        self.interfaces
+            .iter()
+            .find(|iface| {
+                iface
+                    .addrs
+                    .iter()
+                    .any(|it| prefix_matches(it.addr, dst, it.prefix_len))
+            })
+            .map(|it| it.index)
+            .ok_or(LinuxError::ENETUNREACH)
+    }
+
+    /// Returns whether `addr` belongs to the host: an address of an interface
+    /// that is up, or any loopback address.
+    pub fn is_local(&self, addr: IpAddr) -> bool {
+        addr.is_loopback()
+            || self
+                .interfaces
+                .iter()
+                .filter(|it| it.flags.contains(InterfaceFlags::UP))
+                .any(|iface| iface.addrs.iter().any(|it| it.addr == addr))
+    }
+
+    /// Looks up the route to `dst`: the most specific one, then the one with
+    /// the lowest metric.
+    pub fn lookup(&self, dst: IpAddr) -> LinuxResult<&Route> {
+        self.routes
+            .iter()
+            .filter(|it| it.matches(dst))
+            .filter(|it| self.interface(it.oif).is_ok_and(Interface::is_running))
+            .min_by_key(|it| (u8::MAX - it.prefix_len, it.metric))
+            .ok_or(LinuxError::ENETUNREACH)
+    }
+}
+
+/// `RTF_UP`, `RTF_GATEWAY` and `RTF_HOST`, the flags of a route.
+pub const RTF_UP: u16 = 0x1;
+pub const RTF_GATEWAY: u16 = 0x2;
+pub const RTF_HOST: u16 = 0x4;
+
+/// Generates `/proc/net/dev` of the current namespace. No traffic is
+/// counted.
+pub fn proc_net_dev() -> String {
+    let mut out = String::from(
+        "Inter-|   Receive                                                |  Transmit\n face \
+         |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop \
+         fifo colls carrier compressed\n",
+    );
+    for iface in &current_net_ns().config().interfaces {
+        let _ = write!(out, "{:>6}:", iface.name);
+        for width in [8, 7, 4, 4, 4, 5, 10, 9, 8, 7, 4, 4, 4, 5, 7, 10] {
+            let _ = write!(out, "{:>width$} ", 0);
+        }
+        out.pop();
+        out.push('\n');
+    }
+    out
+}
+
+/// Generates `/proc/net/route`, the IPv4 routes of the main table of the
+/// current namespace.
+pub fn proc_net_route() -> String {
+    // Addresses are printed as the native-endian value of the network-order
+    // bytes.
+    fn hex(addr: Ipv4Addr) -> u32 {
+        u32::from_ne_bytes(addr.octets())
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<127}",
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT"
+    );
+    let ns = current_net_ns();
+    let config = ns.config();
+    for route in &config.routes {
+        let (IpAddr::V4(dst), RT_TABLE_MAIN) = (route.dst, route.table) else {
+            continue;
+        };
+        let Ok(iface) = config.interface(route.oif) else {
+            continue;
+        };
+        let gateway = match route.gateway {
+            Some(IpAddr::V4(gateway)) => gateway,
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+        let mut flags = RTF_UP;
+        if route.gateway.is_some() {
+            flags |= RTF_GATEWAY;
+        }
+        if route.prefix_len == 32 {
+            flags |= RTF_HOST;
+        }
+        let mask = Ipv4Addr::from_bits(
+            u32::MAX
+                .checked_shl(32 - route.prefix_len as u32)
+                .unwrap_or(0),
+        );
+        let line = format!(
+            "{}\t{:08X}\t{:08X}\t{:04X}\t0\t0\t{}\t{:08X}\t0\t0\t0",
+            iface.name,
+            hex(dst),
+            hex(gateway),
+            flags,
+            route.metric,
+            hex(mask),
+        );
+        let _ = writeln!(out, "{line:<127}");
+    }
+    out
+}
+
+/// Generates `/proc/net/if_inet6`, the IPv6 addresses of the interfaces of
+/// the current namespace.
+pub fn proc_if_inet6() -> String {
+    /// `IFA_F_PERMANENT`.
+    const IFA_F_PERMANENT: u8 = 0x80;
+
+    let mut out = String::new();
+    for iface in &current_net_ns().config().interfaces {
+        for addr in &iface.addrs {
+            let IpAddr::V6(ip) = addr.addr else {
+                continue;
+            };
+            // Scopes here are `IPV6_ADDR_*`, not `RT_SCOPE_*`.
+            let scope = if ip.is_loopback() {
+                0x10
+            } else if ip.is_unicast_link_local() {
+                0x20
+            } else {
+                0
+            };
+            let _ = writeln!(
+                out,
+                "{:032x} {:02x} {:02x} {:02x} {:02x} {:>8}",
+                ip.to_bits(),
+                iface.index,
+                addr.prefix_len,
+                scope,
+                IFA_F_PERMANENT,
+                iface.name
+            );
+        }
+    }
+    out
+}
@@ -0,0 +1,410 @@
+//! Interface ioctls, `SIOC*`, as `ifconfig` and `route` use them.
+//!
+//! They work on any socket and go to the same table as `rtnetlink`, that
+//! of the namespace of the caller, see [`super::iface`]. Like there, changes
+//! to the interfaces of the network stack fail with `EOPNOTSUPP`.
+
+use alloc::string::String;
+use core::{
+    ffi::c_char,
+    mem::size_of,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use bytemuck::{AnyBitPattern, Zeroable};
+use linux_raw_sys::net::AF_INET;
+use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
+
+use super::{
+    iface::{
+        IFNAMSIZ, InterfaceFlags, MIN_MTU, NetConfig, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE,
+        RT_TABLE_MAIN, RTF_GATEWAY, RTF_HOST, RTPROT_BOOT, Route, network_of,
+    },
+    ns::current_net_ns,
+};
+use crate::mm::vm_load_string;
+
+const SIOCADDRT: u32 = 0x890b;
+const SIOCDELRT: u32 = 0x890c;
+const SIOCGIFNAME: u32 = 0x8910;
+const SIOCGIFCONF: u32 = 0x8912;
+const SIOCGIFFLAGS: u32 = 0x8913;
+const SIOCSIFFLAGS: u32 = 0x8914;
+const SIOCGIFADDR: u32 = 0x8915;
+const SIOCSIFADDR: u32 = 0x8916;
+const SIOCGIFBRDADDR: u32 = 0x8919;
+const SIOCSIFBRDADDR: u32 = 0x891a;
+const SIOCGIFNETMASK: u32 = 0x891b;
+const SIOCSIFNETMASK: u32 = 0x891c;
+const SIOCGIFMETRIC: u32 = 0x891d;
+const SIOCGIFMTU: u32 = 0x8921;
+const SIOCSIFMTU: u32 = 0x8922;
+const SIOCSIFHWADDR: u32 = 0x8924;
+const SIOCGIFHWADDR: u32 = 0x8927;
+const SIOCGIFINDEX: u32 = 0x8933;
+const SIOCGIFTXQLEN: u32 = 0x8942;
+const SIOCSIFTXQLEN: u32 = 0x8943;
+
+/// Size of a `struct sockaddr`.
+const SOCKADDR_SIZE: usize = 16;
+
+/// `struct ifreq`. The union is as large as its largest member, `struct
+/// ifmap`.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+pub(super) struct IfReq {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_ifru: [u8; 2 * size_of::<usize>() + 8],
+}
+
+impl IfReq {
+    pub(super) fn name(&self) -> LinuxResult<&str> {
+        let len = self
+            .ifr_name
+            .iter()
+            .position(|&it| it == 0)
+            .ok_or(LinuxError::EINVAL)?;
+        core::str::from_utf8(&self.ifr_name[..len]).map_err(|_| LinuxError::EINVAL)
+    }
+
+    pub(super) fn set_name(&mut self, name: &str) {
+        let len = name.len().min(IFNAMSIZ - 1);
+        self.ifr_name = [0; IFNAMSIZ];
+        self.ifr_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    fn int(&self) -> i32 {
+        i32::from_ne_bytes(self.ifr_ifru[..4].try_into().unwrap())
+    }
+
+    fn set_int(&mut self, val: i32) {
+        self.ifr_ifru[..4].copy_from_slice(&val.to_ne_bytes());
+    }
+
+    pub(super) fn short(&self) -> i16 {
+        i16::from_ne_bytes(self.ifr_ifru[..2].try_into().unwrap())
+    }
+
+    pub(super) fn set_short(&mut self, val: i16) {
+        self.ifr_ifru[..2].copy_from_slice(&val.to_ne_bytes());
+    }
+
+    fn sockaddr(&mut self) -> &mut [u8; SOCKADDR_SIZE] {
+        (&mut self.ifr_ifru[..SOCKADDR_SIZE]).try_into().unwrap()
+    }
+}
+
+/// `struct ifconf`.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct IfConf {
+    ifc_len: i32,
+    ifc_buf: usize,
+}
+
+/// `struct rtentry`.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct RtEntry {
+    rt_pad1: usize,
+    rt_dst: [u8; SOCKADDR_SIZE],
+    rt_gateway: [u8; SOCKADDR_SIZE],
+    rt_genmask: [u8; SOCKADDR_SIZE],
+    rt_flags: u16,
+    rt_pad2: i16,
+    rt_pad3: usize,
+    rt_pad4: usize,
+    rt_metric: i16,
+    rt_dev: usize,
+    rt_mtu: usize,
+    rt_window: usize,
+    rt_irtt: u16,
+}
+
+/// Reads the address of a `struct sockaddr_in`.
+fn read_ipv4(sockaddr: &[u8; SOCKADDR_SIZE]) -> LinuxResult<Ipv4Addr> {
+    if u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) != AF_INET as u16 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(Ipv4Addr::new(
+        sockaddr[4],
+        sockaddr[5],
+        sockaddr[6],
+        sockaddr[7],
+    ))
+}
+
+fn write_ipv4(sockaddr: &mut [u8; SOCKADDR_SIZE], addr: Ipv4Addr) {
+    *sockaddr = [0; SOCKADDR_SIZE];
+    sockaddr[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    sockaddr[4..8].copy_from_slice(&addr.octets());
+}
+
+/// Returns the prefix length of a netmask, which has to be contiguous.
+fn mask_prefix_len(mask: Ipv4Addr) -> LinuxResult<u8> {
+    let bits = mask.to_bits();
+    if bits.leading_ones() + bits.trailing_zeros() != 32 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(bits.leading_ones() as u8)
+}
+
+/// Returns the prefix length of the network class of `addr`, which a new
+/// address is given before its netmask is set.
+fn classful_prefix_len(addr: Ipv4Addr) -> LinuxResult<u8> {
+    match addr.octets()[0] {
+        0..=127 => Ok(8),
+        128..=191 => Ok(16),
+        192..=223 => Ok(24),
+        _ if addr.is_broadcast() => Ok(32),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+fn broadcast_of(addr: Ipv4Addr, prefix_len: u8) -> Ipv4Addr {
+    Ipv4Addr::from_bits(addr.to_bits() | u32::MAX.checked_shr(prefix_len as u32).unwrap_or(0))
+}
+
+/// Replaces the IPv4 address labelled `label` of interface `index`, or
+/// removes it if `addr` is `None`.
+fn replace_ipv4(
+    config: &mut NetConfig,
+    index: u32,
+    label: &str,
+    addr: Option<(Ipv4Addr, u8)>,
+) -> LinuxResult<()> {
+    let iface = config.interface(index)?;
+    let broadcast = iface.flags.contains(InterfaceFlags::BROADCAST);
+    if let Some(old) = iface.ipv4_addr(label) {
+        let (addr, prefix_len) = (old.addr, old.prefix_len);
+        config.remove_addr(index, addr, Some(prefix_len))?;
+    }
+    if let Some((addr, prefix_len)) = addr {
+        let broadcast = (broadcast && prefix_len < 31).then(|| broadcast_of(addr, prefix_len));
+        config.add_addr(index, addr.into(), prefix_len, broadcast, Some(label))?;
+    }
+    Ok(())
+}
+
+fn get_conf(arg: usize) -> LinuxResult<usize> {
+    let mut conf = (arg as *const IfConf).vm_read()?;
+    let ns = current_net_ns();
+    let config = ns.config();
+    let addrs = config
+        .interfaces
+        .iter()
+        .flat_map(|iface| iface.addrs.iter().filter(|it| it.addr.is_ipv4()));
+
+    if conf.ifc_buf == 0 {
+        // Only the size needed is asked for.
+        conf.ifc_len = (addrs.count() * size_of::<IfReq>()) as i32;
+    } else {
+        let max = conf.ifc_len.max(0) as usize / size_of::<IfReq>();
+        let mut len = 0;
+        for addr in addrs.take(max) {
+            let mut req = IfReq::zeroed();
+            req.set_name(&addr.label);
+            if let IpAddr::V4(ip) = addr.addr {
+                write_ipv4(req.sockaddr(), ip);
+            }
+            vm_write_slice((conf.ifc_buf + len) as *mut u8, bytemuck::bytes_of(&req))?;
+            len += size_of::<IfReq>();
+        }
+        conf.ifc_len = len as i32;
+    }
+    (arg as *mut IfConf).vm_write(conf)?;
+    Ok(0)
+}
+
+fn route_ioctl(cmd: u32, arg: usize) -> LinuxResult<usize> {
+    let rt = (arg as *const RtEntry).vm_read()?;
+    let dst = read_ipv4(&rt.rt_dst)?;
+    let prefix_len = if rt.rt_flags & RTF_HOST != 0 {
+        32
+    } else {
+        // Some tools leave the family of the mask empty.
+        let mask = &rt.rt_genmask;
+        mask_prefix_len(Ipv4Addr::new(mask[4], mask[5], mask[6], mask[7]))?
+    };
+    if network_of(dst.into(), prefix_len) != dst {
+        return Err(LinuxError::EINVAL);
+    }
+    let gateway = if rt.rt_flags & RTF_GATEWAY != 0 {
+        Some(read_ipv4(&rt.rt_gateway)?.into())
+    } else {
+        None
+    };
+    let dev = if rt.rt_dev != 0 {
+        Some(vm_load_string(rt.rt_dev as *const c_char)?)
+    } else {
+        None
+    };
+
+    let ns = current_net_ns();
+    let mut config = ns.config();
+    let oif = match dev {
+        Some(dev) => config.interface_by_name(&dev)?.index,
+        None => 0,
+    };
+    let mut route = Route {
+        dst: dst.into(),
+        prefix_len,
+        gateway,
+        oif,
+        prefsrc: None,
+        // The metric of `route` starts at 1.
+        metric: (rt.rt_metric as u32).saturating_sub(1),
+        table: RT_TABLE_MAIN,
+        protocol: RTPROT_BOOT,
+        scope: if gateway.is_some() {
+            RT_SCOPE_UNIVERSE
+        } else {
+            RT_SCOPE_LINK
+        },
+    };
+    if cmd == SIOCADDRT {
+        if route.oif == 0 && gateway.is_none() {
+            route.oif = config.interface_for(dst.into())?;
+        }
+        config.resolve_route(&mut route)?;
+        config.add_route(route, false)?;
+    } else {
+        config.remove_route(&route)?;
+    }
+    Ok(0)
+}
+
+/// Handles an interface ioctl on a socket.
+pub fn interface_ioctl(cmd: u32, arg: usize) -> LinuxResult<usize> {
+    match cmd {
+        SIOCGIFCONF => return get_conf(arg),
+        SIOCADDRT | SIOCDELRT => return route_ioctl(cmd, arg),
+        SIOCGIFNAME | SIOCGIFFLAGS | SIOCSIFFLAGS | SIOCGIFADDR | SIOCSIFADDR | SIOCGIFBRDADDR
+        | SIOCSIFBRDADDR | SIOCGIFNETMASK | SIOCSIFNETMASK | SIOCGIFMETRIC | SIOCGIFMTU
+        | SIOCSIFMTU | SIOCSIFHWADDR | SIOCGIFHWADDR | SIOCGIFINDEX | SIOCGIFTXQLEN
+        | SIOCSIFTXQLEN => {}
+        _ => return Err(LinuxError::ENOTTY),
+    }
+
+    let mut req = (arg as *const IfReq).vm_read()?;
+    let ns = current_net_ns();
+    let mut config = ns.config();
+    if cmd == SIOCGIFNAME {
+        let name = config.interface(req.int() as u32)?.name.clone();
+        req.set_name(&name);
+        (arg as *mut IfReq).vm_write(req)?;
+        return Ok(0);
+    }
+
+    // The name may carry an alias label, `eth0:1`, which addresses are
+    // looked up by.
+    let label = String::from(req.name()?);
+    let name = label.split(':').next().unwrap_or_default();
+    let index = config.interface_by_name(name)?.index;
+    let iface = config.interface_mut(index)?;
+    match cmd {
+        SIOCGIFFLAGS => req.set_short(iface.reported_flags().bits() as u16 as i16),
+        SIOCSIFFLAGS => {
+            let flags = InterfaceFlags::from_bits_truncate(req.short() as u16 as u32);
+            let flags =
+                (iface.flags - InterfaceFlags::CHANGEABLE) | (flags & InterfaceFlags::CHANGEABLE);
+            if flags != iface.flags {
+                iface.check_configurable()?;
+                iface.flags = flags;
+            }
+        }
+        SIOCGIFADDR | SIOCGIFNETMASK | SIOCGIFBRDADDR => {
+            let addr = iface.ipv4_addr(&label).ok_or(LinuxError::EADDRNOTAVAIL)?;
+            let IpAddr::V4(ip) = addr.addr else {
+                unreachable!()
+            };
+            let val = match cmd {
+                SIOCGIFADDR => ip,
+                SIOCGIFNETMASK => Ipv4Addr::from_bits(
+                    u32::MAX
+                        .checked_shl(32 - addr.prefix_len as u32)
+                        .unwrap_or(0),
+                ),
+                _ => addr.broadcast.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            };
+            write_ipv4(req.sockaddr(), val);
+        }
+        SIOCSIFADDR => {
+            let addr = read_ipv4(req.sockaddr())?;
+            if iface.ipv4_addr(&label).is_some_and(|it| it.addr == addr) {
+                return Ok(0);
+            }
+            // Setting 0.0.0.0 takes the address away.
+            let new = if addr.is_unspecified() {
+                None
+            } else {
+                Some((addr, classful_prefix_len(addr)?))
+            };
+            replace_ipv4(&mut config, index, &label, new)?;
+        }
+        SIOCSIFNETMASK => {
+            let prefix_len = mask_prefix_len(read_ipv4(req.sockaddr())?)?;
+            let addr = iface
+                .ipv4_addr(&label)
+                .ok_or(LinuxError::EADDRNOTAVAIL)?
+                .addr;
+            let IpAddr::V4(addr) = addr else {
+                unreachable!()
+            };
+            replace_ipv4(&mut config, index, &label, Some((addr, prefix_len)))?;
+        }
+        SIOCSIFBRDADDR => {
+            let broadcast = read_ipv4(req.sockaddr())?;
+            iface.check_configurable()?;
+            iface
+                .addrs
+                .iter_mut()
+                .find(|it| it.addr.is_ipv4() && it.label == label)
+                .ok_or(LinuxError::EADDRNOTAVAIL)?
+                .broadcast = Some(broadcast);
+        }
+        SIOCGIFMETRIC => req.set_int(0),
+        SIOCGIFMTU => req.set_int(iface.mtu as i32),
+        SIOCSIFMTU => {
+            let mtu = req.int();
+            if mtu < MIN_MTU as i32 {
+                return Err(LinuxError::EINVAL);
+            }
+            if mtu as u32 != iface.mtu {
+                iface.check_configurable()?;
+                iface.mtu = mtu as u32;
+            }
+        }
+        SIOCGIFHWADDR => {
+            let hw_type = iface.hw_type;
+            let hw_addr = iface.hw_addr;
+            let sockaddr = req.sockaddr();
+            *sockaddr = [0; SOCKADDR_SIZE];
+            sockaddr[..2].copy_from_slice(&hw_type.to_ne_bytes());
+            sockaddr[2..8].copy_from_slice(&hw_addr);
+        }
+        SIOCSIFHWADDR => {
+            let sockaddr = *req.sockaddr();
+            if u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) != iface.hw_type {
+                return Err(LinuxError::EINVAL);
+            }
+            iface.check_configurable()?;
+            iface.hw_addr.copy_from_slice(&sockaddr[2..8]);
+        }
+        SIOCGIFINDEX => req.set_int(index as i32),
+        SIOCGIFTXQLEN => req.set_int(iface.tx_queue_len as i32),
+        SIOCSIFTXQLEN => {
+            let len = req.int();
+            if len < 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            iface.check_configurable()?;
+            iface.tx_queue_len = len as u32;
+        }
+        _ => unreachable!(),
+    }
+    (arg as *mut IfReq).vm_write(req)?;
+    Ok(0)
+}
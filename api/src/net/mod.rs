@@ -0,0 +1,14 @@
+//! Networking the network stack does not cover: interface configuration,
+//! netlink sockets, raw IP sockets, packet sockets, TUN/TAP interfaces and
+//! network namespaces with `veth` pairs.
+
+pub mod bpf;
+pub mod iface;
+pub mod ioctl;
+pub mod netlink;
+pub mod ns;
+pub mod packet;
+pub mod raw;
+mod rtnetlink;
+pub mod tun;
+pub mod veth;
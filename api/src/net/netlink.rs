@@ -0,0 +1,329 @@
+//! Netlink sockets.
+//!
+//! Messages to the kernel are handled while they are sent, and the replies
+//! wait in the receive queue of the socket, one datagram each. Only
+//! `NETLINK_ROUTE` is implemented, see [`super::rtnetlink`]; it works on the
+//! namespace the socket was created in.
+
+use alloc::{
+    collections::{btree_set::BTreeSet, vec_deque::VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
+use axnet::{
+    RecvFlags,
+    options::{GetSocketOption, SetSocketOption},
+};
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use linux_raw_sys::netlink::{
+    NETLINK_ADD_MEMBERSHIP, NETLINK_DROP_MEMBERSHIP, NETLINK_NO_ENOBUFS, NETLINK_ROUTE,
+};
+use starry_core::task::AsThread;
+
+use super::ns::{NetNamespace, current_net_ns};
+use crate::socket::NetlinkAddr;
+
+/// Default size of the send and receive buffers.
+const DEFAULT_BUFFER_SIZE: usize = 212992;
+
+/// First port id handed out once the process id is taken, counting down as
+/// Linux does.
+const FIRST_AUTO_PORT: u32 = -4096i32 as u32;
+
+/// Port ids in use.
+static PORTS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+static NEXT_AUTO_PORT: AtomicU32 = AtomicU32::new(FIRST_AUTO_PORT);
+
+/// Takes `port`, or a free port id if it is 0: the process id if possible.
+fn alloc_port(port: u32) -> LinuxResult<u32> {
+    let mut ports = PORTS.lock();
+    if port != 0 {
+        return if ports.insert(port) {
+            Ok(port)
+        } else {
+            Err(LinuxError::EADDRINUSE)
+        };
+    }
+    let mut port = current().as_thread().proc_data.proc.pid();
+    while !ports.insert(port) {
+        port = NEXT_AUTO_PORT.fetch_sub(1, Ordering::Relaxed);
+    }
+    Ok(port)
+}
+
+pub struct NetlinkSocket {
+    protocol: u32,
+    /// Namespace the socket was created in.
+    ns: Arc<NetNamespace>,
+    /// Port id, 0 until the socket is bound.
+    port: AtomicU32,
+    groups: AtomicU32,
+    /// Socket options of `SOL_NETLINK` that are plain flags, by bit.
+    flags: AtomicU32,
+    nonblocking: AtomicBool,
+    send_buffer: AtomicUsize,
+    recv_buffer: AtomicUsize,
+    rx: Mutex<VecDeque<Vec<u8>>>,
+    /// Set when a reply was dropped because the receive queue was full.
+    overrun: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl NetlinkSocket {
+    /// Creates a socket of the netlink protocol `protocol`.
+    pub fn new(protocol: u32) -> LinuxResult<Self> {
+        if protocol != NETLINK_ROUTE {
+            return Err(LinuxError::EPROTONOSUPPORT);
+        }
+        Ok(Self {
+            protocol,
+            ns: current_net_ns(),
+            port: AtomicU32::new(0),
+            groups: AtomicU32::new(0),
+            flags: AtomicU32::new(0),
+            nonblocking: AtomicBool::new(false),
+            send_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            recv_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            rx: Mutex::new(VecDeque::new()),
+            overrun: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        })
+    }
+
+    /// Returns the port id, binding the socket to a free one first if it is
+    /// not bound yet.
+    fn autobind(&self) -> LinuxResult<u32> {
+        let port = self.port.load(Ordering::Acquire);
+        if port != 0 {
+            return Ok(port);
+        }
+        let port = alloc_port(0)?;
+        if let Err(bound) = self
+            .port
+            .compare_exchange(0, port, Ordering::AcqRel, Ordering::Acquire)
+        {
+            PORTS.lock().remove(&port);
+            return Ok(bound);
+        }
+        Ok(port)
+    }
+
+    pub fn bind(&self, addr: NetlinkAddr) -> LinuxResult<()> {
+        let bound = self.port.load(Ordering::Acquire);
+        if bound != 0 {
+            if addr.pid != 0 && addr.pid != bound {
+                return Err(LinuxError::EINVAL);
+            }
+        } else {
+            let port = alloc_port(addr.pid)?;
+            if self
+                .port
+                .compare_exchange(0, port, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                PORTS.lock().remove(&port);
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        self.groups.store(addr.groups, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn connect(&self, addr: NetlinkAddr) -> LinuxResult<()> {
+        // Only the kernel can be talked to.
+        if addr.pid != 0 {
+            return Err(LinuxError::ECONNREFUSED);
+        }
+        self.autobind()?;
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> NetlinkAddr {
+        NetlinkAddr {
+            pid: self.port.load(Ordering::Acquire),
+            groups: self.groups.load(Ordering::Acquire),
+        }
+    }
+
+    pub fn send(&self, src: &mut impl Buf, to: Option<NetlinkAddr>) -> LinuxResult<usize> {
+        if to.is_some_and(|to| to.pid != 0) {
+            return Err(LinuxError::ECONNREFUSED);
+        }
+        let len = src.remaining();
+        if len > self.send_buffer.load(Ordering::Relaxed) {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let port = self.autobind()?;
+        let mut data = vec![0; len];
+        let len = src.read(&mut data)?;
+
+        let replies = match self.protocol {
+            NETLINK_ROUTE => super::rtnetlink::handle(&self.ns, &data[..len], port),
+            _ => unreachable!(),
+        };
+        if !replies.is_empty() {
+            self.queue(replies);
+            self.poll_rx.wake();
+        }
+        Ok(len)
+    }
+
+    /// Queues replies as long as the receive queue is within the receive
+    /// buffer size. Like Linux, the rest are dropped and the next receive
+    /// fails with `ENOBUFS`, unless `NETLINK_NO_ENOBUFS` is set.
+    fn queue(&self, replies: Vec<Vec<u8>>) {
+        let limit = self.recv_buffer.load(Ordering::Relaxed);
+        let mut rx = self.rx.lock();
+        let mut queued: usize = rx.iter().map(Vec::len).sum();
+        for reply in replies {
+            if queued > limit {
+                if self.netlink_flag(NETLINK_NO_ENOBUFS) {
+                    debug!("netlink: receive queue full, reply dropped");
+                } else {
+                    self.overrun.store(true, Ordering::Release);
+                }
+                break;
+            }
+            queued += reply.len();
+            rx.push_back(reply);
+        }
+    }
+
+    fn netlink_flag(&self, optname: u32) -> bool {
+        self.flags.load(Ordering::Acquire) >> optname & 1 != 0
+    }
+
+    /// Receives a datagram, which always comes from the kernel.
+    pub fn recv(
+        &self,
+        dst: &mut impl BufMut,
+        flags: RecvFlags,
+        dontwait: bool,
+    ) -> LinuxResult<usize> {
+        if self.overrun.swap(false, Ordering::AcqRel) {
+            return Err(LinuxError::ENOBUFS);
+        }
+        let msg = Poller::new(self, IoEvents::IN)
+            .non_blocking(dontwait || self.nonblocking())
+            .poll(|| {
+                let mut rx = self.rx.lock();
+                if flags.contains(RecvFlags::PEEK) {
+                    rx.front().cloned()
+                } else {
+                    rx.pop_front()
+                }
+                .ok_or(LinuxError::EAGAIN)
+            })?;
+        // What does not fit is dropped, as for any datagram.
+        let len = msg.len().min(dst.remaining_mut());
+        dst.write(&msg[..len])?;
+        Ok(if flags.contains(RecvFlags::TRUNCATE) {
+            msg.len()
+        } else {
+            len
+        })
+    }
+
+    pub fn get_option(&self, option: GetSocketOption) -> LinuxResult<()> {
+        match option {
+            GetSocketOption::NonBlocking(value) => *value = self.nonblocking(),
+            GetSocketOption::SendBuffer(size) => *size = self.send_buffer.load(Ordering::Relaxed),
+            GetSocketOption::ReceiveBuffer(size) => {
+                *size = self.recv_buffer.load(Ordering::Relaxed)
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn set_option(&self, option: SetSocketOption) -> LinuxResult<()> {
+        match option {
+            SetSocketOption::NonBlocking(value) => {
+                self.nonblocking.store(*value, Ordering::Release)
+            }
+            SetSocketOption::SendBuffer(size) => self.send_buffer.store(*size, Ordering::Relaxed),
+            SetSocketOption::ReceiveBuffer(size) => {
+                self.recv_buffer.store(*size, Ordering::Relaxed)
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    /// Gets a `SOL_NETLINK` option.
+    pub fn get_netlink_option(&self, optname: u32) -> LinuxResult<i32> {
+        match optname {
+            NETLINK_ADD_MEMBERSHIP | NETLINK_DROP_MEMBERSHIP => Err(LinuxError::ENOPROTOOPT),
+            0..32 => Ok(self.netlink_flag(optname) as i32),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Sets a `SOL_NETLINK` option. Joining groups is recorded, but nothing
+    /// is broadcast to them.
+    pub fn set_netlink_option(&self, optname: u32, val: i32) -> LinuxResult<()> {
+        match optname {
+            NETLINK_ADD_MEMBERSHIP | NETLINK_DROP_MEMBERSHIP => {
+                let group = val as u32;
+                if group == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                if group <= 32 {
+                    let bit = 1 << (group - 1);
+                    if optname == NETLINK_ADD_MEMBERSHIP {
+                        self.groups.fetch_or(bit, Ordering::AcqRel);
+                    } else {
+                        self.groups.fetch_and(!bit, Ordering::AcqRel);
+                    }
+                }
+            }
+            0..32 => {
+                let bit = 1 << optname;
+                if val != 0 {
+                    self.flags.fetch_or(bit, Ordering::AcqRel);
+                } else {
+                    self.flags.fetch_and(!bit, Ordering::AcqRel);
+                }
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        let port = *self.port.get_mut();
+        if port != 0 {
+            PORTS.lock().remove(&port);
+        }
+    }
+}
+
+impl Pollable for NetlinkSocket {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.rx.lock().is_empty());
+        events.set(IoEvents::ERR, self.overrun.load(Ordering::Acquire));
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
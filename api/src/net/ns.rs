@@ -0,0 +1,84 @@
+//! Network namespaces.
+//!
+//! Every namespace has its own interfaces and routes, see [`NetConfig`], and
+//! a new one starts out with only a loopback interface that is down. Raw,
+//! packet and netlink sockets and TUN/TAP interfaces belong to the namespace
+//! of the process that created them and only see traffic there; `veth`
+//! pairs connect namespaces. Abstract Unix socket names are scoped by
+//! namespace. TCP and UDP are those of the network stack, whose interfaces
+//! and port spaces all namespaces share.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::LinuxResult;
+use axsync::{Mutex, MutexGuard};
+use lazy_static::lazy_static;
+use starry_core::task::get_process_data;
+use starry_process::Pid;
+
+use super::iface::NetConfig;
+
+/// Inode number of the first namespace, as Linux numbers them.
+const FIRST_NS_ID: u64 = 0xf000_0098;
+
+static NEXT_NS_ID: AtomicU64 = AtomicU64::new(FIRST_NS_ID);
+
+/// A network namespace.
+pub struct NetNamespace {
+    id: u64,
+    config: Mutex<NetConfig>,
+}
+
+impl NetNamespace {
+    fn new(config: NetConfig) -> Arc<Self> {
+        Arc::new(Self {
+            id: NEXT_NS_ID.fetch_add(1, Ordering::Relaxed),
+            config: Mutex::new(config),
+        })
+    }
+
+    /// Creates an empty namespace, for `CLONE_NEWNET`.
+    pub fn new_empty() -> Arc<Self> {
+        Self::new(NetConfig::new_loopback(false))
+    }
+
+    /// Returns the inode number identifying the namespace.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Locks the interfaces and routes of the namespace.
+    pub fn config(&self) -> MutexGuard<'_, NetConfig> {
+        self.config.lock()
+    }
+}
+
+impl Drop for NetNamespace {
+    fn drop(&mut self) {
+        super::tun::release_namespace(self);
+        super::veth::release_namespace(self);
+    }
+}
+
+lazy_static! {
+    /// The namespace the system starts in, with the interfaces of the
+    /// network stack.
+    static ref INIT_NET: Arc<NetNamespace> = NetNamespace::new(NetConfig::initial());
+}
+
+scope_local::scope_local! {
+    /// The network namespace of the current process.
+    pub static NET_NS: Arc<NetNamespace> = INIT_NET.clone();
+}
+
+/// Returns the network namespace of the current process.
+pub fn current_net_ns() -> Arc<NetNamespace> {
+    Arc::clone(&NET_NS)
+}
+
+/// Returns the network namespace of process `pid`.
+pub fn process_net_ns(pid: Pid) -> LinuxResult<Arc<NetNamespace>> {
+    let proc_data = get_process_data(pid)?;
+    Ok(NET_NS.scope(&proc_data.scope.read()).clone())
+}
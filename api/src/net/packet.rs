@@ -0,0 +1,501 @@
+//! Packet sockets, `AF_PACKET`.
+//!
+//! Frames are tapped where the kernel itself handles them, which is the
+//! loopback interface of [`super::raw`], the TUN/TAP interfaces of
+//! [`super::tun`] and the `veth` pairs of [`super::veth`]: traffic of the
+//! network stack and of the network driver is out of reach. A socket only
+//! sees the interfaces of the namespace it was created in. Frames sent on
+//! the loopback interface are received by the host, those sent on a TUN/TAP
+//! interface are read from its queue and those sent on a `veth` interface
+//! arrive at its peer; other interfaces cannot be sent on.
+
+use alloc::{
+    collections::vec_deque::VecDeque,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
+use axnet::{
+    RecvFlags,
+    options::{GetSocketOption, SetSocketOption},
+};
+use axsync::Mutex;
+use axtask::future::Poller;
+use bytemuck::AnyBitPattern;
+
+use super::{
+    bpf::{BpfProgram, PacketMeta},
+    iface::ARPHRD_LOOPBACK,
+    ns::{NetNamespace, current_net_ns},
+};
+use crate::socket::PacketAddr;
+
+/// `SOL_PACKET`, the level of options of packet sockets.
+pub const SOL_PACKET: u32 = 263;
+pub const PACKET_ADD_MEMBERSHIP: u32 = 1;
+pub const PACKET_DROP_MEMBERSHIP: u32 = 2;
+pub const PACKET_STATISTICS: u32 = 6;
+const PACKET_AUXDATA: u32 = 8;
+
+/// Membership types, `PACKET_MR_*`.
+const PACKET_MR_MULTICAST: u16 = 0;
+const PACKET_MR_PROMISC: u16 = 1;
+const PACKET_MR_ALLMULTI: u16 = 2;
+const PACKET_MR_UNICAST: u16 = 3;
+
+/// `PACKET_HOST`, a frame addressed to the host.
+const PACKET_HOST: u8 = 0;
+
+/// `ETH_P_ALL`, every protocol.
+const ETH_P_ALL: u16 = 0x0003;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+
+/// Length of an Ethernet header.
+pub const ETH_HEADER_LEN: usize = 14;
+pub const ETH_ALEN: usize = 6;
+
+/// Default size of the send and receive buffers.
+const DEFAULT_BUFFER_SIZE: usize = 212992;
+
+/// `struct packet_mreq`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct PacketMreq {
+    mr_ifindex: i32,
+    mr_type: u16,
+    mr_alen: u16,
+    mr_address: [u8; 8],
+}
+
+/// `struct tpacket_stats`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PacketStats {
+    tp_packets: u32,
+    tp_drops: u32,
+}
+
+/// Open sockets, which frames are delivered to.
+static SOCKETS: Mutex<Vec<Weak<Endpoint>>> = Mutex::new(Vec::new());
+
+/// Passes a frame that went through interface `ifindex` of namespace `ns`
+/// to the sockets there that want it.
+pub fn tap(ns: &Arc<NetNamespace>, ifindex: u32, frame: &[u8]) {
+    if frame.len() < ETH_HEADER_LEN {
+        return;
+    }
+    let Ok(hatype) = ns.config().interface(ifindex).map(|it| it.hw_type) else {
+        return;
+    };
+    let sockets = {
+        let mut sockets = SOCKETS.lock();
+        sockets.retain(|it| it.strong_count() > 0);
+        sockets
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|it| Arc::ptr_eq(&it.ns, ns))
+            .collect::<Vec<_>>()
+    };
+    let mut from = PacketAddr {
+        protocol: u16::from_be_bytes([frame[12], frame[13]]),
+        ifindex,
+        hatype,
+        pkttype: PACKET_HOST,
+        halen: ETH_ALEN as u8,
+        addr: [0; 8],
+    };
+    from.addr[..ETH_ALEN].copy_from_slice(&frame[ETH_ALEN..2 * ETH_ALEN]);
+    for socket in &sockets {
+        socket.receive(&from, frame);
+    }
+}
+
+/// Passes an IP packet going through the loopback interface of namespace
+/// `ns` to the sockets that want it.
+pub fn tap_loopback(ns: &Arc<NetNamespace>, packet: &[u8]) {
+    let Some(lo) = ns.config().loopback_index() else {
+        return;
+    };
+    tap_ip(ns, lo, packet);
+}
+
+/// Passes an IP packet that went through interface `ifindex` of namespace
+/// `ns`, which has no link layer, to the sockets that want it.
+pub fn tap_ip(ns: &Arc<NetNamespace>, ifindex: u32, packet: &[u8]) {
+    let protocol = if packet.first().is_some_and(|it| it >> 4 == 6) {
+        ETH_P_IPV6
+    } else {
+        ETH_P_IP
+    };
+    // Such interfaces have an Ethernet header of zero addresses, as the
+    // loopback interface does.
+    let mut frame = vec![0; ETH_HEADER_LEN];
+    frame[12..14].copy_from_slice(&protocol.to_be_bytes());
+    frame.extend_from_slice(packet);
+    tap(ns, ifindex, &frame);
+}
+
+struct State {
+    /// Ethernet protocol received, 0 for none.
+    protocol: u16,
+    /// Interface bound to, 0 for all.
+    ifindex: u32,
+    filter: Option<BpfProgram>,
+    /// Memberships as interface and `PACKET_MR_*` type.
+    memberships: Vec<(u32, u16)>,
+    auxdata: bool,
+    stats: PacketStats,
+}
+
+struct Endpoint {
+    /// Namespace the socket was created in.
+    ns: Arc<NetNamespace>,
+    /// Whether the link-layer header is left out, for `SOCK_DGRAM`.
+    cooked: bool,
+    state: Mutex<State>,
+    nonblocking: AtomicBool,
+    send_buffer: AtomicUsize,
+    recv_buffer: AtomicUsize,
+    rx: Mutex<VecDeque<(PacketAddr, Vec<u8>)>>,
+    poll_rx: PollSet,
+}
+
+impl Endpoint {
+    /// Queues `frame` if it is for this socket.
+    fn receive(&self, from: &PacketAddr, frame: &[u8]) {
+        let mut state = self.state.lock();
+        if state.protocol == 0
+            || (state.protocol != ETH_P_ALL && state.protocol != from.protocol)
+            || (state.ifindex != 0 && state.ifindex != from.ifindex)
+        {
+            return;
+        }
+        let data = if self.cooked {
+            &frame[ETH_HEADER_LEN..]
+        } else {
+            frame
+        };
+        let mut len = data.len();
+        if let Some(filter) = &state.filter {
+            let meta = PacketMeta {
+                protocol: from.protocol,
+                pkttype: from.pkttype,
+                ifindex: from.ifindex,
+            };
+            len = len.min(filter.run(data, &meta) as usize);
+            if len == 0 {
+                return;
+            }
+        }
+
+        let mut rx = self.rx.lock();
+        let queued = rx.iter().map(|(_, it)| it.len()).sum::<usize>();
+        if queued + len > self.recv_buffer.load(Ordering::Relaxed) {
+            state.stats.tp_drops += 1;
+            return;
+        }
+        state.stats.tp_packets += 1;
+        rx.push_back((*from, data[..len].to_vec()));
+        drop(rx);
+        drop(state);
+        self.poll_rx.wake();
+    }
+}
+
+/// Changes how many users keep interface `ifindex` of namespace `ns`
+/// promiscuous.
+fn set_promisc(ns: &NetNamespace, ifindex: u32, on: bool) {
+    if let Ok(iface) = ns.config().interface_mut(ifindex) {
+        if on {
+            iface.promiscuity += 1;
+        } else {
+            iface.promiscuity = iface.promiscuity.saturating_sub(1);
+        }
+    }
+}
+
+/// A packet socket.
+pub struct PacketSocket(Arc<Endpoint>);
+
+impl PacketSocket {
+    /// Creates a socket receiving Ethernet protocol `protocol`, with the
+    /// link-layer header if `cooked` is not set.
+    pub fn new(cooked: bool, protocol: u16) -> Self {
+        let endpoint = Arc::new(Endpoint {
+            ns: current_net_ns(),
+            cooked,
+            state: Mutex::new(State {
+                protocol,
+                ifindex: 0,
+                filter: None,
+                memberships: Vec::new(),
+                auxdata: false,
+                stats: PacketStats::default(),
+            }),
+            nonblocking: AtomicBool::new(false),
+            send_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            recv_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            rx: Mutex::new(VecDeque::new()),
+            poll_rx: PollSet::new(),
+        });
+        SOCKETS.lock().push(Arc::downgrade(&endpoint));
+        Self(endpoint)
+    }
+
+    pub fn bind(&self, addr: PacketAddr) -> LinuxResult<()> {
+        if addr.ifindex != 0 {
+            self.0.ns.config().interface(addr.ifindex)?;
+        }
+        let mut state = self.0.state.lock();
+        // A protocol of 0 keeps the one the socket has.
+        if addr.protocol != 0 {
+            state.protocol = addr.protocol;
+        }
+        state.ifindex = addr.ifindex;
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> PacketAddr {
+        let state = self.0.state.lock();
+        let mut addr = PacketAddr {
+            protocol: state.protocol,
+            ifindex: state.ifindex,
+            ..Default::default()
+        };
+        if let Ok(iface) = self.0.ns.config().interface(state.ifindex) {
+            addr.hatype = iface.hw_type;
+            addr.halen = ETH_ALEN as u8;
+            addr.addr[..ETH_ALEN].copy_from_slice(&iface.hw_addr);
+        }
+        addr
+    }
+
+    pub fn send(&self, src: &mut impl Buf, to: Option<PacketAddr>) -> LinuxResult<usize> {
+        let state = self.0.state.lock();
+        let ifindex = to.map_or(state.ifindex, |it| it.ifindex);
+        let protocol = to.map_or(state.protocol, |it| it.protocol);
+        drop(state);
+        if ifindex == 0 {
+            return Err(LinuxError::ENXIO);
+        }
+        let (hw_type, hw_addr, mtu) = {
+            let config = self.0.ns.config();
+            let iface = config.interface(ifindex).map_err(|_| LinuxError::ENXIO)?;
+            if !iface.is_running() {
+                return Err(LinuxError::ENETDOWN);
+            }
+            (iface.hw_type, iface.hw_addr, iface.mtu as usize)
+        };
+
+        let len = src.remaining();
+        if len > self.0.send_buffer.load(Ordering::Relaxed) {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let mut frame = if self.0.cooked {
+            // The header is built from the address, sent to from the
+            // interface.
+            let mut header = vec![0; ETH_HEADER_LEN];
+            if let Some(to) = to {
+                let halen = (to.halen as usize).min(ETH_ALEN);
+                header[..halen].copy_from_slice(&to.addr[..halen]);
+            }
+            header[ETH_ALEN..2 * ETH_ALEN].copy_from_slice(&hw_addr);
+            header[12..14].copy_from_slice(&protocol.to_be_bytes());
+            header
+        } else {
+            Vec::new()
+        };
+        let header_len = frame.len();
+        frame.resize(header_len + len, 0);
+        let len = src.read(&mut frame[header_len..])?;
+        frame.truncate(header_len + len);
+        if frame.len() < ETH_HEADER_LEN {
+            return Err(LinuxError::EINVAL);
+        }
+        if frame.len() > mtu + ETH_HEADER_LEN {
+            return Err(LinuxError::EMSGSIZE);
+        }
+
+        let ns = &self.0.ns;
+        if hw_type == ARPHRD_LOOPBACK {
+            tap(ns, ifindex, &frame);
+            // The loopback interface passes IP packets back to the host.
+            if matches!(
+                u16::from_be_bytes([frame[12], frame[13]]),
+                ETH_P_IP | ETH_P_IPV6
+            ) {
+                super::raw::input(ns, ifindex, &frame[ETH_HEADER_LEN..]);
+            }
+        } else if !super::tun::transmit_frame(ns, ifindex, &frame)
+            && !super::veth::transmit_frame(ns, ifindex, &frame)
+        {
+            warn!("Cannot send frames on interface {}", ifindex);
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        Ok(len)
+    }
+
+    /// Receives a frame together with the address it came from.
+    pub fn recv(
+        &self,
+        dst: &mut impl BufMut,
+        flags: RecvFlags,
+        dontwait: bool,
+    ) -> LinuxResult<(usize, PacketAddr)> {
+        let (from, msg) = Poller::new(self, IoEvents::IN)
+            .non_blocking(dontwait || self.nonblocking())
+            .poll(|| {
+                let mut rx = self.0.rx.lock();
+                if flags.contains(RecvFlags::PEEK) {
+                    rx.front().cloned()
+                } else {
+                    rx.pop_front()
+                }
+                .ok_or(LinuxError::EAGAIN)
+            })?;
+        let len = msg.len().min(dst.remaining_mut());
+        dst.write(&msg[..len])?;
+        let len = if flags.contains(RecvFlags::TRUNCATE) {
+            msg.len()
+        } else {
+            len
+        };
+        Ok((len, from))
+    }
+
+    /// Attaches a socket filter, replacing any attached before.
+    pub fn attach_filter(&self, filter: BpfProgram) {
+        self.0.state.lock().filter = Some(filter);
+    }
+
+    /// Detaches the socket filter, `ENOENT` if there is none.
+    pub fn detach_filter(&self) -> LinuxResult<()> {
+        self.0
+            .state
+            .lock()
+            .filter
+            .take()
+            .map(drop)
+            .ok_or(LinuxError::ENOENT)
+    }
+
+    /// Joins or leaves a membership, `PACKET_ADD_MEMBERSHIP` and
+    /// `PACKET_DROP_MEMBERSHIP`. Only promiscuous mode has an effect.
+    pub fn set_membership(&self, optname: u32, mreq: &PacketMreq) -> LinuxResult<()> {
+        let ifindex = mreq.mr_ifindex as u32;
+        self.0.ns.config().interface(ifindex)?;
+        if !matches!(
+            mreq.mr_type,
+            PACKET_MR_MULTICAST | PACKET_MR_PROMISC | PACKET_MR_ALLMULTI | PACKET_MR_UNICAST
+        ) {
+            return Err(LinuxError::EINVAL);
+        }
+        let membership = (ifindex, mreq.mr_type);
+        let mut state = self.0.state.lock();
+        match optname {
+            PACKET_ADD_MEMBERSHIP => {
+                state.memberships.push(membership);
+                if mreq.mr_type == PACKET_MR_PROMISC {
+                    set_promisc(&self.0.ns, ifindex, true);
+                }
+            }
+            PACKET_DROP_MEMBERSHIP => {
+                let pos = state
+                    .memberships
+                    .iter()
+                    .position(|it| *it == membership)
+                    .ok_or(LinuxError::EADDRNOTAVAIL)?;
+                state.memberships.remove(pos);
+                if mreq.mr_type == PACKET_MR_PROMISC {
+                    set_promisc(&self.0.ns, ifindex, false);
+                }
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    /// Gets `PACKET_STATISTICS`, resetting the counters.
+    pub fn take_stats(&self) -> PacketStats {
+        core::mem::take(&mut self.0.state.lock().stats)
+    }
+
+    /// Gets an integer `SOL_PACKET` option.
+    pub fn get_packet_option(&self, optname: u32) -> LinuxResult<i32> {
+        match optname {
+            PACKET_AUXDATA => Ok(self.0.state.lock().auxdata as _),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Sets an integer `SOL_PACKET` option.
+    pub fn set_packet_option(&self, optname: u32, val: i32) -> LinuxResult<()> {
+        match optname {
+            PACKET_AUXDATA => self.0.state.lock().auxdata = val != 0,
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn get_option(&self, option: GetSocketOption) -> LinuxResult<()> {
+        match option {
+            GetSocketOption::NonBlocking(value) => *value = self.nonblocking(),
+            GetSocketOption::SendBuffer(size) => *size = self.0.send_buffer.load(Ordering::Relaxed),
+            GetSocketOption::ReceiveBuffer(size) => {
+                *size = self.0.recv_buffer.load(Ordering::Relaxed)
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn set_option(&self, option: SetSocketOption) -> LinuxResult<()> {
+        match option {
+            SetSocketOption::NonBlocking(value) => {
+                self.0.nonblocking.store(*value, Ordering::Release)
+            }
+            SetSocketOption::SendBuffer(size) => self.0.send_buffer.store(*size, Ordering::Relaxed),
+            SetSocketOption::ReceiveBuffer(size) => {
+                self.0.recv_buffer.store(*size, Ordering::Relaxed)
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn nonblocking(&self) -> bool {
+        self.0.nonblocking.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        for (ifindex, ty) in self.0.state.lock().memberships.drain(..) {
+            if ty == PACKET_MR_PROMISC {
+                set_promisc(&self.0.ns, ifindex, false);
+            }
+        }
+    }
+}
+
+impl Pollable for PacketSocket {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.0.rx.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.0.poll_rx.register(context.waker());
+        }
+    }
+}
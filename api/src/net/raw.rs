@@ -0,0 +1,1003 @@
+//! Raw IP sockets and ICMP ping sockets.
+//!
+//! The network stack neither sends nor receives raw IP packets, so these
+//! sockets are served by a small IP layer here that only reaches the host
+//! itself, TUN/TAP interfaces and `veth` pairs. A packet sent to a local
+//! address is delivered to the raw sockets of its protocol in the same
+//! namespace, and ICMP echo requests are answered the way the host would
+//! answer them. Packets routed through a TUN/TAP or `veth` interface are
+//! handed to it, and packets to other hosts fail with `EHOSTUNREACH`.
+
+use alloc::{
+    collections::{btree_set::BTreeSet, vec_deque::VecDeque},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{TimeValue, wall_time};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
+use axnet::{
+    RecvFlags,
+    options::{GetSocketOption, SetSocketOption},
+};
+use axsync::Mutex;
+use axtask::future::Poller;
+use linux_raw_sys::net::{AF_INET, AF_INET6, IP_HDRINCL, IPV6_CHECKSUM};
+
+use super::ns::{NetNamespace, current_net_ns};
+
+/// `SOL_RAW`, the level of options of raw sockets.
+pub const SOL_RAW: u32 = 255;
+/// `ICMP_FILTER`, the ICMP types an IPv4 raw socket drops, at `SOL_RAW`.
+pub const ICMP_FILTER: u32 = 1;
+/// `ICMPV6_FILTER`, the ICMPv6 types an IPv6 raw socket drops, at
+/// `IPPROTO_ICMPV6`.
+pub const ICMPV6_FILTER: u32 = 1;
+
+const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
+const PROTO_IPV6: u32 = linux_raw_sys::net::IPPROTO_IPV6 as u32;
+const PROTO_ICMP: u8 = linux_raw_sys::net::IPPROTO_ICMP as u8;
+const PROTO_ICMPV6: u8 = linux_raw_sys::net::IPPROTO_ICMPV6 as u8;
+const PROTO_RAW: u8 = linux_raw_sys::net::IPPROTO_RAW as u8;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_FRAG_NEEDED: u8 = 4;
+const ICMPV6_DEST_UNREACH: u8 = 1;
+const ICMPV6_PKT_TOOBIG: u8 = 2;
+const ICMPV6_TIME_EXCEED: u8 = 3;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Length of an IPv4 header without options.
+const IPV4_HEADER_LEN: usize = 20;
+/// Length of an IPv6 header.
+const IPV6_HEADER_LEN: usize = 40;
+/// Length of an ICMP header, up to and including the echo sequence number.
+const ICMP_HEADER_LEN: usize = 8;
+/// Largest IP packet.
+const MAX_PACKET_LEN: usize = 65535;
+
+/// Default time to live of the packets sent.
+pub const DEFAULT_TTL: u8 = 64;
+/// Default size of the send and receive buffers.
+const DEFAULT_BUFFER_SIZE: usize = 212992;
+
+/// Group ids allowed to create ping sockets, `/proc/sys/net/ipv4/
+/// ping_group_range`. Nobody by default.
+pub static PING_GROUP_RANGE: Mutex<(u32, u32)> = Mutex::new((1, 0));
+
+/// Sets the group ids allowed to create ping sockets. A range that is empty
+/// allows nobody.
+pub fn set_ping_group_range(low: u32, high: u32) {
+    *PING_GROUP_RANGE.lock() = if high < low { (1, 0) } else { (low, high) };
+}
+
+/// Open sockets, which packets are delivered to.
+static SOCKETS: Mutex<Vec<Weak<Endpoint>>> = Mutex::new(Vec::new());
+/// Echo identifiers taken by ping sockets, by namespace and address family.
+static PING_IDENTS: Mutex<BTreeSet<(u64, u32, u16)>> = Mutex::new(BTreeSet::new());
+static NEXT_PING_IDENT: AtomicU16 = AtomicU16::new(1);
+/// Identification of the next IPv4 packet.
+static NEXT_IP_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Takes echo identifier `ident` in namespace `ns`, or a free one if it is
+/// 0.
+fn alloc_ident(ns: u64, family: u32, ident: u16) -> LinuxResult<u16> {
+    let mut idents = PING_IDENTS.lock();
+    if ident != 0 {
+        return if idents.insert((ns, family, ident)) {
+            Ok(ident)
+        } else {
+            Err(LinuxError::EADDRINUSE)
+        };
+    }
+    for _ in 0..u16::MAX {
+        let ident = NEXT_PING_IDENT.fetch_add(1, Ordering::Relaxed);
+        if ident != 0 && idents.insert((ns, family, ident)) {
+            return Ok(ident);
+        }
+    }
+    Err(LinuxError::EAGAIN)
+}
+
+/// Computes the internet checksum of `data`, starting from the partial sum
+/// `sum`.
+fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    for chunk in data.chunks(2) {
+        sum += u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Returns the partial checksum of the IPv6 pseudo header.
+fn pseudo_header_sum(src: Ipv6Addr, dst: Ipv6Addr, len: usize, protocol: u8) -> u32 {
+    let words = src
+        .segments()
+        .into_iter()
+        .chain(dst.segments())
+        .map(u32::from);
+    words.sum::<u32>() + (len >> 16) as u32 + (len & 0xffff) as u32 + protocol as u32
+}
+
+/// Writes the checksum of `data` to `data[offset..offset + 2]`.
+fn fill_checksum(data: &mut [u8], offset: usize, sum: u32) {
+    data[offset..offset + 2].fill(0);
+    let csum = checksum(data, sum);
+    data[offset..offset + 2].copy_from_slice(&csum.to_be_bytes());
+}
+
+/// Where and how a datagram came in, for `IP_PKTINFO`, `IP_RECVTTL` and the
+/// like.
+#[derive(Clone, Copy)]
+pub struct PacketInfo {
+    /// Destination address of the packet.
+    pub dst: IpAddr,
+    /// Index of the interface the packet came in on.
+    pub ifindex: u32,
+    /// Time to live, or hop limit, if the header is known.
+    pub ttl: Option<u8>,
+    /// Type of service, or traffic class, if the header is known.
+    pub tos: Option<u8>,
+    /// Time the packet came in.
+    pub time: TimeValue,
+}
+
+/// An ICMP error about a packet a socket sent, queued for `IP_RECVERR`.
+#[derive(Clone)]
+pub struct IcmpError {
+    /// Error the ICMP message stands for.
+    pub errno: LinuxError,
+    pub ty: u8,
+    pub code: u8,
+    /// MTU reported by a "fragmentation needed" or "packet too big" message.
+    pub info: u32,
+    /// Host that sent the ICMP message.
+    pub offender: IpAddr,
+    /// Destination of the packet in error.
+    pub dst: IpAddr,
+    /// What the ICMP message quotes of the packet in error, from its
+    /// transport header on.
+    pub data: Vec<u8>,
+}
+
+/// A datagram waiting to be received.
+#[derive(Clone)]
+struct Datagram {
+    from: IpAddr,
+    info: PacketInfo,
+    data: Vec<u8>,
+}
+
+/// Returns the error an ICMP "destination unreachable" message stands for.
+fn unreach_errno(ipv6: bool, code: u8) -> LinuxError {
+    if ipv6 {
+        match code {
+            0 => LinuxError::ENETUNREACH,
+            1 | 5 | 6 => LinuxError::EACCES,
+            4 => LinuxError::ECONNREFUSED,
+            _ => LinuxError::EHOSTUNREACH,
+        }
+    } else {
+        match code {
+            0 | 6 | 9 | 11 => LinuxError::ENETUNREACH,
+            2 => LinuxError::ENOPROTOOPT,
+            3 => LinuxError::ECONNREFUSED,
+            ICMP_FRAG_NEEDED => LinuxError::EMSGSIZE,
+            5 => LinuxError::EOPNOTSUPP,
+            7 => LinuxError::EHOSTDOWN,
+            8 => LinuxError::ENONET,
+            _ => LinuxError::EHOSTUNREACH,
+        }
+    }
+}
+
+/// An IP packet on its way through the host.
+struct Packet {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    /// IP header, which only IPv4 raw sockets receive.
+    header: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Packet {
+    fn new(src: IpAddr, dst: IpAddr, protocol: u8, ttl: u8, payload: Vec<u8>) -> Self {
+        let header = match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut header = vec![0; IPV4_HEADER_LEN];
+                header[0] = 0x45;
+                header[2..4]
+                    .copy_from_slice(&((IPV4_HEADER_LEN + payload.len()) as u16).to_be_bytes());
+                header[4..6]
+                    .copy_from_slice(&NEXT_IP_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+                header[8] = ttl;
+                header[9] = protocol;
+                header[12..16].copy_from_slice(&src.octets());
+                header[16..20].copy_from_slice(&dst.octets());
+                fill_checksum(&mut header, 10, 0);
+                header
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let mut header = vec![0; IPV6_HEADER_LEN];
+                header[0] = 0x60;
+                header[4..6].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+                header[6] = protocol;
+                header[7] = ttl;
+                header[8..24].copy_from_slice(&src.octets());
+                header[24..40].copy_from_slice(&dst.octets());
+                header
+            }
+            _ => unreachable!(),
+        };
+        Self {
+            src,
+            dst,
+            protocol,
+            header,
+            payload,
+        }
+    }
+
+    /// Builds a packet from one with an IPv4 header given by user space,
+    /// filling in what is left empty as Linux does.
+    fn from_ipv4(mut data: Vec<u8>, src: Option<IpAddr>) -> LinuxResult<Self> {
+        if data.len() < IPV4_HEADER_LEN || data[0] >> 4 != 4 {
+            return Err(LinuxError::EINVAL);
+        }
+        let header_len = (data[0] & 0xf) as usize * 4;
+        if header_len < IPV4_HEADER_LEN || header_len > data.len() {
+            return Err(LinuxError::EINVAL);
+        }
+        let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&data[16..20]).unwrap());
+        let mut src_addr = Ipv4Addr::from(<[u8; 4]>::try_from(&data[12..16]).unwrap());
+        if src_addr.is_unspecified() {
+            src_addr = match src {
+                Some(IpAddr::V4(src)) => src,
+                _ => dst,
+            };
+            data[12..16].copy_from_slice(&src_addr.octets());
+        }
+        let len = data.len() as u16;
+        data[2..4].copy_from_slice(&len.to_be_bytes());
+        if data[4..6] == [0, 0] {
+            data[4..6].copy_from_slice(&NEXT_IP_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        }
+        fill_checksum(&mut data[..header_len], 10, 0);
+
+        let payload = data.split_off(header_len);
+        Ok(Self {
+            src: src_addr.into(),
+            dst: dst.into(),
+            protocol: data[9],
+            header: data,
+            payload,
+        })
+    }
+
+    /// Parses a packet injected at the link layer, without filling in
+    /// anything.
+    fn parse(data: &[u8]) -> Option<Self> {
+        match data.first()? >> 4 {
+            4 => {
+                let header_len = (data[0] & 0xf) as usize * 4;
+                let total_len = u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize;
+                if header_len < IPV4_HEADER_LEN || total_len < header_len {
+                    return None;
+                }
+                let data = data.get(..total_len)?;
+                Some(Self {
+                    src: Ipv4Addr::from(<[u8; 4]>::try_from(&data[12..16]).ok()?).into(),
+                    dst: Ipv4Addr::from(<[u8; 4]>::try_from(&data[16..20]).ok()?).into(),
+                    protocol: data[9],
+                    header: data[..header_len].to_vec(),
+                    payload: data[header_len..].to_vec(),
+                })
+            }
+            6 => {
+                let payload_len = u16::from_be_bytes(data.get(4..6)?.try_into().ok()?) as usize;
+                let data = data.get(..IPV6_HEADER_LEN + payload_len)?;
+                Some(Self {
+                    src: Ipv6Addr::from(<[u8; 16]>::try_from(&data[8..24]).ok()?).into(),
+                    dst: Ipv6Addr::from(<[u8; 16]>::try_from(&data[24..40]).ok()?).into(),
+                    protocol: data[6],
+                    header: data[..IPV6_HEADER_LEN].to_vec(),
+                    payload: data[IPV6_HEADER_LEN..].to_vec(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the ICMP type if this is an ICMP or ICMPv6 message.
+    fn icmp_type(&self) -> Option<u8> {
+        let icmp = match self.dst {
+            IpAddr::V4(_) => PROTO_ICMP,
+            IpAddr::V6(_) => PROTO_ICMPV6,
+        };
+        (self.protocol == icmp)
+            .then(|| self.payload.first().copied())
+            .flatten()
+    }
+
+    /// Returns the time to live, or hop limit.
+    fn ttl(&self) -> u8 {
+        match self.dst {
+            IpAddr::V4(_) => self.header[8],
+            IpAddr::V6(_) => self.header[7],
+        }
+    }
+
+    /// Returns the type of service, or traffic class.
+    fn tos(&self) -> u8 {
+        match self.dst {
+            IpAddr::V4(_) => self.header[1],
+            IpAddr::V6(_) => (self.header[0] << 4) | (self.header[1] >> 4),
+        }
+    }
+
+    /// Returns the error of an ICMP error message, with the destination,
+    /// protocol and header length of the packet in error it quotes.
+    fn icmp_error(&self) -> Option<(IcmpError, u8, usize)> {
+        let ty = self.icmp_type()?;
+        let code = *self.payload.get(1)?;
+        let word = |at: usize| -> Option<u32> {
+            Some(u32::from_be_bytes(
+                self.payload.get(at..at + 4)?.try_into().ok()?,
+            ))
+        };
+        let (errno, info) = match (self.dst, ty) {
+            (IpAddr::V4(_), ICMP_DEST_UNREACH) => {
+                // The MTU is in the lower half of the second word.
+                let mtu = if code == ICMP_FRAG_NEEDED {
+                    word(4)? & 0xffff
+                } else {
+                    0
+                };
+                (unreach_errno(false, code), mtu)
+            }
+            (IpAddr::V4(_), ICMP_TIME_EXCEEDED) | (IpAddr::V6(_), ICMPV6_TIME_EXCEED) => {
+                (LinuxError::EHOSTUNREACH, 0)
+            }
+            (IpAddr::V6(_), ICMPV6_DEST_UNREACH) => (unreach_errno(true, code), 0),
+            (IpAddr::V6(_), ICMPV6_PKT_TOOBIG) => (LinuxError::EMSGSIZE, word(4)?),
+            _ => return None,
+        };
+
+        let quoted = self.payload.get(ICMP_HEADER_LEN..)?;
+        let (dst, protocol, header_len) = match self.dst {
+            IpAddr::V4(_) => {
+                let header_len = (*quoted.first()? & 0xf) as usize * 4;
+                let dst = <[u8; 4]>::try_from(quoted.get(16..20)?).ok()?;
+                (Ipv4Addr::from(dst).into(), *quoted.get(9)?, header_len)
+            }
+            IpAddr::V6(_) => {
+                let dst = <[u8; 16]>::try_from(quoted.get(24..40)?).ok()?;
+                (Ipv6Addr::from(dst).into(), *quoted.get(6)?, IPV6_HEADER_LEN)
+            }
+        };
+        let error = IcmpError {
+            errno,
+            ty,
+            code,
+            info,
+            offender: self.src,
+            dst,
+            data: quoted.to_vec(),
+        };
+        Some((error, protocol, header_len))
+    }
+
+    /// Returns the identifier of an echo request or reply.
+    fn echo_ident(&self) -> Option<u16> {
+        (self.payload.len() >= ICMP_HEADER_LEN)
+            .then(|| u16::from_be_bytes([self.payload[4], self.payload[5]]))
+    }
+
+    /// Builds the answer of the host to an echo request.
+    fn echo_reply(&self) -> Option<Packet> {
+        let ty = self.icmp_type()?;
+        if self.payload.len() < ICMP_HEADER_LEN || self.payload[1] != 0 {
+            return None;
+        }
+        let mut payload = self.payload.clone();
+        match (self.src, self.dst) {
+            (IpAddr::V4(_), IpAddr::V4(_)) if ty == ICMP_ECHO_REQUEST => {
+                payload[0] = ICMP_ECHO_REPLY;
+                fill_checksum(&mut payload, 2, 0);
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) if ty == ICMPV6_ECHO_REQUEST => {
+                payload[0] = ICMPV6_ECHO_REPLY;
+                let sum = pseudo_header_sum(dst, src, payload.len(), PROTO_ICMPV6);
+                fill_checksum(&mut payload, 2, sum);
+            }
+            _ => return None,
+        }
+        Some(Packet::new(
+            self.dst,
+            self.src,
+            self.protocol,
+            DEFAULT_TTL,
+            payload,
+        ))
+    }
+}
+
+/// Delivers a packet that came in on interface `ifindex` to the host in
+/// namespace `ns`: to every socket there that wants it, and to the host
+/// itself for echo requests.
+fn deliver(ns: &Arc<NetNamespace>, ifindex: u32, packet: &Packet) {
+    let sockets = {
+        let mut sockets = SOCKETS.lock();
+        sockets.retain(|it| it.strong_count() > 0);
+        sockets
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|it| Arc::ptr_eq(&it.ns, ns))
+            .collect::<Vec<_>>()
+    };
+    for socket in &sockets {
+        socket.receive(packet, ifindex);
+    }
+    if let Some(reply) = packet.echo_reply() {
+        let _ = output(ns, &reply);
+    }
+}
+
+/// Sends a packet out of namespace `ns`. A packet for the host goes through
+/// the loopback interface, where packet sockets see it, and others through
+/// the TUN/TAP or `veth` interface they are routed to.
+fn output(ns: &Arc<NetNamespace>, packet: &Packet) -> LinuxResult<()> {
+    let oif = {
+        let config = ns.config();
+        if config.is_local(packet.dst) {
+            None
+        } else {
+            Some(config.lookup(packet.dst)?.oif)
+        }
+    };
+    let data = [packet.header.as_slice(), &packet.payload].concat();
+    match oif {
+        None => {
+            super::packet::tap_loopback(ns, &data);
+            let lo = ns.config().loopback_index().unwrap_or(0);
+            deliver(ns, lo, packet);
+            Ok(())
+        }
+        Some(oif) => {
+            if super::tun::transmit(ns, oif, &data) || super::veth::transmit(ns, oif, &data) {
+                Ok(())
+            } else {
+                warn!("Raw IP packet to {} cannot leave the host", packet.dst);
+                Err(LinuxError::EHOSTUNREACH)
+            }
+        }
+    }
+}
+
+/// Receives an IP packet that came in on interface `ifindex` of namespace
+/// `ns`. Packets not for the host are dropped, nothing is forwarded.
+pub fn input(ns: &Arc<NetNamespace>, ifindex: u32, data: &[u8]) {
+    let Some(packet) = Packet::parse(data) else {
+        return;
+    };
+    let local = ns.config().is_local(packet.dst);
+    if local {
+        deliver(ns, ifindex, &packet);
+    }
+}
+
+/// Options and addresses of a socket.
+struct State {
+    /// Address the socket is bound to.
+    local: Option<IpAddr>,
+    /// Address the socket is connected to.
+    peer: Option<IpAddr>,
+    /// Echo identifier of a ping socket, once it is bound.
+    ident: Option<u16>,
+    /// Whether user space gives the IPv4 header, `IP_HDRINCL`.
+    hdrincl: bool,
+    ttl: u8,
+    /// Offset of the checksum IPv6 raw sockets fill in, `IPV6_CHECKSUM`.
+    checksum: Option<usize>,
+    /// ICMP types to drop, one bit each.
+    icmp_filter: [u32; 8],
+}
+
+struct Endpoint {
+    /// Namespace the socket was created in.
+    ns: Arc<NetNamespace>,
+    family: u32,
+    protocol: u8,
+    /// Whether this is a ping socket rather than a raw one.
+    ping: bool,
+    state: Mutex<State>,
+    nonblocking: AtomicBool,
+    send_buffer: AtomicUsize,
+    recv_buffer: AtomicUsize,
+    rx: Mutex<VecDeque<Datagram>>,
+    /// Whether ICMP errors are queued, `IP_RECVERR`.
+    recv_err: AtomicBool,
+    errq: Mutex<VecDeque<IcmpError>>,
+    poll_rx: PollSet,
+}
+
+impl Endpoint {
+    fn icmp_protocol(&self) -> u8 {
+        if self.family == AF_INET {
+            PROTO_ICMP
+        } else {
+            PROTO_ICMPV6
+        }
+    }
+
+    /// Queues the ICMP error `packet` carries if it is about a packet this
+    /// socket sent.
+    fn receive_error(&self, packet: &Packet, state: &State) {
+        let Some((mut error, protocol, header_len)) = packet.icmp_error() else {
+            return;
+        };
+        let transport = error.data.get(header_len..).unwrap_or_default();
+        if self.ping {
+            let request = if self.family == AF_INET {
+                ICMP_ECHO_REQUEST
+            } else {
+                ICMPV6_ECHO_REQUEST
+            };
+            let ident = transport
+                .get(4..6)
+                .map(|it| u16::from_be_bytes([it[0], it[1]]));
+            if protocol != self.protocol
+                || transport.first() != Some(&request)
+                || state.ident.is_none()
+                || ident != state.ident
+            {
+                return;
+            }
+        } else if protocol != self.protocol {
+            return;
+        }
+        // IPv4 sockets that give the header get it back.
+        if !(state.hdrincl && self.family == AF_INET) {
+            error.data = transport.to_vec();
+        }
+
+        let mut errq = self.errq.lock();
+        let queued = errq.iter().map(|it| it.data.len()).sum::<usize>();
+        if queued + error.data.len() > self.recv_buffer.load(Ordering::Relaxed) {
+            return;
+        }
+        errq.push_back(error);
+        drop(errq);
+        self.poll_rx.wake();
+    }
+
+    /// Queues `packet` if it is for this socket.
+    fn receive(&self, packet: &Packet, ifindex: u32) {
+        let is_v4 = self.family == AF_INET;
+        if is_v4 != packet.dst.is_ipv4() {
+            return;
+        }
+        let state = self.state.lock();
+        // Errors come from routers as well as from the peer.
+        if self.recv_err.load(Ordering::Relaxed) && state.local.is_none_or(|it| it == packet.dst) {
+            self.receive_error(packet, &state);
+        }
+        if state.local.is_some_and(|it| it != packet.dst)
+            || state.peer.is_some_and(|it| it != packet.src)
+        {
+            return;
+        }
+
+        let data = if self.ping {
+            let reply = if is_v4 {
+                ICMP_ECHO_REPLY
+            } else {
+                ICMPV6_ECHO_REPLY
+            };
+            if packet.icmp_type() != Some(reply)
+                || state.ident.is_none()
+                || packet.echo_ident() != state.ident
+            {
+                return;
+            }
+            packet.payload.clone()
+        } else {
+            // `IPPROTO_RAW` sockets only send.
+            if self.protocol != packet.protocol || self.protocol == PROTO_RAW {
+                return;
+            }
+            if let Some(ty) = packet.icmp_type() {
+                // IPv4 filters only cover the first 32 types.
+                let filtered = if is_v4 {
+                    ty < 32 && state.icmp_filter[0] >> ty & 1 != 0
+                } else {
+                    state.icmp_filter[ty as usize / 32] >> (ty % 32) & 1 != 0
+                };
+                if filtered {
+                    return;
+                }
+            }
+            if is_v4 {
+                [packet.header.as_slice(), &packet.payload].concat()
+            } else {
+                packet.payload.clone()
+            }
+        };
+        drop(state);
+
+        let info = PacketInfo {
+            dst: packet.dst,
+            ifindex,
+            ttl: Some(packet.ttl()),
+            tos: Some(packet.tos()),
+            time: wall_time(),
+        };
+        let mut rx = self.rx.lock();
+        let queued = rx.iter().map(|it| it.data.len()).sum::<usize>();
+        if queued + data.len() > self.recv_buffer.load(Ordering::Relaxed) {
+            return;
+        }
+        rx.push_back(Datagram {
+            from: packet.src,
+            info,
+            data,
+        });
+        drop(rx);
+        self.poll_rx.wake();
+    }
+}
+
+/// A raw IP socket, or a ping socket.
+pub struct RawSocket(Arc<Endpoint>);
+
+impl RawSocket {
+    fn new(family: u32, protocol: u8, ping: bool) -> Self {
+        let endpoint = Arc::new(Endpoint {
+            ns: current_net_ns(),
+            family,
+            protocol,
+            ping,
+            state: Mutex::new(State {
+                local: None,
+                peer: None,
+                ident: None,
+                hdrincl: protocol == PROTO_RAW,
+                ttl: DEFAULT_TTL,
+                checksum: (protocol == PROTO_ICMPV6).then_some(2),
+                icmp_filter: [0; 8],
+            }),
+            nonblocking: AtomicBool::new(false),
+            send_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            recv_buffer: AtomicUsize::new(DEFAULT_BUFFER_SIZE),
+            rx: Mutex::new(VecDeque::new()),
+            recv_err: AtomicBool::new(false),
+            errq: Mutex::new(VecDeque::new()),
+            poll_rx: PollSet::new(),
+        });
+        SOCKETS.lock().push(Arc::downgrade(&endpoint));
+        Self(endpoint)
+    }
+
+    /// Creates a raw socket of IP protocol `protocol`.
+    pub fn new_raw(family: u32, protocol: u32) -> LinuxResult<Self> {
+        match protocol {
+            0 => Err(LinuxError::EPROTONOSUPPORT),
+            1..=255 => Ok(Self::new(family, protocol as u8, false)),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+
+    /// Creates a ping socket, if the group of the caller is in
+    /// [`PING_GROUP_RANGE`].
+    pub fn new_ping(family: u32) -> LinuxResult<Self> {
+        let (low, high) = *PING_GROUP_RANGE.lock();
+        // Every task runs with group id 0.
+        if !(low..=high).contains(&0) {
+            return Err(LinuxError::EACCES);
+        }
+        let protocol = if family == AF_INET {
+            PROTO_ICMP
+        } else {
+            PROTO_ICMPV6
+        };
+        Ok(Self::new(family, protocol, true))
+    }
+
+    /// Returns the echo identifier, taking a free one first if the socket
+    /// has none yet.
+    fn autobind(&self, state: &mut State) -> LinuxResult<u16> {
+        if let Some(ident) = state.ident {
+            return Ok(ident);
+        }
+        let ident = alloc_ident(self.0.ns.id(), self.0.family, 0)?;
+        state.ident = Some(ident);
+        Ok(ident)
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> LinuxResult<()> {
+        let ip = addr.ip();
+        if !ip.is_unspecified() && !self.0.ns.config().is_local(ip) {
+            return Err(LinuxError::EADDRNOTAVAIL);
+        }
+        let mut state = self.0.state.lock();
+        if state.local.is_some() || state.ident.is_some() {
+            return Err(LinuxError::EINVAL);
+        }
+        if self.0.ping {
+            state.ident = Some(alloc_ident(self.0.ns.id(), self.0.family, addr.port())?);
+        }
+        state.local = (!ip.is_unspecified()).then_some(ip);
+        Ok(())
+    }
+
+    pub fn connect(&self, addr: SocketAddr) -> LinuxResult<()> {
+        let mut state = self.0.state.lock();
+        if self.0.ping {
+            self.autobind(&mut state)?;
+        }
+        state.peer = Some(addr.ip());
+        Ok(())
+    }
+
+    fn unspecified(&self) -> IpAddr {
+        if self.0.family == AF_INET {
+            Ipv4Addr::UNSPECIFIED.into()
+        } else {
+            Ipv6Addr::UNSPECIFIED.into()
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        let state = self.0.state.lock();
+        SocketAddr::new(
+            state.local.unwrap_or_else(|| self.unspecified()),
+            state.ident.unwrap_or(0),
+        )
+    }
+
+    pub fn peer_addr(&self) -> LinuxResult<SocketAddr> {
+        let peer = self.0.state.lock().peer.ok_or(LinuxError::ENOTCONN)?;
+        Ok(SocketAddr::new(peer, 0))
+    }
+
+    pub fn is_bound(&self) -> bool {
+        let state = self.0.state.lock();
+        state.local.is_some() || state.ident.is_some()
+    }
+
+    pub fn send(&self, src: &mut impl Buf, to: Option<SocketAddr>) -> LinuxResult<usize> {
+        let len = src.remaining();
+        if len > MAX_PACKET_LEN || len > self.0.send_buffer.load(Ordering::Relaxed) {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let mut data = vec![0; len];
+        let len = src.read(&mut data)?;
+        data.truncate(len);
+
+        let mut state = self.0.state.lock();
+        let dst = to
+            .map(|it| it.ip())
+            .or(state.peer)
+            .ok_or(LinuxError::EDESTADDRREQ)?;
+        let protocol = self.0.protocol;
+
+        let packet = if state.hdrincl && self.0.family == AF_INET {
+            Packet::from_ipv4(data, state.local)?
+        } else {
+            let src = state.local.unwrap_or(dst);
+            if self.0.ping {
+                let request = if self.0.family == AF_INET {
+                    ICMP_ECHO_REQUEST
+                } else {
+                    ICMPV6_ECHO_REQUEST
+                };
+                if data.len() < ICMP_HEADER_LEN || data[0] != request || data[1] != 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                let ident = self.autobind(&mut state)?;
+                data[4..6].copy_from_slice(&ident.to_be_bytes());
+            }
+            match (src, dst) {
+                (IpAddr::V4(_), IpAddr::V4(_)) => {
+                    if self.0.ping {
+                        fill_checksum(&mut data, 2, 0);
+                    }
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    if let Some(offset) = state.checksum {
+                        if offset + 2 > data.len() {
+                            return Err(LinuxError::EINVAL);
+                        }
+                        let sum = pseudo_header_sum(src, dst, data.len(), protocol);
+                        fill_checksum(&mut data, offset, sum);
+                    }
+                }
+                _ => return Err(LinuxError::EINVAL),
+            }
+            Packet::new(src, dst, protocol, state.ttl, data)
+        };
+        drop(state);
+
+        output(&self.0.ns, &packet)?;
+        Ok(len)
+    }
+
+    /// Receives a datagram together with its source address and where it
+    /// came in.
+    pub fn recv(
+        &self,
+        dst: &mut impl BufMut,
+        flags: RecvFlags,
+        dontwait: bool,
+    ) -> LinuxResult<(usize, IpAddr, PacketInfo)> {
+        let msg = Poller::new(self, IoEvents::IN)
+            .non_blocking(dontwait || self.nonblocking())
+            .poll(|| {
+                let mut rx = self.0.rx.lock();
+                if flags.contains(RecvFlags::PEEK) {
+                    rx.front().cloned()
+                } else {
+                    rx.pop_front()
+                }
+                .ok_or(LinuxError::EAGAIN)
+            })?;
+        let len = msg.data.len().min(dst.remaining_mut());
+        dst.write(&msg.data[..len])?;
+        let len = if flags.contains(RecvFlags::TRUNCATE) {
+            msg.data.len()
+        } else {
+            len
+        };
+        Ok((len, msg.from, msg.info))
+    }
+
+    /// Takes the oldest ICMP error queued, and copies what it quotes of the
+    /// packet in error to `dst`. The full length of that is returned, as
+    /// with [`RecvFlags::TRUNCATE`].
+    pub fn recv_error(&self, dst: &mut impl BufMut) -> LinuxResult<(usize, IcmpError)> {
+        let error = self.0.errq.lock().pop_front().ok_or(LinuxError::EAGAIN)?;
+        let len = error.data.len().min(dst.remaining_mut());
+        dst.write(&error.data[..len])?;
+        Ok((error.data.len(), error))
+    }
+
+    /// Sets whether ICMP errors are queued, `IP_RECVERR`. Turning that off
+    /// drops the errors queued.
+    pub fn set_recv_err(&self, recv_err: bool) {
+        self.0.recv_err.store(recv_err, Ordering::Relaxed);
+        if !recv_err {
+            self.0.errq.lock().clear();
+        }
+    }
+
+    pub fn get_option(&self, option: GetSocketOption) -> LinuxResult<()> {
+        match option {
+            GetSocketOption::NonBlocking(value) => *value = self.nonblocking(),
+            GetSocketOption::SendBuffer(size) => *size = self.0.send_buffer.load(Ordering::Relaxed),
+            GetSocketOption::ReceiveBuffer(size) => {
+                *size = self.0.recv_buffer.load(Ordering::Relaxed)
+            }
+            GetSocketOption::Ttl(ttl) => *ttl = self.0.state.lock().ttl,
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    pub fn set_option(&self, option: SetSocketOption) -> LinuxResult<()> {
+        match option {
+            SetSocketOption::NonBlocking(value) => {
+                self.0.nonblocking.store(*value, Ordering::Release)
+            }
+            SetSocketOption::SendBuffer(size) => self.0.send_buffer.store(*size, Ordering::Relaxed),
+            SetSocketOption::ReceiveBuffer(size) => {
+                self.0.recv_buffer.store(*size, Ordering::Relaxed)
+            }
+            SetSocketOption::Ttl(ttl) => {
+                if *ttl == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                self.0.state.lock().ttl = *ttl;
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    /// Gets `IP_HDRINCL` or `IPV6_CHECKSUM`.
+    pub fn get_raw_option(&self, level: u32, optname: u32) -> LinuxResult<i32> {
+        let state = self.0.state.lock();
+        match (self.0.family, level, optname) {
+            _ if self.0.ping => Err(LinuxError::ENOPROTOOPT),
+            (AF_INET, PROTO_IP, IP_HDRINCL) => Ok(state.hdrincl as _),
+            (AF_INET6, SOL_RAW | PROTO_IPV6, IPV6_CHECKSUM) => {
+                Ok(state.checksum.map_or(-1, |it| it as _))
+            }
+            _ => Err(LinuxError::ENOPROTOOPT),
+        }
+    }
+
+    /// Sets `IP_HDRINCL` or `IPV6_CHECKSUM`.
+    pub fn set_raw_option(&self, level: u32, optname: u32, val: i32) -> LinuxResult<()> {
+        let mut state = self.0.state.lock();
+        match (self.0.family, level, optname) {
+            _ if self.0.ping => return Err(LinuxError::ENOPROTOOPT),
+            (AF_INET, PROTO_IP, IP_HDRINCL) => {
+                // `IPPROTO_RAW` sockets always carry the header.
+                state.hdrincl = val != 0 || self.0.protocol == PROTO_RAW;
+            }
+            (AF_INET6, SOL_RAW | PROTO_IPV6, IPV6_CHECKSUM) => {
+                // The ICMPv6 checksum is mandatory.
+                if self.0.protocol == PROTO_ICMPV6 && level == PROTO_IPV6 {
+                    return Err(LinuxError::EINVAL);
+                }
+                state.checksum = match val {
+                    -1 => None,
+                    _ if val >= 0 && val % 2 == 0 => Some(val as usize),
+                    _ => return Err(LinuxError::EINVAL),
+                };
+            }
+            _ => return Err(LinuxError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    /// Returns the ICMP types dropped, `ICMP_FILTER` or `ICMPV6_FILTER`.
+    pub fn icmp_filter(&self) -> LinuxResult<[u32; 8]> {
+        if self.0.ping || self.0.protocol != self.0.icmp_protocol() {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        Ok(self.0.state.lock().icmp_filter)
+    }
+
+    /// Sets the ICMP types dropped, see [`Self::icmp_filter`].
+    pub fn set_icmp_filter(&self, filter: [u32; 8]) -> LinuxResult<()> {
+        if self.0.ping || self.0.protocol != self.0.icmp_protocol() {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        self.0.state.lock().icmp_filter = filter;
+        Ok(())
+    }
+
+    pub fn nonblocking(&self) -> bool {
+        self.0.nonblocking.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        if let Some(ident) = self.0.state.lock().ident {
+            PING_IDENTS
+                .lock()
+                .remove(&(self.0.ns.id(), self.0.family, ident));
+        }
+    }
+}
+
+impl Pollable for RawSocket {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.0.rx.lock().is_empty());
+        events.set(IoEvents::ERR, !self.0.errq.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.intersects(IoEvents::IN | IoEvents::ERR) {
+            self.0.poll_rx.register(context.waker());
+        }
+    }
+}
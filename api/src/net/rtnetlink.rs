@@ -0,0 +1,742 @@
+//! The `NETLINK_ROUTE` protocol.
+//!
+//! Requests are answered as soon as they are sent, from the table in
+//! [`super::iface`] of the namespace of the socket. Links other than `veth`
+//! pairs cannot be created or deleted, and only those can be moved to
+//! another namespace. Dumps come back as multi-part messages ended by
+//! `NLMSG_DONE`, several to a datagram, and other requests are answered with
+//! an `NLMSG_ERROR` carrying their result when they fail or ask for an ack.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{
+    iter, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::{
+    net::{AF_INET, AF_INET6, AF_UNSPEC},
+    netlink::{
+        IFA_ADDRESS, IFA_BROADCAST, IFA_F_PERMANENT, IFA_FLAGS, IFA_LABEL, IFA_LOCAL, IFLA_ADDRESS,
+        IFLA_BROADCAST, IFLA_IFNAME, IFLA_INFO_DATA, IFLA_INFO_KIND, IFLA_LINK, IFLA_LINKINFO,
+        IFLA_LINKMODE, IFLA_MTU, IFLA_NET_NS_FD, IFLA_NET_NS_PID, IFLA_OPERSTATE, IFLA_TXQLEN,
+        NLM_F_ACK, NLM_F_CAPPED, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_MULTI, NLM_F_REPLACE,
+        NLM_F_REQUEST, NLMSG_DONE, NLMSG_ERROR, NLMSG_MIN_TYPE, RTA_DST, RTA_GATEWAY, RTA_OIF,
+        RTA_PREFSRC, RTA_PRIORITY, RTA_TABLE, RTM_DELADDR, RTM_DELLINK, RTM_DELROUTE, RTM_F_CLONED,
+        RTM_GETADDR, RTM_GETLINK, RTM_GETROUTE, RTM_NEWADDR, RTM_NEWLINK, RTM_NEWROUTE,
+        RTM_SETLINK, RTN_UNICAST,
+    },
+};
+use memory_addr::PAGE_SIZE_4K;
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes, KnownLayout};
+
+use super::{
+    iface::{
+        ARPHRD_ETHER, ARPHRD_LOOPBACK, IFNAMSIZ, Interface, InterfaceAddr, InterfaceFlags, MIN_MTU,
+        NetConfig, RT_TABLE_MAIN, RTPROT_BOOT, Route, max_prefix_len, network_of,
+    },
+    ns::{NetNamespace, process_net_ns},
+    veth,
+};
+
+/// Size the datagrams of a dump are filled up to.
+const DUMP_SIZE: usize = PAGE_SIZE_4K;
+
+/// Operational states of a link, `IF_OPER_*`.
+const IF_OPER_UNKNOWN: u8 = 0;
+const IF_OPER_DOWN: u8 = 2;
+const IF_OPER_UP: u8 = 6;
+
+/// `RT_TABLE_COMPAT`, reported in `rtm_table` for tables past 255.
+const RT_TABLE_COMPAT: u32 = 252;
+
+/// `VETH_INFO_PEER`, the link message of the peer in `IFLA_INFO_DATA`.
+const VETH_INFO_PEER: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct NlMsgHdr {
+    nlmsg_len: u32,
+    nlmsg_type: u16,
+    nlmsg_flags: u16,
+    nlmsg_seq: u32,
+    nlmsg_pid: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct NlMsgErr {
+    error: i32,
+    msg: NlMsgHdr,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct IfInfoMsg {
+    ifi_family: u8,
+    ifi_pad: u8,
+    ifi_type: u16,
+    ifi_index: i32,
+    ifi_flags: u32,
+    ifi_change: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct IfAddrMsg {
+    ifa_family: u8,
+    ifa_prefixlen: u8,
+    ifa_flags: u8,
+    ifa_scope: u8,
+    ifa_index: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct RtMsg {
+    rtm_family: u8,
+    rtm_dst_len: u8,
+    rtm_src_len: u8,
+    rtm_tos: u8,
+    rtm_table: u8,
+    rtm_protocol: u8,
+    rtm_scope: u8,
+    rtm_type: u8,
+    rtm_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct RtAttr {
+    rta_len: u16,
+    rta_type: u16,
+}
+
+/// Rounds `len` up to the 4-byte alignment of messages and attributes.
+const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Reads the fixed part of a request and returns it with the attributes
+/// following it. A short payload is padded with zeros, as old tools send only
+/// the family for dumps.
+fn parse<T: FromBytes + IntoBytes>(payload: &[u8]) -> (T, Attrs) {
+    let mut val = T::new_zeroed();
+    let len = payload.len().min(size_of::<T>());
+    val.as_mut_bytes()[..len].copy_from_slice(&payload[..len]);
+    let attrs = payload.get(align(size_of::<T>())..).unwrap_or_default();
+    (val, Attrs(attrs))
+}
+
+/// The attributes of a request.
+#[derive(Clone, Copy)]
+struct Attrs<'a>(&'a [u8]);
+
+impl<'a> Attrs<'a> {
+    fn iter(self) -> impl Iterator<Item = (u32, &'a [u8])> {
+        let mut data = self.0;
+        iter::from_fn(move || {
+            let (attr, _) = RtAttr::read_from_prefix(data).ok()?;
+            let len = attr.rta_len as usize;
+            if len < size_of::<RtAttr>() || len > data.len() {
+                return None;
+            }
+            let value = &data[size_of::<RtAttr>()..len];
+            data = &data[align(len).min(data.len())..];
+            Some((attr.rta_type as u32, value))
+        })
+    }
+
+    fn get(self, ty: u32) -> Option<&'a [u8]> {
+        self.iter()
+            .find(|(it, _)| *it == ty)
+            .map(|(_, value)| value)
+    }
+
+    fn u32(self, ty: u32) -> LinuxResult<Option<u32>> {
+        self.get(ty)
+            .map(|value| {
+                value
+                    .try_into()
+                    .map(u32::from_ne_bytes)
+                    .map_err(|_| LinuxError::EINVAL)
+            })
+            .transpose()
+    }
+
+    fn str(self, ty: u32) -> LinuxResult<Option<&'a str>> {
+        self.get(ty)
+            .map(|value| {
+                let end = value.iter().position(|&c| c == 0).unwrap_or(value.len());
+                str::from_utf8(&value[..end]).map_err(|_| LinuxError::EINVAL)
+            })
+            .transpose()
+    }
+
+    fn addr(self, ty: u32, family: u8) -> LinuxResult<Option<IpAddr>> {
+        self.get(ty)
+            .map(|value| parse_addr(family, value))
+            .transpose()
+    }
+}
+
+fn parse_addr(family: u8, data: &[u8]) -> LinuxResult<IpAddr> {
+    match family as u32 {
+        AF_INET => <[u8; 4]>::try_from(data).map(|it| Ipv4Addr::from(it).into()),
+        AF_INET6 => <[u8; 16]>::try_from(data).map(|it| Ipv6Addr::from(it).into()),
+        _ => return Err(LinuxError::EAFNOSUPPORT),
+    }
+    .map_err(|_| LinuxError::EINVAL)
+}
+
+fn addr_family(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => AF_INET as u8,
+        IpAddr::V6(_) => AF_INET6 as u8,
+    }
+}
+
+/// A message being put together.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(ty: u32, flags: u32, req: &NlMsgHdr, port: u32) -> Self {
+        let hdr = NlMsgHdr {
+            nlmsg_len: 0,
+            nlmsg_type: ty as u16,
+            nlmsg_flags: flags as u16,
+            nlmsg_seq: req.nlmsg_seq,
+            nlmsg_pid: port,
+        };
+        Self(hdr.as_bytes().into())
+    }
+
+    fn put<T: IntoBytes + Immutable>(&mut self, val: &T) {
+        self.0.extend_from_slice(val.as_bytes());
+        self.0.resize(align(self.0.len()), 0);
+    }
+
+    fn attr(&mut self, ty: u32, value: &[u8]) {
+        self.put(&RtAttr {
+            rta_len: (size_of::<RtAttr>() + value.len()) as u16,
+            rta_type: ty as u16,
+        });
+        self.0.extend_from_slice(value);
+        self.0.resize(align(self.0.len()), 0);
+    }
+
+    fn attr_u8(&mut self, ty: u32, value: u8) {
+        self.attr(ty, &[value]);
+    }
+
+    fn attr_u32(&mut self, ty: u32, value: u32) {
+        self.attr(ty, &value.to_ne_bytes());
+    }
+
+    fn attr_str(&mut self, ty: u32, value: &str) {
+        let mut data = Vec::with_capacity(value.len() + 1);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        self.attr(ty, &data);
+    }
+
+    fn attr_addr(&mut self, ty: u32, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(addr) => self.attr(ty, &addr.octets()),
+            IpAddr::V6(addr) => self.attr(ty, &addr.octets()),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[..size_of::<u32>()].copy_from_slice(&len.to_ne_bytes());
+        self.0
+    }
+}
+
+/// Builds the `NLMSG_ERROR` answering `req`, an ack if `error` is 0.
+fn error_message(req: &NlMsgHdr, port: u32, error: i32) -> Vec<u8> {
+    // Only the header of the request is echoed back.
+    let mut msg = Message::new(NLMSG_ERROR, NLM_F_CAPPED, req, port);
+    msg.put(&NlMsgErr { error, msg: *req });
+    msg.finish()
+}
+
+fn link_message(iface: &Interface, req: &NlMsgHdr, port: u32, flags: u32) -> Vec<u8> {
+    let oper_state = if iface.hw_type == ARPHRD_LOOPBACK {
+        IF_OPER_UNKNOWN
+    } else if iface.is_running() {
+        IF_OPER_UP
+    } else {
+        IF_OPER_DOWN
+    };
+
+    let mut msg = Message::new(RTM_NEWLINK, flags, req, port);
+    msg.put(&IfInfoMsg {
+        ifi_family: AF_UNSPEC as u8,
+        ifi_pad: 0,
+        ifi_type: iface.hw_type,
+        ifi_index: iface.index as i32,
+        ifi_flags: iface.reported_flags().bits(),
+        ifi_change: 0,
+    });
+    msg.attr_str(IFLA_IFNAME, &iface.name);
+    msg.attr_u32(IFLA_TXQLEN, iface.tx_queue_len);
+    msg.attr_u8(IFLA_OPERSTATE, oper_state);
+    msg.attr_u8(IFLA_LINKMODE, 0);
+    msg.attr_u32(IFLA_MTU, iface.mtu);
+    msg.attr(IFLA_ADDRESS, &iface.hw_addr);
+    let broadcast = if iface.hw_type == ARPHRD_ETHER {
+        [0xff; 6]
+    } else {
+        [0; 6]
+    };
+    msg.attr(IFLA_BROADCAST, &broadcast);
+    if iface.link != 0 {
+        msg.attr_u32(IFLA_LINK, iface.link);
+    }
+    if let Some(kind) = iface.kind {
+        let mut info = Message(Vec::new());
+        info.attr_str(IFLA_INFO_KIND, kind);
+        msg.attr(IFLA_LINKINFO, &info.0);
+    }
+    msg.finish()
+}
+
+fn addr_message(
+    iface: &Interface,
+    addr: &InterfaceAddr,
+    req: &NlMsgHdr,
+    port: u32,
+    flags: u32,
+) -> Vec<u8> {
+    let mut msg = Message::new(RTM_NEWADDR, flags, req, port);
+    msg.put(&IfAddrMsg {
+        ifa_family: addr_family(addr.addr),
+        ifa_prefixlen: addr.prefix_len,
+        ifa_flags: IFA_F_PERMANENT as u8,
+        ifa_scope: addr.scope(),
+        ifa_index: iface.index,
+    });
+    msg.attr_addr(IFA_ADDRESS, addr.addr);
+    if addr.addr.is_ipv4() {
+        msg.attr_addr(IFA_LOCAL, addr.addr);
+    }
+    if let Some(broadcast) = addr.broadcast {
+        msg.attr_addr(IFA_BROADCAST, broadcast.into());
+    }
+    if addr.addr.is_ipv4() {
+        msg.attr_str(IFA_LABEL, &addr.label);
+    }
+    msg.attr_u32(IFA_FLAGS, IFA_F_PERMANENT);
+    msg.finish()
+}
+
+fn route_message(route: &Route, req: &NlMsgHdr, port: u32, flags: u32, rtm_flags: u32) -> Vec<u8> {
+    let table = if route.table > 255 {
+        RT_TABLE_COMPAT
+    } else {
+        route.table
+    };
+    let mut msg = Message::new(RTM_NEWROUTE, flags, req, port);
+    msg.put(&RtMsg {
+        rtm_family: addr_family(route.dst),
+        rtm_dst_len: route.prefix_len,
+        rtm_src_len: 0,
+        rtm_tos: 0,
+        rtm_table: table as u8,
+        rtm_protocol: route.protocol,
+        rtm_scope: route.scope,
+        rtm_type: RTN_UNICAST as u8,
+        rtm_flags,
+    });
+    msg.attr_u32(RTA_TABLE, route.table);
+    if route.prefix_len > 0 {
+        msg.attr_addr(RTA_DST, route.dst);
+    }
+    if route.metric != 0 {
+        msg.attr_u32(RTA_PRIORITY, route.metric);
+    }
+    if let Some(prefsrc) = route.prefsrc {
+        msg.attr_addr(RTA_PREFSRC, prefsrc);
+    }
+    if let Some(gateway) = route.gateway {
+        msg.attr_addr(RTA_GATEWAY, gateway);
+    }
+    msg.attr_u32(RTA_OIF, route.oif);
+    msg.finish()
+}
+
+/// Returns whether an address of `family` is asked for by a dump of `filter`.
+fn family_matches(filter: u8, addr: IpAddr) -> bool {
+    filter == AF_UNSPEC as u8 || filter == addr_family(addr)
+}
+
+fn dump(config: &NetConfig, req: &NlMsgHdr, payload: &[u8], port: u32) -> Vec<Vec<u8>> {
+    // Every dump request starts with the family.
+    let family = payload.first().copied().unwrap_or(AF_UNSPEC as u8);
+    let flags = NLM_F_MULTI;
+    let mut msgs = Vec::new();
+    match req.nlmsg_type as u32 {
+        RTM_GETLINK => {
+            for iface in &config.interfaces {
+                msgs.push(link_message(iface, req, port, flags));
+            }
+        }
+        RTM_GETADDR => {
+            for iface in &config.interfaces {
+                for addr in &iface.addrs {
+                    if family_matches(family, addr.addr) {
+                        msgs.push(addr_message(iface, addr, req, port, flags));
+                    }
+                }
+            }
+        }
+        RTM_GETROUTE => {
+            for route in &config.routes {
+                if family_matches(family, route.dst) {
+                    msgs.push(route_message(route, req, port, flags, 0));
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    let mut done = Message::new(NLMSG_DONE, flags, req, port);
+    done.put(&0i32);
+
+    let mut datagrams = Vec::new();
+    let mut datagram = Vec::new();
+    for msg in msgs.into_iter().chain(iter::once(done.finish())) {
+        if !datagram.is_empty() && datagram.len() + msg.len() > DUMP_SIZE {
+            datagrams.push(mem::take(&mut datagram));
+        }
+        datagram.extend_from_slice(&msg);
+    }
+    datagrams.push(datagram);
+    datagrams
+}
+
+/// Finds the interface a link request is about, by index or else by name.
+fn find_link(config: &NetConfig, ifm: &IfInfoMsg, attrs: Attrs) -> LinuxResult<u32> {
+    if ifm.ifi_index > 0 {
+        return Ok(config.interface(ifm.ifi_index as u32)?.index);
+    }
+    match attrs.str(IFLA_IFNAME)? {
+        Some(name) => Ok(config.interface_by_name(name)?.index),
+        None => Err(LinuxError::EINVAL),
+    }
+}
+
+fn get_link(config: &NetConfig, req: &NlMsgHdr, payload: &[u8], port: u32) -> LinuxResult<Vec<u8>> {
+    let (ifm, attrs) = parse::<IfInfoMsg>(payload);
+    let index = find_link(config, &ifm, attrs)?;
+    Ok(link_message(config.interface(index)?, req, port, 0))
+}
+
+/// Returns the namespace a link request moves the link to, if any.
+fn target_ns(attrs: Attrs) -> LinuxResult<Option<Arc<NetNamespace>>> {
+    if attrs.get(IFLA_NET_NS_FD).is_some() {
+        warn!("rtnetlink: namespaces can only be given by process id");
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    attrs
+        .u32(IFLA_NET_NS_PID)?
+        .map(|pid| process_net_ns(pid as _))
+        .transpose()
+}
+
+/// Handles `RTM_NEWLINK` and `RTM_SETLINK`: changes a link, or creates a
+/// `veth` pair if it does not exist and the request asks for that.
+fn new_link(ns: &Arc<NetNamespace>, req: &NlMsgHdr, payload: &[u8]) -> LinuxResult<()> {
+    let (ifm, attrs) = parse::<IfInfoMsg>(payload);
+    let target = target_ns(attrs)?;
+    let mut config = ns.config();
+    let index = match find_link(&config, &ifm, attrs) {
+        Err(LinuxError::ENODEV) if req.nlmsg_flags as u32 & NLM_F_CREATE != 0 => {
+            drop(config);
+            return create_link(target.as_ref().unwrap_or(ns), &ifm, attrs);
+        }
+        result => result?,
+    };
+    set_link(&mut config, index, &ifm, attrs)?;
+    drop(config);
+    if let Some(target) = target {
+        veth::move_link(ns, index, &target)?;
+    }
+    Ok(())
+}
+
+/// Creates a link in namespace `ns`. Only `veth` pairs can be created.
+fn create_link(ns: &Arc<NetNamespace>, ifm: &IfInfoMsg, attrs: Attrs) -> LinuxResult<()> {
+    let info = Attrs(attrs.get(IFLA_LINKINFO).unwrap_or_default());
+    if info.str(IFLA_INFO_KIND)? != Some("veth") {
+        warn!("rtnetlink: only veth links can be created");
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    // The peer is described by a link message of its own.
+    let data = Attrs(info.get(IFLA_INFO_DATA).unwrap_or_default());
+    let (_, peer_attrs) = parse::<IfInfoMsg>(data.get(VETH_INFO_PEER).unwrap_or_default());
+    let peer_ns = target_ns(peer_attrs)?.unwrap_or_else(|| ns.clone());
+
+    let name = attrs.str(IFLA_IFNAME)?.unwrap_or("veth%d");
+    let peer_name = peer_attrs.str(IFLA_IFNAME)?.unwrap_or("veth%d");
+    if name.contains(['/', ' ']) || peer_name.contains(['/', ' ']) {
+        return Err(LinuxError::EINVAL);
+    }
+    let index = veth::create(ns, name, &peer_ns, peer_name)?;
+    // The name is taken already, and the rest applies to the new link.
+    let ifm = IfInfoMsg {
+        ifi_index: 0,
+        ..*ifm
+    };
+    set_link(&mut ns.config(), index, &ifm, attrs)
+}
+
+/// Handles `RTM_DELLINK`. Only `veth` pairs can be deleted.
+fn del_link(ns: &Arc<NetNamespace>, payload: &[u8]) -> LinuxResult<()> {
+    let (ifm, attrs) = parse::<IfInfoMsg>(payload);
+    let index = find_link(&ns.config(), &ifm, attrs)?;
+    veth::delete(ns, index)
+}
+
+fn set_link(config: &mut NetConfig, index: u32, ifm: &IfInfoMsg, attrs: Attrs) -> LinuxResult<()> {
+    // Check everything before changing anything.
+    let mtu = attrs.u32(IFLA_MTU)?;
+    if mtu.is_some_and(|mtu| mtu < MIN_MTU) {
+        return Err(LinuxError::EINVAL);
+    }
+    let name = match attrs.str(IFLA_IFNAME)? {
+        Some(name) if ifm.ifi_index > 0 => {
+            if name.is_empty() || name.len() >= IFNAMSIZ || name.contains(['/', ' ']) {
+                return Err(LinuxError::EINVAL);
+            }
+            if config
+                .interface_by_name(name)
+                .is_ok_and(|it| it.index != index)
+            {
+                return Err(LinuxError::EEXIST);
+            }
+            Some(String::from(name))
+        }
+        _ => None,
+    };
+    let hw_addr = attrs
+        .get(IFLA_ADDRESS)
+        .map(|it| <[u8; 6]>::try_from(it).map_err(|_| LinuxError::EINVAL))
+        .transpose()?;
+
+    let iface = config.interface_mut(index)?;
+    let mut flags = iface.flags;
+    if ifm.ifi_flags != 0 || ifm.ifi_change != 0 {
+        let change = match ifm.ifi_change {
+            0 => InterfaceFlags::all(),
+            change => InterfaceFlags::from_bits_truncate(change),
+        } & InterfaceFlags::CHANGEABLE;
+        flags = (flags - change) | (InterfaceFlags::from_bits_truncate(ifm.ifi_flags) & change);
+    }
+    // Requests that change nothing succeed on any interface.
+    if flags != iface.flags
+        || mtu.is_some_and(|mtu| mtu != iface.mtu)
+        || name.as_ref().is_some_and(|name| *name != iface.name)
+        || hw_addr.is_some_and(|hw_addr| hw_addr != iface.hw_addr)
+    {
+        iface.check_configurable()?;
+    }
+    iface.flags = flags;
+    if let Some(mtu) = mtu {
+        iface.mtu = mtu;
+    }
+    if let Some(name) = name {
+        iface.name = name;
+    }
+    if let Some(hw_addr) = hw_addr {
+        iface.hw_addr = hw_addr;
+    }
+    Ok(())
+}
+
+fn new_addr(config: &mut NetConfig, req: &NlMsgHdr, payload: &[u8]) -> LinuxResult<()> {
+    let (ifa, attrs) = parse::<IfAddrMsg>(payload);
+    let addr = match attrs.addr(IFA_LOCAL, ifa.ifa_family)? {
+        Some(addr) => addr,
+        None => attrs
+            .addr(IFA_ADDRESS, ifa.ifa_family)?
+            .ok_or(LinuxError::EINVAL)?,
+    };
+    let broadcast = match attrs.addr(IFA_BROADCAST, ifa.ifa_family)? {
+        Some(IpAddr::V4(broadcast)) => Some(broadcast),
+        _ => None,
+    };
+    let label = attrs.str(IFA_LABEL)?;
+
+    match config.add_addr(ifa.ifa_index, addr, ifa.ifa_prefixlen, broadcast, label) {
+        Err(LinuxError::EEXIST)
+            if req.nlmsg_flags as u32 & NLM_F_REPLACE != 0
+                && req.nlmsg_flags as u32 & NLM_F_EXCL == 0 =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn del_addr(config: &mut NetConfig, payload: &[u8]) -> LinuxResult<()> {
+    let (ifa, attrs) = parse::<IfAddrMsg>(payload);
+    let addr = match attrs.addr(IFA_LOCAL, ifa.ifa_family)? {
+        Some(addr) => addr,
+        None => attrs
+            .addr(IFA_ADDRESS, ifa.ifa_family)?
+            .ok_or(LinuxError::EINVAL)?,
+    };
+    let prefix_len = (ifa.ifa_prefixlen != 0).then_some(ifa.ifa_prefixlen);
+    config.remove_addr(ifa.ifa_index, addr, prefix_len)
+}
+
+/// Reads the route a `RTM_NEWROUTE` or `RTM_DELROUTE` request is about.
+fn parse_route(payload: &[u8]) -> LinuxResult<Route> {
+    let (rtm, attrs) = parse::<RtMsg>(payload);
+    let family = rtm.rtm_family;
+    let unspecified: IpAddr = match family as u32 {
+        AF_INET => Ipv4Addr::UNSPECIFIED.into(),
+        AF_INET6 => Ipv6Addr::UNSPECIFIED.into(),
+        _ => return Err(LinuxError::EAFNOSUPPORT),
+    };
+    // Only plain unicast routes are supported.
+    if rtm.rtm_type != 0 && rtm.rtm_type as u32 != RTN_UNICAST {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    let dst = attrs.addr(RTA_DST, family)?.unwrap_or(unspecified);
+    if rtm.rtm_dst_len > max_prefix_len(dst) || network_of(dst, rtm.rtm_dst_len) != dst {
+        return Err(LinuxError::EINVAL);
+    }
+    let table = match attrs.u32(RTA_TABLE)?.unwrap_or(rtm.rtm_table as u32) {
+        0 => RT_TABLE_MAIN,
+        table => table,
+    };
+    Ok(Route {
+        dst,
+        prefix_len: rtm.rtm_dst_len,
+        gateway: attrs.addr(RTA_GATEWAY, family)?,
+        oif: attrs.u32(RTA_OIF)?.unwrap_or(0),
+        prefsrc: attrs.addr(RTA_PREFSRC, family)?,
+        metric: attrs.u32(RTA_PRIORITY)?.unwrap_or(0),
+        table,
+        protocol: match rtm.rtm_protocol {
+            0 => RTPROT_BOOT,
+            protocol => protocol,
+        },
+        scope: rtm.rtm_scope,
+    })
+}
+
+fn new_route(config: &mut NetConfig, req: &NlMsgHdr, payload: &[u8]) -> LinuxResult<()> {
+    let mut route = parse_route(payload)?;
+    config.resolve_route(&mut route)?;
+    let replace = req.nlmsg_flags as u32 & NLM_F_REPLACE != 0;
+    config.add_route(route, replace)
+}
+
+fn del_route(config: &mut NetConfig, payload: &[u8]) -> LinuxResult<()> {
+    config.remove_route(&parse_route(payload)?)
+}
+
+/// Answers `ip route get`: the route a packet to the destination would take.
+fn get_route(
+    config: &NetConfig,
+    req: &NlMsgHdr,
+    payload: &[u8],
+    port: u32,
+) -> LinuxResult<Vec<u8>> {
+    let (rtm, attrs) = parse::<RtMsg>(payload);
+    let dst = attrs
+        .addr(RTA_DST, rtm.rtm_family)?
+        .ok_or(LinuxError::EINVAL)?;
+    let found = config.lookup(dst)?;
+    let prefsrc = found.prefsrc.or_else(|| {
+        config.interface(found.oif).ok().and_then(|iface| {
+            iface
+                .addrs
+                .iter()
+                .find(|it| it.addr.is_ipv4() == dst.is_ipv4())
+                .map(|it| it.addr)
+        })
+    });
+    let route = Route {
+        dst,
+        prefix_len: max_prefix_len(dst),
+        prefsrc,
+        ..found.clone()
+    };
+    Ok(route_message(&route, req, port, 0, RTM_F_CLONED))
+}
+
+/// Handles one request, pushing the replies to `replies`.
+fn handle_message(
+    ns: &Arc<NetNamespace>,
+    req: &NlMsgHdr,
+    payload: &[u8],
+    port: u32,
+    replies: &mut Vec<Vec<u8>>,
+) {
+    let flags = req.nlmsg_flags as u32;
+    if flags & NLM_F_REQUEST == 0 {
+        return;
+    }
+    let ty = req.nlmsg_type as u32;
+    let result = match ty {
+        ty if ty < NLMSG_MIN_TYPE => Ok(()),
+        // These may reach into other namespaces, so they lock the tables
+        // themselves.
+        RTM_NEWLINK | RTM_SETLINK => new_link(ns, req, payload),
+        RTM_DELLINK => del_link(ns, payload),
+        _ => {
+            let mut config = ns.config();
+            match ty {
+                RTM_GETLINK | RTM_GETADDR | RTM_GETROUTE if flags & NLM_F_DUMP == NLM_F_DUMP => {
+                    replies.extend(dump(&config, req, payload, port));
+                    return;
+                }
+                RTM_GETLINK => get_link(&config, req, payload, port).map(|msg| replies.push(msg)),
+                RTM_GETROUTE => get_route(&config, req, payload, port).map(|msg| replies.push(msg)),
+                // Addresses are only dumped.
+                RTM_GETADDR => Err(LinuxError::EOPNOTSUPP),
+                RTM_NEWADDR => new_addr(&mut config, req, payload),
+                RTM_DELADDR => del_addr(&mut config, payload),
+                RTM_NEWROUTE => new_route(&mut config, req, payload),
+                RTM_DELROUTE => del_route(&mut config, payload),
+                _ => Err(LinuxError::EOPNOTSUPP),
+            }
+        }
+    };
+    match result {
+        Ok(()) if flags & NLM_F_ACK != 0 => replies.push(error_message(req, port, 0)),
+        Ok(()) => {}
+        Err(err) => {
+            debug!("rtnetlink: request {} failed: {:?}", ty, err);
+            replies.push(error_message(req, port, -err.code()));
+        }
+    }
+}
+
+/// Handles the requests in a datagram sent from port `port` of a socket in
+/// namespace `ns` and returns the datagrams to send back.
+pub fn handle(ns: &Arc<NetNamespace>, data: &[u8], port: u32) -> Vec<Vec<u8>> {
+    let mut replies = Vec::new();
+    let mut rest = data;
+    while let Ok((req, _)) = NlMsgHdr::read_from_prefix(rest) {
+        let len = req.nlmsg_len as usize;
+        if len < size_of::<NlMsgHdr>() || len > rest.len() {
+            break;
+        }
+        handle_message(
+            ns,
+            &req,
+            &rest[size_of::<NlMsgHdr>()..len],
+            port,
+            &mut replies,
+        );
+        rest = &rest[align(len).min(rest.len())..];
+    }
+    replies
+}
@@ -0,0 +1,690 @@
+//! TUN/TAP interfaces, `/dev/net/tun`.
+//!
+//! Every open of `/dev/net/tun` is a queue that `TUNSETIFF` attaches to a
+//! `tunN` interface, which carries IP packets, or a `tapN` interface, which
+//! carries Ethernet frames, creating the interface if needed. Packets
+//! written to a queue come in on its interface and packets the host sends
+//! out of the interface are read from a queue of it. The interface is in the
+//! namespace of the process that created it, and goes away with it.
+//!
+//! The host side of these interfaces is the IP layer of [`super::raw`] and
+//! packet sockets; TCP and UDP of the network stack do not reach them. TAP
+//! interfaces answer ARP requests for local addresses and send IPv4 packets
+//! to the link-layer addresses they learned, everything else is broadcast.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use axio::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use bitflags::bitflags;
+use starry_core::vfs::{Device, DeviceOps, SimpleFs};
+use starry_vm::{VmMutPtr, VmPtr};
+
+use super::{
+    iface::{ARPHRD_ETHER, ARPHRD_NONE, IFNAMSIZ, InterfaceFlags, NetConfig},
+    ioctl::IfReq,
+    ns::{NetNamespace, current_net_ns},
+    packet::{ETH_ALEN, ETH_HEADER_LEN, ETH_P_IP, ETH_P_IPV6, tap, tap_ip},
+};
+
+const TUNSETIFF: u32 = 0x400454ca;
+const TUNSETPERSIST: u32 = 0x400454cb;
+const TUNGETFEATURES: u32 = 0x800454cf;
+const TUNGETIFF: u32 = 0x800454d2;
+const TUNSETQUEUE: u32 = 0x400454d9;
+
+/// `TUN_PKT_STRIP`, set in the packet information of a packet that did not
+/// fit into the buffer.
+const TUN_PKT_STRIP: u16 = 0x0001;
+/// Length of the packet information, `struct tun_pi`.
+const TUN_PI_LEN: usize = 4;
+
+const ETH_P_ARP: u16 = 0x0806;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+/// Length of an ARP message for IPv4 over Ethernet.
+const ARP_LEN: usize = 28;
+
+const BROADCAST_ADDR: [u8; ETH_ALEN] = [0xff; ETH_ALEN];
+
+/// Default MTU of the interfaces.
+const DEFAULT_MTU: u32 = 1500;
+
+bitflags! {
+    /// Flags of `TUNSETIFF`, `IFF_*`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct TunFlags: u16 {
+        const TUN = 0x0001;
+        const TAP = 0x0002;
+        const MULTI_QUEUE = 0x0100;
+        const ATTACH_QUEUE = 0x0200;
+        const DETACH_QUEUE = 0x0400;
+        const PERSIST = 0x0800;
+        const NO_PI = 0x1000;
+        const ONE_QUEUE = 0x2000;
+    }
+}
+
+impl TunFlags {
+    /// Flags reported by `TUNGETFEATURES`.
+    const FEATURES: Self = Self::TUN
+        .union(Self::TAP)
+        .union(Self::MULTI_QUEUE)
+        .union(Self::NO_PI)
+        .union(Self::ONE_QUEUE);
+}
+
+/// TUN/TAP interfaces, by interface index.
+static DEVICES: Mutex<Vec<Arc<TunDevice>>> = Mutex::new(Vec::new());
+
+/// A TUN/TAP interface.
+struct TunDevice {
+    /// Namespace the interface is in.
+    ns: Weak<NetNamespace>,
+    ifindex: u32,
+    tap: bool,
+    multi_queue: bool,
+    /// Whether packets are read and written without packet information.
+    no_pi: AtomicBool,
+    persist: AtomicBool,
+    /// Queues attached, including those detached with `TUNSETQUEUE`.
+    queues: Mutex<Vec<Weak<Queue>>>,
+    /// Link-layer addresses of IPv4 neighbours, for TAP interfaces.
+    neighbours: Mutex<BTreeMap<Ipv4Addr, [u8; ETH_ALEN]>>,
+}
+
+impl TunDevice {
+    fn ns(&self) -> LinuxResult<Arc<NetNamespace>> {
+        self.ns.upgrade().ok_or(LinuxError::ENODEV)
+    }
+
+    /// Returns the flags `TUNGETIFF` reports.
+    fn flags(&self) -> TunFlags {
+        let mut flags = if self.tap {
+            TunFlags::TAP
+        } else {
+            TunFlags::TUN
+        };
+        flags.set(TunFlags::MULTI_QUEUE, self.multi_queue);
+        flags.set(TunFlags::NO_PI, self.no_pi.load(Ordering::Acquire));
+        flags.set(TunFlags::PERSIST, self.persist.load(Ordering::Acquire));
+        flags
+    }
+
+    /// Returns the queues packets can be handed to.
+    fn enabled_queues(&self) -> Vec<Arc<Queue>> {
+        self.queues
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|it| it.enabled.load(Ordering::Acquire))
+            .collect()
+    }
+
+    /// Turns the carrier of the interface on while a queue is enabled, and
+    /// removes the interface once the last queue is gone unless it persists.
+    fn update(self: &Arc<Self>) {
+        let attached = {
+            let mut queues = self.queues.lock();
+            queues.retain(|it| it.strong_count() > 0);
+            !queues.is_empty()
+        };
+        if !attached && !self.persist.load(Ordering::Acquire) {
+            DEVICES.lock().retain(|it| !Arc::ptr_eq(it, self));
+            if let Ok(ns) = self.ns() {
+                let _ = ns.config().remove_interface(self.ifindex);
+            }
+            return;
+        }
+        let Ok(ns) = self.ns() else {
+            return;
+        };
+        let carrier = !self.enabled_queues().is_empty();
+        if let Ok(iface) = ns.config().interface_mut(self.ifindex) {
+            iface
+                .flags
+                .set(InterfaceFlags::RUNNING | InterfaceFlags::LOWER_UP, carrier);
+        }
+    }
+
+    /// Hands a packet leaving the host to a queue, the same one for every
+    /// packet of a flow.
+    fn queue(&self, packet: Vec<u8>, max_len: usize) {
+        let queues = self.enabled_queues();
+        if queues.is_empty() {
+            return;
+        }
+        let offset = if self.tap { ETH_HEADER_LEN } else { 0 };
+        // Source and destination addresses of IPv4 or IPv6.
+        let addrs = match packet.get(offset).map(|it| it >> 4) {
+            Some(4) => packet.get(offset + 12..offset + 20),
+            Some(6) => packet.get(offset + 8..offset + 40),
+            _ => None,
+        };
+        let hash = addrs.unwrap_or_default().iter().fold(0usize, |acc, &it| {
+            acc.wrapping_mul(31).wrapping_add(it as usize)
+        });
+        queues[hash % queues.len()].push(packet, max_len);
+    }
+
+    /// Handles a frame that came in on a TAP interface.
+    fn receive_frame(&self, ns: &Arc<NetNamespace>, hw_addr: [u8; ETH_ALEN], frame: &[u8]) {
+        tap(ns, self.ifindex, frame);
+        let dst = &frame[..ETH_ALEN];
+        let src: [u8; ETH_ALEN] = frame[ETH_ALEN..2 * ETH_ALEN].try_into().unwrap();
+        if dst != hw_addr && dst[0] & 1 == 0 {
+            // Unicast to another host.
+            return;
+        }
+        let payload = &frame[ETH_HEADER_LEN..];
+        match u16::from_be_bytes([frame[12], frame[13]]) {
+            ETH_P_IP => {
+                if let Some(addr) = payload.get(12..16) {
+                    let addr = Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap());
+                    self.neighbours.lock().insert(addr, src);
+                }
+                super::raw::input(ns, self.ifindex, payload);
+            }
+            ETH_P_IPV6 => super::raw::input(ns, self.ifindex, payload),
+            ETH_P_ARP => self.receive_arp(ns, hw_addr, payload),
+            _ => {}
+        }
+    }
+
+    /// Learns the sender of an ARP message, and answers requests for local
+    /// addresses.
+    fn receive_arp(&self, ns: &NetNamespace, hw_addr: [u8; ETH_ALEN], arp: &[u8]) {
+        // IPv4 over Ethernet only.
+        if arp.len() < ARP_LEN || arp[..6] != [0, 1, 0x08, 0x00, ETH_ALEN as u8, 4] {
+            return;
+        }
+        let sender_hw: [u8; ETH_ALEN] = arp[8..14].try_into().unwrap();
+        let sender = Ipv4Addr::from(<[u8; 4]>::try_from(&arp[14..18]).unwrap());
+        let target = Ipv4Addr::from(<[u8; 4]>::try_from(&arp[24..28]).unwrap());
+        self.neighbours.lock().insert(sender, sender_hw);
+
+        let (local, mtu) = {
+            let config = ns.config();
+            let mtu = config.interface(self.ifindex).map_or(0, |it| it.mtu);
+            (config.is_local(target.into()), mtu)
+        };
+        if u16::from_be_bytes([arp[6], arp[7]]) != ARP_REQUEST || !local {
+            return;
+        }
+        let mut reply = Vec::with_capacity(ETH_HEADER_LEN + ARP_LEN);
+        reply.extend_from_slice(&sender_hw);
+        reply.extend_from_slice(&hw_addr);
+        reply.extend_from_slice(&ETH_P_ARP.to_be_bytes());
+        reply.extend_from_slice(&arp[..6]);
+        reply.extend_from_slice(&ARP_REPLY.to_be_bytes());
+        reply.extend_from_slice(&hw_addr);
+        reply.extend_from_slice(&target.octets());
+        reply.extend_from_slice(&sender_hw);
+        reply.extend_from_slice(&sender.octets());
+        self.queue(reply, mtu as usize + ETH_HEADER_LEN);
+    }
+}
+
+/// Looks a TUN/TAP interface of namespace `ns` up by index.
+fn device(ns: &Arc<NetNamespace>, ifindex: u32) -> Option<Arc<TunDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|it| it.ifindex == ifindex && it.ns.as_ptr() == Arc::as_ptr(ns))
+        .cloned()
+}
+
+/// Sends an IP packet out of interface `ifindex` of namespace `ns`. Returns
+/// `false` if that is not a TUN/TAP interface.
+pub fn transmit(ns: &Arc<NetNamespace>, ifindex: u32, packet: &[u8]) -> bool {
+    let Some(device) = device(ns, ifindex) else {
+        return false;
+    };
+    let Ok((hw_addr, mtu)) = ns
+        .config()
+        .interface(ifindex)
+        .map(|it| (it.hw_addr, it.mtu as usize))
+    else {
+        return false;
+    };
+    if !device.tap {
+        device.queue(packet.to_vec(), mtu);
+        return true;
+    }
+
+    let protocol = if packet.first().is_some_and(|it| it >> 4 == 6) {
+        ETH_P_IPV6
+    } else {
+        ETH_P_IP
+    };
+    let dst = match packet.get(16..20) {
+        Some(addr) if protocol == ETH_P_IP => {
+            let addr = Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap());
+            device.neighbours.lock().get(&addr).copied()
+        }
+        _ => None,
+    };
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + packet.len());
+    frame.extend_from_slice(&dst.unwrap_or(BROADCAST_ADDR));
+    frame.extend_from_slice(&hw_addr);
+    frame.extend_from_slice(&protocol.to_be_bytes());
+    frame.extend_from_slice(packet);
+    device.queue(frame, mtu + ETH_HEADER_LEN);
+    true
+}
+
+/// Sends a frame of a packet socket out of interface `ifindex` of namespace
+/// `ns`. Returns `false` if that is not a TUN/TAP interface.
+pub fn transmit_frame(ns: &Arc<NetNamespace>, ifindex: u32, frame: &[u8]) -> bool {
+    let Some(device) = device(ns, ifindex) else {
+        return false;
+    };
+    // TUN interfaces have no link layer, see `tap_ip`.
+    let packet = if device.tap {
+        frame.to_vec()
+    } else {
+        frame[ETH_HEADER_LEN..].to_vec()
+    };
+    device.queue(packet, usize::MAX);
+    true
+}
+
+/// Removes the TUN/TAP interfaces of a namespace that goes away. Their
+/// queues stay open but detached.
+pub fn release_namespace(ns: &NetNamespace) {
+    let devices = {
+        let mut devices = DEVICES.lock();
+        let (gone, kept) = devices
+            .drain(..)
+            .partition(|it| it.ns.as_ptr() == ns as *const _);
+        *devices = kept;
+        gone
+    };
+    for device in devices {
+        let queues = core::mem::take(&mut *device.queues.lock());
+        for queue in queues.iter().filter_map(Weak::upgrade) {
+            queue.device.lock().take();
+            queue.rx.lock().clear();
+            queue.poll_rx.wake();
+        }
+    }
+}
+
+/// Returns `name` with `%d` replaced by the lowest number no interface of
+/// `config` has.
+pub(super) fn expand_name(config: &NetConfig, name: &str) -> LinuxResult<String> {
+    if !name.contains("%d") {
+        return Ok(name.into());
+    }
+    (0..)
+        .map(|n| name.replacen("%d", &format!("{n}"), 1))
+        .take_while(|it| it.len() < IFNAMSIZ)
+        .find(|it| config.interface_by_name(it).is_err())
+        .ok_or(LinuxError::ENFILE)
+}
+
+/// `/dev/net/tun`. Opening it gives a [`TunQueue`] instead, see
+/// [`TunClone::open`].
+pub struct TunClone(pub Arc<SimpleFs>);
+
+impl TunClone {
+    /// Creates the device of a new queue.
+    pub fn open(&self) -> Arc<Device> {
+        Device::new(
+            self.0.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(10, 200),
+            Arc::new(TunQueue(Arc::new(Queue::new()))),
+        )
+    }
+}
+
+// This is implemented as null-ops since opening `TunClone` would result in a
+// new queue and these implementations wouldn't actually be used
+impl DeviceOps for TunClone {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        unreachable!()
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        unreachable!()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A queue of a TUN/TAP interface.
+struct Queue {
+    device: Mutex<Option<Arc<TunDevice>>>,
+    /// Whether packets are handed to the queue, see `IFF_DETACH_QUEUE`.
+    enabled: AtomicBool,
+    rx: Mutex<VecDeque<Vec<u8>>>,
+    poll_rx: PollSet,
+}
+
+impl Queue {
+    fn new() -> Self {
+        Self {
+            device: Mutex::new(None),
+            enabled: AtomicBool::new(false),
+            rx: Mutex::new(VecDeque::new()),
+            poll_rx: PollSet::new(),
+        }
+    }
+
+    fn attached(&self) -> LinuxResult<Arc<TunDevice>> {
+        self.device.lock().clone().ok_or(LinuxError::EBADFD)
+    }
+
+    /// Queues a packet for reading, truncated to `max_len`. Packets are
+    /// dropped while the transmit queue of the interface is full.
+    fn push(&self, mut packet: Vec<u8>, max_len: usize) {
+        let Some(device) = self.device.lock().clone() else {
+            return;
+        };
+        let Ok(ns) = device.ns() else {
+            return;
+        };
+        let limit = ns
+            .config()
+            .interface(device.ifindex)
+            .map_or(0, |it| it.tx_queue_len as usize);
+        packet.truncate(max_len);
+        let mut rx = self.rx.lock();
+        if rx.len() >= limit.max(1) {
+            return;
+        }
+        rx.push_back(packet);
+        drop(rx);
+        self.poll_rx.wake();
+    }
+}
+
+/// An open `/dev/net/tun`.
+pub struct TunQueue(Arc<Queue>);
+
+impl TunQueue {
+    /// Attaches the queue to an interface, `TUNSETIFF`.
+    fn set_iff(&self, req: &mut IfReq) -> LinuxResult<()> {
+        let flags = TunFlags::from_bits_truncate(req.short() as u16);
+        let kind = flags & (TunFlags::TUN | TunFlags::TAP);
+        if kind != TunFlags::TUN && kind != TunFlags::TAP {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut attached = self.0.device.lock();
+        if attached.is_some() {
+            return Err(LinuxError::EEXIST);
+        }
+
+        let mut name = req.name()?;
+        if name.is_empty() {
+            name = if kind == TunFlags::TUN {
+                "tun%d"
+            } else {
+                "tap%d"
+            };
+        }
+        let ns = current_net_ns();
+        let mut devices = DEVICES.lock();
+        let mut config = ns.config();
+        let name = expand_name(&config, name)?;
+        let existing = config.interface_by_name(&name).map(|it| it.index);
+        let device = match existing {
+            Ok(ifindex) => {
+                let device = devices
+                    .iter()
+                    .find(|it| it.ifindex == ifindex && it.ns.as_ptr() == Arc::as_ptr(&ns))
+                    .ok_or(LinuxError::EINVAL)?;
+                if device.tap != (kind == TunFlags::TAP)
+                    || device.multi_queue != flags.contains(TunFlags::MULTI_QUEUE)
+                {
+                    return Err(LinuxError::EINVAL);
+                }
+                let queues = device.queues.lock();
+                if !device.multi_queue && queues.iter().any(|it| it.strong_count() > 0) {
+                    return Err(LinuxError::EBUSY);
+                }
+                drop(queues);
+                device
+                    .no_pi
+                    .store(flags.contains(TunFlags::NO_PI), Ordering::Release);
+                device.clone()
+            }
+            Err(_) => {
+                let device = Arc::new(TunDevice {
+                    ns: Arc::downgrade(&ns),
+                    ifindex: create_interface(&mut config, &name, kind == TunFlags::TAP),
+                    tap: kind == TunFlags::TAP,
+                    multi_queue: flags.contains(TunFlags::MULTI_QUEUE),
+                    no_pi: AtomicBool::new(flags.contains(TunFlags::NO_PI)),
+                    persist: AtomicBool::new(false),
+                    queues: Mutex::new(Vec::new()),
+                    neighbours: Mutex::new(BTreeMap::new()),
+                });
+                devices.push(device.clone());
+                device
+            }
+        };
+        drop(config);
+        drop(devices);
+
+        device.queues.lock().push(Arc::downgrade(&self.0));
+        self.0.enabled.store(true, Ordering::Release);
+        *attached = Some(device.clone());
+        drop(attached);
+        device.update();
+        debug!("TUNSETIFF: attached to {}", name);
+
+        req.set_name(&name);
+        req.set_short(device.flags().bits() as i16);
+        Ok(())
+    }
+
+    /// Enables or disables the queue, `TUNSETQUEUE`.
+    fn set_queue(&self, flags: TunFlags) -> LinuxResult<()> {
+        let device = self.0.attached()?;
+        let enabled = self.0.enabled.load(Ordering::Acquire);
+        if flags.contains(TunFlags::ATTACH_QUEUE) {
+            if enabled || !device.multi_queue {
+                return Err(LinuxError::EINVAL);
+            }
+            self.0.enabled.store(true, Ordering::Release);
+        } else if flags.contains(TunFlags::DETACH_QUEUE) {
+            if !enabled {
+                return Err(LinuxError::EINVAL);
+            }
+            self.0.enabled.store(false, Ordering::Release);
+            self.0.rx.lock().clear();
+        } else {
+            return Err(LinuxError::EINVAL);
+        }
+        device.update();
+        Ok(())
+    }
+}
+
+/// Adds the interface of a new TUN or TAP device and returns its index. It
+/// is down until user space brings it up.
+fn create_interface(config: &mut NetConfig, name: &str, tap: bool) -> u32 {
+    let index = if tap {
+        config.add_interface(
+            name,
+            ARPHRD_ETHER,
+            // A locally administered address made from the index.
+            [0x02, 0x00, 0x00, 0x00, 0x00, 0x00],
+            InterfaceFlags::BROADCAST | InterfaceFlags::MULTICAST,
+            DEFAULT_MTU,
+        )
+    } else {
+        config.add_interface(
+            name,
+            ARPHRD_NONE,
+            [0; ETH_ALEN],
+            InterfaceFlags::POINTOPOINT | InterfaceFlags::NOARP | InterfaceFlags::MULTICAST,
+            DEFAULT_MTU,
+        )
+    };
+    if let Ok(iface) = config.interface_mut(index) {
+        if tap {
+            iface.hw_addr[4..].copy_from_slice(&(index as u16).to_be_bytes());
+        }
+        iface.kind = Some("tun");
+    }
+    index
+}
+
+impl DeviceOps for TunQueue {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let device = self.0.attached()?;
+        let no_pi = device.no_pi.load(Ordering::Acquire);
+        if !no_pi && buf.len() < TUN_PI_LEN {
+            return Err(LinuxError::EINVAL);
+        }
+        let packet = self.0.rx.lock().pop_front().ok_or(LinuxError::EAGAIN)?;
+        if no_pi {
+            let len = packet.len().min(buf.len());
+            buf[..len].copy_from_slice(&packet[..len]);
+            return Ok(len);
+        }
+
+        let (buf, data) = buf.split_at_mut(TUN_PI_LEN);
+        let len = packet.len().min(data.len());
+        data[..len].copy_from_slice(&packet[..len]);
+        let protocol = if device.tap {
+            u16::from_be_bytes([packet[12], packet[13]])
+        } else if packet.first().is_some_and(|it| it >> 4 == 6) {
+            ETH_P_IPV6
+        } else {
+            ETH_P_IP
+        };
+        let flags = if len < packet.len() { TUN_PKT_STRIP } else { 0 };
+        buf[..2].copy_from_slice(&flags.to_ne_bytes());
+        buf[2..].copy_from_slice(&protocol.to_be_bytes());
+        Ok(TUN_PI_LEN + len)
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        let device = self.0.attached()?;
+        let data = if device.no_pi.load(Ordering::Acquire) {
+            buf
+        } else {
+            buf.get(TUN_PI_LEN..).ok_or(LinuxError::EINVAL)?
+        };
+        let ns = device.ns()?;
+        let (flags, hw_addr) = {
+            let config = ns.config();
+            let iface = config.interface(device.ifindex)?;
+            (iface.flags, iface.hw_addr)
+        };
+        if !flags.contains(InterfaceFlags::UP) {
+            return Err(LinuxError::EIO);
+        }
+        if device.tap {
+            if data.len() < ETH_HEADER_LEN {
+                return Err(LinuxError::EINVAL);
+            }
+            device.receive_frame(&ns, hw_addr, data);
+        } else {
+            if data.is_empty() {
+                return Err(LinuxError::EINVAL);
+            }
+            tap_ip(&ns, device.ifindex, data);
+            super::raw::input(&ns, device.ifindex, data);
+        }
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            TUNSETIFF => {
+                let mut req = (arg as *const IfReq).vm_read()?;
+                self.set_iff(&mut req)?;
+                (arg as *mut IfReq).vm_write(req)?;
+            }
+            TUNGETIFF => {
+                let device = self.0.attached()?;
+                let mut req = (arg as *const IfReq).vm_read()?;
+                let name = device
+                    .ns()?
+                    .config()
+                    .interface(device.ifindex)?
+                    .name
+                    .clone();
+                req.set_name(&name);
+                req.set_short(device.flags().bits() as i16);
+                (arg as *mut IfReq).vm_write(req)?;
+            }
+            TUNSETPERSIST => {
+                let device = self.0.attached()?;
+                device.persist.store(arg != 0, Ordering::Release);
+            }
+            TUNGETFEATURES => {
+                (arg as *mut u32).vm_write(TunFlags::FEATURES.bits() as u32)?;
+            }
+            TUNSETQUEUE => {
+                let req = (arg as *const IfReq).vm_read()?;
+                self.set_queue(TunFlags::from_bits_truncate(req.short() as u16))?;
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Drop for TunQueue {
+    fn drop(&mut self) {
+        let device = self.0.device.lock().take();
+        if let Some(device) = device {
+            device
+                .queues
+                .lock()
+                .retain(|it| it.as_ptr() != Arc::as_ptr(&self.0));
+            device.update();
+        }
+    }
+}
+
+impl Pollable for TunQueue {
+    fn poll(&self) -> IoEvents {
+        if self.0.device.lock().is_none() {
+            return IoEvents::ERR;
+        }
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.0.rx.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.0.poll_rx.register(context.waker());
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Virtual Ethernet pairs, `veth`.
+//!
+//! The two interfaces of a pair are connected back to back, and usually put
+//! in different namespaces: a frame sent out of one comes in on the other.
+//! There is no ARP, IP packets are sent straight to the link-layer address of
+//! the peer. The carrier of both interfaces is always on, and frames are
+//! dropped while either of them is down.
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+
+use super::{
+    iface::{ARPHRD_ETHER, IFNAMSIZ, InterfaceFlags},
+    ns::NetNamespace,
+    packet::{ETH_ALEN, ETH_HEADER_LEN, ETH_P_IP, ETH_P_IPV6, tap},
+    tun::expand_name,
+};
+
+/// Default MTU of the interfaces.
+const DEFAULT_MTU: u32 = 1500;
+
+/// One interface of a pair.
+struct End {
+    ns: Weak<NetNamespace>,
+    ifindex: u32,
+}
+
+impl End {
+    fn is(&self, ns: *const NetNamespace, ifindex: u32) -> bool {
+        self.ns.as_ptr() == ns && self.ifindex == ifindex
+    }
+}
+
+/// Pairs in all namespaces.
+static PAIRS: Mutex<Vec<[End; 2]>> = Mutex::new(Vec::new());
+/// Number of the next link-layer address handed out.
+static NEXT_HW_ADDR: AtomicU32 = AtomicU32::new(1);
+
+/// Returns the namespace and index of the peer of interface `ifindex` of
+/// namespace `ns`, if that is a `veth` interface.
+fn peer(ns: &Arc<NetNamespace>, ifindex: u32) -> Option<(Arc<NetNamespace>, u32)> {
+    let pairs = PAIRS.lock();
+    let peer = pairs.iter().find_map(|[a, b]| {
+        if a.is(Arc::as_ptr(ns), ifindex) {
+            Some(b)
+        } else if b.is(Arc::as_ptr(ns), ifindex) {
+            Some(a)
+        } else {
+            None
+        }
+    })?;
+    Some((peer.ns.upgrade()?, peer.ifindex))
+}
+
+/// Adds one interface of a new pair to namespace `ns`.
+fn add_interface(ns: &NetNamespace, name: &str) -> LinuxResult<u32> {
+    let mut config = ns.config();
+    let name = expand_name(&config, name)?;
+    if name.is_empty() || name.len() >= IFNAMSIZ {
+        return Err(LinuxError::EINVAL);
+    }
+    if config.interface_by_name(&name).is_ok() {
+        return Err(LinuxError::EEXIST);
+    }
+    let n = NEXT_HW_ADDR.fetch_add(1, Ordering::Relaxed);
+    // A locally administered address.
+    let mut hw_addr = [0x02, 0x01, 0, 0, 0, 0];
+    hw_addr[2..].copy_from_slice(&n.to_be_bytes());
+    let index = config.add_interface(
+        &name,
+        ARPHRD_ETHER,
+        hw_addr,
+        InterfaceFlags::BROADCAST
+            | InterfaceFlags::MULTICAST
+            | InterfaceFlags::RUNNING
+            | InterfaceFlags::LOWER_UP,
+        DEFAULT_MTU,
+    );
+    config.interface_mut(index)?.kind = Some("veth");
+    Ok(index)
+}
+
+/// Sets the peer index of interface `ifindex` of namespace `ns`, reported as
+/// `IFLA_LINK`.
+fn set_link(ns: &NetNamespace, ifindex: u32, link: u32) {
+    if let Ok(iface) = ns.config().interface_mut(ifindex) {
+        iface.link = link;
+    }
+}
+
+/// Creates a pair of interface `name` in namespace `ns` and interface
+/// `peer_name` in namespace `peer_ns`, and returns the index of the former.
+/// Names may contain `%d`.
+pub fn create(
+    ns: &Arc<NetNamespace>,
+    name: &str,
+    peer_ns: &Arc<NetNamespace>,
+    peer_name: &str,
+) -> LinuxResult<u32> {
+    let index = add_interface(ns, name)?;
+    let peer_index = match add_interface(peer_ns, peer_name) {
+        Ok(index) => index,
+        Err(err) => {
+            let _ = ns.config().remove_interface(index);
+            return Err(err);
+        }
+    };
+    set_link(ns, index, peer_index);
+    set_link(peer_ns, peer_index, index);
+    PAIRS.lock().push([
+        End {
+            ns: Arc::downgrade(ns),
+            ifindex: index,
+        },
+        End {
+            ns: Arc::downgrade(peer_ns),
+            ifindex: peer_index,
+        },
+    ]);
+    debug!("veth: created pair {} <-> {}", index, peer_index);
+    Ok(index)
+}
+
+/// Deletes the pair interface `ifindex` of namespace `ns` belongs to.
+pub fn delete(ns: &Arc<NetNamespace>, ifindex: u32) -> LinuxResult<()> {
+    let pair = {
+        let mut pairs = PAIRS.lock();
+        let pos = pairs
+            .iter()
+            .position(|it| it.iter().any(|end| end.is(Arc::as_ptr(ns), ifindex)))
+            .ok_or(LinuxError::EOPNOTSUPP)?;
+        pairs.remove(pos)
+    };
+    for end in pair {
+        if let Some(ns) = end.ns.upgrade() {
+            let _ = ns.config().remove_interface(end.ifindex);
+        }
+    }
+    Ok(())
+}
+
+/// Moves interface `ifindex` of namespace `from` to namespace `to`, and
+/// returns its new index. Only `veth` interfaces can be moved.
+pub fn move_link(
+    from: &Arc<NetNamespace>,
+    ifindex: u32,
+    to: &Arc<NetNamespace>,
+) -> LinuxResult<u32> {
+    if Arc::ptr_eq(from, to) {
+        return Ok(ifindex);
+    }
+    let mut pairs = PAIRS.lock();
+    let Some((end, peer)) = pairs.iter_mut().find_map(|[a, b]| {
+        if a.is(Arc::as_ptr(from), ifindex) {
+            Some((a, b))
+        } else if b.is(Arc::as_ptr(from), ifindex) {
+            Some((b, a))
+        } else {
+            None
+        }
+    }) else {
+        warn!("Only veth interfaces can change namespaces");
+        return Err(LinuxError::EOPNOTSUPP);
+    };
+
+    // Holding `PAIRS` keeps other moves from locking the two the other way
+    // round.
+    let new_index = {
+        let mut to_config = to.config();
+        let mut from_config = from.config();
+        let name = &from_config.interface(ifindex)?.name;
+        if to_config.interface_by_name(name).is_ok() {
+            return Err(LinuxError::EEXIST);
+        }
+        let iface = from_config.remove_interface(ifindex)?;
+        to_config.adopt_interface(iface)?
+    };
+    end.ns = Arc::downgrade(to);
+    end.ifindex = new_index;
+    let peer = (peer.ns.upgrade(), peer.ifindex);
+    drop(pairs);
+
+    if let (Some(peer_ns), peer_index) = peer {
+        set_link(&peer_ns, peer_index, new_index);
+    }
+    debug!("veth: moved interface {} to {}", ifindex, new_index);
+    Ok(new_index)
+}
+
+/// Removes the pairs with an interface in a namespace that goes away,
+/// together with the peers of those interfaces.
+pub fn release_namespace(ns: &NetNamespace) {
+    let peers = {
+        let mut pairs = PAIRS.lock();
+        let (gone, kept): (Vec<_>, _) = pairs
+            .drain(..)
+            .partition(|[a, b]| a.ns.as_ptr() == ns as *const _ || b.ns.as_ptr() == ns as *const _);
+        *pairs = kept;
+        gone.into_iter()
+            .flatten()
+            .filter_map(|end| Some((end.ns.upgrade()?, end.ifindex)))
+            .collect::<Vec<_>>()
+    };
+    // The namespaces may go away here too, with `PAIRS` unlocked.
+    for (ns, ifindex) in peers {
+        let _ = ns.config().remove_interface(ifindex);
+    }
+}
+
+/// Passes a frame sent out of interface `ifindex` of namespace `ns` to its
+/// peer. Returns `false` if that is not a `veth` interface.
+pub fn transmit_frame(ns: &Arc<NetNamespace>, ifindex: u32, frame: &[u8]) -> bool {
+    let Some((peer_ns, peer_index)) = peer(ns, ifindex) else {
+        return false;
+    };
+    let up = ns
+        .config()
+        .interface(ifindex)
+        .is_ok_and(|it| it.is_running());
+    let Ok((peer_up, hw_addr)) = peer_ns
+        .config()
+        .interface(peer_index)
+        .map(|it| (it.is_running(), it.hw_addr))
+    else {
+        return true;
+    };
+    if !up || !peer_up {
+        return true;
+    }
+
+    tap(&peer_ns, peer_index, frame);
+    let dst = &frame[..ETH_ALEN];
+    if dst != hw_addr && dst[0] & 1 == 0 {
+        // Unicast to another host.
+        return true;
+    }
+    if matches!(
+        u16::from_be_bytes([frame[12], frame[13]]),
+        ETH_P_IP | ETH_P_IPV6
+    ) {
+        super::raw::input(&peer_ns, peer_index, &frame[ETH_HEADER_LEN..]);
+    }
+    true
+}
+
+/// Sends an IP packet out of interface `ifindex` of namespace `ns`. Returns
+/// `false` if that is not a `veth` interface.
+pub fn transmit(ns: &Arc<NetNamespace>, ifindex: u32, packet: &[u8]) -> bool {
+    let Some((peer_ns, peer_index)) = peer(ns, ifindex) else {
+        return false;
+    };
+    let Ok(dst) = peer_ns.config().interface(peer_index).map(|it| it.hw_addr) else {
+        return true;
+    };
+    let Ok(src) = ns.config().interface(ifindex).map(|it| it.hw_addr) else {
+        return true;
+    };
+    let protocol = if packet.first().is_some_and(|it| it >> 4 == 6) {
+        ETH_P_IPV6
+    } else {
+        ETH_P_IP
+    };
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + packet.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&protocol.to_be_bytes());
+    frame.extend_from_slice(packet);
+    transmit_frame(ns, ifindex, &frame)
+}
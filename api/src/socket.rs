@@ -9,9 +9,12 @@ use core::{
 
 use axerrno::{LinuxError, LinuxResult};
 use axnet::{SocketAddrEx, unix::UnixSocketAddr};
-use linux_raw_sys::net::{
-    __kernel_sa_family_t, AF_INET, AF_INET6, AF_UNIX, in_addr, in6_addr, sockaddr, sockaddr_in,
-    sockaddr_in6, socklen_t,
+use linux_raw_sys::{
+    net::{
+        __kernel_sa_family_t, AF_INET, AF_INET6, AF_NETLINK, AF_PACKET, AF_UNIX, in_addr, in6_addr,
+        sockaddr, sockaddr_in, sockaddr_in6, socklen_t,
+    },
+    netlink::sockaddr_nl,
 };
 
 use crate::mm::{UserConstPtr, UserPtr};
@@ -107,6 +110,48 @@ impl SocketAddrExt for SocketAddrV4 {
     }
 }
 
+// IPv6 sockets share the IPv4 network stack, which cannot carry IPv6
+// itself. IPv4-mapped addresses are carried as their IPv4 counterparts, as
+// are the unspecified and loopback addresses unless the socket is
+// `IPV6_V6ONLY`, which gives dual-stack sockets. Peers are reported as
+// IPv4-mapped addresses, as Linux does for IPv4 peers of those sockets.
+
+/// Converts the address of an IPv6 socket to the one the network stack uses.
+pub fn ipv6_to_stack(addr: SocketAddrV6, v6only: bool) -> LinuxResult<SocketAddr> {
+    let ip = addr.ip();
+    let v4 = if let Some(v4) = ip.to_ipv4_mapped() {
+        if v6only {
+            return Err(LinuxError::EADDRNOTAVAIL);
+        }
+        v4
+    } else if v6only {
+        return Err(LinuxError::EAFNOSUPPORT);
+    } else if ip.is_unspecified() {
+        Ipv4Addr::UNSPECIFIED
+    } else if ip.is_loopback() {
+        Ipv4Addr::LOCALHOST
+    } else {
+        return Err(LinuxError::EAFNOSUPPORT);
+    };
+    Ok(SocketAddr::V4(SocketAddrV4::new(v4, addr.port())))
+}
+
+/// Converts an address from the network stack to the one an IPv6 socket
+/// reports.
+pub fn ipv6_from_stack(addr: SocketAddr) -> SocketAddrV6 {
+    match addr {
+        SocketAddr::V6(addr) => addr,
+        SocketAddr::V4(addr) => {
+            let ip = if addr.ip().is_unspecified() {
+                Ipv6Addr::UNSPECIFIED
+            } else {
+                addr.ip().to_ipv6_mapped()
+            };
+            SocketAddrV6::new(ip, addr.port(), 0, 0)
+        }
+    }
+}
+
 impl SocketAddrExt for SocketAddrV6 {
     fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<Self> {
         if addrlen != size_of::<sockaddr_in6>() as socklen_t {
@@ -212,6 +257,167 @@ impl SocketAddrExt for SocketAddrEx {
     }
 
     fn family(&self) -> u16 {
-        AF_INET as u16
+        match self {
+            SocketAddrEx::Ip(ip_addr) => ip_addr.family(),
+            SocketAddrEx::Unix(unix_addr) => unix_addr.family(),
+        }
+    }
+}
+
+/// Address of a netlink socket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetlinkAddr {
+    /// Port id, 0 for the kernel.
+    pub pid: u32,
+    /// Multicast groups.
+    pub groups: u32,
+}
+
+impl SocketAddrExt for NetlinkAddr {
+    fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<Self> {
+        if (addrlen as usize) < size_of::<sockaddr_nl>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let addr_nl = addr.cast::<sockaddr_nl>().get_as_ref()?;
+        if addr_nl.nl_family as u32 != AF_NETLINK {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(Self {
+            pid: addr_nl.nl_pid,
+            groups: addr_nl.nl_groups,
+        })
+    }
+
+    fn write_to_user(&self, addr: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> LinuxResult<()> {
+        let addr_nl = sockaddr_nl {
+            nl_family: AF_NETLINK as _,
+            nl_pad: 0,
+            nl_pid: self.pid,
+            nl_groups: self.groups,
+        };
+        fill_addr(addr, addrlen, unsafe { cast_to_slice(&addr_nl) })
+    }
+
+    fn family(&self) -> u16 {
+        AF_NETLINK as u16
+    }
+}
+
+/// A link-layer address of a packet socket, `struct sockaddr_ll`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PacketAddr {
+    /// Ethernet protocol, `ETH_P_*`, in host byte order.
+    pub protocol: u16,
+    pub ifindex: u32,
+    /// Hardware type, `ARPHRD_*`.
+    pub hatype: u16,
+    /// Packet type, `PACKET_*`.
+    pub pkttype: u8,
+    /// Length of the hardware address.
+    pub halen: u8,
+    pub addr: [u8; 8],
+}
+
+impl PacketAddr {
+    /// Offset of `sll_addr`, the shortest address accepted.
+    const ADDR_OFFSET: usize = 12;
+    /// Size of a `struct sockaddr_ll`.
+    const SIZE: usize = 20;
+}
+
+impl SocketAddrExt for PacketAddr {
+    fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<Self> {
+        if (addrlen as usize) < Self::ADDR_OFFSET {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut data = [0; Self::SIZE];
+        let len = (addrlen as usize).min(Self::SIZE);
+        data[..len].copy_from_slice(addr.cast::<u8>().get_as_slice(len)?);
+        if u16::from_ne_bytes([data[0], data[1]]) as u32 != AF_PACKET {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(Self {
+            protocol: u16::from_be_bytes([data[2], data[3]]),
+            ifindex: i32::from_ne_bytes(data[4..8].try_into().unwrap()) as u32,
+            hatype: u16::from_ne_bytes([data[8], data[9]]),
+            pkttype: data[10],
+            halen: data[11],
+            addr: data[12..20].try_into().unwrap(),
+        })
+    }
+
+    fn write_to_user(&self, addr: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> LinuxResult<()> {
+        let mut data = [0; Self::SIZE];
+        data[0..2].copy_from_slice(&(AF_PACKET as u16).to_ne_bytes());
+        data[2..4].copy_from_slice(&self.protocol.to_be_bytes());
+        data[4..8].copy_from_slice(&(self.ifindex as i32).to_ne_bytes());
+        data[8..10].copy_from_slice(&self.hatype.to_ne_bytes());
+        data[10] = self.pkttype;
+        data[11] = self.halen;
+        data[12..20].copy_from_slice(&self.addr);
+        fill_addr(addr, addrlen, &data)
+    }
+
+    fn family(&self) -> u16 {
+        AF_PACKET as u16
+    }
+}
+
+/// A socket address of any family the kernel supports, whether the network
+/// stack handles it or not.
+#[derive(Debug, Clone)]
+pub enum SockAddr {
+    Stack(SocketAddrEx),
+    Netlink(NetlinkAddr),
+    Packet(PacketAddr),
+}
+
+impl SockAddr {
+    /// Returns the netlink address, or `EINVAL` for other families.
+    pub fn into_netlink(self) -> LinuxResult<NetlinkAddr> {
+        match self {
+            SockAddr::Netlink(addr) => Ok(addr),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+
+    /// Returns the link-layer address, or `EINVAL` for other families.
+    pub fn into_packet(self) -> LinuxResult<PacketAddr> {
+        match self {
+            SockAddr::Packet(addr) => Ok(addr),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+}
+
+impl From<SocketAddrEx> for SockAddr {
+    fn from(addr: SocketAddrEx) -> Self {
+        Self::Stack(addr)
+    }
+}
+
+impl SocketAddrExt for SockAddr {
+    fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<Self> {
+        match read_family(addr, addrlen)? as u32 {
+            AF_NETLINK => NetlinkAddr::read_from_user(addr, addrlen).map(Self::Netlink),
+            AF_PACKET => PacketAddr::read_from_user(addr, addrlen).map(Self::Packet),
+            _ => SocketAddrEx::read_from_user(addr, addrlen).map(Self::Stack),
+        }
+    }
+
+    fn write_to_user(&self, addr: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> LinuxResult<()> {
+        match self {
+            SockAddr::Stack(addr_ex) => addr_ex.write_to_user(addr, addrlen),
+            SockAddr::Netlink(addr_nl) => addr_nl.write_to_user(addr, addrlen),
+            SockAddr::Packet(addr_ll) => addr_ll.write_to_user(addr, addrlen),
+        }
+    }
+
+    fn family(&self) -> u16 {
+        match self {
+            SockAddr::Stack(addr) => addr.family(),
+            SockAddr::Netlink(addr) => addr.family(),
+            SockAddr::Packet(addr) => addr.family(),
+        }
     }
 }
@@ -0,0 +1,599 @@
+//! Linux native AIO.
+//!
+//! Every context owns a completion ring mapped into the address space of the
+//! process, whose address is the `aio_context_t` handed to user space. Each
+//! submitted request is executed by its own worker task, which posts the
+//! result into the ring where `io_getevents` (or libaio, reading the ring
+//! directly) reaps it.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::{paging::MappingFlags, time::TimeValue};
+use axio::{IoEvents, PollSet, Pollable};
+use axmm::backend::Backend;
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use bytemuck::AnyBitPattern;
+use linux_raw_sys::general::timespec;
+use memory_addr::{VirtAddr, VirtAddrRange};
+use starry_core::task::AsThread;
+use starry_process::Process;
+use starry_signal::SignalSet;
+use starry_vm::{VmBytes, VmBytesMut};
+
+use crate::{
+    file::{File, FileLike, event::EventFd, get_file_like},
+    io::{IoVec, IoVectorBuf},
+    mm::{SharedRegion, UserConstPtr, UserPtr, nullable},
+    signal::with_replacen_blocked,
+    syscall::{io_mpx::SignalSetWithSize, signal::check_sigset_size},
+    task::spawn_io_worker,
+    time::TimeValueLike,
+};
+
+const AIO_RING_MAGIC: u32 = 0xa10a10a1;
+const AIO_RING_COMPAT_FEATURES: u32 = 1;
+const AIO_RING_INCOMPAT_FEATURES: u32 = 0;
+
+// Layout of `struct aio_ring`.
+const RING_NR: usize = 4;
+const RING_HEAD: usize = 8;
+const RING_TAIL: usize = 12;
+const RING_MAGIC: usize = 16;
+const RING_COMPAT_FEATURES: usize = 20;
+const RING_INCOMPAT_FEATURES: usize = 24;
+const RING_HEADER_LENGTH: usize = 28;
+const RING_EVENTS: usize = 32;
+
+const IOCB_CMD_PREAD: u16 = 0;
+const IOCB_CMD_PWRITE: u16 = 1;
+const IOCB_CMD_FSYNC: u16 = 2;
+const IOCB_CMD_FDSYNC: u16 = 3;
+const IOCB_CMD_POLL: u16 = 5;
+const IOCB_CMD_PREADV: u16 = 7;
+const IOCB_CMD_PWRITEV: u16 = 8;
+
+const IOCB_FLAG_RESFD: u32 = 1 << 0;
+const IOCB_FLAG_IOPRIO: u32 = 1 << 1;
+
+/// Upper bound of the events of a single context.
+const AIO_MAX_EVENTS: u32 = 0x1000_0000 / size_of::<IoEvent>() as u32;
+/// Upper bound of the events of all contexts, see `/proc/sys/fs/aio-max-nr`.
+const AIO_MAX_NR: u32 = 0x10000;
+
+/// Number of events reserved by all contexts.
+static AIO_NR: AtomicU32 = AtomicU32::new(0);
+
+/// `struct iocb`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct Iocb {
+    pub aio_data: u64,
+    pub aio_key: u32,
+    pub aio_rw_flags: i32,
+    pub aio_lio_opcode: u16,
+    pub aio_reqprio: i16,
+    pub aio_fildes: u32,
+    pub aio_buf: u64,
+    pub aio_nbytes: u64,
+    pub aio_offset: i64,
+    pub aio_reserved2: u64,
+    pub aio_flags: u32,
+    pub aio_resfd: u32,
+}
+
+/// `struct io_event`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoEvent {
+    pub data: u64,
+    pub obj: u64,
+    pub res: i64,
+    pub res2: i64,
+}
+
+struct Request {
+    iocb: Iocb,
+    /// User address of the iocb, reported back in [`IoEvent::obj`].
+    obj: u64,
+    file: Arc<dyn FileLike>,
+    eventfd: Option<Arc<EventFd>>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// An AIO context created by `io_setup`.
+struct AioContext {
+    ring: SharedRegion,
+    /// Address of the ring in user space.
+    addr: VirtAddr,
+    /// Number of slots in the ring.
+    nr: u32,
+    /// Number of events reserved by `io_setup`.
+    nr_events: u32,
+    /// Next slot to post into. The copy in the ring is only published for
+    /// user space, which may overwrite it.
+    tail: AtomicU32,
+    /// Requests being executed, keyed by the user address of their iocb.
+    running: Mutex<Vec<(u64, Arc<AtomicBool>)>>,
+    dead: AtomicBool,
+    /// Woken when an event is posted, a request is cancelled or the context
+    /// is destroyed.
+    event: PollSet,
+    proc: Arc<Process>,
+    exit_event: Arc<PollSet>,
+}
+
+impl AioContext {
+    fn new(nr_events: u32) -> LinuxResult<Self> {
+        AIO_NR
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |nr| {
+                nr.checked_add(nr_events).filter(|&nr| nr <= AIO_MAX_NR)
+            })
+            .map_err(|_| LinuxError::EAGAIN)?;
+        // One slot is always left empty to tell a full ring from an empty
+        // one.
+        let size = RING_EVENTS + (nr_events as usize + 1) * size_of::<IoEvent>();
+        let ring = SharedRegion::new(size).inspect_err(|_| {
+            AIO_NR.fetch_sub(nr_events, Ordering::AcqRel);
+        })?;
+        let nr = ((ring.size() - RING_EVENTS) / size_of::<IoEvent>()) as u32;
+        ring.write(RING_NR, nr);
+        ring.write(RING_MAGIC, AIO_RING_MAGIC);
+        ring.write(RING_COMPAT_FEATURES, AIO_RING_COMPAT_FEATURES);
+        ring.write(RING_INCOMPAT_FEATURES, AIO_RING_INCOMPAT_FEATURES);
+        ring.write(RING_HEADER_LENGTH, RING_EVENTS as u32);
+
+        let proc_data = &current().as_thread().proc_data;
+        Ok(Self {
+            ring,
+            addr: VirtAddr::from(0),
+            nr,
+            nr_events,
+            tail: AtomicU32::new(0),
+            running: Mutex::new(Vec::new()),
+            dead: AtomicBool::new(false),
+            event: PollSet::new(),
+            proc: proc_data.proc.clone(),
+            exit_event: proc_data.exit_event.clone(),
+        })
+    }
+
+    /// Number of events in the ring not reaped yet.
+    fn ready(&self) -> u32 {
+        // The head is written by user space, don't trust it.
+        let head = self.ring.word(RING_HEAD).load(Ordering::Acquire) % self.nr;
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + self.nr - head) % self.nr
+    }
+
+    /// Reserves a slot in the ring for the request at `obj`.
+    fn reserve(&self, obj: u64) -> LinuxResult<Arc<AtomicBool>> {
+        let mut running = self.running.lock();
+        if self.dead.load(Ordering::Acquire) {
+            return Err(LinuxError::EINVAL);
+        }
+        if running.len() as u32 + self.ready() >= self.nr - 1 {
+            return Err(LinuxError::EAGAIN);
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        running.push((obj, cancel.clone()));
+        Ok(cancel)
+    }
+
+    /// Posts the completion of `req` into the ring.
+    fn post(&self, req: &Request, res: i64) {
+        {
+            let mut running = self.running.lock();
+            running.retain(|(_, cancel)| !Arc::ptr_eq(cancel, &req.cancel));
+            let tail = self.tail.load(Ordering::Acquire);
+            self.ring.write(
+                RING_EVENTS + tail as usize * size_of::<IoEvent>(),
+                IoEvent {
+                    data: req.iocb.aio_data,
+                    obj: req.obj,
+                    res,
+                    res2: 0,
+                },
+            );
+            let tail = (tail + 1) % self.nr;
+            self.tail.store(tail, Ordering::Release);
+            self.ring.word(RING_TAIL).store(tail, Ordering::Release);
+        }
+        self.event.wake();
+        if let Some(eventfd) = &req.eventfd {
+            let _ = eventfd.write(&mut 1u64.to_ne_bytes().as_slice().into());
+        }
+    }
+
+    /// Takes up to `max` events out of the ring.
+    fn reap(&self, max: usize) -> Vec<IoEvent> {
+        let _running = self.running.lock();
+        let mut head = self.ring.word(RING_HEAD).load(Ordering::Acquire) % self.nr;
+        let count = (self.ready() as usize).min(max);
+        let mut events = Vec::with_capacity(count);
+        for _ in 0..count {
+            events.push(
+                self.ring
+                    .read(RING_EVENTS + head as usize * size_of::<IoEvent>()),
+            );
+            head = (head + 1) % self.nr;
+        }
+        self.ring.word(RING_HEAD).store(head, Ordering::Release);
+        events
+    }
+
+    /// Asks the request at `obj` to stop, returns whether it was found.
+    fn cancel(&self, obj: u64) -> bool {
+        let running = self.running.lock();
+        let Some((_, cancel)) = running.iter().find(|(it, _)| *it == obj) else {
+            return false;
+        };
+        cancel.store(true, Ordering::Release);
+        self.event.wake();
+        true
+    }
+
+    /// Fails with `ECANCELED` once the request should stop waiting.
+    fn check_live(&self, cancel: &AtomicBool) -> LinuxResult<()> {
+        if self.dead.load(Ordering::Acquire)
+            || self.proc.is_zombie()
+            || cancel.load(Ordering::Acquire)
+        {
+            return Err(LinuxError::ECANCELED);
+        }
+        Ok(())
+    }
+
+    /// Waits until `file` reports any of `events`.
+    fn wait_ready(
+        &self,
+        file: &dyn FileLike,
+        events: IoEvents,
+        cancel: &AtomicBool,
+    ) -> LinuxResult<IoEvents> {
+        let events = events | IoEvents::ALWAYS_POLL;
+        Poller::new(&AioWaiter::new(self, Some(file)), events).poll(|| {
+            self.check_live(cancel)?;
+            let ready = file.poll() & events;
+            if ready.is_empty() {
+                Err(LinuxError::EAGAIN)
+            } else {
+                Ok(ready)
+            }
+        })
+    }
+
+    /// Waits until at least `min` events are available and reaps up to `max`
+    /// of them.
+    fn getevents(
+        &self,
+        min: usize,
+        max: usize,
+        timeout: Option<TimeValue>,
+    ) -> LinuxResult<Vec<IoEvent>> {
+        let result = Poller::new(&AioWaiter::new(self, None), IoEvents::IN)
+            .timeout(timeout)
+            .poll(|| {
+                if self.dead.load(Ordering::Acquire) || self.ready() as usize >= min {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            });
+        match result {
+            Ok(()) | Err(LinuxError::ETIMEDOUT) => {}
+            Err(LinuxError::EINTR) if self.ready() > 0 => {}
+            Err(err) => return Err(err),
+        }
+        Ok(self.reap(max))
+    }
+
+    /// Cancels all requests and waits for them to finish.
+    fn destroy(&self) {
+        self.dead.store(true, Ordering::Release);
+        self.event.wake();
+        let _ = Poller::new(&AioWaiter::new(self, None), IoEvents::IN).poll(|| {
+            if self.running.lock().is_empty() {
+                Ok(())
+            } else {
+                Err(LinuxError::EAGAIN)
+            }
+        });
+    }
+}
+
+impl Drop for AioContext {
+    fn drop(&mut self) {
+        AIO_NR.fetch_sub(self.nr_events, Ordering::AcqRel);
+    }
+}
+
+/// Wakes a waiter on `file` readiness, new events, cancellation, context
+/// destruction or process exit.
+struct AioWaiter<'a> {
+    ctx: &'a AioContext,
+    file: Option<&'a dyn FileLike>,
+}
+
+impl<'a> AioWaiter<'a> {
+    fn new(ctx: &'a AioContext, file: Option<&'a dyn FileLike>) -> Self {
+        Self { ctx, file }
+    }
+}
+
+impl Pollable for AioWaiter<'_> {
+    fn poll(&self) -> IoEvents {
+        unreachable!()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if let Some(file) = self.file {
+            file.register(context, events);
+        }
+        self.ctx.event.register(context.waker());
+        self.ctx.exit_event.register(context.waker());
+    }
+}
+
+scope_local::scope_local! {
+    /// The AIO contexts of the current process, keyed by ring address.
+    static AIO_CONTEXTS: Mutex<BTreeMap<u64, Arc<AioContext>>> = Mutex::new(BTreeMap::new());
+}
+
+fn get_context(ctx_id: u64) -> LinuxResult<Arc<AioContext>> {
+    AIO_CONTEXTS
+        .lock()
+        .get(&ctx_id)
+        .cloned()
+        .ok_or(LinuxError::EINVAL)
+}
+
+fn execute(ctx: &AioContext, req: &Request) -> LinuxResult<isize> {
+    let iocb = &req.iocb;
+    let file = &*req.file;
+    let regular = req.file.clone().into_any().downcast::<File>().ok();
+    let buf = iocb.aio_buf as usize;
+    let len = iocb.aio_nbytes as usize;
+    let offset = if regular.is_some() {
+        u64::try_from(iocb.aio_offset).map_err(|_| LinuxError::EINVAL)?
+    } else {
+        0
+    };
+
+    let n = match iocb.aio_lio_opcode {
+        IOCB_CMD_PREAD => {
+            ctx.wait_ready(file, IoEvents::IN, &req.cancel)?;
+            let mut dst = VmBytesMut::new(buf as *mut u8, len);
+            match &regular {
+                Some(f) => f.inner().read_at(&mut dst, offset)?,
+                None => file.read(&mut dst.into())?,
+            }
+        }
+        IOCB_CMD_PREADV => {
+            ctx.wait_ready(file, IoEvents::IN, &req.cancel)?;
+            let mut dst = IoVectorBuf::new(buf as *const IoVec, len)?.into_io();
+            match &regular {
+                Some(f) => f.inner().read_at(&mut dst, offset)?,
+                None => file.read(&mut dst.into())?,
+            }
+        }
+        IOCB_CMD_PWRITE => {
+            ctx.wait_ready(file, IoEvents::OUT, &req.cancel)?;
+            let src = VmBytes::new(buf as *const u8, len);
+            match &regular {
+                Some(f) => f.write_at(&mut src.into(), offset)?,
+                None => file.write(&mut src.into())?,
+            }
+        }
+        IOCB_CMD_PWRITEV => {
+            ctx.wait_ready(file, IoEvents::OUT, &req.cancel)?;
+            let src = IoVectorBuf::new(buf as *const IoVec, len)?.into_io();
+            match &regular {
+                Some(f) => f.write_at(&mut src.into(), offset)?,
+                None => file.write(&mut src.into())?,
+            }
+        }
+        IOCB_CMD_FSYNC | IOCB_CMD_FDSYNC => {
+            let f = regular.ok_or(LinuxError::EINVAL)?;
+            f.sync(iocb.aio_lio_opcode == IOCB_CMD_FDSYNC)?;
+            0
+        }
+        IOCB_CMD_POLL => {
+            let events = IoEvents::from_bits_truncate(iocb.aio_buf as u16 as u32);
+            ctx.wait_ready(file, events, &req.cancel)?.bits() as usize
+        }
+        _ => return Err(LinuxError::EINVAL),
+    };
+    Ok(n as isize)
+}
+
+fn worker(ctx: Arc<AioContext>, req: Request) {
+    let res = match execute(&ctx, &req) {
+        Ok(n) => n as i64,
+        Err(err) => -(err.code() as i64),
+    };
+    ctx.post(&req, res);
+}
+
+fn submit_one(ctx: &Arc<AioContext>, obj: u64) -> LinuxResult<()> {
+    let iocb = *UserConstPtr::<Iocb>::from(obj as usize).get_as_ref()?;
+    if iocb.aio_reserved2 != 0
+        || iocb.aio_flags & !(IOCB_FLAG_RESFD | IOCB_FLAG_IOPRIO) != 0
+        || isize::try_from(iocb.aio_nbytes).is_err()
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    if iocb.aio_rw_flags != 0 {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+
+    let file = get_file_like(iocb.aio_fildes as _)?;
+    match iocb.aio_lio_opcode {
+        IOCB_CMD_PREAD | IOCB_CMD_PREADV | IOCB_CMD_PWRITE | IOCB_CMD_PWRITEV | IOCB_CMD_POLL => {}
+        IOCB_CMD_FSYNC | IOCB_CMD_FDSYNC if File::from_fd(iocb.aio_fildes as _).is_ok() => {}
+        _ => return Err(LinuxError::EINVAL),
+    }
+    let eventfd = if iocb.aio_flags & IOCB_FLAG_RESFD != 0 {
+        Some(EventFd::from_fd(iocb.aio_resfd as _)?)
+    } else {
+        None
+    };
+
+    let req = Request {
+        iocb,
+        obj,
+        file,
+        eventfd,
+        cancel: ctx.reserve(obj)?,
+    };
+    let ctx = ctx.clone();
+    spawn_io_worker("aio-wrk", move || worker(ctx, req));
+    Ok(())
+}
+
+pub fn sys_io_setup(nr_events: u32, ctxp: UserPtr<u64>) -> LinuxResult<isize> {
+    debug!("sys_io_setup <= nr_events: {}", nr_events);
+    let ctxp = ctxp.get_as_mut()?;
+    if *ctxp != 0 || nr_events == 0 || nr_events > AIO_MAX_EVENTS {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let mut ctx = AioContext::new(nr_events)?;
+    let size = ctx.ring.size();
+    let curr = current();
+    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let start = aspace
+        .find_free_area(
+            aspace.base(),
+            size,
+            VirtAddrRange::new(aspace.base(), aspace.end()),
+        )
+        .ok_or(LinuxError::ENOMEM)?;
+    let backend = Backend::new_shared(start, ctx.ring.pages().clone());
+    aspace.map(
+        start,
+        size,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+        true,
+        backend,
+    )?;
+    drop(aspace);
+    ctx.addr = start;
+
+    let ctx_id = start.as_usize() as u64;
+    AIO_CONTEXTS.lock().insert(ctx_id, Arc::new(ctx));
+    *ctxp = ctx_id;
+    Ok(0)
+}
+
+pub fn sys_io_destroy(ctx_id: u64) -> LinuxResult<isize> {
+    debug!("sys_io_destroy <= ctx: {:#x}", ctx_id);
+    let ctx = AIO_CONTEXTS
+        .lock()
+        .remove(&ctx_id)
+        .ok_or(LinuxError::EINVAL)?;
+    ctx.destroy();
+    current()
+        .as_thread()
+        .proc_data
+        .aspace
+        .lock()
+        .unmap(ctx.addr, ctx.ring.size())?;
+    Ok(0)
+}
+
+pub fn sys_io_submit(ctx_id: u64, nr: isize, iocbpp: UserConstPtr<u64>) -> LinuxResult<isize> {
+    debug!("sys_io_submit <= ctx: {:#x}, nr: {}", ctx_id, nr);
+    if nr < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let ctx = get_context(ctx_id)?;
+    let nr = (nr as usize).min(ctx.nr_events as usize);
+    let iocbs = iocbpp.get_as_slice(nr)?;
+
+    let mut submitted = 0;
+    for &obj in iocbs {
+        match submit_one(&ctx, obj) {
+            Ok(()) => submitted += 1,
+            Err(err) if submitted == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(submitted)
+}
+
+pub fn sys_io_cancel(ctx_id: u64, iocb: u64, _result: UserPtr<IoEvent>) -> LinuxResult<isize> {
+    debug!("sys_io_cancel <= ctx: {:#x}, iocb: {:#x}", ctx_id, iocb);
+    let ctx = get_context(ctx_id)?;
+    if ctx.cancel(iocb) {
+        // The request completes with `ECANCELED` through the ring.
+        Err(LinuxError::EINPROGRESS)
+    } else {
+        Err(LinuxError::EINVAL)
+    }
+}
+
+fn do_getevents(
+    ctx_id: u64,
+    min_nr: isize,
+    nr: isize,
+    events: UserPtr<IoEvent>,
+    timeout: UserConstPtr<timespec>,
+    sigmask: Option<SignalSet>,
+) -> LinuxResult<isize> {
+    if min_nr < 0 || nr < 0 || min_nr > nr {
+        return Err(LinuxError::EINVAL);
+    }
+    let ctx = get_context(ctx_id)?;
+    let timeout = nullable!(timeout.get_as_ref())?
+        .map(|ts| ts.try_into_time_value())
+        .transpose()?;
+    let out = events.get_as_mut_slice(nr as usize)?;
+
+    let reaped = with_replacen_blocked(sigmask, || {
+        ctx.getevents(min_nr as usize, nr as usize, timeout)
+    })?;
+    out[..reaped.len()].copy_from_slice(&reaped);
+    Ok(reaped.len() as _)
+}
+
+pub fn sys_io_getevents(
+    ctx_id: u64,
+    min_nr: isize,
+    nr: isize,
+    events: UserPtr<IoEvent>,
+    timeout: UserConstPtr<timespec>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_io_getevents <= ctx: {:#x}, min_nr: {}, nr: {}",
+        ctx_id, min_nr, nr
+    );
+    do_getevents(ctx_id, min_nr, nr, events, timeout, None)
+}
+
+pub fn sys_io_pgetevents(
+    ctx_id: u64,
+    min_nr: isize,
+    nr: isize,
+    events: UserPtr<IoEvent>,
+    timeout: UserConstPtr<timespec>,
+    usig: UserConstPtr<SignalSetWithSize>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_io_pgetevents <= ctx: {:#x}, min_nr: {}, nr: {}",
+        ctx_id, min_nr, nr
+    );
+    let sigmask = if let Some(usig) = nullable!(usig.get_as_ref())? {
+        check_sigset_size(usig.sigsetsize)?;
+        let set = usig.set;
+        nullable!(set.get_as_ref())?.copied()
+    } else {
+        None
+    };
+    do_getevents(ctx_id, min_nr, nr, events, timeout, sigmask)
+}
@@ -0,0 +1,559 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{ffi::c_char, sync::atomic::AtomicBool};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::monotonic_time;
+use axio::IoEvents;
+use bytemuck::AnyBitPattern;
+use linux_raw_sys::general::__kernel_timespec;
+use starry_signal::SignalSet;
+use starry_vm::{VmMutPtr, VmPtr};
+
+use crate::{
+    file::{
+        File, FileLike,
+        event::EventFd,
+        get_file_like,
+        io_uring::{
+            IORING_MAX_CQ_ENTRIES, IORING_MAX_ENTRIES, IoUring, IoUringParams, IoUringSqe, Ring,
+            SetupFlags, SqeFlags,
+        },
+    },
+    io::IoVec,
+    mm::{UserConstPtr, UserPtr, nullable},
+    signal::with_replacen_blocked,
+    syscall::{
+        fs::{
+            sys_close, sys_fdatasync, sys_fsync, sys_openat, sys_pread64, sys_preadv2,
+            sys_pwrite64, sys_pwritev2, sys_read, sys_write,
+        },
+        net::{sys_accept4, sys_connect, sys_recvfrom, sys_recvmsg, sys_sendmsg, sys_sendto},
+        signal::check_sigset_size,
+    },
+    task::spawn_io_worker,
+    time::TimeValueLike,
+};
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_READV: u8 = 1;
+const IORING_OP_WRITEV: u8 = 2;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ_FIXED: u8 = 4;
+const IORING_OP_WRITE_FIXED: u8 = 5;
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_POLL_REMOVE: u8 = 7;
+const IORING_OP_SENDMSG: u8 = 9;
+const IORING_OP_RECVMSG: u8 = 10;
+const IORING_OP_TIMEOUT: u8 = 11;
+const IORING_OP_TIMEOUT_REMOVE: u8 = 12;
+const IORING_OP_ACCEPT: u8 = 13;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+const IORING_OP_CONNECT: u8 = 16;
+const IORING_OP_OPENAT: u8 = 18;
+const IORING_OP_CLOSE: u8 = 19;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_OP_SEND: u8 = 26;
+const IORING_OP_RECV: u8 = 27;
+
+/// Opcodes executed by the workers, reported by `IORING_REGISTER_PROBE`.
+const SUPPORTED_OPS: &[u8] = &[
+    IORING_OP_NOP,
+    IORING_OP_READV,
+    IORING_OP_WRITEV,
+    IORING_OP_FSYNC,
+    IORING_OP_READ_FIXED,
+    IORING_OP_WRITE_FIXED,
+    IORING_OP_POLL_ADD,
+    IORING_OP_POLL_REMOVE,
+    IORING_OP_SENDMSG,
+    IORING_OP_RECVMSG,
+    IORING_OP_TIMEOUT,
+    IORING_OP_TIMEOUT_REMOVE,
+    IORING_OP_ACCEPT,
+    IORING_OP_ASYNC_CANCEL,
+    IORING_OP_CONNECT,
+    IORING_OP_OPENAT,
+    IORING_OP_CLOSE,
+    IORING_OP_READ,
+    IORING_OP_WRITE,
+    IORING_OP_SEND,
+    IORING_OP_RECV,
+];
+/// `IORING_OP_LAST`, one past the highest opcode of the ABI we follow.
+const IORING_OP_LAST: u8 = 58;
+
+const IORING_FSYNC_DATASYNC: u32 = 1 << 0;
+const IORING_TIMEOUT_ABS: u32 = 1 << 0;
+const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+
+const IORING_FEAT_NODROP: u32 = 1 << 1;
+const IORING_FEAT_SUBMIT_STABLE: u32 = 1 << 2;
+const IORING_FEAT_RW_CUR_POS: u32 = 1 << 3;
+const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+const IORING_FEAT_NATIVE_WORKERS: u32 = 1 << 9;
+const IORING_FEAT_CQE_SKIP: u32 = 1 << 11;
+
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+const IORING_ENTER_SQ_WAKEUP: u32 = 1 << 1;
+const IORING_ENTER_SQ_WAIT: u32 = 1 << 2;
+const IORING_ENTER_EXT_ARG: u32 = 1 << 3;
+
+const IORING_REGISTER_BUFFERS: u32 = 0;
+const IORING_UNREGISTER_BUFFERS: u32 = 1;
+const IORING_REGISTER_EVENTFD: u32 = 4;
+const IORING_UNREGISTER_EVENTFD: u32 = 5;
+const IORING_REGISTER_EVENTFD_ASYNC: u32 = 7;
+const IORING_REGISTER_PROBE: u32 = 8;
+const IORING_REGISTER_ENABLE_RINGS: u32 = 12;
+
+const IO_URING_OP_SUPPORTED: u16 = 1 << 0;
+
+/// `struct io_uring_getevents_arg`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    min_wait_usec: u32,
+    ts: u64,
+}
+
+/// `struct io_uring_probe` without the trailing ops.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ProbeHeader {
+    last_op: u8,
+    ops_len: u8,
+    resv: u16,
+    resv2: [u32; 3],
+}
+
+/// `struct io_uring_probe_op`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ProbeOp {
+    op: u8,
+    resv: u8,
+    flags: u16,
+    resv2: u32,
+}
+
+fn ring_from_fd(fd: i32) -> LinuxResult<Arc<Ring>> {
+    get_file_like(fd)?
+        .into_any()
+        .downcast::<IoUring>()
+        .map(|it| it.ring().clone())
+        .map_err(|_| LinuxError::EOPNOTSUPP)
+}
+
+/// Reads from `fd`, at the current position if `off` is `-1` or the file is
+/// not seekable.
+fn do_read(sqe: &IoUringSqe) -> LinuxResult<isize> {
+    let (fd, buf, len) = (sqe.fd, sqe.addr as *mut u8, sqe.len as usize);
+    if sqe.off == u64::MAX || File::from_fd(fd).is_err() {
+        sys_read(fd, buf, len)
+    } else {
+        sys_pread64(fd, buf, len, sqe.off as _)
+    }
+}
+
+fn do_write(sqe: &IoUringSqe) -> LinuxResult<isize> {
+    let (fd, buf, len) = (sqe.fd, sqe.addr as *mut u8, sqe.len as usize);
+    if sqe.off == u64::MAX || File::from_fd(fd).is_err() {
+        sys_write(fd, buf, len)
+    } else {
+        sys_pwrite64(fd, buf.cast_const(), len, sqe.off as _)
+    }
+}
+
+/// Returns the offset of a vectored request, ignoring it for streams.
+fn stream_offset(sqe: &IoUringSqe) -> i64 {
+    if File::from_fd(sqe.fd).is_err() {
+        -1
+    } else {
+        sqe.off as i64
+    }
+}
+
+fn do_connect(ring: &Ring, sqe: &IoUringSqe, cancel: &AtomicBool) -> LinuxResult<isize> {
+    let addr = UserConstPtr::from(sqe.addr as usize);
+    let mut waited = false;
+    loop {
+        match sys_connect(sqe.fd, addr, sqe.off as u32) {
+            Err(LinuxError::EINPROGRESS | LinuxError::EALREADY) => {
+                // Non-blocking socket, wait for the connection to complete.
+                ring.wait_ready(&*get_file_like(sqe.fd)?, IoEvents::OUT, cancel)?;
+                waited = true;
+            }
+            Err(LinuxError::EISCONN) if waited => return Ok(0),
+            result => return result,
+        }
+    }
+}
+
+fn do_timeout(ring: &Ring, sqe: &IoUringSqe, cancel: &AtomicBool) -> LinuxResult<isize> {
+    if sqe.len != 1 {
+        return Err(LinuxError::EINVAL);
+    }
+    // FIXME: AnyBitPattern
+    let ts = unsafe {
+        (sqe.addr as *const __kernel_timespec)
+            .vm_read_uninit()?
+            .assume_init()
+    }
+    .try_into_time_value()?;
+    let timeout = if sqe.op_flags & IORING_TIMEOUT_ABS != 0 {
+        ts.saturating_sub(monotonic_time())
+    } else {
+        ts
+    };
+    ring.wait_timeout(sqe.off, timeout, cancel)?;
+    Ok(0)
+}
+
+/// Executes a single request in the context of a worker.
+fn execute(ring: &Ring, sqe: &IoUringSqe, cancel: &AtomicBool) -> LinuxResult<isize> {
+    let flags = sqe.sqe_flags();
+    if flags.intersects(SqeFlags::FIXED_FILE | SqeFlags::IO_DRAIN | SqeFlags::BUFFER_SELECT) {
+        // Registered files, draining and provided buffers are not supported.
+        return Err(LinuxError::EINVAL);
+    }
+    let fd = sqe.fd;
+    let wait = |events| ring.wait_ready(&*get_file_like(fd)?, events, cancel);
+
+    match sqe.opcode {
+        IORING_OP_NOP => Ok(0),
+        IORING_OP_READ => {
+            wait(IoEvents::IN)?;
+            do_read(sqe)
+        }
+        IORING_OP_WRITE => {
+            wait(IoEvents::OUT)?;
+            do_write(sqe)
+        }
+        IORING_OP_READ_FIXED | IORING_OP_WRITE_FIXED => {
+            ring.check_buffer(sqe.buf_index, sqe.addr as usize, sqe.len as usize)?;
+            if sqe.opcode == IORING_OP_READ_FIXED {
+                wait(IoEvents::IN)?;
+                do_read(sqe)
+            } else {
+                wait(IoEvents::OUT)?;
+                do_write(sqe)
+            }
+        }
+        IORING_OP_READV => {
+            wait(IoEvents::IN)?;
+            sys_preadv2(
+                fd,
+                sqe.addr as *const IoVec,
+                sqe.len as usize,
+                stream_offset(sqe),
+                sqe.op_flags,
+            )
+        }
+        IORING_OP_WRITEV => {
+            wait(IoEvents::OUT)?;
+            sys_pwritev2(
+                fd,
+                sqe.addr as *const IoVec,
+                sqe.len as usize,
+                stream_offset(sqe),
+                sqe.op_flags,
+            )
+        }
+        IORING_OP_FSYNC => {
+            if sqe.op_flags & IORING_FSYNC_DATASYNC != 0 {
+                sys_fdatasync(fd)
+            } else {
+                sys_fsync(fd)
+            }
+        }
+        IORING_OP_POLL_ADD => {
+            if sqe.len & IORING_POLL_ADD_MULTI != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let events = IoEvents::from_bits_truncate(sqe.op_flags);
+            Ok(wait(events)?.bits() as _)
+        }
+        IORING_OP_POLL_REMOVE | IORING_OP_TIMEOUT_REMOVE | IORING_OP_ASYNC_CANCEL => {
+            if ring.cancel(sqe.addr) {
+                Ok(0)
+            } else {
+                Err(LinuxError::ENOENT)
+            }
+        }
+        IORING_OP_TIMEOUT => do_timeout(ring, sqe, cancel),
+        IORING_OP_ACCEPT => {
+            wait(IoEvents::IN)?;
+            sys_accept4(
+                fd,
+                UserPtr::from(sqe.addr as usize),
+                UserPtr::from(sqe.off as usize),
+                sqe.op_flags,
+            )
+        }
+        IORING_OP_CONNECT => do_connect(ring, sqe, cancel),
+        IORING_OP_SEND => {
+            wait(IoEvents::OUT)?;
+            sys_sendto(
+                fd,
+                sqe.addr as *const u8,
+                sqe.len as usize,
+                sqe.op_flags,
+                UserConstPtr::default(),
+                0,
+            )
+        }
+        IORING_OP_RECV => {
+            wait(IoEvents::IN)?;
+            sys_recvfrom(
+                fd,
+                sqe.addr as *mut u8,
+                sqe.len as usize,
+                sqe.op_flags,
+                UserPtr::default(),
+                UserPtr::default(),
+            )
+        }
+        IORING_OP_SENDMSG => {
+            wait(IoEvents::OUT)?;
+            sys_sendmsg(fd, UserConstPtr::from(sqe.addr as usize), sqe.op_flags)
+        }
+        IORING_OP_RECVMSG => {
+            wait(IoEvents::IN)?;
+            sys_recvmsg(fd, UserPtr::from(sqe.addr as usize), sqe.op_flags)
+        }
+        IORING_OP_OPENAT => sys_openat(
+            fd,
+            sqe.addr as *const c_char,
+            sqe.op_flags as _,
+            sqe.len as _,
+        ),
+        IORING_OP_CLOSE => sys_close(fd),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Executes a chain of linked requests. Once a request fails, the rest of
+/// the chain is cancelled unless it was linked with `IOSQE_IO_HARDLINK`.
+fn run_chain(ring: &Ring, chain: Vec<IoUringSqe>) {
+    let mut broken = false;
+    for sqe in &chain {
+        let res = if broken {
+            -LinuxError::ECANCELED.code()
+        } else {
+            let cancel = ring.begin(sqe.user_data);
+            let result = execute(ring, sqe, &cancel);
+            ring.end(&cancel);
+            match result {
+                Ok(n) => n as i32,
+                Err(err) => -err.code(),
+            }
+        };
+        ring.complete(sqe, res);
+        if res < 0 && !sqe.sqe_flags().contains(SqeFlags::IO_HARDLINK) {
+            broken = true;
+        }
+    }
+}
+
+fn worker(ring: Arc<Ring>) {
+    while let Some(chain) = ring.next_chain() {
+        run_chain(&ring, chain);
+        if !ring.worker_idle() {
+            break;
+        }
+    }
+}
+
+pub fn sys_io_uring_setup(entries: u32, params: UserPtr<IoUringParams>) -> LinuxResult<isize> {
+    let params = params.get_as_mut()?;
+    debug!(
+        "sys_io_uring_setup <= entries: {}, flags: {:#x}",
+        entries, params.flags
+    );
+
+    let flags = SetupFlags::from_bits(params.flags).ok_or(LinuxError::EINVAL)?;
+    if params.resv != [0; 3] {
+        return Err(LinuxError::EINVAL);
+    }
+    // Polling modes and alternative ring layouts are not supported.
+    if flags.intersects(
+        SetupFlags::IOPOLL
+            | SetupFlags::SQPOLL
+            | SetupFlags::SQ_AFF
+            | SetupFlags::ATTACH_WQ
+            | SetupFlags::SQE128
+            | SetupFlags::CQE32
+            | SetupFlags::NO_MMAP
+            | SetupFlags::REGISTERED_FD_ONLY
+            | SetupFlags::NO_SQARRAY,
+    ) {
+        return Err(LinuxError::EINVAL);
+    }
+    if flags.contains(SetupFlags::DEFER_TASKRUN) && !flags.contains(SetupFlags::SINGLE_ISSUER) {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let clamp = flags.contains(SetupFlags::CLAMP);
+    if entries == 0 || (entries > IORING_MAX_ENTRIES && !clamp) {
+        return Err(LinuxError::EINVAL);
+    }
+    let sq_entries = entries.min(IORING_MAX_ENTRIES).next_power_of_two();
+    let cq_entries = if flags.contains(SetupFlags::CQSIZE) {
+        let cq_entries = params.cq_entries;
+        if cq_entries == 0 || (cq_entries > IORING_MAX_CQ_ENTRIES && !clamp) {
+            return Err(LinuxError::EINVAL);
+        }
+        let cq_entries = cq_entries.min(IORING_MAX_CQ_ENTRIES).next_power_of_two();
+        if cq_entries < sq_entries {
+            return Err(LinuxError::EINVAL);
+        }
+        cq_entries
+    } else {
+        2 * sq_entries
+    };
+
+    let file = IoUring::new(sq_entries, cq_entries, flags)?;
+    file.ring().fill_params(params);
+    params.features = IORING_FEAT_NODROP
+        | IORING_FEAT_SUBMIT_STABLE
+        | IORING_FEAT_RW_CUR_POS
+        | IORING_FEAT_EXT_ARG
+        | IORING_FEAT_NATIVE_WORKERS
+        | IORING_FEAT_CQE_SKIP;
+    file.add_to_fd_table(true).map(|fd| fd as isize)
+}
+
+pub fn sys_io_uring_enter(
+    fd: i32,
+    to_submit: u32,
+    min_complete: u32,
+    flags: u32,
+    arg: usize,
+    argsz: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_io_uring_enter <= fd: {}, to_submit: {}, min_complete: {}, flags: {:#x}",
+        fd, to_submit, min_complete, flags
+    );
+    if flags
+        & !(IORING_ENTER_GETEVENTS
+            | IORING_ENTER_SQ_WAKEUP
+            | IORING_ENTER_SQ_WAIT
+            | IORING_ENTER_EXT_ARG)
+        != 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let ring = ring_from_fd(fd)?;
+    ring.check_enabled()?;
+
+    let mut submitted = 0;
+    if to_submit > 0 {
+        let spawn;
+        (submitted, spawn) = ring.submit(to_submit);
+        for _ in 0..spawn {
+            let ring = ring.clone();
+            spawn_io_worker("iou-wrk", move || worker(ring));
+        }
+    }
+
+    if flags & IORING_ENTER_GETEVENTS != 0 {
+        let (sigmask, sigmask_sz, timeout) = if flags & IORING_ENTER_EXT_ARG != 0 {
+            if argsz != size_of::<GeteventsArg>() {
+                return Err(LinuxError::EINVAL);
+            }
+            let ext = (arg as *const GeteventsArg).vm_read()?;
+            let timeout = if ext.ts == 0 {
+                None
+            } else {
+                // FIXME: AnyBitPattern
+                let ts = unsafe {
+                    (ext.ts as *const __kernel_timespec)
+                        .vm_read_uninit()?
+                        .assume_init()
+                };
+                Some(ts.try_into_time_value()?)
+            };
+            (ext.sigmask as usize, ext.sigmask_sz as usize, timeout)
+        } else {
+            (arg, argsz, None)
+        };
+        check_sigset_size(sigmask_sz)?;
+        let sigmask = UserConstPtr::<SignalSet>::from(sigmask);
+        let sigmask = nullable!(sigmask.get_as_ref())?.copied();
+
+        let min_complete = min_complete.min(ring.cq_entries());
+        let result = with_replacen_blocked(sigmask, || ring.wait_cqes(min_complete, timeout));
+        if let Err(err) = result
+            && submitted == 0
+        {
+            return Err(err);
+        }
+    }
+
+    Ok(submitted as _)
+}
+
+pub fn sys_io_uring_register(fd: i32, opcode: u32, arg: usize, nr_args: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_io_uring_register <= fd: {}, opcode: {}, nr_args: {}",
+        fd, opcode, nr_args
+    );
+    let ring = ring_from_fd(fd)?;
+
+    match opcode {
+        IORING_REGISTER_BUFFERS => {
+            if nr_args == 0 || nr_args > u16::MAX as u32 + 1 {
+                return Err(LinuxError::EINVAL);
+            }
+            let iovs = arg as *const IoVec;
+            let buffers = (0..nr_args as usize)
+                .map(|i| {
+                    let iov = iovs.wrapping_add(i).vm_read()?;
+                    if iov.iov_len < 0 {
+                        return Err(LinuxError::EINVAL);
+                    }
+                    Ok((iov.iov_base as usize, iov.iov_len as usize))
+                })
+                .collect::<LinuxResult<Vec<_>>>()?;
+            ring.register_buffers(buffers)?;
+        }
+        IORING_UNREGISTER_BUFFERS => ring.unregister_buffers()?,
+        IORING_REGISTER_EVENTFD | IORING_REGISTER_EVENTFD_ASYNC => {
+            if nr_args != 1 {
+                return Err(LinuxError::EINVAL);
+            }
+            // Every completion is posted asynchronously, so both variants
+            // behave the same.
+            let eventfd = EventFd::from_fd((arg as *const i32).vm_read()?)?;
+            ring.register_eventfd(eventfd)?;
+        }
+        IORING_UNREGISTER_EVENTFD => ring.unregister_eventfd()?,
+        IORING_REGISTER_PROBE => {
+            let ops_len = (nr_args as usize).min(IORING_OP_LAST as usize);
+            (arg as *mut ProbeHeader).vm_write(ProbeHeader {
+                last_op: IORING_OP_LAST - 1,
+                ops_len: ops_len as u8,
+                resv: 0,
+                resv2: [0; 3],
+            })?;
+            let ops = (arg + size_of::<ProbeHeader>()) as *mut ProbeOp;
+            for op in 0..ops_len {
+                let supported = SUPPORTED_OPS.contains(&(op as u8));
+                ops.wrapping_add(op).vm_write(ProbeOp {
+                    op: op as u8,
+                    resv: 0,
+                    flags: if supported { IO_URING_OP_SUPPORTED } else { 0 },
+                    resv2: 0,
+                })?;
+            }
+        }
+        IORING_REGISTER_ENABLE_RINGS => ring.enable()?,
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(0)
+}
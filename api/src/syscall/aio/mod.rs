@@ -0,0 +1,4 @@
+mod aio;
+mod io_uring;
+
+pub use self::{aio::*, io_uring::*};
@@ -6,10 +6,11 @@ use core::{
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
+use axfs_ng::{FS_CONTEXT, FsContext, OpenOptions};
 use axfs_ng_vfs::{MetadataUpdate, NodePermission, NodeType, path::Path};
 use axhal::time::wall_time;
 use axtask::current;
+use bytemuck::AnyBitPattern;
 use linux_raw_sys::{
     general::*,
     ioctl::{FIONBIO, TIOCGWINSZ},
@@ -18,11 +19,24 @@ use starry_core::task::AsThread;
 use starry_vm::{VmPtr, vm_write_slice};
 
 use crate::{
-    file::{Directory, FileLike, get_file_like, resolve_at, with_fs},
+    file::{Directory, File, FileLike, get_file_like, resolve_at, with_fs},
     mm::vm_load_string,
     time::TimeValueLike,
+    vfs::{acl, mount, pagecache, reflink, writeback},
 };
 
+const FICLONE: u32 = 0x4004_9409;
+const FICLONERANGE: u32 = 0x4020_940d;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
 /// The ioctl() system call manipulates the underlying device parameters
 /// of special files.
 pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> LinuxResult<isize> {
@@ -36,6 +50,26 @@ pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> LinuxResult<isize> {
         f.set_nonblocking(val != 0)?;
         return Ok(0);
     }
+    match cmd {
+        FICLONE => {
+            let src = File::from_fd(arg as c_int)?;
+            reflink::clone_range(&src, 0, &File::from_fd(fd)?, 0, 0)?;
+            return Ok(0);
+        }
+        FICLONERANGE => {
+            let range = (arg as *const FileCloneRange).vm_read()?;
+            let src = File::from_fd(range.src_fd as c_int)?;
+            reflink::clone_range(
+                &src,
+                range.src_offset,
+                &File::from_fd(fd)?,
+                range.dest_offset,
+                range.src_length,
+            )?;
+            return Ok(0);
+        }
+        _ => {}
+    }
     f.ioctl(cmd, arg)
         .map(|result| result as isize)
         .inspect_err(|err| {
@@ -93,11 +127,68 @@ pub fn sys_mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> LinuxResult<is
         dirfd, path, mode
     );
 
-    let mode = mode & !current().as_thread().proc_data.umask();
-    let mode = NodePermission::from_bits_truncate(mode as u16);
+    with_fs(dirfd, |fs| {
+        acl::check_search(fs, &path)?;
+        let (parent, _) = fs.resolve_nonexistent(Path::new(&path))?;
+        mount::check_writable(&parent)?;
+        acl::check_dir_write(&parent)?;
+        let mode = if acl::has_default_acl(&parent) {
+            mode
+        } else {
+            mode & !current().as_thread().proc_data.umask()
+        };
+        fs.create_dir(
+            path.as_str(),
+            NodePermission::from_bits_truncate(mode as u16),
+        )?;
+        let loc = fs.resolve(path.as_str())?;
+        let cred = current().as_thread().proc_data.cred();
+        loc.update_metadata(MetadataUpdate {
+            owner: Some((cred.fsuid, cred.fsgid)),
+            ..Default::default()
+        })?;
+        acl::inherit_acl(&parent, &loc)?;
+        Ok(0)
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_mknod(path: *const c_char, mode: u32, dev: u32) -> LinuxResult<isize> {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
+pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u32) -> LinuxResult<isize> {
+    let path = vm_load_string(path)?;
+    debug!(
+        "sys_mknodat <= dirfd: {}, path: {}, mode: {:#o}, dev: {:#x}",
+        dirfd, path, mode, dev
+    );
+
+    match mode & S_IFMT {
+        0 | S_IFREG => {}
+        // none of the filesystems can store special files
+        S_IFIFO | S_IFSOCK | S_IFCHR | S_IFBLK => return Err(LinuxError::EPERM),
+        _ => return Err(LinuxError::EINVAL),
+    }
 
     with_fs(dirfd, |fs| {
-        fs.create_dir(path, mode)?;
+        acl::check_search(fs, &path)?;
+        let (parent, _) = fs.resolve_nonexistent(Path::new(&path))?;
+        mount::check_writable(&parent)?;
+        acl::check_dir_write(&parent)?;
+        let mode = if acl::has_default_acl(&parent) {
+            mode & 0o7777
+        } else {
+            mode & 0o7777 & !current().as_thread().proc_data.umask()
+        };
+        let cred = current().as_thread().proc_data.cred();
+        OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .mode(mode)
+            .user(cred.fsuid, cred.fsgid)
+            .open(fs, path.as_str())?;
+        acl::inherit_acl(&parent, &fs.resolve(path.as_str())?)?;
         Ok(0)
     })
 }
@@ -209,8 +300,15 @@ pub fn sys_linkat(
     if old.is_dir() {
         return Err(LinuxError::EPERM);
     }
-    let (new_dir, new_name) =
-        with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    let (new_dir, new_name) = with_fs(new_dirfd, |fs| {
+        acl::check_search(fs, &new_path)?;
+        fs.resolve_nonexistent(Path::new(&new_path))
+    })?;
+    if old.mountpoint().device() != new_dir.mountpoint().device() {
+        return Err(LinuxError::EXDEV);
+    }
+    mount::check_writable(&new_dir)?;
+    acl::check_dir_write(&new_dir)?;
 
     new_dir.link(new_name, &old)?;
     Ok(0)
@@ -235,10 +333,19 @@ pub fn sys_unlinkat(dirfd: i32, path: *const c_char, flags: usize) -> LinuxResul
     );
 
     with_fs(dirfd, |fs| {
+        acl::check_search(fs, &path)?;
+        let (parent, _) = fs.resolve_parent(Path::new(&path))?;
+        mount::check_writable(&parent)?;
+        acl::check_dir_write(&parent)?;
         if flags == AT_REMOVEDIR as _ {
             fs.remove_dir(path)?;
         } else {
+            let loc = fs.resolve_no_follow(path.as_str())?;
+            let last = loc.metadata()?.nlink <= 1;
             fs.remove_file(path)?;
+            if last {
+                pagecache::forget(&loc, 0, 0);
+            }
         }
         Ok(0)
     })
@@ -293,7 +400,19 @@ pub fn sys_symlinkat(
     );
 
     with_fs(new_dirfd, |fs| {
-        fs.symlink(target, linkpath)?;
+        acl::check_search(fs, &linkpath)?;
+        let (parent, _) = fs.resolve_nonexistent(Path::new(&linkpath))?;
+        mount::check_writable(&parent)?;
+        acl::check_dir_write(&parent)?;
+        // As on Linux, symlinks do not inherit the default ACL of their parent:
+        // their permissions are never checked.
+        fs.symlink(target, &linkpath)?;
+        let loc = fs.resolve_no_follow(linkpath.as_str())?;
+        let cred = current().as_thread().proc_data.cred();
+        loc.update_metadata(MetadataUpdate {
+            owner: Some((cred.fsuid, cred.fsgid)),
+            ..Default::default()
+        })?;
         Ok(0)
     })
 }
@@ -348,8 +467,24 @@ pub fn sys_fchownat(
     let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
         .ok_or(LinuxError::EBADF)?;
+    mount::check_writable(&loc)?;
     let meta = loc.metadata()?;
 
+    // Only root may give a file away; the owner may change its group to one
+    // it belongs to.
+    let cred = current().as_thread().proc_data.cred();
+    if !cred.is_privileged() {
+        let uid_ok = uid == -1 || (cred.fsuid == meta.uid && uid as u32 == meta.uid);
+        let gid_ok = gid == -1
+            || (cred.fsuid == meta.uid
+                && (gid as u32 == meta.gid
+                    || gid as u32 == cred.fsgid
+                    || cred.groups.contains(&(gid as u32))));
+        if !uid_ok || !gid_ok {
+            return Err(LinuxError::EPERM);
+        }
+    }
+
     let mut mode = meta.mode;
     // chown always clears the setuid bits
     mode.remove(NodePermission::SET_UID);
@@ -379,13 +514,19 @@ pub fn sys_fchmod(fd: i32, mode: u32) -> LinuxResult<isize> {
 
 pub fn sys_fchmodat(dirfd: i32, path: *const c_char, mode: u32, flags: u32) -> LinuxResult<isize> {
     let path = path.nullable().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
-        .ok_or(LinuxError::EBADF)?
-        .update_metadata(MetadataUpdate {
-            mode: Some(NodePermission::from_bits_truncate(mode as u16)),
-            ..Default::default()
-        })?;
+        .ok_or(LinuxError::EBADF)?;
+    mount::check_writable(&loc)?;
+    let cred = current().as_thread().proc_data.cred();
+    if !cred.is_privileged() && cred.fsuid != loc.metadata()?.uid {
+        return Err(LinuxError::EPERM);
+    }
+    loc.update_metadata(MetadataUpdate {
+        mode: Some(NodePermission::from_bits_truncate(mode as u16)),
+        ..Default::default()
+    })?;
+    acl::chmod_acl(&loc, mode as u16)?;
     Ok(0)
 }
 
@@ -397,14 +538,15 @@ fn update_times(
     flags: u32,
 ) -> LinuxResult<()> {
     let path = path.nullable().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
-        .ok_or(LinuxError::EBADF)?
-        .update_metadata(MetadataUpdate {
-            atime,
-            mtime,
-            ..Default::default()
-        })?;
+        .ok_or(LinuxError::EBADF)?;
+    mount::check_writable(&loc)?;
+    loc.update_metadata(MetadataUpdate {
+        atime,
+        mtime,
+        ..Default::default()
+    })?;
     Ok(())
 }
 
@@ -514,20 +656,47 @@ pub fn sys_renameat2(
         old_dirfd, old_path, new_dirfd, new_path, flags
     );
 
-    let (old_dir, old_name) = with_fs(old_dirfd, |fs| fs.resolve_parent(Path::new(&old_path)))?;
-    let (new_dir, new_name) =
-        with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    let (old_dir, old_name) = with_fs(old_dirfd, |fs| {
+        acl::check_search(fs, &old_path)?;
+        fs.resolve_parent(Path::new(&old_path))
+    })?;
+    let (new_dir, new_name) = with_fs(new_dirfd, |fs| {
+        acl::check_search(fs, &new_path)?;
+        fs.resolve_nonexistent(Path::new(&new_path))
+    })?;
+    if old_dir.mountpoint().device() != new_dir.mountpoint().device() {
+        return Err(LinuxError::EXDEV);
+    }
+    mount::check_writable(&old_dir)?;
+    mount::check_writable(&new_dir)?;
+    acl::check_dir_write(&old_dir)?;
+    acl::check_dir_write(&new_dir)?;
 
     old_dir.rename(&old_name, &new_dir, new_name)?;
     Ok(0)
 }
 
 pub fn sys_sync() -> LinuxResult<isize> {
-    warn!("dummy sys_sync");
+    debug!("sys_sync");
+    // sync(2) always succeeds; failures are only logged.
+    if let Err(err) = writeback::sync_all() {
+        warn!("sys_sync: {:?}", err);
+    }
     Ok(0)
 }
 
-pub fn sys_syncfs(_fd: i32) -> LinuxResult<isize> {
-    warn!("dummy sys_syncfs");
+pub fn sys_syncfs(fd: i32) -> LinuxResult<isize> {
+    debug!("sys_syncfs <= fd: {}", fd);
+    let loc = if let Ok(file) = File::from_fd(fd) {
+        file.inner().location().clone()
+    } else if let Ok(dir) = Directory::from_fd(fd) {
+        dir.inner().clone()
+    } else {
+        // Pipes, sockets and the like live on pseudo filesystems with
+        // nothing to write back.
+        get_file_like(fd)?;
+        return Ok(0);
+    };
+    writeback::sync_fs(&loc)?;
     Ok(0)
 }
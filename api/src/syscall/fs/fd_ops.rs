@@ -7,7 +7,7 @@ use core::{
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::{FS_CONTEXT, FileBackend, OpenOptions, OpenResult};
-use axfs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference};
+use axfs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference, path::Path};
 use axtask::current;
 use bitflags::bitflags;
 use linux_raw_sys::general::*;
@@ -19,8 +19,12 @@ use crate::{
         with_fs,
     },
     mm::{UserPtr, vm_load_string},
-    syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    net::tun::TunClone,
+    vfs::{
+        acl::{self, ACL_EXECUTE, ACL_READ, ACL_WRITE},
+        dev::tty,
+        mount,
+    },
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -78,6 +82,16 @@ fn add_to_fd(result: OpenResult, flags: u32) -> LinuxResult<i32> {
                     );
                     let loc = Location::new(file.location().mountpoint().clone(), entry);
                     file = axfs_ng::File::new(FileBackend::Direct(loc), file.flags());
+                } else if let Some(tun) = inner.downcast_ref::<TunClone>() {
+                    // Opening /dev/net/tun creates a new queue
+                    let net = FS_CONTEXT.lock().resolve("/dev/net")?;
+                    let entry = DirEntry::new_file(
+                        FileNode::new(tun.open()),
+                        NodeType::CharacterDevice,
+                        Reference::new(Some(net.entry().clone()), "tun".to_string()),
+                    );
+                    let loc = Location::new(file.location().mountpoint().clone(), entry);
+                    file = axfs_ng::File::new(FileBackend::Direct(loc), file.flags());
                 } else if inner.is::<tty::CurrentTty>() {
                     let term = current()
                         .as_thread()
@@ -98,7 +112,9 @@ fn add_to_fd(result: OpenResult, flags: u32) -> LinuxResult<i32> {
                     file = axfs_ng::File::new(FileBackend::Direct(loc), file.flags());
                 }
             }
-            Arc::new(File::new(file))
+            let file = File::new(file);
+            file.set_sync_flags(flags);
+            Arc::new(file)
         }
         OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
     };
@@ -126,12 +142,68 @@ pub fn sys_openat(
         dirfd, path, flags, mode
     );
 
-    let mode = mode & !current().as_thread().proc_data.umask();
+    let cred = current().as_thread().proc_data.cred();
+    let user = (cred.fsuid, cred.fsgid);
+    let flags_u = flags as u32;
+    with_fs(dirfd, |fs| {
+        acl::check_search(fs, &path)?;
+        let existing = if flags_u & O_NOFOLLOW != 0 {
+            fs.resolve_no_follow(path.as_str())
+        } else {
+            fs.resolve(path.as_str())
+        }
+        .ok();
+        // An exclusive create of an existing file fails with EEXIST before
+        // anything is checked.
+        let exclusive = flags_u & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL;
+        let parent = if flags_u & O_CREAT != 0 && existing.is_none() {
+            Some(fs.resolve_nonexistent(Path::new(&path))?.0)
+        } else {
+            None
+        };
+        // Permissions are checked before opening, which may already create
+        // or truncate the file.
+        if let Some(parent) = &parent {
+            mount::check_writable(parent)?;
+            acl::check_access(parent, ACL_WRITE | ACL_EXECUTE)?;
+        } else if let Some(loc) = existing
+            .as_ref()
+            .filter(|_| flags_u & O_PATH == 0 && !exclusive)
+        {
+            mount::check_dev(loc)?;
+            let writes = flags_u & 0b11 != O_RDONLY || flags_u & O_TRUNC != 0;
+            if writes && matches!(loc.node_type(), NodeType::RegularFile | NodeType::Directory) {
+                mount::check_writable(loc)?;
+            }
+            let mut want = match flags_u & 0b11 {
+                O_RDONLY => ACL_READ,
+                O_WRONLY => ACL_WRITE,
+                _ => ACL_READ | ACL_WRITE,
+            };
+            if flags_u & O_TRUNC != 0 {
+                want |= ACL_WRITE;
+            }
+            acl::check_access(loc, want)?;
+        }
+        // Files created inside a directory with a default ACL inherit it, and
+        // the umask is ignored in that case.
+        let mode = match &parent {
+            Some(parent) if acl::has_default_acl(parent) => mode,
+            _ => mode & !current().as_thread().proc_data.umask(),
+        };
 
-    let options = flags_to_options(flags, mode, (sys_geteuid()? as _, sys_getegid()? as _));
-    with_fs(dirfd, |fs| options.open(fs, path))
-        .and_then(|it| add_to_fd(it, flags as _))
-        .map(|fd| fd as isize)
+        let result = flags_to_options(flags, mode, user).open(fs, path.as_str())?;
+        if let Some(parent) = parent {
+            let loc = match &result {
+                OpenResult::File(file) => file.location(),
+                OpenResult::Dir(dir) => dir,
+            };
+            acl::inherit_acl(&parent, loc)?;
+        }
+        Ok(result)
+    })
+    .and_then(|it| add_to_fd(it, flags as _))
+    .map(|fd| fd as isize)
 }
 
 /// Open a file by `filename` and insert it into the file descriptor table.
@@ -269,6 +341,9 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
             if f.nonblocking() {
                 ret |= O_NONBLOCK;
             }
+            if let Ok(file) = File::from_fd(fd) {
+                ret |= file.sync_flags();
+            }
 
             let perm = NodePermission::from_bits_truncate(f.stat()?.mode as _);
             if perm.contains(NodePermission::OWNER_WRITE) {
@@ -6,16 +6,26 @@ use core::{
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::{FS_CONTEXT, FileFlags, OpenOptions};
-use axio::{IoEvents, Pollable, Seek, SeekFrom};
+use axio::{Buf, BufMut, IoEvents, Pollable, Read, Seek, SeekFrom, Write};
 use axtask::current;
-use linux_raw_sys::general::__kernel_off_t;
+use linux_raw_sys::general::{
+    __kernel_off_t, POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE, POSIX_FADV_NORMAL, POSIX_FADV_RANDOM,
+    POSIX_FADV_SEQUENTIAL, POSIX_FADV_WILLNEED,
+};
 use starry_vm::{VmBytes, VmBytesMut, VmMutPtr, VmPtr};
 use syscalls::Sysno;
 
 use crate::{
-    file::{File, FileLike, Pipe, SealedBuf, SealedBufMut, get_file_like},
+    file::{
+        File, FileLike, Pipe, SealedBuf, SealedBufMut, get_file_like,
+        readahead::{self, Advice},
+    },
     io::{IoVec, IoVectorBuf},
     mm::UserConstPtr,
+    vfs::{
+        falloc::{self, FallocFlags},
+        mount, pagecache, reflink, writeback,
+    },
 };
 
 struct DummyFd;
@@ -88,12 +98,21 @@ pub fn sys_writev(fd: i32, iov: *const IoVec, iovcnt: usize) -> LinuxResult<isiz
         .map(|n| n as _)
 }
 
+const SEEK_DATA: c_int = 3;
+const SEEK_HOLE: c_int = 4;
+
 pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> LinuxResult<isize> {
     debug!("sys_lseek <= {} {} {}", fd, offset, whence);
     let pos = match whence {
         0 => SeekFrom::Start(offset as _),
         1 => SeekFrom::Current(offset as _),
         2 => SeekFrom::End(offset as _),
+        SEEK_DATA | SEEK_HOLE => {
+            let f = File::from_fd(fd)?;
+            let offset = u64::try_from(offset).map_err(|_| LinuxError::ENXIO)?;
+            let pos = falloc::seek_hole_data(&f, offset, whence == SEEK_HOLE)?;
+            return Ok(f.inner().seek(SeekFrom::Start(pos))? as _);
+        }
         _ => return Err(LinuxError::EINVAL),
     };
     let off = File::from_fd(fd)?.inner().seek(pos)?;
@@ -110,14 +129,21 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> Linux
         .write(true)
         .open(&FS_CONTEXT.lock(), path)?
         .into_file()?;
+    mount::check_writable(file.location())?;
     file.access(FileFlags::WRITE)?.set_len(length as _)?;
+    pagecache::forget(file.location(), length as _, 0);
     Ok(0)
 }
 
 pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> LinuxResult<isize> {
     debug!("sys_ftruncate <= {} {}", fd, length);
+    if length < 0 {
+        return Err(LinuxError::EINVAL);
+    }
     let f = File::from_fd(fd)?;
+    mount::check_writable(f.inner().location())?;
     f.inner().access(FileFlags::WRITE)?.set_len(length as _)?;
+    pagecache::forget(f.inner().location(), length as _, 0);
     Ok(0)
 }
 
@@ -131,27 +157,27 @@ pub fn sys_fallocate(
         "sys_fallocate <= fd: {}, mode: {}, offset: {}, len: {}",
         fd, mode, offset, len
     );
-    if mode != 0 {
+    if offset < 0 || len <= 0 {
         return Err(LinuxError::EINVAL);
     }
+    let mode = FallocFlags::from_bits(mode).ok_or(LinuxError::EOPNOTSUPP)?;
     let f = File::from_fd(fd)?;
-    let inner = f.inner();
-    let file = inner.access(FileFlags::WRITE)?;
-    file.set_len(file.location().len()?.max(offset as u64 + len as u64))?;
+    mount::check_writable(f.inner().location())?;
+    falloc::fallocate(&f, mode, offset as u64, len as u64)?;
     Ok(0)
 }
 
 pub fn sys_fsync(fd: c_int) -> LinuxResult<isize> {
     debug!("sys_fsync <= {}", fd);
     let f = File::from_fd(fd)?;
-    f.inner().sync(false)?;
+    f.sync(false)?;
     Ok(0)
 }
 
 pub fn sys_fdatasync(fd: c_int) -> LinuxResult<isize> {
     debug!("sys_fdatasync <= {}", fd);
     let f = File::from_fd(fd)?;
-    f.inner().sync(true)?;
+    f.sync(true)?;
     Ok(0)
 }
 
@@ -168,9 +194,33 @@ pub fn sys_fadvise64(
     if Pipe::from_fd(fd).is_ok() {
         return Err(LinuxError::ESPIPE);
     }
-    if advice > 5 {
+    if advice > POSIX_FADV_NOREUSE || len < 0 {
         return Err(LinuxError::EINVAL);
     }
+    let f = match File::from_fd(fd) {
+        Ok(f) => f,
+        Err(LinuxError::EBADF) => return Err(LinuxError::EBADF),
+        // Advice on anything else has no effect.
+        Err(_) => return Ok(0),
+    };
+    let start = offset.max(0) as u64;
+    let end = if len == 0 {
+        u64::MAX
+    } else {
+        start.saturating_add(len as u64)
+    };
+    match advice {
+        POSIX_FADV_NORMAL | POSIX_FADV_NOREUSE => f.readahead().set_advice(Advice::Normal),
+        POSIX_FADV_RANDOM => f.readahead().set_advice(Advice::Random),
+        POSIX_FADV_SEQUENTIAL => f.readahead().set_advice(Advice::Sequential),
+        POSIX_FADV_WILLNEED => readahead::prefetch(f.inner().backend()?, start..end),
+        POSIX_FADV_DONTNEED => {
+            // Write the range back first, so that dropping it loses nothing.
+            writeback::sync_file(f.inner().backend()?, true)?;
+            pagecache::forget(f.inner().location(), start, len as u64);
+        }
+        _ => unreachable!(),
+    }
     Ok(0)
 }
 
@@ -187,9 +237,31 @@ pub fn sys_pread64(
     let read = f
         .inner()
         .read_at(&mut VmBytesMut::new(buf, len), offset as _)?;
+    f.finish_read(offset as _, read);
     Ok(read as _)
 }
 
+pub fn sys_readahead(fd: c_int, offset: __kernel_off_t, count: usize) -> LinuxResult<isize> {
+    debug!(
+        "sys_readahead <= fd: {}, offset: {}, count: {}",
+        fd, offset, count
+    );
+    let f = File::from_fd(fd).map_err(|err| match err {
+        LinuxError::EBADF => LinuxError::EBADF,
+        _ => LinuxError::EINVAL,
+    })?;
+    f.inner().access(FileFlags::READ)?;
+    if offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let start = offset as u64;
+    readahead::prefetch(
+        f.inner().backend()?,
+        start..start.saturating_add(count as u64),
+    );
+    Ok(0)
+}
+
 pub fn sys_pwrite64(
     fd: c_int,
     buf: *const u8,
@@ -200,9 +272,7 @@ pub fn sys_pwrite64(
         return Ok(0);
     }
     let f = File::from_fd(fd)?;
-    let write = f
-        .inner()
-        .write_at(&mut VmBytes::new(buf, len), offset as _)?;
+    let write = f.write_at(&mut VmBytes::new(buf, len).into(), offset as _)?;
     Ok(write as _)
 }
 
@@ -224,21 +294,81 @@ pub fn sys_pwritev(
     sys_pwritev2(fd, iov, iovcnt, offset, 0)
 }
 
+bitflags::bitflags! {
+    /// `RWF_*` flags for [`sys_preadv2`] and [`sys_pwritev2`].
+    #[derive(Debug, Clone, Copy)]
+    pub struct RwFlags: u32 {
+        /// High priority request, poll if possible.
+        const HIPRI = 1 << 0;
+        /// Per-I/O `O_DSYNC`.
+        const DSYNC = 1 << 1;
+        /// Per-I/O `O_SYNC`.
+        const SYNC = 1 << 2;
+        /// Fail with `EAGAIN` instead of blocking.
+        const NOWAIT = 1 << 3;
+        /// Per-I/O `O_APPEND`.
+        const APPEND = 1 << 4;
+        /// Ignore `O_APPEND` for this I/O.
+        const NOAPPEND = 1 << 5;
+    }
+}
+
+/// Fails with `EAGAIN` if an I/O for `events` of `len` bytes at `offset` on
+/// `f` may have to wait. An `offset` of -1 stands for the file offset.
+fn check_nowait(
+    f: &Arc<dyn FileLike>,
+    events: IoEvents,
+    offset: __kernel_off_t,
+    len: usize,
+) -> LinuxResult<()> {
+    let would_block = match f.clone().into_any().downcast::<File>() {
+        Ok(file) => {
+            let pos = if offset == -1 {
+                file.inner().seek(SeekFrom::Current(0))?
+            } else {
+                offset as u64
+            };
+            file.would_block(events, pos, len)
+        }
+        Err(_) => !f.poll().intersects(events),
+    };
+    if would_block {
+        return Err(LinuxError::EAGAIN);
+    }
+    Ok(())
+}
+
 pub fn sys_preadv2(
     fd: c_int,
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
-        "sys_preadv2 <= fd: {}, iovcnt: {}, offset: {}, flags: {}",
-        fd, iovcnt, offset, _flags
+        "sys_preadv2 <= fd: {}, iovcnt: {}, offset: {}, flags: {:#x}",
+        fd, iovcnt, offset, flags
     );
-    let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let flags = RwFlags::from_bits(flags).ok_or(LinuxError::EOPNOTSUPP)?;
+    if offset < -1 {
+        return Err(LinuxError::EINVAL);
+    }
+    let f = get_file_like(fd)?;
+    let mut dst = IoVectorBuf::new(iov, iovcnt)?.into_io();
+    if flags.contains(RwFlags::NOWAIT) {
+        check_nowait(&f, IoEvents::IN, offset, dst.remaining_mut())?;
+    }
+
+    if offset == -1 {
+        return f.read(&mut dst.into()).map(|n| n as _);
+    }
+    let file = f
+        .into_any()
+        .downcast::<File>()
+        .map_err(|_| LinuxError::ESPIPE)?;
+    let read = file.inner().read_at(&mut dst, offset as _)?;
+    file.finish_read(offset as _, read);
+    Ok(read as _)
 }
 
 pub fn sys_pwritev2(
@@ -246,16 +376,70 @@ pub fn sys_pwritev2(
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
-        "sys_pwritev2 <= fd: {}, iovcnt: {}, offset: {}, flags: {}",
-        fd, iovcnt, offset, _flags
+        "sys_pwritev2 <= fd: {}, iovcnt: {}, offset: {}, flags: {:#x}",
+        fd, iovcnt, offset, flags
     );
-    let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let flags = RwFlags::from_bits(flags).ok_or(LinuxError::EOPNOTSUPP)?;
+    if offset < -1 {
+        return Err(LinuxError::EINVAL);
+    }
+    let f = get_file_like(fd)?;
+    let nowait = flags.contains(RwFlags::NOWAIT);
+    if nowait {
+        // Nothing waits for writeback without blocking.
+        if flags.intersects(RwFlags::SYNC | RwFlags::DSYNC) {
+            return Err(LinuxError::EAGAIN);
+        }
+        check_nowait(&f, IoEvents::OUT, offset, 0)?;
+    }
+
+    let src = IoVectorBuf::new(iov, iovcnt)?.into_io();
+    let Ok(file) = f.clone().into_any().downcast::<File>() else {
+        if offset != -1 {
+            return Err(LinuxError::ESPIPE);
+        }
+        return f.write(&mut src.into()).map(|n| n as _);
+    };
+
+    let inner = file.inner();
+    let append = if flags.contains(RwFlags::APPEND) {
+        true
+    } else if flags.contains(RwFlags::NOAPPEND) {
+        false
+    } else {
+        // Positioned writes never append, just like `pwrite64`.
+        offset == -1 && inner.access(FileFlags::APPEND).is_ok()
+    };
+    // The file offset is only left to the file when nothing else is asked.
+    let plain = offset == -1 && !nowait && !flags.intersects(RwFlags::APPEND | RwFlags::NOAPPEND);
+    let written = if plain {
+        file.write(&mut src.into())?
+    } else {
+        let pos = if append {
+            inner.location().len()?
+        } else if offset == -1 {
+            inner.seek(SeekFrom::Current(0))?
+        } else {
+            offset as u64
+        };
+        let written = if nowait {
+            file.write_at_nowait(&mut src.into(), pos)?
+        } else {
+            file.write_at(&mut src.into(), pos)?
+        };
+        if offset == -1 {
+            inner.seek(SeekFrom::Start(pos + written as u64))?;
+        }
+        written
+    };
+
+    if flags.intersects(RwFlags::SYNC | RwFlags::DSYNC) {
+        writeback::sync_file(inner.backend()?, !flags.contains(RwFlags::SYNC))?;
+    }
+    Ok(written as _)
 }
 
 enum SendFile {
@@ -284,12 +468,12 @@ impl SendFile {
         }
     }
 
-    fn write(&mut self, mut buf: &[u8]) -> LinuxResult<usize> {
+    fn write(&mut self, buf: &[u8]) -> LinuxResult<usize> {
         match self {
             SendFile::Direct(file) => file.write(&mut buf.into()),
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
-                let bytes_written = file.inner().write_at(&mut buf, off)?;
+                let bytes_written = file.write_at(&mut buf.into(), off)?;
                 offset.vm_write(off + bytes_written as u64)?;
                 Ok(bytes_written)
             }
@@ -358,11 +542,11 @@ pub fn sys_sendfile(
 
 pub fn sys_copy_file_range(
     fd_in: c_int,
-    off_in: *mut u64,
+    off_in: *mut i64,
     fd_out: c_int,
-    off_out: *mut u64,
+    off_out: *mut i64,
     len: usize,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
         "sys_copy_file_range <= fd_in: {}, off_in: {}, fd_out: {}, off_out: {}, len: {}, flags: {}",
@@ -371,26 +555,73 @@ pub fn sys_copy_file_range(
         fd_out,
         !off_out.is_null(),
         len,
-        _flags
+        flags
     );
+    if flags != 0 {
+        return Err(LinuxError::EINVAL);
+    }
 
-    // TODO: check flags
-    // TODO: check both regular files
-    // TODO: check same file and overlap
-
-    let src = if !off_in.is_null() {
-        SendFile::Offset(File::from_fd(fd_in)?, off_in)
-    } else {
-        SendFile::Direct(get_file_like(fd_in)?)
+    let open = |fd| {
+        File::from_fd(fd).map_err(|err| match err {
+            LinuxError::ESPIPE => LinuxError::EINVAL,
+            err => err,
+        })
+    };
+    let src = open(fd_in)?;
+    let dst = open(fd_out)?;
+    reflink::check_copy(&src, &dst)?;
+
+    let position = |file: &File, off: *mut i64| -> LinuxResult<u64> {
+        if off.is_null() {
+            file.inner().seek(SeekFrom::Current(0))
+        } else {
+            u64::try_from(off.vm_read()?).map_err(|_| LinuxError::EINVAL)
+        }
     };
+    let src_pos = position(&src, off_in)?;
+    let dst_pos = position(&dst, off_out)?;
+    let len = len as u64;
+    if src_pos.checked_add(len).is_none() || dst_pos.checked_add(len).is_none() {
+        return Err(LinuxError::EOVERFLOW);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
 
-    let dst = if !off_out.is_null() {
-        SendFile::Offset(File::from_fd(fd_out)?, off_out)
-    } else {
-        SendFile::Direct(get_file_like(fd_out)?)
+    let copied = reflink::copy_range(&src, src_pos, &dst, dst_pos, len)?;
+
+    let advance = |file: &File, off: *mut i64, pos: u64| -> LinuxResult<()> {
+        if off.is_null() {
+            file.inner().seek(SeekFrom::Start(pos + copied))?;
+        } else {
+            off.vm_write((pos + copied) as i64)?;
+        }
+        Ok(())
     };
+    advance(&src, off_in, src_pos)?;
+    advance(&dst, off_out, dst_pos)?;
+    Ok(copied as _)
+}
 
-    do_send(src, dst, len).map(|n| n as _)
+bitflags::bitflags! {
+    /// `SPLICE_F_*` flags for [`sys_splice`], [`sys_tee`] and
+    /// [`sys_vmsplice`].
+    #[derive(Debug, Clone, Copy)]
+    pub struct SpliceFlags: u32 {
+        /// Move pages instead of copying, only a hint.
+        const MOVE = 1 << 0;
+        /// Don't block on the pipe.
+        const NONBLOCK = 1 << 1;
+        /// More data will follow in a later splice, only a hint.
+        const MORE = 1 << 2;
+        /// The user pages are gifted to the kernel, only a hint.
+        const GIFT = 1 << 3;
+    }
+}
+
+/// Returns whether a splice may not block on `pipe`.
+fn splice_nonblocking(pipe: &Pipe, flags: SpliceFlags) -> bool {
+    flags.contains(SpliceFlags::NONBLOCK) || pipe.nonblocking()
 }
 
 pub fn sys_splice(
@@ -399,69 +630,121 @@ pub fn sys_splice(
     fd_out: c_int,
     off_out: *mut i64,
     len: usize,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
-        "sys_splice <= fd_in: {}, off_in: {}, fd_out: {}, off_out: {}, len: {}, flags: {}",
+        "sys_splice <= fd_in: {}, off_in: {}, fd_out: {}, off_out: {}, len: {}, flags: {:#x}",
         fd_in,
         !off_in.is_null(),
         fd_out,
         !off_out.is_null(),
         len,
-        _flags
+        flags
     );
-
-    let mut has_pipe = false;
+    let flags = SpliceFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
 
     if DummyFd::from_fd(fd_in).is_ok() || DummyFd::from_fd(fd_out).is_ok() {
         return Err(LinuxError::EBADF);
     }
 
-    let src = if !off_in.is_null() {
-        if off_in.vm_read()? < 0 {
-            return Err(LinuxError::EINVAL);
-        }
-        SendFile::Offset(File::from_fd(fd_in)?, off_in.cast())
-    } else {
-        if let Ok(src) = Pipe::from_fd(fd_in) {
-            if !src.is_read() {
-                return Err(LinuxError::EBADF);
-            }
-            has_pipe = true;
-        }
-        if let Ok(file) = File::from_fd(fd_in)
-            && file.inner().is_path()
-        {
-            return Err(LinuxError::EINVAL);
-        }
-        SendFile::Direct(get_file_like(fd_in)?)
-    };
+    let src_pipe = Pipe::from_fd(fd_in).ok();
+    let dst_pipe = Pipe::from_fd(fd_out).ok();
+    if (src_pipe.is_some() && !off_in.is_null()) || (dst_pipe.is_some() && !off_out.is_null()) {
+        return Err(LinuxError::ESPIPE);
+    }
 
-    let dst = if !off_out.is_null() {
-        if off_out.vm_read()? < 0 {
-            return Err(LinuxError::EINVAL);
+    match (src_pipe, dst_pipe) {
+        // Pages are moved from one pipe to the other.
+        (Some(src), Some(dst)) => src.splice_to(
+            &dst,
+            len,
+            splice_nonblocking(&src, flags) || dst.nonblocking(),
+        ),
+        // Only pages of pipes can be moved. The page cache does not hand out
+        // its pages, so files are copied like sockets, once, straight from
+        // or into the pipe pages, without a bounce buffer.
+        (Some(pipe), None) => {
+            let mut dst = if !off_out.is_null() {
+                if off_out.vm_read()? < 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                SendFile::Offset(File::from_fd(fd_out)?, off_out.cast())
+            } else {
+                // Checked before waiting on the pipe, which may stay empty.
+                if let Ok(file) = File::from_fd(fd_out) {
+                    let inner = file.inner();
+                    if inner.access(FileFlags::APPEND).is_ok() {
+                        return Err(LinuxError::EINVAL);
+                    }
+                    inner.access(FileFlags::WRITE)?;
+                }
+                SendFile::Direct(get_file_like(fd_out)?)
+            };
+            pipe.drain(len, splice_nonblocking(&pipe, flags), |data| {
+                dst.write(data)
+            })
         }
-        SendFile::Offset(File::from_fd(fd_out)?, off_out.cast())
-    } else {
-        if let Ok(dst) = Pipe::from_fd(fd_out) {
-            if !dst.is_write() {
-                return Err(LinuxError::EBADF);
-            }
-            has_pipe = true;
-        }
-        if let Ok(file) = File::from_fd(fd_out)
-            && file.inner().access(FileFlags::APPEND).is_ok()
-        {
-            return Err(LinuxError::EINVAL);
+        (None, Some(pipe)) => {
+            let mut src = if !off_in.is_null() {
+                if off_in.vm_read()? < 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                SendFile::Offset(File::from_fd(fd_in)?, off_in.cast())
+            } else {
+                if let Ok(file) = File::from_fd(fd_in) {
+                    let inner = file.inner();
+                    if inner.is_path() {
+                        return Err(LinuxError::EINVAL);
+                    }
+                    inner.access(FileFlags::READ)?;
+                }
+                SendFile::Direct(get_file_like(fd_in)?)
+            };
+            pipe.fill(len, splice_nonblocking(&pipe, flags), |buf| src.read(buf))
         }
-        let f = get_file_like(fd_out)?;
-        f.write(&mut b"".as_slice().into())?;
-        SendFile::Direct(f)
-    };
-
-    if !has_pipe {
-        return Err(LinuxError::EINVAL);
+        (None, None) => Err(LinuxError::EINVAL),
     }
+    .map(|n| n as _)
+}
 
-    do_send(src, dst, len).map(|n| n as _)
+pub fn sys_tee(fd_in: c_int, fd_out: c_int, len: usize, flags: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_tee <= fd_in: {}, fd_out: {}, len: {}, flags: {:#x}",
+        fd_in, fd_out, len, flags
+    );
+    let flags = SpliceFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    let src = Pipe::from_fd(fd_in)?;
+    let dst = Pipe::from_fd(fd_out)?;
+    src.tee_to(
+        &dst,
+        len,
+        splice_nonblocking(&src, flags) || dst.nonblocking(),
+    )
+    .map(|n| n as _)
+}
+
+pub fn sys_vmsplice(
+    fd: c_int,
+    iov: *const IoVec,
+    nr_segs: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_vmsplice <= fd: {}, nr_segs: {}, flags: {:#x}",
+        fd, nr_segs, flags
+    );
+    let flags = SpliceFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    let pipe = Pipe::from_fd(fd).map_err(|_| LinuxError::EBADF)?;
+    let non_blocking = splice_nonblocking(&pipe, flags);
+
+    if pipe.is_write() {
+        // User pages can't be pinned, so even gifted pages are copied into
+        // the pipe.
+        let mut src: SealedBuf = IoVectorBuf::new(iov, nr_segs)?.into_io().into();
+        pipe.fill(src.remaining(), non_blocking, |buf| src.read(buf))
+    } else {
+        let mut dst: SealedBufMut = IoVectorBuf::new(iov, nr_segs)?.into_io().into();
+        pipe.drain(dst.remaining_mut(), non_blocking, |data| dst.write(data))
+    }
+    .map(|n| n as _)
 }
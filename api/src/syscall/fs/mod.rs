@@ -7,7 +7,8 @@ mod mount;
 mod pidfd;
 mod pipe;
 mod stat;
+mod xattr;
 
 pub use self::{
-    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*, stat::*,
+    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*, stat::*, xattr::*,
 };
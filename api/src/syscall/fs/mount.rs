@@ -1,41 +1,717 @@
-use core::ffi::{c_char, c_void};
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    ffi::{c_char, c_void},
+    mem,
+};
 
-use axerrno::LinuxResult;
-use axfs_ng::FS_CONTEXT;
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::{FS_CONTEXT, FsContext, OpenOptions, OpenResult};
+use axfs_ng_vfs::{Filesystem, Location, NodeType};
+use linux_raw_sys::general::{
+    AT_EMPTY_PATH, AT_SYMLINK_NOFOLLOW, MS_BIND, MS_MOVE, MS_PRIVATE, MS_REC, MS_REMOUNT,
+    MS_SHARED, MS_SILENT, MS_SLAVE, MS_UNBINDABLE, O_CLOEXEC,
+};
+use starry_vm::VmPtr;
 
-use crate::{mm::vm_load_string, vfs::MemoryFs};
+use crate::{
+    file::{
+        DetachedMount, Directory, File, FileLike, FsContextFd, FsContextPhase, FsContextState,
+        MountFd, MountTree, ResolveAtResult, add_file_like, get_file_like, resolve_at, with_fs,
+    },
+    mm::vm_load_string,
+    vfs::{
+        fstype::{self, MountOptions},
+        mount::{self, MountEntry, MountFlags, MountSpec, NewMount},
+        pagecache, tmp, writeback,
+    },
+};
+
+const MNT_FORCE: u32 = 1;
+const MNT_DETACH: u32 = 2;
+const MNT_EXPIRE: u32 = 4;
+const UMOUNT_NOFOLLOW: u32 = 8;
+
+const MS_PROPAGATION: u32 = MS_SHARED | MS_PRIVATE | MS_SLAVE | MS_UNBINDABLE;
+
+/// Returns the mount entry whose root is `loc`.
+fn mount_root(loc: &Location) -> LinuxResult<MountEntry> {
+    let entry = mount::lookup(loc).ok_or(LinuxError::EINVAL)?;
+    if loc.absolute_path()?.to_string() != entry.target {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(entry)
+}
+
+/// Mounts `fs` at `path` and records it in the mount table.
+fn attach(fs: &FsContext, path: &str, mount_fs: Filesystem, info: NewMount) -> LinuxResult<()> {
+    let target = fs.resolve(path)?;
+    if !target.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    target.mount(&mount_fs)?;
+    mount::register(&fs.resolve(path)?, Some(mount_fs), info)?;
+    Ok(())
+}
+
+/// Detaches the mount described by `entry` from the VFS and the mount
+/// table.
+fn detach(fs: &FsContext, entry: &MountEntry) -> LinuxResult<()> {
+    let loc = fs.resolve(entry.target.as_str())?;
+    writeback::sync_fs(&loc)?;
+    pagecache::forget_fs(&loc);
+    loc.unmount()?;
+    mount::unregister(entry.device);
+    Ok(())
+}
+
+/// Mounts `entry` again at `target`, keeping its source, flags and options.
+fn reattach(fs: &FsContext, entry: &MountEntry, target: &str) -> LinuxResult<()> {
+    let mount_fs = entry.fs.clone().ok_or(LinuxError::EINVAL)?;
+    attach(fs, target, mount_fs, MountSpec::from(entry).as_new())
+}
+
+/// Translates `path`, which lies under `old`, to the same place under `new`.
+fn rebase(path: &str, old: &str, new: &str) -> String {
+    let rest = if old == "/" {
+        path
+    } else {
+        path.strip_prefix(old).unwrap_or_default()
+    };
+    match (new, rest) {
+        (_, "" | "/") => new.to_string(),
+        ("/", _) => rest.to_string(),
+        _ => format!("{}{}", new, rest),
+    }
+}
+
+/// Creates a detached copy of the tree at `source`, like a bind mount that
+/// has not been attached yet.
+fn clone_tree(fs: &FsContext, source: &Location, recursive: bool) -> LinuxResult<DetachedMount> {
+    if !source.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    let source_path = source.absolute_path()?.to_string();
+    let parent = mount::lookup(source).ok_or(LinuxError::EINVAL)?;
+    let mut spec = MountSpec::from(&parent);
+    spec.root = rebase(&source_path, &parent.target, &parent.root);
+
+    let mut children = Vec::new();
+    if recursive {
+        // Shallowest first, so that each mount lands on top of its parent.
+        for entry in mount::submounts(&source_path).iter().rev() {
+            children.push((
+                rebase(&entry.target, &source_path, "/"),
+                mount::bind_fs(&fs.resolve(entry.target.as_str())?),
+                MountSpec::from(entry),
+            ));
+        }
+    }
+    Ok(DetachedMount {
+        fs: mount::bind_fs(source),
+        spec,
+        children,
+    })
+}
+
+/// Attaches a detached mount tree at `target`.
+fn attach_tree(fs: &FsContext, target: &str, tree: DetachedMount) -> LinuxResult<()> {
+    attach(fs, target, tree.fs, tree.spec.as_new())?;
+    for (path, child_fs, spec) in tree.children {
+        attach(fs, &rebase(&path, "/", target), child_fs, spec.as_new())?;
+    }
+    Ok(())
+}
+
+fn do_move(fs: &FsContext, source: &str, target: &str) -> LinuxResult<()> {
+    let entry = mount_root(&fs.resolve(source)?)?;
+    if entry.fs.is_none() {
+        // The root filesystem cannot be moved.
+        return Err(LinuxError::EINVAL);
+    }
+    // The target is checked before anything is detached, so that a bad one
+    // leaves the mount where it was.
+    let target = fs.resolve(target)?;
+    if !target.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    let target_path = target.absolute_path()?.to_string();
+    if target_path == entry.target || target_path.starts_with(&format!("{}/", entry.target)) {
+        return Err(LinuxError::ELOOP);
+    }
+
+    // Deepest first, so that nothing is left mounted inside.
+    let submounts = mount::submounts(&entry.target);
+    for sub in &submounts {
+        detach(fs, sub)?;
+    }
+    detach(fs, &entry)?;
+
+    if let Err(err) = reattach(fs, &entry, &target_path) {
+        reattach(fs, &entry, &entry.target)?;
+        for sub in submounts.iter().rev() {
+            reattach(fs, sub, &sub.target)?;
+        }
+        return Err(err);
+    }
+    for sub in submounts.iter().rev() {
+        reattach(fs, sub, &rebase(&sub.target, &entry.target, &target_path))?;
+    }
+    Ok(())
+}
 
 pub fn sys_mount(
     source: *const c_char,
     target: *const c_char,
     fs_type: *const c_char,
-    _flags: i32,
-    _data: *const c_void,
+    flags: u32,
+    data: *const c_void,
 ) -> LinuxResult<isize> {
-    let source = vm_load_string(source)?;
+    let source = if source.is_null() {
+        String::new()
+    } else {
+        vm_load_string(source)?
+    };
     let target = vm_load_string(target)?;
-    let fs_type = vm_load_string(fs_type)?;
+    let fs_type = if fs_type.is_null() {
+        String::new()
+    } else {
+        vm_load_string(fs_type)?
+    };
+    let data = if data.is_null() {
+        None
+    } else {
+        Some(vm_load_string(data.cast())?)
+    };
     debug!(
-        "sys_mount <= source: {:?}, target: {:?}, fs_type: {:?}",
-        source, target, fs_type
+        "sys_mount <= source: {:?}, target: {:?}, fs_type: {:?}, flags: {:#x}, data: {:?}",
+        source, target, fs_type, flags, data
     );
 
-    if fs_type != "tmpfs" {
-        return Err(axerrno::LinuxError::ENODEV);
+    let opts = MountOptions::parse(data.as_deref().unwrap_or_default());
+    let mount_flags = opts.apply(MountFlags::from_bits_truncate(flags));
+    let fs = FS_CONTEXT.lock();
+
+    if flags & MS_REMOUNT != 0 {
+        let loc = fs.resolve(target.as_str())?;
+        mount_root(&loc)?;
+        let flags = mount::mount_flags(&loc).remount(MountFlags::from_bits_truncate(flags));
+        let fs_data = data.as_ref().map(|_| opts.fs_data());
+        tmp::reconfigure(&loc, &opts)?;
+        mount::remount(&loc, opts.apply(flags), fs_data.as_deref())?;
+        return Ok(0);
+    }
+    if flags & MS_BIND != 0 {
+        let tree = clone_tree(&fs, &fs.resolve(source.as_str())?, flags & MS_REC != 0)?;
+        attach_tree(&fs, &target, tree)?;
+        return Ok(0);
+    }
+    if flags & MS_PROPAGATION != 0 {
+        // There is only one mount namespace, so propagation types have no
+        // observable effect.
+        let extra = flags & !(MS_PROPAGATION | MS_REC | MS_SILENT);
+        if extra != 0 || (flags & MS_PROPAGATION).count_ones() != 1 {
+            return Err(LinuxError::EINVAL);
+        }
+        mount_root(&fs.resolve(target.as_str())?)?;
+        return Ok(0);
+    }
+    if flags & MS_MOVE != 0 {
+        do_move(&fs, &source, &target)?;
+        return Ok(0);
     }
 
-    let fs = MemoryFs::new();
+    // Disk filesystems take a block device as their source, others treat it
+    // as a free-form name.
+    let source_loc = if source.is_empty() {
+        None
+    } else {
+        fs.resolve(source.as_str()).ok()
+    };
+    let mut source_name = source.clone();
+    if let Some(loc) = source_loc
+        .as_ref()
+        .filter(|it| matches!(it.node_type(), NodeType::BlockDevice))
+    {
+        mount::check_dev(loc)?;
+        source_name = loc.absolute_path()?.to_string();
+        if mount::is_mounted(&source_name) {
+            return Err(LinuxError::EBUSY);
+        }
+    }
 
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.mount(&fs)?;
+    let (mount_fs, fs_type) = fstype::new_filesystem(&fs_type, source_loc.as_ref(), &opts)?;
+    attach(
+        &fs,
+        &target,
+        mount_fs,
+        NewMount {
+            source: &source_name,
+            fs_type,
+            root: "/",
+            flags: mount_flags,
+            data: &opts.fs_data(),
+        },
+    )?;
 
     Ok(0)
 }
 
-pub fn sys_umount2(target: *const c_char, _flags: i32) -> LinuxResult<isize> {
+pub fn sys_umount2(target: *const c_char, flags: u32) -> LinuxResult<isize> {
     let target = vm_load_string(target)?;
-    debug!("sys_umount2 <= target: {:?}", target);
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.unmount()?;
+    debug!("sys_umount2 <= target: {:?}, flags: {:#x}", target, flags);
+
+    if flags & !(MNT_FORCE | MNT_DETACH | MNT_EXPIRE | UMOUNT_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if flags & MNT_EXPIRE != 0 {
+        if flags & (MNT_FORCE | MNT_DETACH) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        // Expiry is not tracked. Behave like the first MNT_EXPIRE call on
+        // an unused mount, which only marks it.
+        return Err(LinuxError::EAGAIN);
+    }
+
+    let fs = FS_CONTEXT.lock();
+    let loc = if flags & UMOUNT_NOFOLLOW != 0 {
+        fs.resolve_no_follow(target.as_str())?
+    } else {
+        fs.resolve(target.as_str())?
+    };
+    let entry = mount_root(&loc)?;
+    if entry.fs.is_none() {
+        return Err(LinuxError::EBUSY);
+    }
+
+    let submounts = mount::submounts(&entry.target);
+    if !submounts.is_empty() {
+        if flags & MNT_DETACH == 0 {
+            return Err(LinuxError::EBUSY);
+        }
+        // Lazy unmount: the whole subtree disappears from the namespace at
+        // once, open files keep the filesystems alive until closed.
+        for sub in &submounts {
+            detach(&fs, sub)?;
+        }
+    }
+    detach(&fs, &entry)?;
+    Ok(0)
+}
+
+const FSOPEN_CLOEXEC: u32 = 1;
+
+const FSPICK_CLOEXEC: u32 = 1;
+const FSPICK_SYMLINK_NOFOLLOW: u32 = 2;
+const FSPICK_NO_AUTOMOUNT: u32 = 4;
+const FSPICK_EMPTY_PATH: u32 = 8;
+
+const FSCONFIG_SET_FLAG: u32 = 0;
+const FSCONFIG_SET_STRING: u32 = 1;
+const FSCONFIG_SET_BINARY: u32 = 2;
+const FSCONFIG_SET_PATH: u32 = 3;
+const FSCONFIG_SET_PATH_EMPTY: u32 = 4;
+const FSCONFIG_SET_FD: u32 = 5;
+const FSCONFIG_CMD_CREATE: u32 = 6;
+const FSCONFIG_CMD_RECONFIGURE: u32 = 7;
+const FSCONFIG_CMD_CREATE_EXCL: u32 = 8;
+
+const FSMOUNT_CLOEXEC: u32 = 1;
+
+const MOUNT_ATTR_RDONLY: u32 = 0x1;
+const MOUNT_ATTR_NOSUID: u32 = 0x2;
+const MOUNT_ATTR_NODEV: u32 = 0x4;
+const MOUNT_ATTR_NOEXEC: u32 = 0x8;
+const MOUNT_ATTR__ATIME: u32 = 0x70;
+const MOUNT_ATTR_RELATIME: u32 = 0x0;
+const MOUNT_ATTR_NOATIME: u32 = 0x10;
+const MOUNT_ATTR_STRICTATIME: u32 = 0x20;
+const MOUNT_ATTR_NODIRATIME: u32 = 0x80;
+
+const OPEN_TREE_CLONE: u32 = 1;
+const AT_NO_AUTOMOUNT: u32 = 0x800;
+const AT_RECURSIVE: u32 = 0x8000;
+
+const MOVE_MOUNT_F_SYMLINKS: u32 = 0x1;
+const MOVE_MOUNT_F_AUTOMOUNTS: u32 = 0x2;
+const MOVE_MOUNT_F_EMPTY_PATH: u32 = 0x4;
+const MOVE_MOUNT_T_SYMLINKS: u32 = 0x10;
+const MOVE_MOUNT_T_AUTOMOUNTS: u32 = 0x20;
+const MOVE_MOUNT_T_EMPTY_PATH: u32 = 0x40;
+
+fn mount_attr_flags(attr: u32) -> LinuxResult<MountFlags> {
+    let known = MOUNT_ATTR_RDONLY
+        | MOUNT_ATTR_NOSUID
+        | MOUNT_ATTR_NODEV
+        | MOUNT_ATTR_NOEXEC
+        | MOUNT_ATTR__ATIME
+        | MOUNT_ATTR_NODIRATIME;
+    if attr & !known != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut flags = MountFlags::empty();
+    for (bit, flag) in [
+        (MOUNT_ATTR_RDONLY, MountFlags::RDONLY),
+        (MOUNT_ATTR_NOSUID, MountFlags::NOSUID),
+        (MOUNT_ATTR_NODEV, MountFlags::NODEV),
+        (MOUNT_ATTR_NOEXEC, MountFlags::NOEXEC),
+        (MOUNT_ATTR_NODIRATIME, MountFlags::NODIRATIME),
+    ] {
+        if attr & bit != 0 {
+            flags |= flag;
+        }
+    }
+    flags |= match attr & MOUNT_ATTR__ATIME {
+        MOUNT_ATTR_RELATIME => MountFlags::RELATIME,
+        MOUNT_ATTR_NOATIME => MountFlags::NOATIME,
+        MOUNT_ATTR_STRICTATIME => MountFlags::STRICTATIME,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    Ok(flags)
+}
+
+fn fs_context(fd: i32) -> LinuxResult<Arc<FsContextFd>> {
+    get_file_like(fd)?
+        .into_any()
+        .downcast::<FsContextFd>()
+        .map_err(|_| LinuxError::EINVAL)
+}
+
+pub fn sys_fsopen(fs_name: *const c_char, flags: u32) -> LinuxResult<isize> {
+    let fs_name = vm_load_string(fs_name)?;
+    debug!("sys_fsopen <= fs_name: {:?}, flags: {:#x}", fs_name, flags);
+
+    if flags & !FSOPEN_CLOEXEC != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let fs_type = fstype::lookup(&fs_name).ok_or(LinuxError::ENODEV)?;
+    let fc = FsContextFd::new(fs_type.into(), FsContextPhase::Create);
+    fc.add_to_fd_table(flags & FSOPEN_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_fspick(dirfd: i32, path: *const c_char, flags: u32) -> LinuxResult<isize> {
+    let path = path.nullable().map(vm_load_string).transpose()?;
+    debug!(
+        "sys_fspick <= dirfd: {}, path: {:?}, flags: {:#x}",
+        dirfd, path, flags
+    );
+
+    if flags & !(FSPICK_CLOEXEC | FSPICK_SYMLINK_NOFOLLOW | FSPICK_NO_AUTOMOUNT | FSPICK_EMPTY_PATH)
+        != 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut at_flags = 0;
+    if flags & FSPICK_SYMLINK_NOFOLLOW != 0 {
+        at_flags |= AT_SYMLINK_NOFOLLOW;
+    }
+    if flags & FSPICK_EMPTY_PATH != 0 {
+        at_flags |= AT_EMPTY_PATH;
+    }
+    let loc = resolve_at(dirfd, path.as_deref(), at_flags)?
+        .into_file()
+        .ok_or(LinuxError::EINVAL)?;
+    let entry = mount_root(&loc)?;
+
+    let fc = FsContextFd::new(entry.fs_type, FsContextPhase::Reconfigure(loc));
+    fc.add_to_fd_table(flags & FSPICK_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
+
+/// Executes `FSCONFIG_CMD_CREATE`.
+fn fsconfig_create(state: &mut FsContextState, exclusive: bool) -> LinuxResult<()> {
+    let FsContextPhase::Create = state.phase else {
+        return Err(LinuxError::EBUSY);
+    };
+    let opts = MountOptions::parse(&state.options.join(","));
+    let source = state
+        .source
+        .clone()
+        .unwrap_or_else(|| state.fs_type.clone());
+    if state.source_loc.is_some() && exclusive && mount::is_mounted(&source) {
+        return Err(LinuxError::EBUSY);
+    }
+    let (fs, fs_type) = fstype::new_filesystem(&state.fs_type, state.source_loc.as_ref(), &opts)?;
+    state.phase = FsContextPhase::Created(
+        fs,
+        MountSpec {
+            source,
+            fs_type: fs_type.into(),
+            root: "/".into(),
+            flags: opts.apply(MountFlags::empty()),
+            data: opts.fs_data(),
+        },
+    );
+    Ok(())
+}
+
+/// Executes `FSCONFIG_CMD_RECONFIGURE`.
+fn fsconfig_reconfigure(state: &mut FsContextState) -> LinuxResult<()> {
+    let FsContextPhase::Reconfigure(loc) = &state.phase else {
+        return Err(LinuxError::EOPNOTSUPP);
+    };
+    let opts = MountOptions::parse(&state.options.join(","));
+    let flags = opts.apply(mount::mount_flags(loc));
+    let data = opts.fs_data();
+    tmp::reconfigure(loc, &opts)?;
+    mount::remount(loc, flags, (!opts.fs.is_empty()).then_some(data.as_str()))?;
+    state.options.clear();
+    Ok(())
+}
+
+pub fn sys_fsconfig(
+    fd: i32,
+    cmd: u32,
+    key: *const c_char,
+    value: *const c_void,
+    aux: i32,
+) -> LinuxResult<isize> {
+    let key = key.nullable().map(vm_load_string).transpose()?;
+    debug!(
+        "sys_fsconfig <= fd: {}, cmd: {}, key: {:?}, aux: {}",
+        fd, cmd, key, aux
+    );
+
+    let fc = fs_context(fd)?;
+    let mut state = fc.state.lock();
+    if let FsContextPhase::Failed | FsContextPhase::Mounted = state.phase {
+        return Err(LinuxError::EBUSY);
+    }
+
+    match cmd {
+        FSCONFIG_SET_FLAG => {
+            let key = key.ok_or(LinuxError::EINVAL)?;
+            if !value.is_null() || aux != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            state.options.push(key);
+        }
+        FSCONFIG_SET_STRING => {
+            let key = key.ok_or(LinuxError::EINVAL)?;
+            if value.is_null() || aux != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let value = vm_load_string(value.cast())?;
+            if key == "source" {
+                if state.source.is_some() {
+                    return Err(LinuxError::EINVAL);
+                }
+                state.source_loc = FS_CONTEXT.lock().resolve(value.as_str()).ok();
+                state.source = Some(value);
+            } else {
+                state.options.push(format!("{}={}", key, value));
+            }
+        }
+        FSCONFIG_SET_BINARY => return Err(LinuxError::EINVAL),
+        FSCONFIG_SET_PATH | FSCONFIG_SET_PATH_EMPTY | FSCONFIG_SET_FD => {
+            let key = key.ok_or(LinuxError::EINVAL)?;
+            if key != "source" {
+                return Err(LinuxError::EINVAL);
+            }
+            if state.source.is_some() {
+                return Err(LinuxError::EINVAL);
+            }
+            let loc = if cmd == FSCONFIG_SET_FD {
+                if !value.is_null() {
+                    return Err(LinuxError::EINVAL);
+                }
+                resolve_at(aux, None, AT_EMPTY_PATH)?
+            } else {
+                let path = vm_load_string(value.cast())?;
+                let flags = if cmd == FSCONFIG_SET_PATH_EMPTY {
+                    AT_EMPTY_PATH
+                } else {
+                    0
+                };
+                resolve_at(aux, Some(&path), flags)?
+            }
+            .into_file()
+            .ok_or(LinuxError::ENOTBLK)?;
+            state.source = Some(loc.absolute_path()?.to_string());
+            state.source_loc = Some(loc);
+        }
+        FSCONFIG_CMD_CREATE | FSCONFIG_CMD_CREATE_EXCL => {
+            if key.is_some() || !value.is_null() || aux != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            if let Err(err) = fsconfig_create(&mut state, cmd == FSCONFIG_CMD_CREATE_EXCL) {
+                if !matches!(err, LinuxError::EBUSY) {
+                    state.phase = FsContextPhase::Failed;
+                }
+                return Err(err);
+            }
+        }
+        FSCONFIG_CMD_RECONFIGURE => {
+            if key.is_some() || !value.is_null() || aux != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            fsconfig_reconfigure(&mut state)?;
+        }
+        _ => return Err(LinuxError::EOPNOTSUPP),
+    }
+    Ok(0)
+}
+
+pub fn sys_fsmount(fs_fd: i32, flags: u32, attr_flags: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_fsmount <= fs_fd: {}, flags: {:#x}, attr_flags: {:#x}",
+        fs_fd, flags, attr_flags
+    );
+
+    if flags & !FSMOUNT_CLOEXEC != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let attrs = mount_attr_flags(attr_flags)?;
+
+    let fc = fs_context(fs_fd)?;
+    let mut state = fc.state.lock();
+    if !matches!(state.phase, FsContextPhase::Created(..)) {
+        return Err(LinuxError::EINVAL);
+    }
+    let FsContextPhase::Created(fs, mut spec) =
+        mem::replace(&mut state.phase, FsContextPhase::Mounted)
+    else {
+        unreachable!()
+    };
+    spec.flags |= attrs;
+
+    let mount = MountFd::new(DetachedMount {
+        fs,
+        spec,
+        children: Vec::new(),
+    });
+    mount
+        .add_to_fd_table(flags & FSMOUNT_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_open_tree(dirfd: i32, path: *const c_char, flags: u32) -> LinuxResult<isize> {
+    let path = path.nullable().map(vm_load_string).transpose()?;
+    debug!(
+        "sys_open_tree <= dirfd: {}, path: {:?}, flags: {:#x}",
+        dirfd, path, flags
+    );
+
+    let known = OPEN_TREE_CLONE
+        | O_CLOEXEC
+        | AT_EMPTY_PATH
+        | AT_NO_AUTOMOUNT
+        | AT_RECURSIVE
+        | AT_SYMLINK_NOFOLLOW;
+    if flags & !known != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if flags & AT_RECURSIVE != 0 && flags & OPEN_TREE_CLONE == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let cloexec = flags & O_CLOEXEC != 0;
+    let at_flags = flags & (AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW);
+
+    if flags & OPEN_TREE_CLONE == 0 {
+        // Without OPEN_TREE_CLONE this is just an O_PATH open.
+        let f: Arc<dyn FileLike> = match resolve_at(dirfd, path.as_deref(), at_flags)? {
+            ResolveAtResult::File(loc) if loc.is_dir() => Arc::new(Directory::new(loc)),
+            ResolveAtResult::File(_) => {
+                let path = path.as_deref().filter(|it| !it.is_empty());
+                let Some(path) = path else {
+                    return add_file_like(get_file_like(dirfd)?, cloexec).map(|fd| fd as _);
+                };
+                let mut options = OpenOptions::new();
+                options.path(true);
+                if at_flags & AT_SYMLINK_NOFOLLOW != 0 {
+                    options.no_follow(true);
+                }
+                match with_fs(dirfd, |fs| options.open(fs, path))? {
+                    OpenResult::File(file) => Arc::new(File::new(file)),
+                    OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
+                }
+            }
+            ResolveAtResult::Other(f) => f,
+        };
+        return add_file_like(f, cloexec).map(|fd| fd as _);
+    }
+
+    let loc = resolve_at(dirfd, path.as_deref(), at_flags)?
+        .into_file()
+        .ok_or(LinuxError::EINVAL)?;
+    let tree = clone_tree(&FS_CONTEXT.lock(), &loc, flags & AT_RECURSIVE != 0)?;
+    MountFd::new(tree)
+        .add_to_fd_table(cloexec)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_move_mount(
+    from_dirfd: i32,
+    from_path: *const c_char,
+    to_dirfd: i32,
+    to_path: *const c_char,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let from_path = from_path.nullable().map(vm_load_string).transpose()?;
+    let to_path = to_path.nullable().map(vm_load_string).transpose()?;
+    debug!(
+        "sys_move_mount <= from: ({}, {:?}), to: ({}, {:?}), flags: {:#x}",
+        from_dirfd, from_path, to_dirfd, to_path, flags
+    );
+
+    let known = MOVE_MOUNT_F_SYMLINKS
+        | MOVE_MOUNT_F_AUTOMOUNTS
+        | MOVE_MOUNT_F_EMPTY_PATH
+        | MOVE_MOUNT_T_SYMLINKS
+        | MOVE_MOUNT_T_AUTOMOUNTS
+        | MOVE_MOUNT_T_EMPTY_PATH;
+    if flags & !known != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let at_flags = |empty, symlinks| {
+        let mut at = 0;
+        if flags & empty != 0 {
+            at |= AT_EMPTY_PATH;
+        }
+        if flags & symlinks == 0 {
+            at |= AT_SYMLINK_NOFOLLOW;
+        }
+        at
+    };
+
+    let target = resolve_at(
+        to_dirfd,
+        to_path.as_deref(),
+        at_flags(MOVE_MOUNT_T_EMPTY_PATH, MOVE_MOUNT_T_SYMLINKS),
+    )?
+    .into_file()
+    .ok_or(LinuxError::EINVAL)?;
+    let target = target.absolute_path()?.to_string();
+
+    // A mount fd from fsmount() or open_tree(OPEN_TREE_CLONE).
+    if flags & MOVE_MOUNT_F_EMPTY_PATH != 0
+        && from_path.as_deref().is_none_or(str::is_empty)
+        && let Ok(mount_fd) = get_file_like(from_dirfd)?.into_any().downcast::<MountFd>()
+    {
+        let mut tree = mount_fd.tree.lock();
+        let fs = FS_CONTEXT.lock();
+        match &*tree {
+            MountTree::Detached(detached, _) => attach_tree(&fs, &target, detached.clone())?,
+            // The mount is found from its root, wherever its path now leads.
+            MountTree::Attached(root) => do_move(&fs, &mount_root(root)?.target, &target)?,
+        }
+        *tree = MountTree::Attached(fs.resolve(target.as_str())?);
+        return Ok(0);
+    }
+
+    let source = resolve_at(
+        from_dirfd,
+        from_path.as_deref(),
+        at_flags(MOVE_MOUNT_F_EMPTY_PATH, MOVE_MOUNT_F_SYMLINKS),
+    )?
+    .into_file()
+    .ok_or(LinuxError::EINVAL)?;
+    let source = mount_root(&source)?.target;
+    do_move(&FS_CONTEXT.lock(), &source, &target)?;
     Ok(0)
 }
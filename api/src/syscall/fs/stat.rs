@@ -2,15 +2,19 @@ use core::ffi::{c_char, c_int};
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::FS_CONTEXT;
-use axfs_ng_vfs::{Location, NodePermission};
+use axfs_ng_vfs::{Location, NodeType};
 use linux_raw_sys::general::{
     __kernel_fsid_t, AT_EMPTY_PATH, R_OK, W_OK, X_OK, stat, statfs, statx,
 };
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
-    file::{File, FileLike, resolve_at},
+    file::{File, FileLike, ResolveAtResult, resolve_at},
     mm::vm_load_string,
+    vfs::{
+        acl::{self, ACL_EXECUTE, ACL_READ, ACL_WRITE},
+        mount,
+    },
 };
 
 /// Get the file metadata by `path` and write into `statbuf`.
@@ -128,19 +132,34 @@ pub fn sys_faccessat2(
     if mode == 0 {
         return Ok(0);
     }
-    let mut required_mode = NodePermission::empty();
+    let mut want = 0;
     if mode & R_OK != 0 {
-        required_mode |= NodePermission::OWNER_READ;
+        want |= ACL_READ;
     }
     if mode & W_OK != 0 {
-        required_mode |= NodePermission::OWNER_WRITE;
+        want |= ACL_WRITE;
     }
     if mode & X_OK != 0 {
-        required_mode |= NodePermission::OWNER_EXEC;
+        want |= ACL_EXECUTE;
     }
-    let required_mode = required_mode.bits();
-    if (file.stat()?.mode as u16 & required_mode) != required_mode {
-        return Err(LinuxError::EACCES);
+    match file {
+        ResolveAtResult::File(loc) => {
+            acl::check_access(&loc, want)?;
+            if want & ACL_WRITE != 0
+                && matches!(loc.node_type(), NodeType::RegularFile | NodeType::Directory)
+            {
+                mount::check_writable(&loc)?;
+            }
+            if want & ACL_EXECUTE != 0 && matches!(loc.node_type(), NodeType::RegularFile) {
+                mount::check_exec(&loc)?;
+            }
+        }
+        ResolveAtResult::Other(f) => {
+            let perm = f.stat()?.mode as u16 >> 6;
+            if perm & want != want {
+                return Err(LinuxError::EACCES);
+            }
+        }
     }
 
     Ok(0)
@@ -163,7 +182,7 @@ fn statfs(loc: &Location) -> LinuxResult<statfs> {
     };
     result.f_namelen = stat.name_length as _;
     result.f_frsize = stat.fragment_size as _;
-    result.f_flags = stat.mount_flags as _;
+    result.f_flags = (stat.mount_flags as u64 | mount::mount_flags(loc).statfs_flags()) as _;
     Ok(result)
 }
 
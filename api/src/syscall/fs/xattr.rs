@@ -0,0 +1,202 @@
+use alloc::{string::String, vec::Vec};
+use core::ffi::{c_char, c_int};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
+use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use starry_vm::{vm_load, vm_write_slice};
+
+use crate::{
+    file::resolve_at,
+    mm::vm_load_string,
+    vfs::{
+        mount,
+        xattr::{self, XATTR_NAME_MAX, XATTR_SIZE_MAX, XattrFlags},
+    },
+};
+
+fn resolve_path(path: *const c_char, flags: u32) -> LinuxResult<Location> {
+    let path = vm_load_string(path)?;
+    resolve_at(AT_FDCWD, Some(&path), flags)?
+        .into_file()
+        .ok_or(LinuxError::EBADF)
+}
+
+fn resolve_fd(fd: c_int) -> LinuxResult<Location> {
+    resolve_at(fd, None, AT_EMPTY_PATH)?
+        .into_file()
+        .ok_or(LinuxError::EBADF)
+}
+
+fn load_name(name: *const c_char) -> LinuxResult<String> {
+    let name = vm_load_string(name)?;
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(LinuxError::ERANGE);
+    }
+    Ok(name)
+}
+
+fn do_setxattr(
+    loc: Location,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let name = load_name(name)?;
+    mount::check_writable(&loc)?;
+    let flags = XattrFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    if flags.contains(XattrFlags::CREATE | XattrFlags::REPLACE) {
+        return Err(LinuxError::EINVAL);
+    }
+    if size > XATTR_SIZE_MAX {
+        return Err(LinuxError::E2BIG);
+    }
+    debug!(
+        "setxattr <= name: {:?}, size: {}, flags: {:?}",
+        name, size, flags
+    );
+    let value = if size == 0 {
+        Vec::new()
+    } else {
+        vm_load(value, size)?
+    };
+    xattr::set_xattr(&loc, &name, &value, flags)?;
+    Ok(0)
+}
+
+fn do_getxattr(
+    loc: Location,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    let name = load_name(name)?;
+    debug!("getxattr <= name: {:?}, size: {}", name, size);
+    let data = xattr::get_xattr(&loc, &name)?;
+    if size == 0 {
+        return Ok(data.len() as _);
+    }
+    if data.len() > size {
+        return Err(LinuxError::ERANGE);
+    }
+    vm_write_slice(value, &data)?;
+    Ok(data.len() as _)
+}
+
+fn do_listxattr(loc: Location, list: *mut c_char, size: usize) -> LinuxResult<isize> {
+    let mut data = Vec::new();
+    for name in xattr::list_xattr(&loc)? {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+    }
+    if size == 0 {
+        return Ok(data.len() as _);
+    }
+    if data.len() > size {
+        return Err(LinuxError::ERANGE);
+    }
+    vm_write_slice(list.cast::<u8>(), &data)?;
+    Ok(data.len() as _)
+}
+
+fn do_removexattr(loc: Location, name: *const c_char) -> LinuxResult<isize> {
+    let name = load_name(name)?;
+    debug!("removexattr <= name: {:?}", name);
+    mount::check_writable(&loc)?;
+    xattr::remove_xattr(&loc, &name)?;
+    Ok(0)
+}
+
+pub fn sys_setxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let loc = resolve_path(path, 0)?;
+    do_setxattr(loc, name, value, size, flags)
+}
+
+pub fn sys_lsetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let loc = resolve_path(path, AT_SYMLINK_NOFOLLOW)?;
+    do_setxattr(loc, name, value, size, flags)
+}
+
+pub fn sys_fsetxattr(
+    fd: c_int,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let loc = resolve_fd(fd)?;
+    do_setxattr(loc, name, value, size, flags)
+}
+
+pub fn sys_getxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    let loc = resolve_path(path, 0)?;
+    do_getxattr(loc, name, value, size)
+}
+
+pub fn sys_lgetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    let loc = resolve_path(path, AT_SYMLINK_NOFOLLOW)?;
+    do_getxattr(loc, name, value, size)
+}
+
+pub fn sys_fgetxattr(
+    fd: c_int,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    let loc = resolve_fd(fd)?;
+    do_getxattr(loc, name, value, size)
+}
+
+pub fn sys_listxattr(path: *const c_char, list: *mut c_char, size: usize) -> LinuxResult<isize> {
+    let loc = resolve_path(path, 0)?;
+    do_listxattr(loc, list, size)
+}
+
+pub fn sys_llistxattr(path: *const c_char, list: *mut c_char, size: usize) -> LinuxResult<isize> {
+    let loc = resolve_path(path, AT_SYMLINK_NOFOLLOW)?;
+    do_listxattr(loc, list, size)
+}
+
+pub fn sys_flistxattr(fd: c_int, list: *mut c_char, size: usize) -> LinuxResult<isize> {
+    let loc = resolve_fd(fd)?;
+    do_listxattr(loc, list, size)
+}
+
+pub fn sys_removexattr(path: *const c_char, name: *const c_char) -> LinuxResult<isize> {
+    let loc = resolve_path(path, 0)?;
+    do_removexattr(loc, name)
+}
+
+pub fn sys_lremovexattr(path: *const c_char, name: *const c_char) -> LinuxResult<isize> {
+    let loc = resolve_path(path, AT_SYMLINK_NOFOLLOW)?;
+    do_removexattr(loc, name)
+}
+
+pub fn sys_fremovexattr(fd: c_int, name: *const c_char) -> LinuxResult<isize> {
+    let loc = resolve_fd(fd)?;
+    do_removexattr(loc, name)
+}
@@ -164,8 +164,8 @@ pub fn sys_select(
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct SignalSetWithSize {
-    set: UserConstPtr<SignalSet>,
-    sigsetsize: usize,
+    pub set: UserConstPtr<SignalSet>,
+    pub sigsetsize: usize,
 }
 
 pub fn sys_pselect6(
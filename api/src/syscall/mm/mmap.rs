@@ -13,7 +13,10 @@ use starry_core::{
 };
 use starry_vm::{vm_load, vm_write_slice};
 
-use crate::file::{File, FileLike};
+use crate::{
+    file::{File, FileLike, io_uring::IoUring},
+    vfs::{mount, tmp, writeback},
+};
 
 bitflags::bitflags! {
     /// `PROT_*` flags for use with [`sys_mmap`].
@@ -169,11 +172,29 @@ pub fn sys_mmap(
             .ok_or(LinuxError::ENOMEM)?
     };
 
+    if fd > 0
+        && let Ok(ring) = IoUring::from_fd(fd)
+    {
+        // The rings are shared with the workers, so they can only be mapped
+        // as a whole and shared.
+        if map_type == MmapFlags::PRIVATE {
+            return Err(LinuxError::EINVAL);
+        }
+        let backend = Backend::new_shared(start, ring.ring().mmap_pages(offset, length)?);
+        aspace.map(start, length, permission_flags.into(), false, backend)?;
+        return Ok(start.as_usize() as _);
+    }
+
     let file = if fd > 0 {
         Some(File::from_fd(fd)?)
     } else {
         None
     };
+    if let Some(file) = &file
+        && permission_flags.contains(MmapProt::EXEC)
+    {
+        mount::check_exec(file.inner().location()).map_err(|_| LinuxError::EPERM)?;
+    }
 
     let backend = match map_type {
         MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE => {
@@ -182,6 +203,16 @@ pub fn sys_mmap(
                 let backend = file.backend()?.clone();
                 match file.backend()?.clone() {
                     FileBackend::Cached(cache) => {
+                        if permission_flags.contains(MmapProt::WRITE) {
+                            // Stores through the mapping are only written
+                            // back by a later sync.
+                            writeback::mark_dirty(&backend, offset as u64, 0);
+                            // They may also fill holes of a tmpfs file.
+                            if let Some(node) = tmp::memory_node(file.location()) {
+                                node.populate(offset as u64, offset as u64 + length as u64)
+                                    .map_err(|_| LinuxError::ENOMEM)?;
+                            }
+                        }
                         // TODO(mivik): file mmap page size
                         Backend::new_file(
                             start,
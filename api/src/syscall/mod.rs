@@ -1,3 +1,4 @@
+mod aio;
 mod fs;
 mod io_mpx;
 mod ipc;
@@ -15,8 +16,8 @@ use axhal::context::TrapFrame;
 use syscalls::Sysno;
 
 use self::{
-    fs::*, io_mpx::*, ipc::*, mm::*, net::*, resources::*, signal::*, sync::*, sys::*, task::*,
-    time::*,
+    aio::*, fs::*, io_mpx::*, ipc::*, mm::*, net::*, resources::*, signal::*, sync::*, sys::*,
+    task::*, time::*,
 };
 
 pub fn handle_syscall(tf: &mut TrapFrame) {
@@ -37,6 +38,14 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
         #[cfg(target_arch = "x86_64")]
         Sysno::mkdir => sys_mkdir(tf.arg0() as _, tf.arg1() as _),
         Sysno::mkdirat => sys_mkdirat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::mknod => sys_mknod(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::mknodat => sys_mknodat(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
         Sysno::getdents64 => sys_getdents64(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::link => sys_link(tf.arg0() as _, tf.arg1() as _),
@@ -111,6 +120,53 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
             tf.arg3() as _,
         ),
 
+        // xattr
+        Sysno::setxattr => sys_setxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        Sysno::lsetxattr => sys_lsetxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        Sysno::fsetxattr => sys_fsetxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        Sysno::getxattr => sys_getxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::lgetxattr => sys_lgetxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::fgetxattr => sys_fgetxattr(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::listxattr => sys_listxattr(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::llistxattr => sys_llistxattr(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::flistxattr => sys_flistxattr(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::removexattr => sys_removexattr(tf.arg0() as _, tf.arg1() as _),
+        Sysno::lremovexattr => sys_lremovexattr(tf.arg0() as _, tf.arg1() as _),
+        Sysno::fremovexattr => sys_fremovexattr(tf.arg0() as _, tf.arg1() as _),
+
         // fd ops
         #[cfg(target_arch = "x86_64")]
         Sysno::open => sys_open(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
@@ -151,6 +207,7 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
             tf.arg2() as _,
             tf.arg3() as _,
         ),
+        Sysno::readahead => sys_readahead(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         Sysno::pread64 => sys_pread64(
             tf.arg0() as _,
             tf.arg1() as _,
@@ -211,6 +268,18 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
             tf.arg4() as _,
             tf.arg5() as _,
         ),
+        Sysno::tee => sys_tee(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::vmsplice => sys_vmsplice(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
 
         // io mpx
         #[cfg(target_arch = "x86_64")]
@@ -262,6 +331,42 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
             tf.arg5() as _,
         ),
 
+        // aio
+        Sysno::io_setup => sys_io_setup(tf.arg0() as _, tf.arg1().into()),
+        Sysno::io_destroy => sys_io_destroy(tf.arg0() as _),
+        Sysno::io_submit => sys_io_submit(tf.arg0() as _, tf.arg1() as _, tf.arg2().into()),
+        Sysno::io_cancel => sys_io_cancel(tf.arg0() as _, tf.arg1() as _, tf.arg2().into()),
+        Sysno::io_getevents => sys_io_getevents(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4().into(),
+        ),
+        Sysno::io_pgetevents => sys_io_pgetevents(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4().into(),
+            tf.arg5().into(),
+        ),
+        Sysno::io_uring_setup => sys_io_uring_setup(tf.arg0() as _, tf.arg1().into()),
+        Sysno::io_uring_enter => sys_io_uring_enter(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::io_uring_register => sys_io_uring_register(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+
         // fs mount
         Sysno::mount => sys_mount(
             tf.arg0() as _,
@@ -271,6 +376,24 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
             tf.arg4() as _,
         ) as _,
         Sysno::umount2 => sys_umount2(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::fsopen => sys_fsopen(tf.arg0() as _, tf.arg1() as _),
+        Sysno::fspick => sys_fspick(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::fsconfig => sys_fsconfig(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        Sysno::fsmount => sys_fsmount(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::open_tree => sys_open_tree(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::move_mount => sys_move_mount(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
 
         // pipe
         Sysno::pipe2 => sys_pipe2(tf.arg0() as _, tf.arg1() as _),
@@ -400,7 +523,10 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
         Sysno::umask => sys_umask(tf.arg0() as _),
         Sysno::setreuid => sys_setreuid(tf.arg0() as _, tf.arg1() as _),
         Sysno::setresuid => sys_setresuid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::setregid => sys_setregid(tf.arg0() as _, tf.arg1() as _),
         Sysno::setresgid => sys_setresgid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::getresuid => sys_getresuid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::getresgid => sys_getresgid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         Sysno::get_mempolicy => sys_get_mempolicy(
             tf.arg0() as _,
             tf.arg1() as _,
@@ -420,6 +546,7 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
         ),
         #[cfg(target_arch = "x86_64")]
         Sysno::fork => sys_fork(tf),
+        Sysno::unshare => sys_unshare(tf.arg0() as _),
         Sysno::exit => sys_exit(tf.arg0() as _),
         Sysno::exit_group => sys_exit_group(tf.arg0() as _),
         Sysno::wait4 => sys_waitpid(tf, tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
@@ -554,6 +681,19 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
         ),
         Sysno::sendmsg => sys_sendmsg(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
         Sysno::recvmsg => sys_recvmsg(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::sendmmsg => sys_sendmmsg(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::recvmmsg => sys_recvmmsg(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4().into(),
+        ),
         Sysno::getsockopt => sys_getsockopt(
             tf.arg0() as _,
             tf.arg1() as _,
@@ -576,11 +716,7 @@ pub fn handle_syscall(tf: &mut TrapFrame) {
         | Sysno::inotify_init1
         | Sysno::userfaultfd
         | Sysno::perf_event_open
-        | Sysno::io_uring_setup
         | Sysno::bpf
-        | Sysno::fsopen
-        | Sysno::fspick
-        | Sysno::open_tree
         | Sysno::memfd_secret => sys_dummy_fd(sysno),
 
         Sysno::timer_create | Sysno::timer_gettime | Sysno::timer_settime => Ok(0),
@@ -1,15 +1,136 @@
 use alloc::{sync::Arc, vec::Vec};
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use axerrno::{LinuxError, LinuxResult};
-use linux_raw_sys::net::{SCM_RIGHTS, SOL_SOCKET, cmsghdr};
+use axhal::time::TimeValue;
+use axnet::options::UnixCredentials;
+use axtask::current;
+use linux_raw_sys::net::{
+    AF_INET, AF_INET6, IP_PKTINFO, IP_RECVERR, IP_TOS, IP_TTL, IPV6_PKTINFO, IPV6_RECVERR,
+    SCM_CREDENTIALS, SCM_RIGHTS, SOL_SOCKET, cmsghdr,
+};
+use starry_core::task::AsThread;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 use crate::{
-    file::{FileLike, get_file_like},
+    file::{FileLike, add_file_like, get_file_like},
     mm::{UserConstPtr, UserPtr},
+    net::raw::IcmpError,
 };
 
+const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
+const PROTO_IPV6: u32 = linux_raw_sys::net::IPPROTO_IPV6 as u32;
+
+/// `SO_TIMESTAMP`, and `SCM_TIMESTAMP`, with 64-bit time values.
+pub const SO_TIMESTAMP: u32 = 29;
+/// `SO_TIMESTAMPNS`, and `SCM_TIMESTAMPNS`, with 64-bit time values.
+pub const SO_TIMESTAMPNS: u32 = 35;
+
+/// `SO_EE_ORIGIN_ICMP` and `SO_EE_ORIGIN_ICMP6`, extended errors that come
+/// from ICMP messages.
+const SO_EE_ORIGIN_ICMP: u8 = 2;
+const SO_EE_ORIGIN_ICMP6: u8 = 3;
+
+/// Control messages are aligned to this.
+const CMSG_ALIGN: usize = size_of::<usize>();
+
+/// `struct ucred`.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct Ucred {
+    pid: i32,
+    uid: u32,
+    gid: u32,
+}
+
+/// Checks that the sender may claim `cred`, as Linux does: its own process
+/// id unless it is root, and one of its own user and group ids unless it is
+/// root.
+fn check_credentials(cred: &Ucred) -> LinuxResult<()> {
+    let proc_data = &current().as_thread().proc_data;
+    let own = proc_data.cred();
+    if own.is_privileged() {
+        return Ok(());
+    }
+    let pid_ok = cred.pid as u32 == proc_data.proc.pid();
+    let uid_ok = [own.uid, own.euid, own.suid].contains(&cred.uid);
+    let gid_ok = [own.gid, own.egid, own.sgid].contains(&cred.gid);
+    if pid_ok && uid_ok && gid_ok {
+        Ok(())
+    } else {
+        Err(LinuxError::EPERM)
+    }
+}
+
+/// `struct in_pktinfo`.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct InPktInfo {
+    ipi_ifindex: i32,
+    ipi_spec_dst: [u8; 4],
+    ipi_addr: [u8; 4],
+}
+
+/// `struct in6_pktinfo`.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct In6PktInfo {
+    ipi6_addr: [u8; 16],
+    ipi6_ifindex: i32,
+}
+
+/// `struct timeval` or `struct timespec`, which only differ in the unit of
+/// the second field.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct Time {
+    sec: i64,
+    frac: i64,
+}
+
+/// `struct sock_extended_err`, followed by the address of the offender.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct SockExtendedErr {
+    ee_errno: u32,
+    ee_origin: u8,
+    ee_type: u8,
+    ee_code: u8,
+    ee_pad: u8,
+    ee_info: u32,
+    ee_data: u32,
+}
+
 pub enum CMsg {
-    Rights { fds: Vec<Arc<dyn FileLike>> },
+    Rights {
+        fds: Vec<Arc<dyn FileLike>>,
+    },
+    /// `SCM_CREDENTIALS`, the credentials of the sender.
+    Credentials(UnixCredentials),
+    /// `SCM_TIMESTAMP` or `SCM_TIMESTAMPNS`, when a message came in.
+    Timestamp {
+        time: TimeValue,
+        nanos: bool,
+    },
+    /// `IP_PKTINFO`, the interface a packet came in on, the local address
+    /// it came in at and its destination.
+    Ipv4PktInfo {
+        ifindex: u32,
+        spec_dst: Ipv4Addr,
+        addr: Ipv4Addr,
+    },
+    /// `IPV6_PKTINFO`, the interface a packet came in on and its
+    /// destination.
+    Ipv6PktInfo {
+        ifindex: u32,
+        addr: Ipv6Addr,
+    },
+    /// `IP_TTL`, the time to live of a packet.
+    Ttl(u8),
+    /// `IP_TOS`, the type of service of a packet.
+    Tos(u8),
+    /// `IP_RECVERR` or `IPV6_RECVERR`, an extended error.
+    Error(IcmpError),
 }
 impl CMsg {
     pub fn parse(hdr: &cmsghdr) -> LinuxResult<Self> {
@@ -36,11 +157,144 @@ impl CMsg {
                 }
                 Self::Rights { fds }
             }
+            (SOL_SOCKET, SCM_CREDENTIALS) => {
+                let cred = Ucred::read_from_bytes(data).map_err(|_| LinuxError::EINVAL)?;
+                check_credentials(&cred)?;
+                Self::Credentials(UnixCredentials {
+                    pid: cred.pid,
+                    uid: cred.uid,
+                    gid: cred.gid,
+                })
+            }
+            (PROTO_IP, IP_PKTINFO) => {
+                let info = InPktInfo::read_from_bytes(data).map_err(|_| LinuxError::EINVAL)?;
+                Self::Ipv4PktInfo {
+                    ifindex: info.ipi_ifindex as u32,
+                    spec_dst: info.ipi_spec_dst.into(),
+                    addr: info.ipi_addr.into(),
+                }
+            }
+            (PROTO_IPV6, IPV6_PKTINFO) => {
+                let info = In6PktInfo::read_from_bytes(data).map_err(|_| LinuxError::EINVAL)?;
+                Self::Ipv6PktInfo {
+                    ifindex: info.ipi6_ifindex as u32,
+                    addr: info.ipi6_addr.into(),
+                }
+            }
             _ => {
                 return Err(axerrno::LinuxError::EINVAL);
             }
         })
     }
+
+    /// Appends the message to `builder`, installing the files it passes
+    /// with `cloexec`. Returns whether all of it fit.
+    pub fn write_to(self, builder: &mut CMsgBuilder, cloexec: bool) -> LinuxResult<bool> {
+        let mut body = Vec::new();
+        let (level, ty) = match self {
+            Self::Rights { fds } => {
+                let mut fit = false;
+                let pushed = builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
+                    fit = fds.len() <= data.len() / size_of::<i32>();
+                    let mut written = 0;
+                    for (f, chunk) in fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>())) {
+                        let fd = add_file_like(f, cloexec)?;
+                        chunk.copy_from_slice(&fd.to_ne_bytes());
+                        written += size_of::<i32>();
+                    }
+                    Ok(written)
+                })?;
+                return Ok(pushed && fit);
+            }
+            Self::Credentials(cred) => {
+                let cred = Ucred {
+                    pid: cred.pid,
+                    uid: cred.uid,
+                    gid: cred.gid,
+                };
+                body.extend_from_slice(cred.as_bytes());
+                (SOL_SOCKET, SCM_CREDENTIALS)
+            }
+            Self::Timestamp { time, nanos } => {
+                let (frac, ty) = if nanos {
+                    (time.subsec_nanos(), SO_TIMESTAMPNS)
+                } else {
+                    (time.subsec_micros(), SO_TIMESTAMP)
+                };
+                let time = Time {
+                    sec: time.as_secs() as i64,
+                    frac: frac as i64,
+                };
+                body.extend_from_slice(time.as_bytes());
+                (SOL_SOCKET, ty)
+            }
+            Self::Ipv4PktInfo {
+                ifindex,
+                spec_dst,
+                addr,
+            } => {
+                let info = InPktInfo {
+                    ipi_ifindex: ifindex as i32,
+                    ipi_spec_dst: spec_dst.octets(),
+                    ipi_addr: addr.octets(),
+                };
+                body.extend_from_slice(info.as_bytes());
+                (PROTO_IP, IP_PKTINFO)
+            }
+            Self::Ipv6PktInfo { ifindex, addr } => {
+                let info = In6PktInfo {
+                    ipi6_addr: addr.octets(),
+                    ipi6_ifindex: ifindex as i32,
+                };
+                body.extend_from_slice(info.as_bytes());
+                (PROTO_IPV6, IPV6_PKTINFO)
+            }
+            Self::Ttl(ttl) => {
+                body.extend_from_slice(&(ttl as i32).to_ne_bytes());
+                (PROTO_IP, IP_TTL)
+            }
+            Self::Tos(tos) => {
+                body.push(tos);
+                (PROTO_IP, IP_TOS)
+            }
+            Self::Error(err) => {
+                let origin = if err.offender.is_ipv4() {
+                    SO_EE_ORIGIN_ICMP
+                } else {
+                    SO_EE_ORIGIN_ICMP6
+                };
+                let ee = SockExtendedErr {
+                    ee_errno: err.errno.code() as u32,
+                    ee_origin: origin,
+                    ee_type: err.ty,
+                    ee_code: err.code,
+                    ee_pad: 0,
+                    ee_info: err.info,
+                    ee_data: 0,
+                };
+                body.extend_from_slice(ee.as_bytes());
+                // The offender, as a `struct sockaddr_in` or `struct
+                // sockaddr_in6` without a port.
+                match err.offender {
+                    IpAddr::V4(addr) => {
+                        body.extend_from_slice(&(AF_INET as u16).to_ne_bytes());
+                        body.extend_from_slice(&[0; 2]);
+                        body.extend_from_slice(&addr.octets());
+                        body.extend_from_slice(&[0; 8]);
+                        (PROTO_IP, IP_RECVERR)
+                    }
+                    IpAddr::V6(addr) => {
+                        body.extend_from_slice(&(AF_INET6 as u16).to_ne_bytes());
+                        body.extend_from_slice(&[0; 6]);
+                        body.extend_from_slice(&addr.octets());
+                        body.extend_from_slice(&[0; 4]);
+                        (PROTO_IPV6, IPV6_RECVERR)
+                    }
+                }
+            }
+        };
+        builder.push_bytes(level, ty, &body)
+    }
 }
 
 pub struct CMsgBuilder<'a> {
@@ -80,8 +334,25 @@ impl<'a> CMsgBuilder<'a> {
 
         let cmsg_len = size_of::<cmsghdr>() + body_len;
         hdr.cmsg_len = cmsg_len;
-        self.hdr = UserPtr::from(hdr as *const _ as usize + cmsg_len);
-        *self.len += cmsg_len;
+        // The next message starts aligned, unless the buffer ends first.
+        let space = cmsg_len
+            .next_multiple_of(CMSG_ALIGN)
+            .min(self.capacity - *self.len);
+        self.hdr = UserPtr::from(hdr as *const _ as usize + space);
+        *self.len += space;
         Ok(true)
     }
+
+    /// Appends a message with body `data`, or as much of it as fits.
+    /// Returns whether all of it did.
+    pub fn push_bytes(&mut self, level: u32, ty: u32, data: &[u8]) -> LinuxResult<bool> {
+        let mut fit = false;
+        let pushed = self.push(level, ty, |buf| {
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            fit = len == data.len();
+            Ok(len)
+        })?;
+        Ok(pushed && fit)
+    }
 }
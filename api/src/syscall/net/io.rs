@@ -1,47 +1,129 @@
 use alloc::{boxed::Box, vec::Vec};
-use core::net::Ipv4Addr;
+use core::net::{IpAddr, SocketAddr};
 
-use axerrno::LinuxResult;
-use axio::{Buf, BufMut};
-use axnet::{CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx, SocketOps};
-use linux_raw_sys::net::{
-    MSG_PEEK, MSG_TRUNC, SCM_RIGHTS, SOL_SOCKET, cmsghdr, msghdr, sockaddr, socklen_t,
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{TimeValue, monotonic_time, wall_time};
+use axio::{Buf, BufMut, IoEvents, Pollable};
+use axnet::{
+    CMsgData, RecvFlags, SendFlags, SocketAddrEx,
+    options::{GetSocketOption, UnixCredentials},
 };
+use axtask::{current, future::Poller};
+use linux_raw_sys::{
+    general::timespec,
+    net::{
+        AF_UNIX, MSG_CMSG_CLOEXEC, MSG_CTRUNC, MSG_DONTWAIT, MSG_ERRQUEUE, MSG_NOSIGNAL, MSG_OOB,
+        MSG_PEEK, MSG_TRUNC, MSG_WAITALL, cmsghdr, msghdr, sockaddr, socklen_t,
+    },
+};
+use starry_core::task::AsThread;
 use starry_vm::{VmBytes, VmBytesMut};
 
 use crate::{
-    file::{FileLike, Socket, add_file_like},
+    file::{FileLike, Socket, Timestamp, raise_pipe},
     io::{IoVec, IoVectorBuf},
-    mm::{UserConstPtr, UserPtr},
-    socket::SocketAddrExt,
+    mm::{UserConstPtr, UserPtr, nullable},
+    net::raw::PacketInfo,
+    socket::{SockAddr, SocketAddrExt},
     syscall::net::{CMsg, CMsgBuilder},
+    time::TimeValueLike,
 };
 
+/// `MSG_WAITFORONE`, for `recvmmsg`: only wait for the first message.
+const MSG_WAITFORONE: u32 = 0x10000;
+/// Most messages `sendmmsg` and `recvmmsg` handle in one call, `UIO_MAXIOV`.
+const MAX_MMSG: u32 = 1024;
+/// `overflowuid` and `overflowgid`, reported when the sender of a message
+/// is not known.
+const OVERFLOW_ID: u32 = 65534;
+
+/// `struct mmsghdr`.
+#[repr(C)]
+pub struct MMsgHdr {
+    msg_hdr: msghdr,
+    /// Number of bytes sent or received.
+    msg_len: u32,
+}
+
+/// Returns the credentials of the calling process.
+fn own_credentials() -> LinuxResult<UnixCredentials> {
+    let proc_data = &current().as_thread().proc_data;
+    let cred = proc_data.cred();
+    Ok(UnixCredentials {
+        pid: proc_data.proc.pid() as _,
+        uid: cred.uid as _,
+        gid: cred.gid as _,
+    })
+}
+
+/// Returns whether `socket` wants the credentials of senders,
+/// `SO_PASSCRED`.
+fn passes_credentials(socket: &Socket) -> bool {
+    let mut passcred = false;
+    socket.family() == AF_UNIX
+        && socket
+            .get_option(GetSocketOption::PassCredentials(&mut passcred))
+            .is_ok()
+        && passcred
+}
+
 fn send_impl(
     fd: i32,
     mut src: impl Buf,
     flags: u32,
     addr: UserConstPtr<sockaddr>,
     addrlen: socklen_t,
-    cmsg: Vec<CMsgData>,
+    cmsg: Vec<CMsg>,
 ) -> LinuxResult<isize> {
     let addr = if addr.is_null() || addrlen == 0 {
         None
     } else {
-        Some(SocketAddrEx::read_from_user(addr, addrlen)?)
+        Some(SockAddr::read_from_user(addr, addrlen)?)
     };
 
     debug!("sys_send <= fd: {}, flags: {}, addr: {:?}", fd, flags, addr);
 
     let socket = Socket::from_fd(fd)?;
-    let sent = socket.send(
-        &mut src,
-        SendOptions {
-            to: addr,
-            flags: SendFlags::default(),
+    // The network stack has no urgent data. TCP sends it inline, which is
+    // what peers with `SO_OOBINLINE` see anyway.
+    if flags & MSG_OOB != 0 && !socket.is_tcp() {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+
+    let mut has_credentials = false;
+    let mut cmsg = cmsg
+        .into_iter()
+        .filter_map(|msg| {
+            match msg {
+                CMsg::Credentials(_) if socket.family() != AF_UNIX => {
+                    return Some(Err(LinuxError::EINVAL));
+                }
+                CMsg::Credentials(_) => has_credentials = true,
+                // The network stack picks the source address by itself.
+                CMsg::Ipv4PktInfo { .. } | CMsg::Ipv6PktInfo { .. } => return None,
+                _ => {}
+            }
+            Some(Ok(Box::new(msg) as CMsgData))
+        })
+        .collect::<LinuxResult<Vec<_>>>()?;
+    // Senders that want credentials themselves pass theirs.
+    if !has_credentials && passes_credentials(&socket) {
+        cmsg.push(Box::new(CMsg::Credentials(own_credentials()?)));
+    }
+
+    let sent = socket
+        .send(
+            &mut src,
+            addr,
+            SendFlags::default(),
             cmsg,
-        },
-    )?;
+            flags & MSG_DONTWAIT != 0,
+        )
+        .inspect_err(|err| {
+            if *err == LinuxError::EPIPE && flags & MSG_NOSIGNAL == 0 {
+                raise_pipe();
+            }
+        })?;
 
     Ok(sent as isize)
 }
@@ -57,8 +139,7 @@ pub fn sys_sendto(
     send_impl(fd, VmBytes::new(buf, len), flags, addr, addrlen, Vec::new())
 }
 
-pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
-    let msg = msg.get_as_ref()?;
+fn sendmsg_impl(fd: i32, msg: &msghdr, flags: u32) -> LinuxResult<isize> {
     let mut cmsg = Vec::new();
     if !msg.msg_control.is_null() {
         let mut ptr = msg.msg_control as usize;
@@ -66,10 +147,10 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResul
         while ptr + size_of::<cmsghdr>() <= ptr_end {
             let hdr = UserConstPtr::<cmsghdr>::from(ptr).get_as_ref()?;
             if ptr_end - ptr < hdr.cmsg_len {
-                return Err(axerrno::LinuxError::EINVAL);
+                return Err(LinuxError::EINVAL);
             }
-            cmsg.push(Box::new(CMsg::parse(hdr)?) as CMsgData);
-            ptr += hdr.cmsg_len;
+            cmsg.push(CMsg::parse(hdr)?);
+            ptr += hdr.cmsg_len.next_multiple_of(size_of::<usize>());
         }
     }
     send_impl(
@@ -82,6 +163,39 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResul
     )
 }
 
+pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    sendmsg_impl(fd, msg.get_as_ref()?, flags)
+}
+
+pub fn sys_sendmmsg(
+    fd: i32,
+    msgvec: UserPtr<MMsgHdr>,
+    vlen: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_sendmmsg <= fd: {}, vlen: {}, flags: {}",
+        fd, vlen, flags
+    );
+    let msgs = msgvec.get_as_mut_slice(vlen.min(MAX_MMSG) as usize)?;
+    let mut sent = 0;
+    for msg in msgs {
+        match sendmsg_impl(fd, &msg.msg_hdr, flags) {
+            Ok(len) => msg.msg_len = len as u32,
+            // Once a message is sent, an error ends the call successfully.
+            Err(err) if sent > 0 => {
+                debug!("sys_sendmmsg: stopped after {} messages: {:?}", sent, err);
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Receives into `dst`, and returns the length received together with the
+/// `msg_flags` to report.
 fn recv_impl(
     fd: i32,
     mut dst: impl BufMut,
@@ -89,61 +203,203 @@ fn recv_impl(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
     cmsg_builder: Option<CMsgBuilder>,
-) -> LinuxResult<isize> {
+) -> LinuxResult<(isize, u32)> {
     debug!("sys_recv <= fd: {}, flags: {}", fd, flags);
 
     let socket = Socket::from_fd(fd)?;
+    // Unix sockets have no error queue and ignore the flag.
+    if flags & MSG_ERRQUEUE != 0 && socket.family() != AF_UNIX {
+        return recv_error(&socket, dst, addr, addrlen, cmsg_builder);
+    }
+    if flags & MSG_OOB != 0 {
+        // Urgent data comes in inline, so none is ever pending.
+        return Err(if socket.is_tcp() {
+            LinuxError::EINVAL
+        } else {
+            LinuxError::EOPNOTSUPP
+        });
+    }
+
+    let stream = socket.is_stream();
+    let dontwait = flags & MSG_DONTWAIT != 0;
     let mut recv_flags = RecvFlags::empty();
     if flags & MSG_PEEK != 0 {
         recv_flags |= RecvFlags::PEEK;
     }
-    if flags & MSG_TRUNC != 0 {
+    if !stream || flags & MSG_TRUNC != 0 {
+        // The full length of a datagram is needed to tell whether it was
+        // truncated.
         recv_flags |= RecvFlags::TRUNCATE;
     }
 
     let mut cmsg = Vec::new();
+    let capacity = dst.remaining_mut();
 
-    let mut remote_addr =
-        (!addr.is_null()).then(|| SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into()));
-    let recv = socket.recv(
+    let (mut recv, remote_addr, info) = socket.recv(
         &mut dst,
-        RecvOptions {
-            from: remote_addr.as_mut(),
-            flags: recv_flags,
-            cmsg: Some(&mut cmsg),
-        },
+        recv_flags,
+        Some(&mut cmsg),
+        !addr.is_null(),
+        dontwait,
     )?;
 
-    if let Some(remote_addr) = remote_addr {
+    if stream && flags & MSG_WAITALL != 0 && flags & MSG_PEEK == 0 {
+        // What has been received so far is returned once the peer shuts
+        // down or an error occurs; the error is left for the next call,
+        // unless it only says that the wait was cut short.
+        while recv > 0 && dst.remaining_mut() > 0 {
+            match socket.recv(&mut dst, recv_flags, Some(&mut cmsg), false, dontwait) {
+                Ok((0, ..)) => break,
+                Ok((len, ..)) => recv += len,
+                Err(LinuxError::EAGAIN | LinuxError::EINTR) => break,
+                Err(err) => {
+                    socket.set_pending_error(err);
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut msg_flags = 0;
+    if !stream && recv > capacity {
+        msg_flags |= MSG_TRUNC;
+        if flags & MSG_TRUNC == 0 {
+            recv = capacity;
+        }
+    }
+
+    if let Some(remote_addr) = remote_addr
+        && !addr.is_null()
+    {
         remote_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
     }
 
+    let msgs = control_messages(&socket, info, cmsg);
+    let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
     if let Some(mut builder) = cmsg_builder {
-        for cmsg in cmsg {
-            let Ok(cmsg) = cmsg.downcast::<CMsg>() else {
-                warn!("received unexpected cmsg");
-                continue;
-            };
-
-            let pushed = match *cmsg {
-                CMsg::Rights { fds } => builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
-                    let mut written = 0;
-                    for (f, chunk) in fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>())) {
-                        let fd = add_file_like(f, false)?;
-                        chunk.copy_from_slice(&fd.to_ne_bytes());
-                        written += size_of::<i32>();
-                    }
-                    Ok(written)
-                })?,
-            };
-            if !pushed {
+        for msg in msgs {
+            if !msg.write_to(&mut builder, cloexec)? {
+                msg_flags |= MSG_CTRUNC;
                 break;
             }
         }
+    } else if !msgs.is_empty() {
+        msg_flags |= MSG_CTRUNC;
     }
 
     debug!("sys_recv => fd: {}, recv: {}", fd, recv);
-    Ok(recv as isize)
+    Ok((recv as isize, msg_flags))
+}
+
+/// Collects the control messages of a message received: the ancillary data
+/// the socket asks for, then what came with the message.
+fn control_messages(
+    socket: &Socket,
+    info: Option<PacketInfo>,
+    received: Vec<CMsgData>,
+) -> Vec<CMsg> {
+    let opts = *socket.ancillary_options().lock();
+    let mut msgs = Vec::new();
+    if let Some(timestamp) = opts.timestamp {
+        // Other messages than IP datagrams are stamped as they are taken.
+        msgs.push(CMsg::Timestamp {
+            time: info.map_or_else(wall_time, |it| it.time),
+            nanos: timestamp == Timestamp::Nano,
+        });
+    }
+    if let Some(info) = info {
+        if let IpAddr::V4(dst) = info.dst {
+            if opts.pktinfo {
+                msgs.push(CMsg::Ipv4PktInfo {
+                    ifindex: info.ifindex,
+                    spec_dst: dst,
+                    addr: dst,
+                });
+            }
+            if opts.recv_ttl
+                && let Some(ttl) = info.ttl
+            {
+                msgs.push(CMsg::Ttl(ttl));
+            }
+            if opts.recv_tos
+                && let Some(tos) = info.tos
+            {
+                msgs.push(CMsg::Tos(tos));
+            }
+        }
+        if socket.ipv6_options().is_ok_and(|it| it.lock().recv_pktinfo) {
+            let addr = match info.dst {
+                IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+                IpAddr::V6(addr) => addr,
+            };
+            msgs.push(CMsg::Ipv6PktInfo {
+                ifindex: info.ifindex,
+                addr,
+            });
+        }
+    }
+
+    let mut credentials = None;
+    let mut rest = Vec::new();
+    for msg in received {
+        let Ok(msg) = msg.downcast::<CMsg>() else {
+            warn!("received unexpected cmsg");
+            continue;
+        };
+        match *msg {
+            CMsg::Credentials(cred) => {
+                credentials.get_or_insert(cred);
+            }
+            msg => rest.push(msg),
+        }
+    }
+    if passes_credentials(socket) {
+        // Senders that did not pass credentials are taken to be the peer,
+        // if there is one.
+        let credentials = credentials.unwrap_or_else(|| {
+            let mut peer = UnixCredentials::default();
+            match socket.get_option(GetSocketOption::PeerCredentials(&mut peer)) {
+                Ok(()) => peer,
+                Err(_) => UnixCredentials {
+                    pid: 0,
+                    uid: OVERFLOW_ID,
+                    gid: OVERFLOW_ID,
+                },
+            }
+        });
+        msgs.push(CMsg::Credentials(credentials));
+    }
+    msgs.extend(rest);
+    msgs
+}
+
+/// Takes the oldest error queued for `IP_RECVERR`, `MSG_ERRQUEUE`. The
+/// source address given is the destination of the packet in error.
+fn recv_error(
+    socket: &Socket,
+    mut dst: impl BufMut,
+    addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+    cmsg_builder: Option<CMsgBuilder>,
+) -> LinuxResult<(isize, u32)> {
+    let capacity = dst.remaining_mut();
+    let (len, error) = socket.recv_error(&mut dst)?;
+    let mut msg_flags = MSG_ERRQUEUE;
+    if len > capacity {
+        msg_flags |= MSG_TRUNC;
+    }
+    if !addr.is_null() {
+        let dst = SockAddr::from(SocketAddrEx::Ip(SocketAddr::new(error.dst, 0)));
+        dst.write_to_user(addr, addrlen.get_as_mut()?)?;
+    }
+    let pushed = match cmsg_builder {
+        Some(mut builder) => CMsg::Error(error).write_to(&mut builder, false)?,
+        None => false,
+    };
+    if !pushed {
+        msg_flags |= MSG_CTRUNC;
+    }
+    Ok((len.min(capacity) as isize, msg_flags))
 }
 
 pub fn sys_recvfrom(
@@ -154,12 +410,11 @@ pub fn sys_recvfrom(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
-    recv_impl(fd, VmBytesMut::new(buf, len), flags, addr, addrlen, None)
+    recv_impl(fd, VmBytesMut::new(buf, len), flags, addr, addrlen, None).map(|(recv, _)| recv)
 }
 
-pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
-    let msg = msg.get_as_mut()?;
-    recv_impl(
+fn recvmsg_impl(fd: i32, msg: &mut msghdr, flags: u32) -> LinuxResult<isize> {
+    let (recv, msg_flags) = recv_impl(
         fd,
         IoVectorBuf::new(msg.msg_iov as *mut IoVec, msg.msg_iovlen)?.into_io(),
         flags,
@@ -171,5 +426,89 @@ pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isi
                 &mut msg.msg_controllen,
             )
         }),
-    )
+    )?;
+    msg.msg_flags = msg_flags as _;
+    Ok(recv)
+}
+
+pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    recvmsg_impl(fd, msg.get_as_mut()?, flags)
+}
+
+/// Waits up to `timeout` for `socket` to have something to receive.
+fn wait_readable(socket: &Socket, timeout: TimeValue) -> bool {
+    let events = IoEvents::IN | IoEvents::ERR | IoEvents::HUP;
+    Poller::new(socket, IoEvents::IN)
+        .non_blocking(socket.nonblocking() || timeout.is_zero())
+        .timeout(Some(timeout))
+        .poll(|| {
+            if socket.poll().intersects(events) {
+                Ok(())
+            } else {
+                Err(LinuxError::EAGAIN)
+            }
+        })
+        .is_ok()
+}
+
+pub fn sys_recvmmsg(
+    fd: i32,
+    msgvec: UserPtr<MMsgHdr>,
+    vlen: u32,
+    flags: u32,
+    timeout: UserPtr<timespec>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_recvmmsg <= fd: {}, vlen: {}, flags: {}",
+        fd, vlen, flags
+    );
+    let msgs = msgvec.get_as_mut_slice(vlen.min(MAX_MMSG) as usize)?;
+    let timeout = nullable!(timeout.get_as_mut())?;
+    let deadline = timeout
+        .as_deref()
+        .map(|ts| ts.try_into_time_value())
+        .transpose()?
+        .map(|it| monotonic_time() + it);
+    let socket = Socket::from_fd(fd)?;
+
+    let mut received = 0;
+    for msg in msgs {
+        // The first message is waited for as by `recvmsg`, later ones only
+        // until the timeout expires, and not at all with `MSG_WAITFORONE`.
+        if received > 0 {
+            let wait = if flags & MSG_WAITFORONE != 0 {
+                Some(TimeValue::ZERO)
+            } else {
+                deadline.map(|it| it.saturating_sub(monotonic_time()))
+            };
+            if let Some(wait) = wait
+                && !wait_readable(&socket, wait)
+            {
+                break;
+            }
+        }
+        match recvmsg_impl(fd, &mut msg.msg_hdr, flags & !MSG_WAITFORONE) {
+            Ok(len) => msg.msg_len = len as u32,
+            // Once a message is received, an error ends the call
+            // successfully.
+            Err(err) if received > 0 => {
+                debug!(
+                    "sys_recvmmsg: stopped after {} messages: {:?}",
+                    received, err
+                );
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+        received += 1;
+        if deadline.is_some_and(|it| monotonic_time() >= it) {
+            break;
+        }
+    }
+
+    // The time left is written back.
+    if let (Some(ts), Some(deadline)) = (timeout, deadline) {
+        *ts = timespec::from_time_value(deadline.saturating_sub(monotonic_time()));
+    }
+    Ok(received)
 }
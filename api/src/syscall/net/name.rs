@@ -1,5 +1,4 @@
 use axerrno::LinuxResult;
-use axnet::SocketOps;
 use linux_raw_sys::net::{sockaddr, socklen_t};
 
 use crate::{
@@ -14,7 +13,7 @@ pub fn sys_getsockname(
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
     let socket = Socket::from_fd(fd)?;
-    let local_addr = socket.local_addr()?;
+    let local_addr = socket.sock_name()?;
     debug!("sys_getsockname <= fd: {}, addr: {:?}", fd, local_addr);
 
     local_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
@@ -27,7 +26,7 @@ pub fn sys_getpeername(
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
     let socket = Socket::from_fd(fd)?;
-    let peer_addr = socket.peer_addr()?;
+    let peer_addr = socket.peer_name()?;
     debug!("sys_getpeername <= fd: {}, addr: {:?}", fd, peer_addr);
 
     peer_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
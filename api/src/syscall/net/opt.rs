@@ -1,16 +1,129 @@
 use axerrno::{LinuxError, LinuxResult};
-use axnet::options::{Configurable, GetSocketOption, SetSocketOption};
-use linux_raw_sys::net::socklen_t;
+use axnet::options::{GetSocketOption, SetSocketOption};
+use linux_raw_sys::net::{
+    AF_INET, AF_INET6, IP_HDRINCL, IP_PKTINFO, IP_RECVERR, IP_RECVTOS, IP_RECVTTL, IPV6_CHECKSUM,
+    SO_ATTACH_FILTER, SO_DETACH_FILTER, SO_ERROR, SOL_SOCKET, socklen_t,
+};
 
+use super::cmsg::{SO_TIMESTAMP, SO_TIMESTAMPNS};
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, Socket, Timestamp},
     mm::{UserConstPtr, UserPtr},
+    net::{
+        bpf::{BpfProgram, SockFprog},
+        packet::{PACKET_ADD_MEMBERSHIP, PACKET_DROP_MEMBERSHIP, PACKET_STATISTICS, SOL_PACKET},
+        raw::{ICMP_FILTER, ICMPV6_FILTER, SOL_RAW},
+    },
 };
 
 const PROTO_TCP: u32 = linux_raw_sys::net::IPPROTO_TCP as u32;
 
 const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
 
+const PROTO_IPV6: u32 = linux_raw_sys::net::IPPROTO_IPV6 as u32;
+
+const PROTO_ICMPV6: u32 = linux_raw_sys::net::IPPROTO_ICMPV6 as u32;
+
+const SOL_NETLINK: u32 = 270;
+
+/// Default hop limit of unicast packets.
+const DEFAULT_HOPS: u8 = 64;
+
+/// Gets an `IPPROTO_IPV6` option, which the socket keeps by itself except
+/// for the hop limit shared with `IP_TTL`.
+fn get_ipv6_option(socket: &Socket, optname: u32) -> LinuxResult<i32> {
+    use linux_raw_sys::net::*;
+
+    let opts = socket.ipv6_options()?.lock();
+    Ok(match optname {
+        IPV6_V6ONLY => opts.v6only as _,
+        IPV6_MULTICAST_LOOP => opts.multicast_loop as _,
+        IPV6_RECVPKTINFO => opts.recv_pktinfo as _,
+        IPV6_RECVERR => socket.ancillary_options().lock().recv_err as _,
+        IPV6_UNICAST_HOPS => {
+            let mut ttl = 0u8;
+            socket.get_option(GetSocketOption::Ttl(&mut ttl))?;
+            ttl as _
+        }
+        _ => return Err(LinuxError::ENOPROTOOPT),
+    })
+}
+
+/// Sets an `IPPROTO_IPV6` option, see [`get_ipv6_option`].
+fn set_ipv6_option(socket: &Socket, optname: u32, val: i32) -> LinuxResult<()> {
+    use linux_raw_sys::net::*;
+
+    let mut opts = socket.ipv6_options()?.lock();
+    // -1 selects the default hop limit.
+    let hops = |default| match val {
+        -1 => Ok(default),
+        0..=255 => Ok(val),
+        _ => Err(LinuxError::EINVAL),
+    };
+    match optname {
+        IPV6_V6ONLY => {
+            if socket.is_bound() {
+                return Err(LinuxError::EINVAL);
+            }
+            opts.v6only = val != 0;
+        }
+        IPV6_MULTICAST_LOOP => opts.multicast_loop = val != 0,
+        IPV6_RECVPKTINFO => opts.recv_pktinfo = val != 0,
+        IPV6_RECVERR => socket.set_recv_err(val != 0)?,
+        IPV6_UNICAST_HOPS => {
+            let ttl = hops(DEFAULT_HOPS as _)? as u8;
+            socket.set_option(SetSocketOption::Ttl(&ttl))?;
+        }
+        _ => return Err(LinuxError::ENOPROTOOPT),
+    }
+    Ok(())
+}
+
+/// Gets an option choosing the ancillary data received, which the socket
+/// keeps by itself.
+fn get_ancillary_option(socket: &Socket, level: u32, optname: u32) -> LinuxResult<i32> {
+    if level == PROTO_IP && ![AF_INET, AF_INET6].contains(&socket.family()) {
+        return Err(LinuxError::ENOPROTOOPT);
+    }
+    let opts = socket.ancillary_options().lock();
+    Ok(match (level, optname) {
+        (SOL_SOCKET, SO_TIMESTAMP) => (opts.timestamp == Some(Timestamp::Micro)) as _,
+        (SOL_SOCKET, SO_TIMESTAMPNS) => (opts.timestamp == Some(Timestamp::Nano)) as _,
+        (PROTO_IP, IP_PKTINFO) => opts.pktinfo as _,
+        (PROTO_IP, IP_RECVTTL) => opts.recv_ttl as _,
+        (PROTO_IP, IP_RECVTOS) => opts.recv_tos as _,
+        (PROTO_IP, IP_RECVERR) => opts.recv_err as _,
+        _ => return Err(LinuxError::ENOPROTOOPT),
+    })
+}
+
+/// Sets an option choosing the ancillary data received, see
+/// [`get_ancillary_option`]. The two kinds of timestamps replace each other.
+fn set_ancillary_option(socket: &Socket, level: u32, optname: u32, val: i32) -> LinuxResult<()> {
+    if level == PROTO_IP && ![AF_INET, AF_INET6].contains(&socket.family()) {
+        return Err(LinuxError::ENOPROTOOPT);
+    }
+    let on = val != 0;
+    let mut opts = socket.ancillary_options().lock();
+    match (level, optname) {
+        (SOL_SOCKET, SO_TIMESTAMP) => opts.timestamp = on.then_some(Timestamp::Micro),
+        (SOL_SOCKET, SO_TIMESTAMPNS) => opts.timestamp = on.then_some(Timestamp::Nano),
+        (PROTO_IP, IP_PKTINFO) => opts.pktinfo = on,
+        // Only sockets that know the headers they receive can report them.
+        (PROTO_IP, IP_RECVTTL | IP_RECVTOS) if !socket.knows_headers() => {
+            return Err(LinuxError::ENOPROTOOPT);
+        }
+        (PROTO_IP, IP_RECVTTL) => opts.recv_ttl = on,
+        (PROTO_IP, IP_RECVTOS) => opts.recv_tos = on,
+        (PROTO_IP, IP_RECVERR) => {
+            drop(opts);
+            socket.set_recv_err(on)?;
+        }
+        _ => return Err(LinuxError::ENOPROTOOPT),
+    }
+    Ok(())
+}
+
 mod conv {
     use axerrno::{LinuxError, LinuxResult};
     use axnet::options::UnixCredentials;
@@ -138,6 +251,48 @@ pub fn sys_getsockopt(
     }
 
     let socket = Socket::from_fd(fd)?;
+    if (level, optname) == (SOL_SOCKET, SO_ERROR)
+        && let Some(err) = socket.take_pending_error()
+    {
+        *get::<i32>(optval, optlen)? = err.code();
+        return Ok(0);
+    }
+    match (level, optname) {
+        (SOL_RAW, ICMP_FILTER) if socket.family() == AF_INET => {
+            *get::<u32>(optval, optlen)? = socket.raw()?.icmp_filter()?[0];
+            return Ok(0);
+        }
+        (PROTO_ICMPV6, ICMPV6_FILTER) => {
+            *get(optval, optlen)? = socket.raw()?.icmp_filter()?;
+            return Ok(0);
+        }
+        (PROTO_IP, IP_HDRINCL) | (SOL_RAW, _) | (PROTO_IPV6, IPV6_CHECKSUM) => {
+            *get(optval, optlen)? = socket.raw()?.get_raw_option(level, optname)?;
+            return Ok(0);
+        }
+        (SOL_PACKET, PACKET_STATISTICS) => {
+            *get(optval, optlen)? = socket.packet()?.take_stats();
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_TIMESTAMP | SO_TIMESTAMPNS)
+        | (PROTO_IP, IP_PKTINFO | IP_RECVTTL | IP_RECVTOS | IP_RECVERR) => {
+            *get(optval, optlen)? = get_ancillary_option(&socket, level, optname)?;
+            return Ok(0);
+        }
+        (SOL_PACKET, _) => {
+            *get(optval, optlen)? = socket.packet()?.get_packet_option(optname)?;
+            return Ok(0);
+        }
+        _ => {}
+    }
+    if level == PROTO_IPV6 {
+        *get(optval, optlen)? = get_ipv6_option(&socket, optname)?;
+        return Ok(0);
+    }
+    if level == SOL_NETLINK {
+        *get(optval, optlen)? = socket.netlink()?.get_netlink_option(optname)?;
+        return Ok(0);
+    }
     macro_rules! dispatch {
         ($which:ident) => {
             socket.get_option(GetSocketOption::$which(get(optval, optlen)?))?;
@@ -177,6 +332,61 @@ pub fn sys_setsockopt(
     }
 
     let socket = Socket::from_fd(fd)?;
+    match (level, optname) {
+        (SOL_RAW, ICMP_FILTER) if socket.family() == AF_INET => {
+            let mut filter = [0; 8];
+            filter[0] = *get::<u32>(optval, optlen)?;
+            socket.raw()?.set_icmp_filter(filter)?;
+            return Ok(0);
+        }
+        (PROTO_ICMPV6, ICMPV6_FILTER) => {
+            socket.raw()?.set_icmp_filter(*get(optval, optlen)?)?;
+            return Ok(0);
+        }
+        (PROTO_IP, IP_HDRINCL) | (SOL_RAW, _) | (PROTO_IPV6, IPV6_CHECKSUM) => {
+            socket
+                .raw()?
+                .set_raw_option(level, optname, *get(optval, optlen)?)?;
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_ATTACH_FILTER) => {
+            let filter = BpfProgram::load(get::<SockFprog>(optval, optlen)?)?;
+            socket.packet()?.attach_filter(filter);
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_DETACH_FILTER) => {
+            socket.packet()?.detach_filter()?;
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_TIMESTAMP | SO_TIMESTAMPNS)
+        | (PROTO_IP, IP_PKTINFO | IP_RECVTTL | IP_RECVTOS | IP_RECVERR) => {
+            set_ancillary_option(&socket, level, optname, *get(optval, optlen)?)?;
+            return Ok(0);
+        }
+        (SOL_PACKET, PACKET_ADD_MEMBERSHIP | PACKET_DROP_MEMBERSHIP) => {
+            socket
+                .packet()?
+                .set_membership(optname, get(optval, optlen)?)?;
+            return Ok(0);
+        }
+        (SOL_PACKET, _) => {
+            socket
+                .packet()?
+                .set_packet_option(optname, *get(optval, optlen)?)?;
+            return Ok(0);
+        }
+        _ => {}
+    }
+    if level == PROTO_IPV6 {
+        set_ipv6_option(&socket, optname, *get(optval, optlen)?)?;
+        return Ok(0);
+    }
+    if level == SOL_NETLINK {
+        socket
+            .netlink()?
+            .set_netlink_option(optname, *get(optval, optlen)?)?;
+        return Ok(0);
+    }
     macro_rules! dispatch {
         ($which:ident) => {
             socket.set_option(SetSocketOption::$which(get(optval, optlen)?))?;
@@ -1,6 +1,6 @@
 use axerrno::{LinuxError, LinuxResult};
 use axnet::{
-    Shutdown, SocketAddrEx, SocketOps,
+    Shutdown,
     tcp::TcpSocket,
     udp::UdpSocket,
     unix::{DgramTransport, StreamTransport, UnixSocket},
@@ -9,16 +9,18 @@ use axtask::current;
 use linux_raw_sys::{
     general::{O_CLOEXEC, O_NONBLOCK},
     net::{
-        AF_INET, AF_UNIX, IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR, SHUT_WR, SOCK_DGRAM,
+        AF_INET, AF_INET6, AF_NETLINK, AF_PACKET, AF_UNIX, IPPROTO_ICMP, IPPROTO_ICMPV6,
+        IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR, SHUT_WR, SOCK_DGRAM, SOCK_RAW,
         SOCK_SEQPACKET, SOCK_STREAM, sockaddr, socklen_t,
     },
 };
 use starry_core::task::AsThread;
 
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, Socket, SocketInner},
     mm::{UserConstPtr, UserPtr},
-    socket::SocketAddrExt,
+    net::{netlink::NetlinkSocket, packet::PacketSocket, raw::RawSocket},
+    socket::{SockAddr, SocketAddrExt},
 };
 
 pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
@@ -29,22 +31,40 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
     let ty = raw_ty & 0xFF;
 
     let pid = current().as_thread().proc_data.proc.pid();
-    let socket = match (domain, ty) {
-        (AF_INET, SOCK_STREAM) => {
+    let socket: SocketInner = match (domain, ty) {
+        (AF_INET | AF_INET6, SOCK_STREAM) => {
             if proto != 0 && proto != IPPROTO_TCP as _ {
                 return Err(LinuxError::EPROTONOSUPPORT);
             }
-            axnet::Socket::Tcp(TcpSocket::new())
+            axnet::Socket::Tcp(TcpSocket::new()).into()
         }
-        (AF_INET, SOCK_DGRAM) => {
-            if proto != 0 && proto != IPPROTO_UDP as _ {
+        (AF_INET | AF_INET6, SOCK_DGRAM) => {
+            let icmp = if domain == AF_INET {
+                IPPROTO_ICMP as u32
+            } else {
+                IPPROTO_ICMPV6 as u32
+            };
+            if proto == icmp {
+                RawSocket::new_ping(domain)?.into()
+            } else if proto == 0 || proto == IPPROTO_UDP as _ {
+                axnet::Socket::Udp(UdpSocket::new()).into()
+            } else {
                 return Err(LinuxError::EPROTONOSUPPORT);
             }
-            axnet::Socket::Udp(UdpSocket::new())
         }
-        (AF_UNIX, SOCK_STREAM) => axnet::Socket::Unix(UnixSocket::new(StreamTransport::new(pid))),
-        (AF_UNIX, SOCK_DGRAM) => axnet::Socket::Unix(UnixSocket::new(DgramTransport::new(pid))),
-        (AF_INET, _) | (AF_UNIX, _) => {
+        (AF_INET | AF_INET6, SOCK_RAW) => RawSocket::new_raw(domain, proto)?.into(),
+        (AF_UNIX, SOCK_STREAM) => {
+            axnet::Socket::Unix(UnixSocket::new(StreamTransport::new(pid))).into()
+        }
+        (AF_UNIX, SOCK_DGRAM) => {
+            axnet::Socket::Unix(UnixSocket::new(DgramTransport::new(pid))).into()
+        }
+        (AF_NETLINK, SOCK_RAW | SOCK_DGRAM) => NetlinkSocket::new(proto)?.into(),
+        (AF_PACKET, SOCK_RAW | SOCK_DGRAM) => {
+            // The protocol is an ethertype in network byte order.
+            PacketSocket::new(ty == SOCK_DGRAM, u16::from_be(proto as u16)).into()
+        }
+        (AF_INET | AF_INET6 | AF_UNIX | AF_NETLINK | AF_PACKET, _) => {
             warn!("Unsupported socket type: domain: {}, ty: {}", domain, ty);
             return Err(LinuxError::ESOCKTNOSUPPORT);
         }
@@ -52,7 +72,7 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
             return Err(LinuxError::EAFNOSUPPORT);
         }
     };
-    let socket = Socket(socket);
+    let socket = Socket::new(socket, domain, ty);
 
     if raw_ty & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
@@ -63,7 +83,7 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
 }
 
 pub fn sys_bind(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> LinuxResult<isize> {
-    let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
+    let addr = SockAddr::read_from_user(addr, addrlen)?;
     debug!("sys_bind <= fd: {}, addr: {:?}", fd, addr);
 
     Socket::from_fd(fd)?.bind(addr)?;
@@ -72,7 +92,7 @@ pub fn sys_bind(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> LinuxRes
 }
 
 pub fn sys_connect(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> LinuxResult<isize> {
-    let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
+    let addr = SockAddr::read_from_user(addr, addrlen)?;
     debug!("sys_connect <= fd: {}, addr: {:?}", fd, addr);
 
     Socket::from_fd(fd)?.connect(addr).map_err(|e| {
@@ -116,13 +136,12 @@ pub fn sys_accept4(
 
     let cloexec = flags & O_CLOEXEC != 0;
 
-    let socket = Socket::from_fd(fd)?;
-    let socket = Socket(socket.accept()?);
+    let socket = Socket::from_fd(fd)?.accept()?;
     if flags & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
     }
 
-    let remote_addr = socket.local_addr()?;
+    let remote_addr = socket.peer_name()?;
     let fd = socket.add_to_fd_table(cloexec).map(|fd| fd as isize)?;
     debug!("sys_accept => fd: {}, addr: {:?}", fd, remote_addr);
 
@@ -177,8 +196,8 @@ pub fn sys_socketpair(
             return Err(LinuxError::ESOCKTNOSUPPORT);
         }
     };
-    let sock1 = Socket(axnet::Socket::Unix(sock1));
-    let sock2 = Socket(axnet::Socket::Unix(sock2));
+    let sock1 = Socket::new(axnet::Socket::Unix(sock1), AF_UNIX, ty);
+    let sock2 = Socket::new(axnet::Socket::Unix(sock2), AF_UNIX, ty);
 
     if raw_ty & O_NONBLOCK != 0 {
         sock1.set_nonblocking(true)?;
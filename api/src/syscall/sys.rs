@@ -1,51 +1,99 @@
-use alloc::vec;
+use alloc::{vec, vec::Vec};
 use core::ffi::c_char;
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::FS_CONTEXT;
+use axtask::current;
 use linux_raw_sys::{
     general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
     system::{new_utsname, sysinfo},
 };
-use starry_core::task::processes;
-use starry_vm::{VmMutPtr, vm_write_slice};
+use starry_core::task::{AsThread, processes};
+use starry_vm::{VmMutPtr, vm_load, vm_write_slice};
+
+/// Maximum number of supplementary groups.
+const NGROUPS_MAX: usize = 65536;
 
 pub fn sys_getuid() -> LinuxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred().uid as _)
 }
 
 pub fn sys_geteuid() -> LinuxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred().euid as _)
 }
 
 pub fn sys_getgid() -> LinuxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred().gid as _)
 }
 
 pub fn sys_getegid() -> LinuxResult<isize> {
+    Ok(current().as_thread().proc_data.cred().egid as _)
+}
+
+pub fn sys_setuid(uid: u32) -> LinuxResult<isize> {
+    debug!("sys_setuid <= uid: {}", uid);
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    if cred.is_privileged() {
+        cred.uid = uid;
+        cred.suid = uid;
+    } else if uid != cred.uid && uid != cred.suid {
+        return Err(LinuxError::EPERM);
+    }
+    cred.euid = uid;
+    cred.fsuid = uid;
+    proc_data.set_cred(cred);
     Ok(0)
 }
 
-pub fn sys_setuid(_uid: u32) -> LinuxResult<isize> {
-    debug!("sys_setuid <= uid: {}", _uid);
-    Ok(0)
-}
-
-pub fn sys_setgid(_gid: u32) -> LinuxResult<isize> {
-    debug!("sys_setgid <= gid: {}", _gid);
+pub fn sys_setgid(gid: u32) -> LinuxResult<isize> {
+    debug!("sys_setgid <= gid: {}", gid);
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    if cred.is_privileged() {
+        cred.gid = gid;
+        cred.sgid = gid;
+    } else if gid != cred.gid && gid != cred.sgid {
+        return Err(LinuxError::EPERM);
+    }
+    cred.egid = gid;
+    cred.fsgid = gid;
+    proc_data.set_cred(cred);
     Ok(0)
 }
 
 pub fn sys_getgroups(size: usize, list: *mut u32) -> LinuxResult<isize> {
     debug!("sys_getgroups <= size: {}", size);
-    if size < 1 {
+    let groups = current().as_thread().proc_data.cred().groups;
+    if size == 0 {
+        return Ok(groups.len() as _);
+    }
+    if size < groups.len() {
         return Err(LinuxError::EINVAL);
     }
-    vm_write_slice(list, &[0])?;
-    Ok(1)
+    vm_write_slice(list, &groups)?;
+    Ok(groups.len() as _)
 }
 
-pub fn sys_setgroups(_size: usize, _list: *const u32) -> LinuxResult<isize> {
+pub fn sys_setgroups(size: usize, list: *const u32) -> LinuxResult<isize> {
+    debug!("sys_setgroups <= size: {}", size);
+    if size > NGROUPS_MAX {
+        return Err(LinuxError::EINVAL);
+    }
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    if !cred.is_privileged() {
+        return Err(LinuxError::EPERM);
+    }
+    let mut groups = if size == 0 {
+        Vec::new()
+    } else {
+        vm_load(list, size)?
+    };
+    groups.sort_unstable();
+    groups.dedup();
+    cred.groups = groups;
+    proc_data.set_cred(cred);
     Ok(0)
 }
 
@@ -17,6 +17,7 @@ use starry_signal::Signo;
 use crate::{
     file::{FD_TABLE, FileLike, PidFd},
     mm::UserPtr,
+    net::ns::{NET_NS, NetNamespace},
     task::new_user_task,
 };
 
@@ -117,6 +118,10 @@ pub fn sys_clone(
     if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
         return Err(LinuxError::EINVAL);
     }
+    // Namespaces belong to processes, not threads.
+    if flags.contains(CloneFlags::THREAD | CloneFlags::NEWNET) {
+        return Err(LinuxError::EINVAL);
+    }
     let exit_signal = Signo::from_repr(exit_signal as u8);
 
     let mut new_uctx = UserContext::from(*tf);
@@ -183,6 +188,7 @@ pub fn sys_clone(
             exit_signal,
         );
         proc_data.set_umask(old_proc_data.umask());
+        proc_data.set_cred(old_proc_data.cred());
 
         {
             let mut scope = proc_data.scope.write();
@@ -203,6 +209,12 @@ pub fn sys_clone(
                     .lock()
                     .clone_from(&FS_CONTEXT.lock());
             }
+
+            *NET_NS.scope_mut(&mut scope) = if flags.contains(CloneFlags::NEWNET) {
+                NetNamespace::new_empty()
+            } else {
+                Arc::clone(&NET_NS)
+            };
         }
 
         proc_data
@@ -227,6 +239,24 @@ pub fn sys_clone(
     Ok(tid as _)
 }
 
+/// Moves the calling process into new namespaces. Only a new network
+/// namespace is supported, and it is that of every thread of the process.
+pub fn sys_unshare(flags: u32) -> LinuxResult<isize> {
+    let flags = CloneFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    debug!("sys_unshare <= flags: {:?}", flags);
+    if !(flags - CloneFlags::NEWNET).is_empty() {
+        warn!("sys_unshare: unsupported flags {:?}", flags);
+        return Err(LinuxError::EINVAL);
+    }
+
+    if flags.contains(CloneFlags::NEWNET) {
+        let curr = current();
+        let mut scope = curr.as_thread().proc_data.scope.write();
+        *NET_NS.scope_mut(&mut scope) = NetNamespace::new_empty();
+    }
+    Ok(0)
+}
+
 #[cfg(target_arch = "x86_64")]
 pub fn sys_fork(tf: &TrapFrame) -> LinuxResult<isize> {
     sys_clone(tf, SIGCHLD, 0, 0, 0, 0)
@@ -51,15 +51,119 @@ pub fn sys_umask(mask: u32) -> LinuxResult<isize> {
     Ok(old as isize)
 }
 
-pub fn sys_setreuid(_ruid: u32, _euid: u32) -> LinuxResult<isize> {
+/// Returns whether `id` is unset (`-1`) or one of `allowed`.
+fn id_allowed(id: u32, allowed: &[u32]) -> bool {
+    id == u32::MAX || allowed.contains(&id)
+}
+
+/// Replaces `slot` with `id` unless `id` is unset (`-1`).
+fn set_id(slot: &mut u32, id: u32) {
+    if id != u32::MAX {
+        *slot = id;
+    }
+}
+
+pub fn sys_setreuid(ruid: u32, euid: u32) -> LinuxResult<isize> {
+    debug!("sys_setreuid <= ruid: {}, euid: {}", ruid, euid);
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    if !cred.is_privileged()
+        && (!id_allowed(ruid, &[cred.uid, cred.euid])
+            || !id_allowed(euid, &[cred.uid, cred.euid, cred.suid]))
+    {
+        return Err(LinuxError::EPERM);
+    }
+    let old_uid = cred.uid;
+    set_id(&mut cred.uid, ruid);
+    set_id(&mut cred.euid, euid);
+    if ruid != u32::MAX || (euid != u32::MAX && euid != old_uid) {
+        cred.suid = cred.euid;
+    }
+    cred.fsuid = cred.euid;
+    proc_data.set_cred(cred);
+    Ok(0)
+}
+
+pub fn sys_setregid(rgid: u32, egid: u32) -> LinuxResult<isize> {
+    debug!("sys_setregid <= rgid: {}, egid: {}", rgid, egid);
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    if !cred.is_privileged()
+        && (!id_allowed(rgid, &[cred.gid, cred.egid])
+            || !id_allowed(egid, &[cred.gid, cred.egid, cred.sgid]))
+    {
+        return Err(LinuxError::EPERM);
+    }
+    let old_gid = cred.gid;
+    set_id(&mut cred.gid, rgid);
+    set_id(&mut cred.egid, egid);
+    if rgid != u32::MAX || (egid != u32::MAX && egid != old_gid) {
+        cred.sgid = cred.egid;
+    }
+    cred.fsgid = cred.egid;
+    proc_data.set_cred(cred);
+    Ok(0)
+}
+
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_setresuid <= ruid: {}, euid: {}, suid: {}",
+        ruid, euid, suid
+    );
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    let allowed = [cred.uid, cred.euid, cred.suid];
+    if !cred.is_privileged()
+        && [ruid, euid, suid]
+            .iter()
+            .any(|&id| !id_allowed(id, &allowed))
+    {
+        return Err(LinuxError::EPERM);
+    }
+    set_id(&mut cred.uid, ruid);
+    set_id(&mut cred.euid, euid);
+    set_id(&mut cred.suid, suid);
+    cred.fsuid = cred.euid;
+    proc_data.set_cred(cred);
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_setresgid <= rgid: {}, egid: {}, sgid: {}",
+        rgid, egid, sgid
+    );
+    let proc_data = &current().as_thread().proc_data;
+    let mut cred = proc_data.cred();
+    let allowed = [cred.gid, cred.egid, cred.sgid];
+    if !cred.is_privileged()
+        && [rgid, egid, sgid]
+            .iter()
+            .any(|&id| !id_allowed(id, &allowed))
+    {
+        return Err(LinuxError::EPERM);
+    }
+    set_id(&mut cred.gid, rgid);
+    set_id(&mut cred.egid, egid);
+    set_id(&mut cred.sgid, sgid);
+    cred.fsgid = cred.egid;
+    proc_data.set_cred(cred);
     Ok(0)
 }
 
-pub fn sys_setresuid(_ruid: u32, _euid: u32, _suid: u32) -> LinuxResult<isize> {
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> LinuxResult<isize> {
+    let cred = current().as_thread().proc_data.cred();
+    ruid.vm_write(cred.uid)?;
+    euid.vm_write(cred.euid)?;
+    suid.vm_write(cred.suid)?;
     Ok(0)
 }
 
-pub fn sys_setresgid(_rgid: u32, _egid: u32, _sgid: u32) -> LinuxResult<isize> {
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> LinuxResult<isize> {
+    let cred = current().as_thread().proc_data.cred();
+    rgid.vm_write(cred.gid)?;
+    egid.vm_write(cred.egid)?;
+    sgid.vm_write(cred.sgid)?;
     Ok(0)
 }
 
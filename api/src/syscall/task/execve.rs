@@ -3,12 +3,20 @@ use core::ffi::c_char;
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::FS_CONTEXT;
+use axfs_ng_vfs::NodePermission;
 use axhal::context::TrapFrame;
 use axtask::current;
 use starry_core::{mm::load_user_app, task::AsThread};
 use starry_vm::vm_load_until_nul;
 
-use crate::{file::FD_TABLE, mm::vm_load_string};
+use crate::{
+    file::FD_TABLE,
+    mm::vm_load_string,
+    vfs::{
+        acl::{self, ACL_EXECUTE},
+        mount,
+    },
+};
 
 pub fn sys_execve(
     tf: &mut TrapFrame,
@@ -42,12 +50,41 @@ pub fn sys_execve(
         return Err(LinuxError::EAGAIN);
     }
 
+    let loc = {
+        let fs = FS_CONTEXT.lock();
+        acl::check_search(&fs, &path)?;
+        fs.resolve(&path)?
+    };
+    mount::check_exec(&loc)?;
+    acl::check_access(&loc, ACL_EXECUTE)?;
+    let metadata = loc.metadata()?;
+
     let mut aspace = proc_data.aspace.lock();
     let (entry_point, user_stack_base) =
         load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
     drop(aspace);
 
-    let loc = FS_CONTEXT.lock().resolve(&path)?;
+    // Set-user-ID and set-group-ID programs run with the ids of their owner,
+    // unless they are on a `nosuid` mount. The saved ids take the effective
+    // ones either way.
+    let mut cred = proc_data.cred();
+    if mount::honors_set_id(&loc) {
+        if metadata.mode.contains(NodePermission::SET_UID) {
+            cred.euid = metadata.uid;
+        }
+        if metadata
+            .mode
+            .contains(NodePermission::SET_GID | NodePermission::GROUP_EXEC)
+        {
+            cred.egid = metadata.gid;
+        }
+    }
+    cred.suid = cred.euid;
+    cred.fsuid = cred.euid;
+    cred.sgid = cred.egid;
+    cred.fsgid = cred.egid;
+    proc_data.set_cred(cred);
+
     curr.set_name(loc.name());
 
     *proc_data.exe_path.write() = loc.absolute_path()?.to_string();
@@ -2,21 +2,22 @@ use core::{ffi::c_long, sync::atomic::Ordering};
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::uspace::{ExceptionKind, ReturnReason, UserContext};
-use axtask::{TaskInner, current};
+use axtask::{AxTaskRef, TaskExtProxy, TaskInner, current};
 use bytemuck::AnyBitPattern;
 use linux_raw_sys::general::ROBUST_LIST_LIMIT;
 use starry_core::{
     futex::FutexKey,
     mm::access_user_memory,
     shm::SHM_MANAGER,
+    shrink,
     task::{
-        AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
-        set_timer_state,
+        AsThread, Thread, get_process_data, get_task, send_signal_to_process,
+        send_signal_to_thread, set_timer_state,
     },
     time::TimerState,
 };
 use starry_process::Pid;
-use starry_signal::{SignalInfo, Signo};
+use starry_signal::{SignalInfo, SignalSet, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
@@ -50,7 +51,13 @@ pub fn new_user_task(
                 match reason {
                     ReturnReason::Syscall => handle_syscall(&mut uctx),
                     ReturnReason::PageFault(addr, flags) => {
-                        if !thr.proc_data.aspace.lock().handle_page_fault(addr, flags) {
+                        let mut handled =
+                            thr.proc_data.aspace.lock().handle_page_fault(addr, flags);
+                        // The fault may have failed for lack of memory.
+                        if !handled && shrink::reclaim_if_low() > 0 {
+                            handled = thr.proc_data.aspace.lock().handle_page_fault(addr, flags);
+                        }
+                        if !handled {
                             info!(
                                 "{:?}: segmentation fault at {:#x} {:?}",
                                 thr.proc_data.proc, addr, flags
@@ -99,6 +106,24 @@ pub fn new_user_task(
     )
 }
 
+/// Spawns a kernel task doing I/O on behalf of the current process.
+///
+/// The task shares the address space, file descriptor table and filesystem
+/// context of the caller, so it can work on user buffers and file
+/// descriptors. It never enters user space and blocks all signals.
+pub fn spawn_io_worker(name: &str, f: impl FnOnce() + Send + 'static) -> AxTaskRef {
+    let curr = current();
+    let proc_data = curr.as_thread().proc_data.clone();
+
+    let mut task = TaskInner::new(f, name.into(), starry_core::config::KERNEL_STACK_SIZE);
+    task.ctx_mut()
+        .set_page_table_root(proc_data.aspace.lock().page_table_root());
+    let thr = Thread::new(task.id().as_u64() as Pid, proc_data);
+    thr.signal.set_blocked(!SignalSet::default());
+    *task.task_ext_mut() = Some(unsafe { TaskExtProxy::from_impl(thr) });
+    axtask::spawn_task(task)
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, AnyBitPattern)]
 pub struct RobustList {
@@ -0,0 +1,410 @@
+//! POSIX access control lists.
+//!
+//! ACLs are stored as the `system.posix_acl_access` and
+//! `system.posix_acl_default` extended attributes, using the same binary
+//! layout as Linux (`posix_acl_xattr_header` followed by
+//! `posix_acl_xattr_entry`s).
+
+use alloc::{string::String, vec::Vec};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::FsContext;
+use axfs_ng_vfs::{Location, MetadataUpdate, NodePermission, NodeType};
+use axtask::current;
+use starry_core::task::AsThread;
+
+use super::xattr;
+
+/// Name of the extended attribute holding the access ACL.
+pub const XATTR_NAME_ACL_ACCESS: &str = "system.posix_acl_access";
+/// Name of the extended attribute holding the default ACL of a directory.
+pub const XATTR_NAME_ACL_DEFAULT: &str = "system.posix_acl_default";
+
+const ACL_XATTR_VERSION: u32 = 0x0002;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+/// Read permission bit of an ACL entry.
+pub const ACL_READ: u16 = 0o4;
+/// Write permission bit of an ACL entry.
+pub const ACL_WRITE: u16 = 0o2;
+/// Execute permission bit of an ACL entry.
+pub const ACL_EXECUTE: u16 = 0o1;
+
+/// The qualifier of an ACL entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclTag {
+    UserObj,
+    User(u32),
+    GroupObj,
+    Group(u32),
+    Mask,
+    Other,
+}
+
+/// A single ACL entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perm: u16,
+}
+
+/// A POSIX ACL, kept sorted in the canonical order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PosixAcl {
+    entries: Vec<AclEntry>,
+}
+
+impl PosixAcl {
+    /// Parses an ACL from its extended attribute representation.
+    pub fn from_xattr(data: &[u8]) -> LinuxResult<Self> {
+        if data.len() < 4 || (data.len() - 4) % 8 != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let version = u32::from_le_bytes(data[..4].try_into().unwrap());
+        if version != ACL_XATTR_VERSION {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+
+        let mut entries = Vec::with_capacity((data.len() - 4) / 8);
+        for chunk in data[4..].chunks_exact(8) {
+            let tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let perm = u16::from_le_bytes([chunk[2], chunk[3]]);
+            let id = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+            if perm & !(ACL_READ | ACL_WRITE | ACL_EXECUTE) != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let tag = match tag {
+                ACL_USER_OBJ => AclTag::UserObj,
+                ACL_USER => AclTag::User(id),
+                ACL_GROUP_OBJ => AclTag::GroupObj,
+                ACL_GROUP => AclTag::Group(id),
+                ACL_MASK => AclTag::Mask,
+                ACL_OTHER => AclTag::Other,
+                _ => return Err(LinuxError::EINVAL),
+            };
+            entries.push(AclEntry { tag, perm });
+        }
+        entries.sort_by_key(|entry| entry.tag);
+
+        let acl = Self { entries };
+        acl.validate()?;
+        Ok(acl)
+    }
+
+    /// Serializes the ACL into its extended attribute representation.
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.entries.len() * 8);
+        buf.extend_from_slice(&ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            let (tag, id) = match entry.tag {
+                AclTag::UserObj => (ACL_USER_OBJ, ACL_UNDEFINED_ID),
+                AclTag::User(uid) => (ACL_USER, uid),
+                AclTag::GroupObj => (ACL_GROUP_OBJ, ACL_UNDEFINED_ID),
+                AclTag::Group(gid) => (ACL_GROUP, gid),
+                AclTag::Mask => (ACL_MASK, ACL_UNDEFINED_ID),
+                AclTag::Other => (ACL_OTHER, ACL_UNDEFINED_ID),
+            };
+            buf.extend_from_slice(&tag.to_le_bytes());
+            buf.extend_from_slice(&entry.perm.to_le_bytes());
+            buf.extend_from_slice(&id.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Checks the ACL is well-formed: exactly one of each base entry, no
+    /// duplicated qualifiers and a mask whenever named entries are present.
+    fn validate(&self) -> LinuxResult<()> {
+        let count =
+            |pred: fn(&AclTag) -> bool| self.entries.iter().filter(|e| pred(&e.tag)).count();
+        if count(|t| *t == AclTag::UserObj) != 1
+            || count(|t| *t == AclTag::GroupObj) != 1
+            || count(|t| *t == AclTag::Other) != 1
+            || count(|t| *t == AclTag::Mask) > 1
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        if self.entries.windows(2).any(|w| w[0].tag == w[1].tag) {
+            return Err(LinuxError::EINVAL);
+        }
+        let named = count(|t| matches!(t, AclTag::User(_) | AclTag::Group(_)));
+        if named > 0 && self.mask().is_none() {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(())
+    }
+
+    fn find(&self, tag: AclTag) -> Option<&AclEntry> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+
+    fn find_mut(&mut self, tag: AclTag) -> Option<&mut AclEntry> {
+        self.entries.iter_mut().find(|entry| entry.tag == tag)
+    }
+
+    fn mask(&self) -> Option<u16> {
+        self.find(AclTag::Mask).map(|entry| entry.perm)
+    }
+
+    /// Returns whether the ACL can be fully represented by the mode bits.
+    pub fn is_minimal(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    /// Returns the permission bits equivalent to this ACL.
+    ///
+    /// The group class bits come from the mask entry if present, and from the
+    /// owning group entry otherwise.
+    pub fn mode_bits(&self) -> u16 {
+        let perm = |tag| self.find(tag).map_or(0, |entry| entry.perm);
+        let group = self.mask().unwrap_or_else(|| perm(AclTag::GroupObj));
+        (perm(AclTag::UserObj) << 6) | (group << 3) | perm(AclTag::Other)
+    }
+
+    /// Updates the ACL after the mode bits of the file changed.
+    pub fn apply_mode(&mut self, mode: u16) {
+        let group_tag = if self.mask().is_some() {
+            AclTag::Mask
+        } else {
+            AclTag::GroupObj
+        };
+        for (tag, perm) in [
+            (AclTag::UserObj, (mode >> 6) & 0o7),
+            (group_tag, (mode >> 3) & 0o7),
+            (AclTag::Other, mode & 0o7),
+        ] {
+            if let Some(entry) = self.find_mut(tag) {
+                entry.perm = perm;
+            }
+        }
+    }
+
+    /// Evaluates the ACL for the given credentials, following the POSIX.1e
+    /// access check algorithm. `want` is a combination of [`ACL_READ`],
+    /// [`ACL_WRITE`] and [`ACL_EXECUTE`].
+    pub fn permits(&self, owner: (u32, u32), cred: &Credentials, want: u16) -> bool {
+        let mask = self.mask().unwrap_or(0o7);
+        let granted = |perm: u16| perm & want == want;
+
+        if cred.uid == owner.0 {
+            return self.find(AclTag::UserObj).is_some_and(|e| granted(e.perm));
+        }
+        if let Some(entry) = self.find(AclTag::User(cred.uid)) {
+            return granted(entry.perm & mask);
+        }
+
+        let mut group_matched = false;
+        for entry in &self.entries {
+            let matches = match entry.tag {
+                AclTag::GroupObj => cred.in_group(owner.1),
+                AclTag::Group(gid) => cred.in_group(gid),
+                _ => false,
+            };
+            if matches {
+                if granted(entry.perm & mask) {
+                    return true;
+                }
+                group_matched = true;
+            }
+        }
+        if group_matched {
+            return false;
+        }
+
+        self.find(AclTag::Other).is_some_and(|e| granted(e.perm))
+    }
+
+    /// Builds the access ACL of a new file from the default ACL of its parent
+    /// directory, restricting it by the mode requested by the creator.
+    ///
+    /// Returns the new ACL and the resulting mode bits.
+    pub fn inherit(&self, mode: u16) -> (Self, u16) {
+        let mut acl = self.clone();
+        let group_tag = if acl.mask().is_some() {
+            AclTag::Mask
+        } else {
+            AclTag::GroupObj
+        };
+        for (tag, perm) in [
+            (AclTag::UserObj, (mode >> 6) & 0o7),
+            (group_tag, (mode >> 3) & 0o7),
+            (AclTag::Other, mode & 0o7),
+        ] {
+            if let Some(entry) = acl.find_mut(tag) {
+                entry.perm &= perm;
+            }
+        }
+        let mode = (mode & !0o777) | acl.mode_bits();
+        (acl, mode)
+    }
+}
+
+/// Credentials used for access checks.
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Returns the credentials of the current task.
+    pub fn current() -> Self {
+        let cred = current().as_thread().proc_data.cred();
+        Self {
+            uid: cred.fsuid,
+            gid: cred.fsgid,
+            groups: cred.groups,
+        }
+    }
+
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+/// Reads the ACL stored under `name` on `loc`, if any.
+pub fn get_acl(loc: &Location, name: &str) -> LinuxResult<Option<PosixAcl>> {
+    match xattr::get_raw(loc, name) {
+        Ok(data) => PosixAcl::from_xattr(&data).map(Some),
+        Err(LinuxError::ENODATA | LinuxError::EOPNOTSUPP) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Stores an ACL through its extended attribute, keeping the mode bits in
+/// sync for access ACLs.
+pub fn set_acl_xattr(loc: &Location, name: &str, value: &[u8]) -> LinuxResult<()> {
+    let acl = PosixAcl::from_xattr(value)?;
+    match name {
+        XATTR_NAME_ACL_ACCESS => {
+            let mode = loc.metadata()?.mode.bits();
+            let mode = (mode & !0o777) | acl.mode_bits();
+            loc.update_metadata(MetadataUpdate {
+                mode: Some(NodePermission::from_bits_truncate(mode)),
+                ..Default::default()
+            })?;
+            if acl.is_minimal() {
+                // Equivalent to the mode bits, no need to keep it around.
+                return match xattr::remove_raw(loc, name) {
+                    Ok(()) | Err(LinuxError::ENODATA) => Ok(()),
+                    Err(err) => Err(err),
+                };
+            }
+        }
+        XATTR_NAME_ACL_DEFAULT => {
+            if loc.node_type() != NodeType::Directory {
+                return Err(LinuxError::EACCES);
+            }
+        }
+        _ => return Err(LinuxError::EOPNOTSUPP),
+    }
+    xattr::set_raw(loc, name, acl.to_xattr())
+}
+
+/// Updates the access ACL after a `chmod`, so that the mask (or the owning
+/// group entry) keeps reflecting the group bits.
+pub fn chmod_acl(loc: &Location, mode: u16) -> LinuxResult<()> {
+    if let Some(mut acl) = get_acl(loc, XATTR_NAME_ACL_ACCESS)? {
+        acl.apply_mode(mode);
+        xattr::set_raw(loc, XATTR_NAME_ACL_ACCESS, acl.to_xattr())?;
+    }
+    Ok(())
+}
+
+/// Returns whether `dir` carries a default ACL, in which case the umask must
+/// not be applied to files created inside it.
+pub fn has_default_acl(dir: &Location) -> bool {
+    matches!(get_acl(dir, XATTR_NAME_ACL_DEFAULT), Ok(Some(_)))
+}
+
+/// Applies the default ACL of `parent` to a newly created `child`.
+pub fn inherit_acl(parent: &Location, child: &Location) -> LinuxResult<()> {
+    let Some(default) = get_acl(parent, XATTR_NAME_ACL_DEFAULT)? else {
+        return Ok(());
+    };
+    let mode = child.metadata()?.mode.bits();
+    let (access, mode) = default.inherit(mode);
+    child.update_metadata(MetadataUpdate {
+        mode: Some(NodePermission::from_bits_truncate(mode)),
+        ..Default::default()
+    })?;
+    if !access.is_minimal() {
+        xattr::set_raw(child, XATTR_NAME_ACL_ACCESS, access.to_xattr())?;
+    }
+    if child.node_type() == NodeType::Directory {
+        xattr::set_raw(child, XATTR_NAME_ACL_DEFAULT, default.to_xattr())?;
+    }
+    Ok(())
+}
+
+/// Checks whether the current task may access `loc` with `want` permissions
+/// (a combination of [`ACL_READ`], [`ACL_WRITE`] and [`ACL_EXECUTE`]).
+pub fn check_access(loc: &Location, want: u16) -> LinuxResult<()> {
+    let metadata = loc.metadata()?;
+    let cred = Credentials::current();
+    let mode = metadata.mode.bits();
+
+    if cred.uid == 0 {
+        // root bypasses everything but needs at least one execute bit to
+        // execute regular files
+        if want & ACL_EXECUTE != 0 && metadata.node_type != NodeType::Directory && mode & 0o111 == 0
+        {
+            return Err(LinuxError::EACCES);
+        }
+        return Ok(());
+    }
+
+    let owner = (metadata.uid, metadata.gid);
+    let allowed = if let Some(acl) = get_acl(loc, XATTR_NAME_ACL_ACCESS)? {
+        acl.permits(owner, &cred, want)
+    } else {
+        let class = if cred.uid == owner.0 {
+            mode >> 6
+        } else if cred.in_group(owner.1) {
+            mode >> 3
+        } else {
+            mode
+        };
+        class & want == want
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(LinuxError::EACCES)
+    }
+}
+
+/// Checks whether the current task may add entries to or remove entries from
+/// the directory `dir`.
+pub fn check_dir_write(dir: &Location) -> LinuxResult<()> {
+    check_access(dir, ACL_WRITE | ACL_EXECUTE)
+}
+
+/// Checks whether the current task may search every directory `path` passes
+/// through on its way from the current directory of `fs` (or the root) to
+/// its last component.
+pub fn check_search(fs: &FsContext, path: &str) -> LinuxResult<()> {
+    if Credentials::current().uid == 0 {
+        return Ok(());
+    }
+    let mut prefix = String::from(if path.starts_with('/') { "/" } else { "." });
+    check_access(&fs.resolve(prefix.as_str())?, ACL_EXECUTE)?;
+    let components = path
+        .split('/')
+        .filter(|it| !it.is_empty())
+        .collect::<Vec<_>>();
+    for name in components.iter().take(components.len().saturating_sub(1)) {
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        prefix.push_str(name);
+        check_access(&fs.resolve(prefix.as_str())?, ACL_EXECUTE)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Disk devices (/dev/vdX) and their partitions (/dev/vdXN).
+//!
+//! The runtime mounts the root filesystem from the first block device and
+//! hands the remaining ones over with [`add_disks`] before the VFS is set up.
+//! Partitions are read from an MBR or a GPT when the disk is added.
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    any::Any,
+    ffi::c_long,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axdriver::prelude::{AxBlockDevice, BaseDriverOps, BlockDriverOps};
+use axerrno::LinuxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
+use axsync::Mutex;
+use linux_raw_sys::ioctl::{BLKGETSIZE, BLKGETSIZE64, BLKRAGET, BLKRASET, BLKROGET, BLKSSZGET};
+use starry_core::vfs::DeviceOps;
+use starry_vm::VmMutPtr;
+
+/// Major number of virtio block devices.
+const VIRTIO_BLK_MAJOR: u32 = 254;
+/// Minor numbers reserved for each disk: the disk itself and 15 partitions.
+const MINORS_PER_DISK: u32 = 16;
+
+/// Names, device numbers and operations of the disks and their partitions.
+type BlockNode = (String, DeviceId, Arc<BlockDevice>);
+
+/// Number of disks added so far and the nodes for them.
+static DISKS: Mutex<(u32, Vec<BlockNode>)> = Mutex::new((0, Vec::new()));
+
+/// Registers block devices to be exposed as /dev/vda, /dev/vdb, ...
+pub fn add_disks(devices: impl IntoIterator<Item = AxBlockDevice>) {
+    let mut disks = DISKS.lock();
+    let (count, nodes) = &mut *disks;
+    for dev in devices {
+        if *count >= 26 {
+            warn!("too many disks, ignoring {}", dev.device_name());
+            continue;
+        }
+        let disk = Arc::new(Disk::new(dev));
+        let name = format!("vd{}", (b'a' + *count as u8) as char);
+        let minor = *count * MINORS_PER_DISK;
+        *count += 1;
+
+        let len = disk.capacity;
+        nodes.push((
+            name.clone(),
+            DeviceId::new(VIRTIO_BLK_MAJOR, minor),
+            Arc::new(BlockDevice {
+                disk: disk.clone(),
+                start: 0,
+                len,
+                ra: AtomicU32::new(256),
+            }),
+        ));
+        for (n, (start, len)) in disk.partitions().into_iter().enumerate() {
+            nodes.push((
+                format!("{name}{}", n + 1),
+                DeviceId::new(VIRTIO_BLK_MAJOR, minor + n as u32 + 1),
+                Arc::new(BlockDevice {
+                    disk: disk.clone(),
+                    start,
+                    len,
+                    ra: AtomicU32::new(256),
+                }),
+            ));
+        }
+    }
+}
+
+/// Returns the nodes of every disk and partition registered so far.
+pub(crate) fn block_devices() -> Vec<BlockNode> {
+    DISKS.lock().1.clone()
+}
+
+/// A whole disk.
+struct Disk {
+    dev: Mutex<AxBlockDevice>,
+    block_size: u64,
+    capacity: u64,
+}
+
+impl Disk {
+    fn new(dev: AxBlockDevice) -> Self {
+        let block_size = dev.block_size() as u64;
+        let capacity = dev.num_blocks() * block_size;
+        Self {
+            dev: Mutex::new(dev),
+            block_size,
+            capacity,
+        }
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let len = (buf.len() as u64).min(self.capacity.saturating_sub(offset)) as usize;
+        let mut block = vec![0; self.block_size as usize];
+        let mut dev = self.dev.lock();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let skip = (pos % self.block_size) as usize;
+            dev.read_block(pos / self.block_size, &mut block)
+                .map_err(|_| LinuxError::EIO)?;
+            let n = (block.len() - skip).min(len - done);
+            buf[done..done + n].copy_from_slice(&block[skip..skip + n]);
+            done += n;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset >= self.capacity {
+            return Err(LinuxError::ENOSPC);
+        }
+        let len = (buf.len() as u64).min(self.capacity - offset) as usize;
+        let mut block = vec![0; self.block_size as usize];
+        let mut dev = self.dev.lock();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let skip = (pos % self.block_size) as usize;
+            let n = (block.len() - skip).min(len - done);
+            if n < block.len() {
+                dev.read_block(pos / self.block_size, &mut block)
+                    .map_err(|_| LinuxError::EIO)?;
+            }
+            block[skip..skip + n].copy_from_slice(&buf[done..done + n]);
+            dev.write_block(pos / self.block_size, &block)
+                .map_err(|_| LinuxError::EIO)?;
+            done += n;
+        }
+        Ok(len)
+    }
+
+    fn read_exact(&self, buf: &mut [u8], offset: u64) -> Option<()> {
+        (self.read_at(buf, offset).ok()? == buf.len()).then_some(())
+    }
+
+    /// Reads the partition table, returning `(start, len)` in bytes for each
+    /// partition.
+    fn partitions(&self) -> Vec<(u64, u64)> {
+        let mut mbr = [0u8; 512];
+        if self.read_exact(&mut mbr, 0).is_none() || mbr[510..512] != [0x55, 0xaa] {
+            return Vec::new();
+        }
+        let entries = mbr[446..510].chunks_exact(16);
+        if entries.clone().any(|entry| entry[4] == 0xee) {
+            return self.gpt_partitions().unwrap_or_default();
+        }
+        entries
+            .filter(|entry| entry[4] != 0)
+            .map(|entry| {
+                let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+                let len = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+                (start * 512, len * 512)
+            })
+            .filter(|&(start, len)| len > 0 && start + len <= self.capacity)
+            .collect()
+    }
+
+    fn gpt_partitions(&self) -> Option<Vec<(u64, u64)>> {
+        let lba = self.block_size;
+        let mut header = [0u8; 92];
+        self.read_exact(&mut header, lba)?;
+        if &header[..8] != b"EFI PART" {
+            return None;
+        }
+        let table = u64::from_le_bytes(header[72..80].try_into().unwrap()) * lba;
+        let count = u32::from_le_bytes(header[80..84].try_into().unwrap());
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as u64;
+        if entry_size < 48 {
+            return None;
+        }
+
+        let mut result = Vec::new();
+        let mut entry = [0u8; 48];
+        for i in 0..count.min(MINORS_PER_DISK - 1) {
+            self.read_exact(&mut entry, table + i as u64 * entry_size)?;
+            if entry[..16].iter().all(|&b| b == 0) {
+                continue;
+            }
+            let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+            if last >= first && (last + 1) * lba <= self.capacity {
+                result.push((first * lba, (last - first + 1) * lba));
+            }
+        }
+        Some(result)
+    }
+}
+
+/// /dev/vdX and /dev/vdXN devices: a range of a disk.
+pub struct BlockDevice {
+    disk: Arc<Disk>,
+    start: u64,
+    len: u64,
+    /// Readahead size in 512-byte sectors.
+    ra: AtomicU32,
+}
+
+impl DeviceOps for BlockDevice {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let len = (buf.len() as u64).min(self.len.saturating_sub(offset)) as usize;
+        self.disk.read_at(&mut buf[..len], self.start + offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset >= self.len {
+            return Err(LinuxError::ENOSPC);
+        }
+        let len = (buf.len() as u64).min(self.len - offset) as usize;
+        self.disk.write_at(&buf[..len], self.start + offset)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            BLKGETSIZE => (arg as *mut u32).vm_write((self.len / 512) as _)?,
+            BLKGETSIZE64 => (arg as *mut u64).vm_write(self.len)?,
+            BLKSSZGET => (arg as *mut u32).vm_write(self.disk.block_size as _)?,
+            BLKROGET => (arg as *mut u32).vm_write(0)?,
+            BLKRAGET => (arg as *mut c_long).vm_write(self.ra.load(Ordering::Relaxed) as _)?,
+            BLKRASET => {
+                // The new size is passed by value.
+                self.ra.store(
+                    arg.try_into().map_err(|_| LinuxError::EINVAL)?,
+                    Ordering::Relaxed,
+                );
+            }
+            _ => {
+                warn!("unknown ioctl for block device: {cmd}");
+                return Err(LinuxError::ENOTTY);
+            }
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+
+    fn capacity(&self) -> Option<u64> {
+        Some(self.len)
+    }
+
+    fn readahead(&self) -> Option<u64> {
+        Some(self.ra.load(Ordering::Relaxed) as u64 * 512)
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        self.disk.dev.lock().flush().map_err(|_| LinuxError::EIO)?;
+        Ok(())
+    }
+}
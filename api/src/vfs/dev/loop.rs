@@ -1,5 +1,7 @@
+use alloc::format;
 use core::{
     any::Any,
+    ffi::c_long,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
@@ -14,7 +16,13 @@ use linux_raw_sys::{
 use starry_core::vfs::{DeviceMmap, DeviceOps};
 use starry_vm::{VmMutPtr, VmPtr};
 
-use crate::file::get_file_like;
+use crate::{
+    file::{
+        get_file_like,
+        readahead::{self, ReadAhead},
+    },
+    vfs::{mount, writeback},
+};
 
 /// /dev/loopX devices
 pub struct LoopDevice {
@@ -24,8 +32,9 @@ pub struct LoopDevice {
     pub file: Mutex<Option<FileBackend>>,
     /// Read-only flag for the loop device.
     pub ro: AtomicBool,
-    /// Read-ahead size for the loop device, in bytes.
+    /// Read-ahead size for the loop device, in 512-byte sectors.
     pub ra: AtomicU32,
+    readahead: ReadAhead,
 }
 
 impl LoopDevice {
@@ -35,7 +44,8 @@ impl LoopDevice {
             dev_id,
             file: Mutex::new(None),
             ro: AtomicBool::new(false),
-            ra: AtomicU32::new(512),
+            ra: AtomicU32::new(256),
+            readahead: ReadAhead::default(),
         }
     }
 
@@ -64,16 +74,23 @@ impl LoopDevice {
 
 impl DeviceOps for LoopDevice {
     fn read_at(&self, mut buf: &mut [u8], offset: u64) -> VfsResult<usize> {
-        let file = self.file.lock().clone();
-        file.ok_or(LinuxError::EPERM)?.read_at(&mut buf, offset)
+        let file = self.file.lock().clone().ok_or(LinuxError::EPERM)?;
+        let read = file.read_at(&mut buf, offset)?;
+        let window = self.ra.load(Ordering::Relaxed) as u64 * 512;
+        if let Some(range) = self.readahead.on_read(offset, read as u64, window) {
+            readahead::prefetch(&file, range);
+        }
+        Ok(read)
     }
 
     fn write_at(&self, mut buf: &[u8], offset: u64) -> VfsResult<usize> {
         if self.ro.load(Ordering::Relaxed) {
             return Err(LinuxError::EROFS);
         }
-        let file = self.file.lock().clone();
-        file.ok_or(LinuxError::EPERM)?.write_at(&mut buf, offset)
+        let file = self.file.lock().clone().ok_or(LinuxError::EPERM)?;
+        let written = file.write_at(&mut buf, offset)?;
+        writeback::mark_dirty(&file, offset, written as u64);
+        Ok(written)
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
@@ -99,6 +116,9 @@ impl DeviceOps for LoopDevice {
                 if guard.is_none() {
                     return Err(LinuxError::ENXIO);
                 }
+                if mount::is_mounted(&format!("/dev/loop{}", self.number)) {
+                    return Err(LinuxError::EBUSY);
+                }
                 *guard = None;
             }
             LOOP_GET_STATUS => {
@@ -130,11 +150,14 @@ impl DeviceOps for LoopDevice {
                 self.ro.store(ro != 0, Ordering::Relaxed);
             }
             BLKRAGET => {
-                (arg as *mut u32).vm_write(self.ra.load(Ordering::Relaxed))?;
+                (arg as *mut c_long).vm_write(self.ra.load(Ordering::Relaxed) as _)?;
             }
             BLKRASET => {
-                self.ra
-                    .store((arg as *const u32).vm_read()? as _, Ordering::Relaxed);
+                // The new size is passed by value.
+                self.ra.store(
+                    arg.try_into().map_err(|_| LinuxError::EINVAL)?,
+                    Ordering::Relaxed,
+                );
             }
             _ => {
                 warn!("unknown ioctl for loop device: {cmd}");
@@ -159,4 +182,19 @@ impl DeviceOps for LoopDevice {
     fn flags(&self) -> NodeFlags {
         NodeFlags::NON_CACHEABLE
     }
+
+    fn capacity(&self) -> Option<u64> {
+        self.file.lock().as_ref()?.location().len().ok()
+    }
+
+    fn readahead(&self) -> Option<u64> {
+        Some(self.ra.load(Ordering::Relaxed) as u64 * 512)
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        match self.file.lock().clone() {
+            Some(file) => writeback::sync_file(&file, false),
+            None => Ok(()),
+        }
+    }
 }
@@ -1,5 +1,6 @@
 //! Special devices
 
+mod block;
 #[cfg(feature = "input")]
 mod event;
 mod fb;
@@ -17,6 +18,7 @@ use core::any::Any;
 use axerrno::LinuxError;
 use axfs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsResult};
 use axsync::Mutex;
+pub use block::add_disks;
 #[cfg(feature = "dev-log")]
 pub use log::bind_dev_log;
 use rand::{RngCore, SeedableRng, rngs::SmallRng};
@@ -268,12 +270,32 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         ),
     );
 
+    let mut net = DirMapping::new();
+    net.add(
+        "tun",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(10, 200),
+            Arc::new(crate::net::tun::TunClone(fs.clone())),
+        ),
+    );
+    root.add("net", SimpleDir::new_maker(fs.clone(), Arc::new(net)));
+
     // This is mounted to a tmpfs in `new_procfs`
     root.add(
         "shm",
         SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
     );
 
+    // Disks and partitions
+    for (name, dev_id, ops) in block::block_devices() {
+        root.add(
+            name,
+            Device::new(fs.clone(), NodeType::BlockDevice, dev_id, ops),
+        );
+    }
+
     // Loop devices
     for i in 0..16 {
         let dev_id = DeviceId::new(7, 0);
@@ -0,0 +1,266 @@
+//! Space manipulation for `fallocate` and hole lookup for `lseek`.
+//!
+//! tmpfs tracks which pages of a file have been written or allocated, and
+//! holes are the pages it does not track. Punching a hole stops tracking the
+//! whole pages in the range and drops them from the page cache, and moving
+//! ranges moves the tracked pages along with the data. ext4 does not expose its
+//! extents here, so like any Linux filesystem without hole support it reports
+//! the whole file as data.
+
+use alloc::{sync::Arc, vec};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::FileFlags;
+use axfs_ng_vfs::NodeType;
+use bitflags::bitflags;
+use memory_addr::PAGE_SIZE_4K;
+
+use super::tmp::{self, MemoryNode};
+use crate::file::File;
+
+/// Granularity of holes, collapsed and inserted ranges.
+const BLOCK_SIZE: u64 = PAGE_SIZE_4K as u64;
+
+/// Size of the kernel buffer used for moving data.
+const CHUNK_SIZE: u64 = 0x10000;
+
+bitflags! {
+    /// Modes of `fallocate`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FallocFlags: u32 {
+        /// Do not change the file size.
+        const KEEP_SIZE = 0x01;
+        /// Deallocate the range.
+        const PUNCH_HOLE = 0x02;
+        /// Reserved, never valid from user space.
+        const NO_HIDE_STALE = 0x04;
+        /// Remove the range and shift the rest of the file down.
+        const COLLAPSE_RANGE = 0x08;
+        /// Zero the range.
+        const ZERO_RANGE = 0x10;
+        /// Shift the rest of the file up to open a hole at the range.
+        const INSERT_RANGE = 0x20;
+        /// Unshare shared blocks in the range.
+        const UNSHARE_RANGE = 0x40;
+    }
+}
+
+impl FallocFlags {
+    fn validate(self) -> LinuxResult<()> {
+        if self.contains(Self::NO_HIDE_STALE) {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        let ops = self - Self::KEEP_SIZE;
+        if ops.bits().count_ones() > 1 {
+            return Err(LinuxError::EINVAL);
+        }
+        if ops.contains(Self::PUNCH_HOLE) && !self.contains(Self::KEEP_SIZE) {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        if ops.intersects(Self::COLLAPSE_RANGE | Self::INSERT_RANGE)
+            && self.contains(Self::KEEP_SIZE)
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(())
+    }
+}
+
+fn file_len(file: &File) -> LinuxResult<u64> {
+    Ok(file.inner().location().len()?)
+}
+
+fn set_len(file: &File, len: u64) -> LinuxResult<()> {
+    file.inner().access(FileFlags::WRITE)?.set_len(len)?;
+    Ok(())
+}
+
+/// Writes zeros over `start..end`.
+fn write_zeros(file: &File, start: u64, end: u64) -> LinuxResult<()> {
+    let zeros = vec![0; CHUNK_SIZE.min(end.saturating_sub(start)) as usize];
+    let mut pos = start;
+    while pos < end {
+        let chunk = (end - pos).min(CHUNK_SIZE) as usize;
+        let written = file.inner().write_at(&mut &zeros[..chunk], pos)?;
+        if written == 0 {
+            return Err(LinuxError::EIO);
+        }
+        pos += written as u64;
+    }
+    Ok(())
+}
+
+/// Copies `len` bytes from `src` to `dst` within the file, starting from the
+/// end when moving data up so that overlapping ranges stay intact.
+fn move_data(file: &File, src: u64, dst: u64, len: u64) -> LinuxResult<()> {
+    let mut buf = vec![0; CHUNK_SIZE.min(len) as usize];
+    let mut done = 0;
+    while done < len {
+        let chunk = (len - done).min(CHUNK_SIZE);
+        let off = if dst > src { len - done - chunk } else { done };
+        let buf = &mut buf[..chunk as usize];
+        let read = file.inner().read_at(&mut &mut buf[..], src + off)?;
+        if read as u64 != chunk {
+            return Err(LinuxError::EIO);
+        }
+        file.inner().write_at(&mut &buf[..], dst + off)?;
+        done += chunk;
+    }
+    Ok(())
+}
+
+fn memory_node(file: &File) -> Option<Arc<MemoryNode>> {
+    tmp::memory_node(file.inner().location())
+}
+
+fn punch_hole(file: &File, start: u64, end: u64) -> LinuxResult<()> {
+    let size = file_len(file)?;
+    if start >= size {
+        return Ok(());
+    }
+    if end >= size {
+        // Dropping the tail frees its pages, growing back leaves a hole.
+        set_len(file, start)?;
+        return set_len(file, size);
+    }
+    let Some(node) = memory_node(file) else {
+        return write_zeros(file, start, end);
+    };
+    let (hole_start, hole_end) = (
+        start.next_multiple_of(BLOCK_SIZE),
+        end / BLOCK_SIZE * BLOCK_SIZE,
+    );
+    if hole_start >= hole_end {
+        return write_zeros(file, start, end);
+    }
+    write_zeros(file, start, hole_start)?;
+    write_zeros(file, hole_end, end)?;
+
+    // The page cache can only drop the pages at the end of a file, so the data
+    // after the hole is set aside while the file is cut down to the hole.
+    let mut tail = vec![];
+    let mut pos = hole_end;
+    while let Some(data) = node.seek_data(pos)?.filter(|&data| data < size) {
+        let hole = node.seek_hole(data)?.min(size);
+        let mut buf = vec![0; (hole - data) as usize];
+        if file.inner().read_at(&mut &mut buf[..], data)? != buf.len() {
+            return Err(LinuxError::EIO);
+        }
+        tail.push((data, buf));
+        pos = hole;
+    }
+    set_len(file, hole_start)?;
+    set_len(file, size)?;
+    for (data, buf) in tail {
+        node.populate(data, data + buf.len() as u64)?;
+        file.inner().write_at(&mut &buf[..], data)?;
+    }
+    Ok(())
+}
+
+fn collapse_range(file: &File, start: u64, end: u64) -> LinuxResult<()> {
+    let size = file_len(file)?;
+    if start % BLOCK_SIZE != 0 || end % BLOCK_SIZE != 0 || end >= size {
+        return Err(LinuxError::EINVAL);
+    }
+    move_data(file, end, start, size - end)?;
+    if let Some(node) = memory_node(file) {
+        node.collapse(start, end)?;
+    }
+    set_len(file, size - (end - start))
+}
+
+fn insert_range(file: &File, start: u64, end: u64) -> LinuxResult<()> {
+    let size = file_len(file)?;
+    if start % BLOCK_SIZE != 0 || end % BLOCK_SIZE != 0 || start >= size {
+        return Err(LinuxError::EINVAL);
+    }
+    let len = end - start;
+    let new_size = size.checked_add(len).ok_or(LinuxError::EFBIG)?;
+    if new_size > i64::MAX as u64 {
+        return Err(LinuxError::EFBIG);
+    }
+    set_len(file, new_size)?;
+    move_data(file, start, end, size - start)?;
+    write_zeros(file, start, end)?;
+    if let Some(node) = memory_node(file) {
+        node.insert_hole(start, end)?;
+    }
+    Ok(())
+}
+
+/// Manipulates the space of `file` in `offset..offset + len` according to
+/// `mode`, as `fallocate` does.
+pub fn fallocate(file: &File, mode: FallocFlags, offset: u64, len: u64) -> LinuxResult<()> {
+    mode.validate()?;
+    let inner = file.inner();
+    match inner.location().node_type() {
+        NodeType::RegularFile => {}
+        NodeType::Directory => return Err(LinuxError::EISDIR),
+        NodeType::Fifo => return Err(LinuxError::ESPIPE),
+        _ => return Err(LinuxError::ENODEV),
+    }
+    inner.access(FileFlags::WRITE)?;
+    let end = offset.checked_add(len).ok_or(LinuxError::EFBIG)?;
+    if end > i64::MAX as u64 {
+        return Err(LinuxError::EFBIG);
+    }
+
+    let keep_size = mode.contains(FallocFlags::KEEP_SIZE);
+    let op = mode - FallocFlags::KEEP_SIZE;
+    match op {
+        FallocFlags::PUNCH_HOLE => punch_hole(file, offset, end)?,
+        FallocFlags::COLLAPSE_RANGE => collapse_range(file, offset, end)?,
+        FallocFlags::INSERT_RANGE => insert_range(file, offset, end)?,
+        FallocFlags::ZERO_RANGE => {
+            let size = file_len(file)?;
+            let end = if keep_size { end.min(size) } else { end };
+            if end <= offset {
+                return Ok(());
+            }
+            if let Some(node) = memory_node(file) {
+                node.populate(offset, end)?;
+            }
+            write_zeros(file, offset, end)?;
+        }
+        _ => {
+            // tmpfs allocates the pages, other filesystems only have to care
+            // about the size.
+            if let Some(node) = memory_node(file) {
+                node.populate(offset, end)?;
+            }
+            if !keep_size && end > file_len(file)? {
+                set_len(file, end)?;
+            }
+        }
+    }
+    // Zeros and moved data are written through the page cache.
+    let written = match op {
+        FallocFlags::ZERO_RANGE => len,
+        FallocFlags::COLLAPSE_RANGE | FallocFlags::INSERT_RANGE => {
+            file_len(file)?.saturating_sub(offset)
+        }
+        _ => 0,
+    };
+    file.finish_write(offset, written as usize)
+}
+
+/// Finds the first position at or after `offset` that lies in data (`hole`
+/// is false) or in a hole (`hole` is true), for `SEEK_DATA` and `SEEK_HOLE`.
+/// The end of the file always counts as a hole.
+pub fn seek_hole_data(file: &File, offset: u64, hole: bool) -> LinuxResult<u64> {
+    let size = file_len(file)?;
+    if offset >= size {
+        return Err(LinuxError::ENXIO);
+    }
+    let Some(node) = memory_node(file) else {
+        return Ok(if hole { size } else { offset });
+    };
+    if hole {
+        Ok(node.seek_hole(offset)?.min(size))
+    } else {
+        node.seek_data(offset)?
+            .filter(|&pos| pos < size)
+            .ok_or(LinuxError::ENXIO)
+    }
+}
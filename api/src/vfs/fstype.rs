@@ -0,0 +1,311 @@
+//! Filesystem types that can be mounted with `mount(2)`.
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use axdriver::prelude::{
+    AxBlockDevice, BaseDriverOps, BlockDriverOps, DevError, DevResult, DeviceType,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{Filesystem, Location, NodeType};
+use starry_core::vfs::{Device, DeviceOps};
+
+use super::{MemoryFs, mount::MountFlags, proc::new_procfs, tmp::TmpfsOptions};
+
+/// Block size presented to filesystem drivers.
+const BLOCK_SIZE: usize = 512;
+
+/// A filesystem type known to the kernel.
+struct FsType {
+    name: &'static str,
+    /// Whether the filesystem needs a block device.
+    requires_dev: bool,
+}
+
+const FS_TYPES: &[FsType] = &[
+    FsType {
+        name: "tmpfs",
+        requires_dev: false,
+    },
+    FsType {
+        name: "proc",
+        requires_dev: false,
+    },
+    FsType {
+        name: "ext4",
+        requires_dev: true,
+    },
+    FsType {
+        name: "ext3",
+        requires_dev: true,
+    },
+    FsType {
+        name: "ext2",
+        requires_dev: true,
+    },
+];
+
+/// Returns the canonical name of a known filesystem type.
+pub fn lookup(name: &str) -> Option<&'static str> {
+    FS_TYPES.iter().find(|it| it.name == name).map(|it| it.name)
+}
+
+/// Renders the contents of `/proc/filesystems`.
+pub fn proc_filesystems() -> String {
+    FS_TYPES
+        .iter()
+        .map(|ty| {
+            let prefix = if ty.requires_dev { "" } else { "nodev" };
+            format!("{}\t{}\n", prefix, ty.name)
+        })
+        .collect()
+}
+
+/// Options passed through the `data` argument of `mount(2)`.
+#[derive(Default)]
+pub struct MountOptions {
+    /// Generic flags given by name, e.g. `ro` or `noexec`.
+    pub flags: MountFlags,
+    /// Generic flags explicitly turned off, e.g. `rw` or `exec`.
+    pub clear: MountFlags,
+    /// Filesystem specific options, in their original order.
+    pub fs: Vec<(String, Option<String>)>,
+}
+
+impl MountOptions {
+    /// Parses a comma-separated option string.
+    pub fn parse(data: &str) -> Self {
+        let mut result = Self::default();
+        for opt in data.split(',').filter(|it| !it.is_empty()) {
+            let (set, clear) = match opt {
+                "ro" => (MountFlags::RDONLY, MountFlags::empty()),
+                "rw" => (MountFlags::empty(), MountFlags::RDONLY),
+                "nosuid" => (MountFlags::NOSUID, MountFlags::empty()),
+                "suid" => (MountFlags::empty(), MountFlags::NOSUID),
+                "nodev" => (MountFlags::NODEV, MountFlags::empty()),
+                "dev" => (MountFlags::empty(), MountFlags::NODEV),
+                "noexec" => (MountFlags::NOEXEC, MountFlags::empty()),
+                "exec" => (MountFlags::empty(), MountFlags::NOEXEC),
+                "sync" => (MountFlags::SYNCHRONOUS, MountFlags::empty()),
+                "async" => (MountFlags::empty(), MountFlags::SYNCHRONOUS),
+                "dirsync" => (MountFlags::DIRSYNC, MountFlags::empty()),
+                "noatime" => (MountFlags::NOATIME, MountFlags::empty()),
+                "atime" => (MountFlags::empty(), MountFlags::NOATIME),
+                "nodiratime" => (MountFlags::NODIRATIME, MountFlags::empty()),
+                "diratime" => (MountFlags::empty(), MountFlags::NODIRATIME),
+                "relatime" => (MountFlags::RELATIME, MountFlags::empty()),
+                "norelatime" => (MountFlags::empty(), MountFlags::RELATIME),
+                "strictatime" => (MountFlags::STRICTATIME, MountFlags::empty()),
+                "lazytime" => (MountFlags::LAZYTIME, MountFlags::empty()),
+                "nolazytime" => (MountFlags::empty(), MountFlags::LAZYTIME),
+                "defaults" => (MountFlags::empty(), MountFlags::empty()),
+                _ => {
+                    let (key, value) = match opt.split_once('=') {
+                        Some((key, value)) => (key, Some(value.to_string())),
+                        None => (opt, None),
+                    };
+                    result.fs.push((key.into(), value));
+                    continue;
+                }
+            };
+            result.flags = (result.flags - clear) | set;
+            result.clear = (result.clear - set) | clear;
+        }
+        result
+    }
+
+    /// Applies the generic flags on top of `flags`.
+    pub fn apply(&self, flags: MountFlags) -> MountFlags {
+        (flags - self.clear) | self.flags
+    }
+
+    /// Renders the filesystem specific options back into a string.
+    pub fn fs_data(&self) -> String {
+        self.fs
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn check_ext4_options(opts: &MountOptions) -> LinuxResult<()> {
+    for (key, value) in &opts.fs {
+        let ok = match (key.as_str(), value.as_deref()) {
+            ("data", Some("ordered" | "journal" | "writeback")) => true,
+            ("errors", Some("continue" | "remount-ro" | "panic")) => true,
+            ("commit" | "stripe" | "inode_readahead_blks", Some(n)) => n.parse::<u32>().is_ok(),
+            (
+                "barrier" | "nobarrier" | "noload" | "norecovery" | "discard" | "nodiscard"
+                | "user_xattr" | "nouser_xattr" | "acl" | "noacl" | "journal_checksum"
+                | "nojournal_checksum" | "auto_da_alloc" | "noauto_da_alloc" | "delalloc"
+                | "nodelalloc" | "dioread_lock" | "dioread_nolock" | "init_itable"
+                | "noinit_itable",
+                None,
+            ) => true,
+            _ => false,
+        };
+        if !ok {
+            warn!("ext4: unrecognized mount option {:?}={:?}", key, value);
+            return Err(LinuxError::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+/// Presents a block device node to filesystem drivers.
+struct BlockAdapter {
+    name: String,
+    ops: Arc<dyn DeviceOps>,
+    blocks: u64,
+}
+
+impl BaseDriverOps for BlockAdapter {
+    fn device_name(&self) -> &str {
+        &self.name
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for BlockAdapter {
+    fn num_blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let mut offset = block_id * BLOCK_SIZE as u64;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            match self.ops.read_at(buf, offset) {
+                Ok(0) | Err(_) => return Err(DevError::Io),
+                Ok(n) => {
+                    buf = &mut core::mem::take(&mut buf)[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        let mut offset = block_id * BLOCK_SIZE as u64;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            match self.ops.write_at(buf, offset) {
+                Ok(0) | Err(_) => return Err(DevError::Io),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.ops.flush().map_err(|_| DevError::Io)
+    }
+}
+
+/// Returns the operations of the block device at `loc`.
+fn block_device(loc: &Location) -> LinuxResult<Arc<dyn DeviceOps>> {
+    if !matches!(loc.node_type(), NodeType::BlockDevice) {
+        return Err(LinuxError::ENOTBLK);
+    }
+    let device = loc
+        .entry()
+        .downcast::<Device>()
+        .map_err(|_| LinuxError::ENOTBLK)?;
+    let ops = device.inner().clone();
+    if ops.capacity().is_none_or(|it| it == 0) {
+        // E.g. a loop device without a backing file.
+        return Err(LinuxError::ENXIO);
+    }
+    Ok(ops)
+}
+
+/// Guesses the filesystem type from the superblock of a block device.
+fn detect(ops: &dyn DeviceOps) -> Option<&'static str> {
+    let mut buf = [0u8; 2048];
+    let mut read = 0;
+    while read < buf.len() {
+        match ops.read_at(&mut buf[read..], read as u64) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read += n,
+        }
+    }
+
+    // ext2/3/4: the superblock starts at 1024, s_magic is at offset 56.
+    if read >= 1024 + 100 && u16::from_le_bytes([buf[1024 + 56], buf[1024 + 57]]) == 0xef53 {
+        let compat = u32::from_le_bytes(buf[1024 + 92..1024 + 96].try_into().unwrap());
+        let incompat = u32::from_le_bytes(buf[1024 + 96..1024 + 100].try_into().unwrap());
+        return Some(if incompat & !0xf != 0 {
+            // Anything beyond COMPRESSION, FILETYPE, RECOVER and JOURNAL_DEV
+            // (e.g. extents, 64bit, flex_bg) is an ext4 feature.
+            "ext4"
+        } else if compat & 0x4 != 0 {
+            // HAS_JOURNAL
+            "ext3"
+        } else {
+            "ext2"
+        });
+    }
+    None
+}
+
+/// Instantiates a filesystem of type `fs_type` for a `mount(2)` request.
+///
+/// `source` is the resolved mount source, which must be a block device for
+/// disk filesystems. If `fs_type` is empty or `auto`, it is detected from the
+/// superblock. Returns the filesystem together with its actual type name.
+pub fn new_filesystem(
+    fs_type: &str,
+    source: Option<&Location>,
+    opts: &MountOptions,
+) -> LinuxResult<(Filesystem, &'static str)> {
+    let fs_type = if fs_type.is_empty() || fs_type == "auto" {
+        let ops = block_device(source.ok_or(LinuxError::ENOENT)?)?;
+        detect(ops.as_ref()).ok_or(LinuxError::EINVAL)?
+    } else {
+        lookup(fs_type).ok_or(LinuxError::ENODEV)?
+    };
+
+    let fs = match fs_type {
+        "tmpfs" => MemoryFs::with_options(TmpfsOptions::parse(opts)?),
+        "proc" => new_procfs(),
+        "ext4" | "ext3" | "ext2" => {
+            check_ext4_options(opts)?;
+            let source = source.ok_or(LinuxError::ENOENT)?;
+            let ops = block_device(source)?;
+            if detect(ops.as_ref()).is_none() {
+                return Err(LinuxError::EINVAL);
+            }
+            let blocks = ops.capacity().unwrap_or_default() / BLOCK_SIZE as u64;
+            let dev: AxBlockDevice = Box::new(BlockAdapter {
+                name: source.name().to_string(),
+                ops,
+                blocks,
+            });
+            axfs_ng::fs::ext4::Ext4Filesystem::new(dev).inspect_err(|err| {
+                warn!("ext4: failed to mount {}: {:?}", source.name(), err);
+            })?
+        }
+        _ => unreachable!(),
+    };
+    Ok((fs, fs_type))
+}
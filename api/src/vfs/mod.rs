@@ -1,8 +1,18 @@
 //! Virtual filesystems
 
+pub mod acl;
 pub mod dev;
+pub mod falloc;
+pub mod fstype;
+pub mod mount;
+pub mod pagecache;
 mod proc;
-mod tmp;
+pub mod reflink;
+pub(crate) mod tmp;
+pub mod writeback;
+pub mod xattr;
+
+use alloc::borrow::ToOwned;
 
 use axerrno::LinuxResult;
 use axfs_ng::{FS_CONTEXT, FsContext};
@@ -13,26 +23,61 @@ use axfs_ng_vfs::{
 pub use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
 pub use tmp::MemoryFs;
 
+use self::mount::{MountFlags, NewMount};
+
 const DIR_PERMISSION: NodePermission = NodePermission::from_bits_truncate(0o755);
 
-fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()> {
+fn mount_at(
+    fs: &FsContext,
+    path: &str,
+    mount_fs: Filesystem,
+    flags: MountFlags,
+) -> LinuxResult<()> {
     if fs.resolve(path).is_err() {
         fs.create_dir(path, DIR_PERMISSION)?;
     }
     fs.resolve(path)?.mount(&mount_fs)?;
-    info!("Mounted {} at {}", mount_fs.name(), path);
+    let name = mount_fs.name().to_owned();
+    mount::register(
+        &fs.resolve(path)?,
+        Some(mount_fs),
+        NewMount {
+            source: &name,
+            fs_type: &name,
+            root: "/",
+            flags,
+            data: "",
+        },
+    )?;
+    info!("Mounted {} at {}", name, path);
     Ok(())
 }
 
 /// Mount all filesystems
 pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
-    mount_at(&fs, "/dev", dev::new_devfs())?;
-    mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/proc", proc::new_procfs())?;
+    let root = fs.root_dir();
+    let root_name = root.filesystem().name().to_owned();
+    mount::register(
+        &root,
+        None,
+        NewMount {
+            source: "/dev/root",
+            fs_type: &root_name,
+            root: "/",
+            flags: MountFlags::RELATIME,
+            data: "",
+        },
+    )?;
+
+    let nosuid = MountFlags::NOSUID | MountFlags::RELATIME;
+    let restricted = nosuid | MountFlags::NODEV | MountFlags::NOEXEC;
+    mount_at(&fs, "/dev", dev::new_devfs(), nosuid)?;
+    mount_at(&fs, "/dev/shm", tmp::MemoryFs::new(), nosuid | MountFlags::NODEV)?;
+    mount_at(&fs, "/tmp", tmp::MemoryFs::new(), nosuid | MountFlags::NODEV)?;
+    mount_at(&fs, "/proc", proc::new_procfs(), restricted)?;
 
-    mount_at(&fs, "/sys", tmp::MemoryFs::new())?;
+    mount_at(&fs, "/sys", tmp::MemoryFs::new(), restricted)?;
     let mut path = PathBuf::new();
     for comp in Path::new("/sys/class/graphics/fb0/device").components() {
         path.push(comp.as_str());
@@ -0,0 +1,410 @@
+//! Mount table.
+//!
+//! The VFS only knows which filesystem is mounted where. Everything else
+//! userspace can observe about a mount (its source, type, flags and options)
+//! is recorded here, keyed by the device number of the mountpoint.
+//! `/proc/mounts` and `/proc/[pid]/mountinfo` are rendered from this table.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{DirEntry, Filesystem, FilesystemOps, Location, NodeType, StatFs, VfsResult};
+use axsync::Mutex;
+use linux_raw_sys::general::{
+    MS_DIRSYNC, MS_LAZYTIME, MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_RDONLY,
+    MS_RELATIME, MS_STRICTATIME, MS_SYNCHRONOUS,
+};
+
+bitflags::bitflags! {
+    /// Flags attached to a single mount.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MountFlags: u32 {
+        /// Mount read-only.
+        const RDONLY = MS_RDONLY;
+        /// Ignore set-user-ID and set-group-ID bits.
+        const NOSUID = MS_NOSUID;
+        /// Disallow access to device special files.
+        const NODEV = MS_NODEV;
+        /// Disallow program execution.
+        const NOEXEC = MS_NOEXEC;
+        /// Writes are synced at once.
+        const SYNCHRONOUS = MS_SYNCHRONOUS;
+        /// Directory modifications are synchronous.
+        const DIRSYNC = MS_DIRSYNC;
+        /// Do not update access times.
+        const NOATIME = MS_NOATIME;
+        /// Do not update directory access times.
+        const NODIRATIME = MS_NODIRATIME;
+        /// Update atime relative to mtime/ctime.
+        const RELATIME = MS_RELATIME;
+        /// Always update the last access time.
+        const STRICTATIME = MS_STRICTATIME;
+        /// Update the on-disk timestamps lazily.
+        const LAZYTIME = MS_LAZYTIME;
+    }
+}
+
+impl MountFlags {
+    /// Returns the flags of a mount with these flags after a remount asking
+    /// for `given`.
+    ///
+    /// The given flags replace the current ones, except that the atime flags
+    /// are kept unless some are given.
+    pub fn remount(self, given: Self) -> Self {
+        let atime = Self::NOATIME | Self::NODIRATIME | Self::RELATIME | Self::STRICTATIME;
+        if given.intersects(atime) {
+            given
+        } else {
+            given | (self & atime)
+        }
+    }
+
+    /// Returns the `ST_*` flags reported by `statfs`.
+    pub fn statfs_flags(self) -> u64 {
+        // ST_RDONLY, ST_NOSUID, ... share their values with the MS_* flags,
+        // but there is no ST_* counterpart of the rest.
+        (self - Self::DIRSYNC - Self::STRICTATIME - Self::LAZYTIME).bits() as u64
+    }
+
+    fn options(self) -> String {
+        let mut opts = String::from(if self.contains(Self::RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        });
+        for (flag, name) in [
+            (Self::NOSUID, "nosuid"),
+            (Self::NODEV, "nodev"),
+            (Self::NOEXEC, "noexec"),
+            (Self::SYNCHRONOUS, "sync"),
+            (Self::DIRSYNC, "dirsync"),
+            (Self::NOATIME, "noatime"),
+            (Self::NODIRATIME, "nodiratime"),
+            (Self::RELATIME, "relatime"),
+            (Self::LAZYTIME, "lazytime"),
+        ] {
+            if self.contains(flag) {
+                opts.push(',');
+                opts.push_str(name);
+            }
+        }
+        opts
+    }
+}
+
+/// A single entry of the mount table.
+#[derive(Clone)]
+pub struct MountEntry {
+    /// Unique mount ID.
+    pub id: u32,
+    /// ID of the parent mount.
+    pub parent: u32,
+    /// Device number of the mountpoint.
+    pub device: u64,
+    /// Mount source, e.g. a device path.
+    pub source: String,
+    /// Absolute path of the mountpoint.
+    pub target: String,
+    /// Path of the mount's root within its filesystem.
+    pub root: String,
+    /// Filesystem type.
+    pub fs_type: String,
+    /// Mount flags.
+    pub flags: MountFlags,
+    /// Filesystem specific options.
+    pub data: String,
+    /// The mounted filesystem, absent for the root filesystem.
+    pub fs: Option<Filesystem>,
+}
+
+struct MountTable {
+    next_id: u32,
+    entries: Vec<MountEntry>,
+}
+
+static MOUNTS: Mutex<MountTable> = Mutex::new(MountTable {
+    next_id: 1,
+    entries: Vec::new(),
+});
+
+fn is_under(path: &str, dir: &str) -> bool {
+    dir == "/" || path == dir || path.strip_prefix(dir).is_some_and(|it| it.starts_with('/'))
+}
+
+impl MountTable {
+    fn parent_of(&self, target: &str) -> u32 {
+        self.entries
+            .iter()
+            .filter(|it| it.target != target && is_under(target, &it.target))
+            .max_by_key(|it| it.target.len())
+            .map_or(0, |it| it.id)
+    }
+}
+
+/// Information used to register a new mount.
+pub struct NewMount<'a> {
+    /// Mount source.
+    pub source: &'a str,
+    /// Filesystem type.
+    pub fs_type: &'a str,
+    /// Path of the mount's root within its filesystem.
+    pub root: &'a str,
+    /// Mount flags.
+    pub flags: MountFlags,
+    /// Filesystem specific options.
+    pub data: &'a str,
+}
+
+/// Owned counterpart of [`NewMount`], for mounts that are not attached yet.
+#[derive(Clone)]
+pub struct MountSpec {
+    /// Mount source.
+    pub source: String,
+    /// Filesystem type.
+    pub fs_type: String,
+    /// Path of the mount's root within its filesystem.
+    pub root: String,
+    /// Mount flags.
+    pub flags: MountFlags,
+    /// Filesystem specific options.
+    pub data: String,
+}
+
+impl MountSpec {
+    /// Borrows the spec for [`register`].
+    pub fn as_new(&self) -> NewMount<'_> {
+        NewMount {
+            source: &self.source,
+            fs_type: &self.fs_type,
+            root: &self.root,
+            flags: self.flags,
+            data: &self.data,
+        }
+    }
+}
+
+impl From<&MountEntry> for MountSpec {
+    fn from(entry: &MountEntry) -> Self {
+        Self {
+            source: entry.source.clone(),
+            fs_type: entry.fs_type.clone(),
+            root: entry.root.clone(),
+            flags: entry.flags,
+            data: entry.data.clone(),
+        }
+    }
+}
+
+/// Records a mount. `loc` is the root location of the freshly mounted
+/// filesystem.
+pub fn register(loc: &Location, fs: Option<Filesystem>, info: NewMount) -> LinuxResult<u32> {
+    let target = loc.absolute_path()?.to_string();
+    let mut table = MOUNTS.lock();
+    let id = table.next_id;
+    table.next_id += 1;
+    let parent = table.parent_of(&target);
+    table.entries.push(MountEntry {
+        id,
+        parent: if parent == 0 { id } else { parent },
+        device: loc.mountpoint().device(),
+        source: info.source.into(),
+        target,
+        root: info.root.into(),
+        fs_type: info.fs_type.into(),
+        flags: info.flags,
+        data: info.data.into(),
+        fs,
+    });
+    Ok(id)
+}
+
+/// Returns the mount entry `loc` belongs to.
+pub fn lookup(loc: &Location) -> Option<MountEntry> {
+    let device = loc.mountpoint().device();
+    MOUNTS
+        .lock()
+        .entries
+        .iter()
+        .find(|it| it.device == device)
+        .cloned()
+}
+
+/// Returns a snapshot of the mount table.
+pub fn entries() -> Vec<MountEntry> {
+    MOUNTS.lock().entries.clone()
+}
+
+/// Returns whether `source` is mounted somewhere.
+pub fn is_mounted(source: &str) -> bool {
+    MOUNTS.lock().entries.iter().any(|it| it.source == source)
+}
+
+/// Returns the flags of the mount `loc` belongs to.
+pub fn mount_flags(loc: &Location) -> MountFlags {
+    lookup(loc).map_or(MountFlags::empty(), |it| it.flags)
+}
+
+/// Fails with `EROFS` if `loc` is on a read-only mount.
+pub fn check_writable(loc: &Location) -> LinuxResult<()> {
+    if mount_flags(loc).contains(MountFlags::RDONLY) {
+        return Err(LinuxError::EROFS);
+    }
+    Ok(())
+}
+
+/// Fails with `EACCES` if `loc` is on a `noexec` mount.
+pub fn check_exec(loc: &Location) -> LinuxResult<()> {
+    if mount_flags(loc).contains(MountFlags::NOEXEC) {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// Returns whether the set-user-ID and set-group-ID bits of `loc` take
+/// effect, which they do not on `nosuid` mounts.
+pub fn honors_set_id(loc: &Location) -> bool {
+    !mount_flags(loc).contains(MountFlags::NOSUID)
+}
+
+/// Fails with `EACCES` if `loc` is a device node on a `nodev` mount.
+pub fn check_dev(loc: &Location) -> LinuxResult<()> {
+    if matches!(
+        loc.node_type(),
+        NodeType::CharacterDevice | NodeType::BlockDevice
+    ) && mount_flags(loc).contains(MountFlags::NODEV)
+    {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// Changes the flags and options of an existing mount.
+pub fn remount(loc: &Location, flags: MountFlags, data: Option<&str>) -> LinuxResult<()> {
+    let device = loc.mountpoint().device();
+    let mut table = MOUNTS.lock();
+    let entry = table
+        .entries
+        .iter_mut()
+        .find(|it| it.device == device)
+        .ok_or(LinuxError::EINVAL)?;
+    entry.flags = flags;
+    if let Some(data) = data {
+        entry.data = data.into();
+    }
+    Ok(())
+}
+
+/// Removes the mount with the given device number from the table.
+pub fn unregister(device: u64) -> Option<MountEntry> {
+    let mut table = MOUNTS.lock();
+    let index = table.entries.iter().position(|it| it.device == device)?;
+    Some(table.entries.remove(index))
+}
+
+/// Returns the mounts strictly below `target`, deepest first.
+pub fn submounts(target: &str) -> Vec<MountEntry> {
+    let mut result = MOUNTS
+        .lock()
+        .entries
+        .iter()
+        .filter(|it| it.target != target && is_under(&it.target, target))
+        .cloned()
+        .collect::<Vec<_>>();
+    result.sort_by_key(|it| core::cmp::Reverse(it.target.len()));
+    result
+}
+
+/// Escapes whitespace and backslashes the way `/proc/mounts` does.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ' ' | '\t' | '\n' | '\\' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn options(entry: &MountEntry) -> String {
+    let mut opts = entry.flags.options();
+    if !entry.data.is_empty() {
+        opts.push(',');
+        opts.push_str(&entry.data);
+    }
+    opts
+}
+
+/// Renders the contents of `/proc/mounts`.
+pub fn proc_mounts() -> String {
+    let mut out = String::new();
+    for entry in MOUNTS.lock().entries.iter() {
+        let _ = writeln!(
+            out,
+            "{} {} {} {} 0 0",
+            escape(&entry.source),
+            escape(&entry.target),
+            entry.fs_type,
+            options(entry)
+        );
+    }
+    out
+}
+
+/// Renders the contents of `/proc/[pid]/mountinfo`.
+pub fn proc_mountinfo() -> String {
+    let mut out = String::new();
+    for entry in MOUNTS.lock().entries.iter() {
+        let _ = writeln!(
+            out,
+            "{} {} {}:{} {} {} {} - {} {} {}",
+            entry.id,
+            entry.parent,
+            entry.device >> 32,
+            entry.device as u32,
+            escape(&entry.root),
+            escape(&entry.target),
+            entry.flags.options(),
+            entry.fs_type,
+            escape(&entry.source),
+            options(entry)
+        );
+    }
+    out
+}
+
+/// A filesystem exposing a subtree of another mount, used for bind mounts.
+struct BindFs {
+    name: String,
+    source: Location,
+}
+
+impl FilesystemOps for BindFs {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.source.entry().clone()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        self.source.filesystem().stat()
+    }
+}
+
+/// Creates a filesystem that makes `source` visible at another place.
+pub fn bind_fs(source: &Location) -> Filesystem {
+    Filesystem::new(Arc::new(BindFs {
+        name: source.filesystem().name().into(),
+        source: source.clone(),
+    }))
+}
@@ -0,0 +1,178 @@
+//! Clean page cache kept for reuse and reclaimed under memory pressure.
+//!
+//! The pages of a cached file live as long as someone holds the file. The
+//! files read or written lately are held here as well, so that their pages
+//! survive a close and are found again by the next open. Which pages a file
+//! has is only known for those read, written or read ahead through
+//! [`touch`]: the page cache itself does not tell.
+//!
+//! The shrinker lets go of the least recently used files that are not
+//! dirty. Their pages go away with that unless the file is still open.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use axfs_ng::FileBackend;
+use axfs_ng_vfs::{Location, NodeFlags};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::shrink::{self, Shrinker};
+
+use super::writeback::{self, FileKey};
+
+struct CachedFile {
+    backend: FileBackend,
+    /// Indices of the pages known to be cached.
+    pages: BTreeSet<u64>,
+    /// Value of [`CLOCK`] when the file was last used.
+    used: u64,
+}
+
+static FILES: Mutex<BTreeMap<FileKey, CachedFile>> = Mutex::new(BTreeMap::new());
+/// Number of `pages` of all files.
+static CACHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// Ticks on every use, to tell which file was used least recently.
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn pages(pos: u64, len: u64) -> Range<u64> {
+    let page = PAGE_SIZE_4K as u64;
+    pos / page..pos.saturating_add(len).div_ceil(page)
+}
+
+/// Records that the `len` bytes at `pos` of `backend` were just read or
+/// written through the page cache, and holds on to the file.
+pub fn touch(backend: &FileBackend, pos: u64, len: u64) {
+    // tmpfs files never leave the page cache, there is nothing to reclaim.
+    if !matches!(backend, FileBackend::Cached(_))
+        || backend.location().flags().contains(NodeFlags::ALWAYS_CACHE)
+    {
+        return;
+    }
+    let Ok(key) = writeback::file_key(backend) else {
+        return;
+    };
+    let used = CLOCK.fetch_add(1, Ordering::Relaxed);
+    let mut files = FILES.lock();
+    let file = files.entry(key).or_insert_with(|| CachedFile {
+        backend: backend.clone(),
+        pages: BTreeSet::new(),
+        used,
+    });
+    file.used = used;
+    let added = pages(pos, len)
+        .filter(|&index| file.pages.insert(index))
+        .count();
+    CACHED_PAGES.fetch_add(added, Ordering::Relaxed);
+}
+
+/// Returns whether all pages of `backend` holding the `len` bytes at `pos`
+/// are known to be cached.
+pub fn is_cached(backend: &FileBackend, pos: u64, len: u64) -> bool {
+    let Ok(key) = writeback::file_key(backend) else {
+        return false;
+    };
+    FILES
+        .lock()
+        .get(&key)
+        .is_some_and(|file| pages(pos, len).all(|index| file.pages.contains(&index)))
+}
+
+/// Lets go of the pages holding the `len` bytes at `pos` of the file at
+/// `loc`, and of the file once none are left. A `len` of 0 means up to the
+/// end of the file.
+pub fn forget(loc: &Location, pos: u64, len: u64) {
+    let Ok(key) = writeback::location_key(loc) else {
+        return;
+    };
+    let len = if len == 0 { u64::MAX } else { len };
+    let range = pages(pos, len);
+    let mut files = FILES.lock();
+    let Some(file) = files.get_mut(&key) else {
+        return;
+    };
+    let removed = file.pages.range(range).copied().collect::<Vec<_>>();
+    for index in &removed {
+        file.pages.remove(index);
+    }
+    CACHED_PAGES.fetch_sub(removed.len(), Ordering::Relaxed);
+    if file.pages.is_empty() {
+        files.remove(&key);
+    }
+}
+
+/// Lets go of every file on the filesystem of `loc`, before it is
+/// unmounted.
+pub fn forget_fs(loc: &Location) {
+    let mut files = FILES.lock();
+    let mut removed = 0;
+    files.retain(|_, file| {
+        let keep = !core::ptr::addr_eq(file.backend.location().filesystem(), loc.filesystem());
+        if !keep {
+            removed += file.pages.len();
+        }
+        keep
+    });
+    CACHED_PAGES.fetch_sub(removed, Ordering::Relaxed);
+}
+
+/// Returns the number of pages known to be in the page cache.
+pub fn cached_pages() -> usize {
+    CACHED_PAGES.load(Ordering::Relaxed)
+}
+
+/// Shrinker letting go of the least recently used clean files.
+struct PageCacheShrinker;
+
+impl Shrinker for PageCacheShrinker {
+    fn name(&self) -> &str {
+        "page cache"
+    }
+
+    fn count(&self) -> usize {
+        FILES
+            .lock()
+            .iter()
+            .filter(|(key, _)| !writeback::is_dirty(key))
+            .map(|(_, file)| file.pages.len())
+            .sum()
+    }
+
+    fn scan(&self, nr: usize) -> usize {
+        let files = {
+            let mut files = FILES.lock();
+            let mut oldest = files
+                .iter()
+                .filter(|(key, _)| !writeback::is_dirty(key))
+                .map(|(key, file)| (file.used, *key, file.pages.len()))
+                .collect::<Vec<_>>();
+            oldest.sort_unstable_by_key(|(used, ..)| *used);
+            let mut pages = 0;
+            oldest
+                .into_iter()
+                .take_while(|(.., count)| {
+                    let more = pages < nr;
+                    pages += count;
+                    more
+                })
+                .filter_map(|(_, key, _)| files.remove(&key))
+                .collect::<Vec<_>>()
+        };
+        let count = files.iter().map(|it| it.pages.len()).sum();
+        CACHED_PAGES.fetch_sub(count, Ordering::Relaxed);
+        let (before, _) = shrink::memory_pages();
+        drop(files);
+        shrink::memory_pages().0.saturating_sub(before)
+    }
+}
+
+/// Registers the shrinker of clean page cache.
+pub fn register_shrinker() {
+    shrink::register_shrinker(Arc::new(PageCacheShrinker));
+}
@@ -7,12 +7,19 @@ use alloc::{
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+use core::{
+    ffi::CStr,
+    fmt::Write,
+    iter,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use axfs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
 use axtask::{AxTaskRef, WeakAxTaskRef, current};
 use indoc::indoc;
+use memory_addr::PAGE_SIZE_4K;
 use starry_core::{
+    shrink,
     task::{AsThread, TaskStat, get_task, tasks},
     vfs::{
         DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
@@ -21,7 +28,11 @@ use starry_core::{
 };
 use starry_process::Process;
 
-use crate::file::FD_TABLE;
+use crate::{
+    file::FD_TABLE,
+    net::{iface, raw},
+    vfs::{fstype, mount, pagecache, writeback},
+};
 
 const DUMMY_MEMINFO: &str = indoc! {"
     MemTotal:       32536204 kB
@@ -199,6 +210,7 @@ impl SimpleDirOps for ThreadDir {
                 "task",
                 "maps",
                 "mounts",
+                "mountinfo",
                 "cmdline",
                 "comm",
                 "exe",
@@ -254,10 +266,12 @@ impl SimpleDirOps for ThreadDir {
                 "})
             })
             .into(),
-            "mounts" => SimpleFile::new_regular(fs, move || {
-                Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-            })
-            .into(),
+            "mounts" => {
+                SimpleFile::new_regular(fs, || Ok(mount::proc_mounts().into_bytes())).into()
+            }
+            "mountinfo" => {
+                SimpleFile::new_regular(fs, || Ok(mount::proc_mountinfo().into_bytes())).into()
+            }
             "cmdline" => SimpleFile::new_regular(fs, move || {
                 let cmdline = task.as_thread().proc_data.cmdline.read();
                 let mut buf = Vec::new();
@@ -352,17 +366,95 @@ impl SimpleDirOps for ProcFsHandler {
     }
 }
 
+/// Fills the memory statistics we know into [`DUMMY_MEMINFO`].
+fn meminfo() -> String {
+    let (free, total) = shrink::memory_pages();
+    let reclaimable = shrink::cached_pages();
+    let cached = pagecache::cached_pages();
+    let kb = |pages: usize| pages * PAGE_SIZE_4K / 1024;
+    let known = [
+        ("MemTotal", kb(total)),
+        ("MemFree", kb(free)),
+        ("MemAvailable", kb(free + reclaimable)),
+        ("Buffers", 0),
+        ("Cached", kb(cached)),
+        ("Dirty", writeback::dirty_pages() * 4),
+    ];
+    let mut result = String::new();
+    for line in DUMMY_MEMINFO.lines() {
+        let key = line.split(':').next().unwrap_or_default();
+        match known.iter().find(|(name, _)| *name == key) {
+            Some((name, value)) => {
+                writeln!(result, "{:<15} {:>8} kB", format!("{name}:"), value).unwrap()
+            }
+            None => writeln!(result, "{line}").unwrap(),
+        }
+    }
+    result
+}
+
+fn parse_u32(data: &[u8]) -> VfsResult<u32> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|it| it.trim().parse().ok())
+        .ok_or(VfsError::EINVAL)
+}
+
+/// A sysctl backed by `value`, accepting writes up to `max`.
+fn sysctl_u32(fs: Arc<SimpleFs>, value: &'static AtomicU32, max: u32) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(
+        fs,
+        RwFile::new(move |req| match req {
+            SimpleFileOperation::Read => Ok(Some(format!("{}\n", value.load(Ordering::Relaxed)))),
+            SimpleFileOperation::Write(data) => {
+                let new = parse_u32(data)?;
+                if new > max {
+                    return Err(VfsError::EINVAL);
+                }
+                value.store(new, Ordering::Relaxed);
+                Ok(None)
+            }
+        }),
+    )
+}
+
+/// `/proc/sys/net/ipv4/ping_group_range`, two group ids.
+fn ping_group_range(fs: Arc<SimpleFs>) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(
+        fs,
+        RwFile::new(|req| match req {
+            SimpleFileOperation::Read => {
+                let (low, high) = *raw::PING_GROUP_RANGE.lock();
+                Ok(Some(format!("{low}\t{high}\n")))
+            }
+            SimpleFileOperation::Write(data) => {
+                let mut ids = str::from_utf8(data)
+                    .map_err(|_| VfsError::EINVAL)?
+                    .split_whitespace()
+                    .map(|it| it.parse::<u32>().map_err(|_| VfsError::EINVAL));
+                let (Some(low), Some(high), None) = (ids.next(), ids.next(), ids.next()) else {
+                    return Err(VfsError::EINVAL);
+                };
+                raw::set_ping_group_range(low?, high?);
+                Ok(None)
+            }
+        }),
+    )
+}
+
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
         "mounts",
-        SimpleFile::new_regular(fs.clone(), || {
-            Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-        }),
+        SimpleFile::new_regular(fs.clone(), || Ok(mount::proc_mounts().into_bytes())),
+    );
+    root.add(
+        "filesystems",
+        SimpleFile::new_regular(fs.clone(), || Ok(fstype::proc_filesystems().into_bytes())),
     );
     root.add(
         "meminfo",
-        SimpleFile::new_regular(fs.clone(), || Ok(DUMMY_MEMINFO)),
+        SimpleFile::new_regular(fs.clone(), || Ok(meminfo())),
     );
     root.add(
         "meminfo2",
@@ -403,9 +495,78 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
         });
 
+        sys.add("vm", {
+            let mut vm = DirMapping::new();
+
+            vm.add(
+                "dirty_ratio",
+                sysctl_u32(fs.clone(), &writeback::DIRTY_RATIO, 100),
+            );
+            vm.add(
+                "dirty_background_ratio",
+                sysctl_u32(fs.clone(), &writeback::DIRTY_BACKGROUND_RATIO, 100),
+            );
+            vm.add(
+                "dirty_expire_centisecs",
+                sysctl_u32(fs.clone(), &writeback::DIRTY_EXPIRE_CENTISECS, u32::MAX),
+            );
+            vm.add(
+                "dirty_writeback_centisecs",
+                sysctl_u32(fs.clone(), &writeback::DIRTY_WRITEBACK_CENTISECS, u32::MAX),
+            );
+            vm.add(
+                "drop_caches",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some("0\n")),
+                        SimpleFileOperation::Write(data) => {
+                            writeback::drop_caches(parse_u32(data)?)?;
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(vm))
+        });
+
+        sys.add("net", {
+            let mut net = DirMapping::new();
+
+            net.add("ipv4", {
+                let mut ipv4 = DirMapping::new();
+
+                ipv4.add("ping_group_range", ping_group_range(fs.clone()));
+
+                SimpleDir::new_maker(fs.clone(), Arc::new(ipv4))
+            });
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(net))
+        });
+
         SimpleDir::new_maker(fs.clone(), Arc::new(sys))
     });
 
+    root.add("net", {
+        let mut net = DirMapping::new();
+
+        net.add(
+            "dev",
+            SimpleFile::new_regular(fs.clone(), || Ok(iface::proc_net_dev().into_bytes())),
+        );
+        net.add(
+            "route",
+            SimpleFile::new_regular(fs.clone(), || Ok(iface::proc_net_route().into_bytes())),
+        );
+        net.add(
+            "if_inet6",
+            SimpleFile::new_regular(fs.clone(), || Ok(iface::proc_if_inet6().into_bytes())),
+        );
+
+        SimpleDir::new_maker(fs.clone(), Arc::new(net))
+    });
+
     let proc_dir = ProcFsHandler(fs.clone());
     SimpleDir::new_maker(fs, Arc::new(proc_dir.chain(root)))
 }
@@ -0,0 +1,132 @@
+//! Copies between files done inside the kernel, for `copy_file_range` and
+//! the `FICLONE` family of ioctls.
+//!
+//! tmpfs keeps file data in the page cache only, where a page cannot belong
+//! to two files. A clone there copies the range from cache to cache without
+//! leaving the kernel, which user space cannot tell from sharing the pages
+//! until one of the files is written. Other filesystems have no way to share
+//! extents and refuse clones with `EOPNOTSUPP`.
+
+use alloc::vec;
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::FileFlags;
+use axfs_ng_vfs::{Location, NodeType};
+use memory_addr::PAGE_SIZE_4K;
+
+use super::tmp;
+use crate::file::File;
+
+/// Size of the kernel buffer used for copying.
+const COPY_CHUNK_SIZE: usize = 0x10000;
+
+fn same_fs(a: &Location, b: &Location) -> bool {
+    core::ptr::addr_eq(a.filesystem(), b.filesystem())
+}
+
+/// Returns whether `a` and `b` are the same file.
+pub fn same_file(a: &Location, b: &Location) -> LinuxResult<bool> {
+    Ok(same_fs(a, b) && a.metadata()?.inode == b.metadata()?.inode)
+}
+
+fn check_regular(loc: &Location) -> LinuxResult<()> {
+    match loc.node_type() {
+        NodeType::RegularFile => Ok(()),
+        NodeType::Directory => Err(LinuxError::EISDIR),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Checks that data can be copied from `src` to `dst`, both regular files on
+/// the same filesystem, `src` open for reading and `dst` for writing
+/// without `O_APPEND`.
+pub fn check_copy(src: &File, dst: &File) -> LinuxResult<()> {
+    let (src, dst) = (src.inner(), dst.inner());
+    check_regular(src.location())?;
+    check_regular(dst.location())?;
+    src.access(FileFlags::READ)?;
+    dst.access(FileFlags::WRITE)?;
+    if dst.access(FileFlags::APPEND).is_ok() {
+        return Err(LinuxError::EBADF);
+    }
+    if !same_fs(src.location(), dst.location()) {
+        return Err(LinuxError::EXDEV);
+    }
+    Ok(())
+}
+
+/// Fails with `EINVAL` if the ranges overlap within the same file.
+fn check_overlap(src: &File, src_off: u64, dst: &File, dst_off: u64, len: u64) -> LinuxResult<()> {
+    if same_file(src.inner().location(), dst.inner().location())?
+        && src_off < dst_off.saturating_add(len)
+        && dst_off < src_off.saturating_add(len)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(())
+}
+
+/// Copies up to `len` bytes from `src` at `src_off` to `dst` at `dst_off`,
+/// stopping at the end of `src`. Returns the number of bytes copied.
+pub fn copy_range(
+    src: &File,
+    src_off: u64,
+    dst: &File,
+    dst_off: u64,
+    len: u64,
+) -> LinuxResult<u64> {
+    check_overlap(src, src_off, dst, dst_off, len)?;
+
+    let mut buf = vec![0; COPY_CHUNK_SIZE.min(len as usize)];
+    let mut copied = 0;
+    while copied < len {
+        let chunk = (len - copied).min(buf.len() as u64) as usize;
+        let read = src
+            .inner()
+            .read_at(&mut &mut buf[..chunk], src_off + copied)?;
+        if read == 0 {
+            break;
+        }
+        let written = dst.write_at(&mut buf[..read].into(), dst_off + copied)?;
+        copied += written as u64;
+        if written < read {
+            break;
+        }
+    }
+    Ok(copied)
+}
+
+/// Clones `len` bytes of `src` at `src_off` into `dst` at `dst_off`, as
+/// `FICLONERANGE` does. A `len` of 0 clones up to the end of `src`.
+pub fn clone_range(
+    src: &File,
+    src_off: u64,
+    dst: &File,
+    dst_off: u64,
+    len: u64,
+) -> LinuxResult<()> {
+    check_copy(src, dst)?;
+    if tmp::memory_node(src.inner().location()).is_none() {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+
+    let src_len = src.inner().location().len()?;
+    if src_off > src_len {
+        return Err(LinuxError::EINVAL);
+    }
+    let len = if len == 0 { src_len - src_off } else { len };
+    let end = src_off.checked_add(len).ok_or(LinuxError::EINVAL)?;
+    if end > src_len || dst_off.checked_add(len).is_none() {
+        return Err(LinuxError::EINVAL);
+    }
+    // Only whole blocks can be shared, except for the tail of the source.
+    let block = PAGE_SIZE_4K as u64;
+    if src_off % block != 0 || dst_off % block != 0 || (len % block != 0 && end != src_len) {
+        return Err(LinuxError::EINVAL);
+    }
+
+    if copy_range(src, src_off, dst, dst_off, len)? != len {
+        return Err(LinuxError::EIO);
+    }
+    Ok(())
+}
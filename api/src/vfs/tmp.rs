@@ -1,14 +1,120 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
-use core::{any::Any, borrow::Borrow, cmp::Ordering, task::Context, time::Duration};
+use alloc::{borrow::ToOwned, collections::btree_map::BTreeMap, string::String, sync::Arc};
+use core::{
+    any::Any,
+    borrow::Borrow,
+    cmp::Ordering,
+    sync::atomic::{self, AtomicU64},
+    task::Context,
+    time::Duration,
+};
 
+use axerrno::{LinuxError, LinuxResult};
 use axfs_ng_vfs::{
-    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Location, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission,
+    NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
 };
 use axio::{IoEvents, Pollable};
 use axsync::Mutex;
 use hashbrown::HashMap;
+use memory_addr::PAGE_SIZE_4K;
 use slab::Slab;
-use starry_core::vfs::dummy_stat_fs;
+use starry_core::shrink;
+
+use super::{fstype::MountOptions, xattr::XattrMap};
+
+const TMPFS_MAGIC: u32 = 0x01021994;
+const BLOCK_SIZE: u64 = PAGE_SIZE_4K as u64;
+
+fn pages(len: u64) -> u64 {
+    len.div_ceil(BLOCK_SIZE)
+}
+
+/// Options of a tmpfs mount.
+pub struct TmpfsOptions {
+    /// Maximum number of data blocks, 0 for no limit.
+    pub blocks: u64,
+    /// Maximum number of inodes, 0 for no limit.
+    pub inodes: u64,
+    /// Permission of the root directory.
+    pub mode: NodePermission,
+    /// Owner of the root directory.
+    pub uid: u32,
+    /// Group of the root directory.
+    pub gid: u32,
+}
+
+impl Default for TmpfsOptions {
+    /// Half of the memory for data and as many inodes as half of the memory
+    /// has pages, like Linux.
+    fn default() -> Self {
+        let (_, total) = shrink::memory_pages();
+        Self {
+            blocks: total as u64 / 2,
+            inodes: total as u64 / 2,
+            mode: NodePermission::from_bits_truncate(0o1777),
+            uid: 0,
+            gid: 0,
+        }
+    }
+}
+
+/// Parses a number with an optional `k`, `m`, `g` or `t` suffix.
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        b't' | b'T' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+impl TmpfsOptions {
+    /// Parses the filesystem specific options of a tmpfs mount.
+    pub fn parse(opts: &MountOptions) -> LinuxResult<Self> {
+        let mut result = Self::default();
+        for (key, value) in &opts.fs {
+            let value = value.as_deref();
+            match (key.as_str(), value) {
+                ("size", Some(value)) => {
+                    let bytes = if let Some(percent) = value.strip_suffix('%') {
+                        let percent = percent.parse::<u64>().map_err(|_| LinuxError::EINVAL)?;
+                        let (_, total) = shrink::memory_pages();
+                        total as u64 * BLOCK_SIZE / 100 * percent
+                    } else {
+                        parse_size(value).ok_or(LinuxError::EINVAL)?
+                    };
+                    result.blocks = pages(bytes);
+                }
+                ("nr_blocks", Some(value)) => {
+                    result.blocks = parse_size(value).ok_or(LinuxError::EINVAL)?;
+                }
+                ("nr_inodes", Some(value)) => {
+                    result.inodes = parse_size(value).ok_or(LinuxError::EINVAL)?;
+                }
+                ("mode", Some(value)) => {
+                    let mode = u16::from_str_radix(value, 8).map_err(|_| LinuxError::EINVAL)?;
+                    result.mode = NodePermission::from_bits_truncate(mode & 0o7777);
+                }
+                ("uid", Some(value)) => {
+                    result.uid = value.parse().map_err(|_| LinuxError::EINVAL)?;
+                }
+                ("gid", Some(value)) => {
+                    result.gid = value.parse().map_err(|_| LinuxError::EINVAL)?;
+                }
+                // Accepted for compatibility, without effect here.
+                ("huge" | "mpol", Some(_)) | ("inode32" | "inode64" | "noswap", None) => {}
+                _ => {
+                    warn!("tmpfs: unrecognized mount option {:?}={:?}", key, value);
+                    return Err(LinuxError::EINVAL);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 struct FileName(String);
@@ -51,22 +157,38 @@ impl Borrow<str> for FileName {
 pub struct MemoryFs {
     inodes: Mutex<Slab<Arc<Inode>>>,
     root: Mutex<Option<DirEntry>>,
+    /// Maximum number of data blocks, 0 for no limit.
+    max_blocks: AtomicU64,
+    /// Maximum number of inodes, 0 for no limit.
+    max_inodes: AtomicU64,
+    /// Number of data blocks in use.
+    used_blocks: AtomicU64,
 }
 
 impl MemoryFs {
     /// Creates a new empty memory filesystem.
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Filesystem {
+        let mut opts = TmpfsOptions::default();
+        opts.mode = NodePermission::from_bits_truncate(0o755);
+        Self::with_options(opts)
+    }
+
+    /// Creates a new empty memory filesystem with the given options.
+    pub fn with_options(opts: TmpfsOptions) -> Filesystem {
         let fs = Arc::new(Self {
             inodes: Mutex::new(Slab::new()),
             root: Mutex::default(),
+            max_blocks: AtomicU64::new(opts.blocks),
+            max_inodes: AtomicU64::new(opts.inodes),
+            used_blocks: AtomicU64::new(0),
         });
-        let root_ino = Inode::new(
-            &fs,
-            None,
-            NodeType::Directory,
-            NodePermission::from_bits_truncate(0o755),
-        );
+        let root_ino = Inode::new(&fs, None, NodeType::Directory, opts.mode);
+        {
+            let mut metadata = root_ino.metadata.lock();
+            metadata.uid = opts.uid;
+            metadata.gid = opts.gid;
+        }
         *fs.root.lock() = Some(DirEntry::new_dir(
             |this| DirNode::new(MemoryNode::new(fs.clone(), root_ino, Some(this))),
             Reference::root(),
@@ -77,6 +199,60 @@ impl MemoryFs {
     fn get(&self, ino: u64) -> Arc<Inode> {
         self.inodes.lock()[ino as usize - 1].clone()
     }
+
+    /// Applies the size and inode limits among `opts`, on remount. Like
+    /// Linux, a limit cannot be put on a filesystem mounted without one, nor
+    /// be lowered below what is in use.
+    fn reconfigure(&self, opts: &MountOptions) -> LinuxResult<()> {
+        let new = TmpfsOptions::parse(opts)?;
+        let given = |keys: &[&str]| opts.fs.iter().any(|(key, _)| keys.contains(&key.as_str()));
+        let check = |max: &AtomicU64, new: u64, used: u64| {
+            let old = max.load(atomic::Ordering::Relaxed);
+            if new != 0 && (old == 0 || new < used) {
+                return Err(LinuxError::EINVAL);
+            }
+            Ok(())
+        };
+        // Holding the inode table keeps inodes from being created meanwhile.
+        let inodes = self.inodes.lock();
+        let blocks = given(&["size", "nr_blocks"]);
+        if blocks {
+            let used = self.used_blocks.load(atomic::Ordering::Relaxed);
+            check(&self.max_blocks, new.blocks, used)?;
+        }
+        let nr_inodes = given(&["nr_inodes"]);
+        if nr_inodes {
+            check(&self.max_inodes, new.inodes, inodes.len() as u64)?;
+        }
+        if blocks {
+            self.max_blocks.store(new.blocks, atomic::Ordering::Relaxed);
+        }
+        if nr_inodes {
+            self.max_inodes.store(new.inodes, atomic::Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Accounts for `count` more pages in use.
+    fn charge(&self, count: u64) -> VfsResult<()> {
+        let max = self.max_blocks.load(atomic::Ordering::Relaxed);
+        self.used_blocks
+            .fetch_update(
+                atomic::Ordering::Relaxed,
+                atomic::Ordering::Relaxed,
+                |used| {
+                    let used = used + count;
+                    (max == 0 || used <= max).then_some(used)
+                },
+            )
+            .map(|_| ())
+            .map_err(|_| VfsError::ENOSPC)
+    }
+
+    /// Accounts for `count` pages given back.
+    fn uncharge(&self, count: u64) {
+        self.used_blocks.fetch_sub(count, atomic::Ordering::Relaxed);
+    }
 }
 
 impl FilesystemOps for MemoryFs {
@@ -89,7 +265,31 @@ impl FilesystemOps for MemoryFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(0x01021994))
+        let (_, total) = shrink::memory_pages();
+        let used_blocks = self.used_blocks.load(atomic::Ordering::Relaxed);
+        let blocks = match self.max_blocks.load(atomic::Ordering::Relaxed) {
+            0 => (total as u64).max(used_blocks),
+            max => max,
+        };
+        let used_inodes = self.inodes.lock().len() as u64;
+        let inodes = match self.max_inodes.load(atomic::Ordering::Relaxed) {
+            0 => u64::MAX,
+            max => max,
+        };
+        Ok(StatFs {
+            fs_type: TMPFS_MAGIC,
+            block_size: BLOCK_SIZE as _,
+            blocks,
+            blocks_free: blocks - used_blocks,
+            blocks_available: blocks - used_blocks,
+
+            file_count: inodes,
+            free_file_count: inodes.saturating_sub(used_inodes),
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: BLOCK_SIZE as _,
+            mount_flags: 0,
+        })
     }
 }
 
@@ -99,6 +299,107 @@ fn release_inode(fs: &MemoryFs, inode: &Arc<Inode>, nlink: u64) {
     metadata.nlink -= nlink;
     if metadata.nlink == 0 && Arc::strong_count(inode) == 2 {
         inodes.remove(metadata.inode as usize - 1);
+        if let NodeContent::File(file) = &inode.content {
+            fs.uncharge(file.pages.lock().len());
+        }
+    }
+}
+
+/// Populated pages of a file, as disjoint and non-adjacent ranges of page
+/// indices keyed by their start.
+#[derive(Default)]
+struct PageRanges(BTreeMap<u64, u64>);
+
+impl PageRanges {
+    /// Returns the number of populated pages.
+    fn len(&self) -> u64 {
+        self.0.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// Returns the number of populated pages in `start..end`.
+    fn count(&self, start: u64, end: u64) -> u64 {
+        if start >= end {
+            return 0;
+        }
+        let before = self.0.range(..start).next_back();
+        let overlap = before.map_or(0, |(_, &e)| e.min(end).saturating_sub(start));
+        overlap
+            + self
+                .0
+                .range(start..end)
+                .map(|(&s, &e)| e.min(end) - s)
+                .sum::<u64>()
+    }
+
+    /// Marks `start..end` as populated.
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+        if let Some((&s, &e)) = self.0.range(..=start).next_back()
+            && e >= start
+        {
+            start = s;
+            end = end.max(e);
+            self.0.remove(&s);
+        }
+        while let Some((&s, &e)) = self.0.range(start..=end).next() {
+            end = end.max(e);
+            self.0.remove(&s);
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Marks `start..end` as a hole.
+    fn remove(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        if let Some((&s, &e)) = self.0.range(..start).next_back()
+            && e > start
+        {
+            self.0.insert(s, start);
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+        while let Some((&s, &e)) = self.0.range(start..end).next() {
+            self.0.remove(&s);
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+    }
+
+    /// Moves the pages from `from` on so that `from` ends up at `to`. The
+    /// pages they land on must be holes.
+    fn shift(&mut self, from: u64, to: u64) {
+        if let Some((&s, &e)) = self.0.range(..from).next_back()
+            && e > from
+        {
+            self.0.insert(s, from);
+            self.0.insert(from, e);
+        }
+        let moved = self.0.split_off(&from);
+        for (s, e) in moved {
+            self.insert(s - from + to, e - from + to);
+        }
+    }
+
+    /// Returns the first populated page at or after `page`.
+    fn next_data(&self, page: u64) -> Option<u64> {
+        match self.0.range(..=page).next_back() {
+            Some((_, &e)) if e > page => Some(page),
+            _ => self.0.range(page..).next().map(|(&s, _)| s),
+        }
+    }
+
+    /// Returns the first page at or after `page` that is a hole.
+    fn next_hole(&self, page: u64) -> u64 {
+        match self.0.range(..=page).next_back() {
+            Some((_, &e)) if e > page => e,
+            _ => page,
+        }
     }
 }
 
@@ -109,6 +410,9 @@ struct FileContent {
     /// We only need to store the length here because we delegate the actual
     /// content management to page cache.
     length: Mutex<u64>,
+    /// Pages that have been written or allocated, i.e. everything that is not
+    /// a hole.
+    pages: Mutex<PageRanges>,
     symlink: Mutex<Option<String>>,
 }
 
@@ -125,6 +429,7 @@ enum NodeContent {
 struct Inode {
     ino: u64,
     metadata: Mutex<Metadata>,
+    xattrs: Mutex<XattrMap>,
     content: NodeContent,
 }
 
@@ -161,6 +466,7 @@ impl Inode {
         let result = Arc::new(Self {
             ino,
             metadata: Mutex::new(metadata),
+            xattrs: Mutex::default(),
             content,
         });
         entry.insert(result.clone());
@@ -213,7 +519,7 @@ impl Drop for InodeRef {
     }
 }
 
-struct MemoryNode {
+pub(crate) struct MemoryNode {
     fs: Arc<MemoryFs>,
     inode: Arc<Inode>,
     this: Option<WeakDirEntry>,
@@ -224,6 +530,60 @@ impl MemoryNode {
         Arc::new(Self { fs, inode, this })
     }
 
+    /// Runs `f` on the extended attributes of this node.
+    pub(crate) fn with_xattrs<R>(&self, f: impl FnOnce(&mut XattrMap) -> R) -> R {
+        f(&mut self.inode.xattrs.lock())
+    }
+
+    /// Marks the pages covering `start..end` as populated, before data is
+    /// written there or when space is allocated. The pages that were holes
+    /// are charged to the filesystem.
+    pub(crate) fn populate(&self, start: u64, end: u64) -> VfsResult<()> {
+        let mut pages = self.inode.as_file()?.pages.lock();
+        let (start, end) = (start / BLOCK_SIZE, end.div_ceil(BLOCK_SIZE));
+        if start >= end {
+            return Ok(());
+        }
+        self.fs.charge(end - start - pages.count(start, end))?;
+        pages.insert(start, end);
+        Ok(())
+    }
+
+    /// Removes the pages in `start..end` and moves the following ones down,
+    /// after the data has been moved for `FALLOC_FL_COLLAPSE_RANGE`.
+    pub(crate) fn collapse(&self, start: u64, end: u64) -> VfsResult<()> {
+        let mut pages = self.inode.as_file()?.pages.lock();
+        let (start, end) = (start / BLOCK_SIZE, end / BLOCK_SIZE);
+        self.fs.uncharge(pages.count(start, end));
+        pages.remove(start, end);
+        pages.shift(end, start);
+        Ok(())
+    }
+
+    /// Moves the pages from `start` on up to `end`, leaving a hole in
+    /// `start..end`, after the data has been moved for
+    /// `FALLOC_FL_INSERT_RANGE`.
+    pub(crate) fn insert_hole(&self, start: u64, end: u64) -> VfsResult<()> {
+        let mut pages = self.inode.as_file()?.pages.lock();
+        pages.shift(start / BLOCK_SIZE, end / BLOCK_SIZE);
+        Ok(())
+    }
+
+    /// Returns the first offset at or after `offset` that lies in data, if
+    /// any.
+    pub(crate) fn seek_data(&self, offset: u64) -> VfsResult<Option<u64>> {
+        let pages = self.inode.as_file()?.pages.lock();
+        Ok(pages
+            .next_data(offset / BLOCK_SIZE)
+            .map(|page| (page * BLOCK_SIZE).max(offset)))
+    }
+
+    /// Returns the first offset at or after `offset` that lies in a hole.
+    pub(crate) fn seek_hole(&self, offset: u64) -> VfsResult<u64> {
+        let pages = self.inode.as_file()?.pages.lock();
+        Ok((pages.next_hole(offset / BLOCK_SIZE) * BLOCK_SIZE).max(offset))
+    }
+
     fn new_entry(&self, name: &str, node_type: NodeType, inode: Arc<Inode>) -> VfsResult<DirEntry> {
         let fs = self.fs.clone();
         let reference = Reference::new(
@@ -255,6 +615,8 @@ impl NodeOps for MemoryNode {
         match &self.inode.content {
             NodeContent::File(content) => {
                 metadata.size = *content.length.lock();
+                metadata.block_size = BLOCK_SIZE as _;
+                metadata.blocks = content.pages.lock().len() * (BLOCK_SIZE / 512);
             }
             NodeContent::Dir(dir) => {
                 metadata.size = dir.entries.lock().len() as u64;
@@ -319,13 +681,29 @@ impl FileNodeOps for MemoryNode {
     }
 
     fn set_len(&self, len: u64) -> VfsResult<()> {
-        *self.inode.as_file()?.length.lock() = len;
+        let file = self.inode.as_file()?;
+        let mut length = file.length.lock();
+        // Growing only adds a hole, the pages are charged once they are
+        // written.
+        if len < *length {
+            let mut populated = file.pages.lock();
+            self.fs.uncharge(populated.count(pages(len), u64::MAX));
+            populated.remove(pages(len), u64::MAX);
+        }
+        *length = len;
         Ok(())
     }
 
     fn set_symlink(&self, target: &str) -> VfsResult<()> {
         let file = self.inode.as_file()?;
-        *file.length.lock() = target.len() as u64;
+        let mut length = file.length.lock();
+        let mut populated = file.pages.lock();
+        let count = pages(target.len() as u64);
+        self.fs.charge(count - populated.count(0, count))?;
+        populated.insert(0, count);
+        drop(populated);
+        *length = target.len() as u64;
+        drop(length);
         *file.symlink.lock() = Some(target.to_owned());
         Ok(())
     }
@@ -385,6 +763,10 @@ impl DirNodeOps for MemoryNode {
         if entries.contains_key(name) {
             return Err(VfsError::EEXIST);
         }
+        let max_inodes = self.fs.max_inodes.load(atomic::Ordering::Relaxed);
+        if max_inodes != 0 && self.fs.inodes.lock().len() as u64 >= max_inodes {
+            return Err(VfsError::ENOSPC);
+        }
         let inode = Inode::new(&self.fs, Some(self.inode.ino), node_type, permission);
         entries.insert(name.into(), InodeRef::new(self.fs.clone(), inode.ino));
         self.new_entry(name, node_type, inode)
@@ -457,3 +839,17 @@ impl Drop for MemoryNode {
         release_inode(&self.fs, &self.inode, 0);
     }
 }
+
+/// Applies the tmpfs options among `opts` on remount, if `loc` is on a
+/// tmpfs.
+pub(crate) fn reconfigure(loc: &Location, opts: &MountOptions) -> LinuxResult<()> {
+    match memory_node(loc) {
+        Some(node) => node.fs.reconfigure(opts),
+        None => Ok(()),
+    }
+}
+
+/// Returns the tmpfs node at `loc`, if it is one.
+pub(crate) fn memory_node(loc: &Location) -> Option<Arc<MemoryNode>> {
+    loc.entry().downcast::<MemoryNode>().ok()
+}
@@ -0,0 +1,336 @@
+//! Writeback of dirty page cache data.
+//!
+//! Writes through the page cache only reach the filesystem when the cached
+//! file is synced. Every cached file that may hold dirty pages is remembered
+//! here until it is written back by `sync`, `syncfs`, an unmount, or the
+//! writeback task.
+//!
+//! The writeback task wakes up every `dirty_writeback_centisecs` and writes
+//! back files that have been dirty for longer than `dirty_expire_centisecs`,
+//! or everything once dirty data exceeds `dirty_background_ratio` percent of
+//! memory. Writers pushing dirty data past `dirty_ratio` percent write back
+//! the oldest files themselves before going on. Dirty data is estimated from
+//! the pages written since a file was last synced.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::{FS_CONTEXT, FileBackend, FileFlags};
+use axfs_ng_vfs::Location;
+use axhal::time::monotonic_time;
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::shrink::{self, Shrinker};
+
+use super::mount;
+
+/// Percentage of memory dirty data may take before writers write back.
+pub static DIRTY_RATIO: AtomicU32 = AtomicU32::new(20);
+/// Percentage of memory dirty data may take before the writeback task writes
+/// back everything.
+pub static DIRTY_BACKGROUND_RATIO: AtomicU32 = AtomicU32::new(10);
+/// Age in centiseconds after which dirty data is written back.
+pub static DIRTY_EXPIRE_CENTISECS: AtomicU32 = AtomicU32::new(3000);
+/// Interval in centiseconds between wakeups of the writeback task, 0 to
+/// disable periodic writeback.
+pub static DIRTY_WRITEBACK_CENTISECS: AtomicU32 = AtomicU32::new(500);
+
+struct DirtyFile {
+    backend: FileBackend,
+    /// When the file became dirty.
+    since: Duration,
+    /// Indices of the pages written since the file became dirty.
+    pages: BTreeSet<u64>,
+}
+
+/// Identifies the file of a location: its filesystem and inode number.
+pub(crate) type FileKey = (usize, u64);
+
+static DIRTY: Mutex<BTreeMap<FileKey, DirtyFile>> = Mutex::new(BTreeMap::new());
+/// Number of `pages` of all dirty files.
+static DIRTY_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn location_key(loc: &Location) -> LinuxResult<FileKey> {
+    let fs = loc.filesystem() as *const _ as *const () as usize;
+    Ok((fs, loc.metadata()?.inode))
+}
+
+pub(crate) fn file_key(backend: &FileBackend) -> LinuxResult<FileKey> {
+    location_key(backend.location())
+}
+
+/// Returns whether the file of `key` may have dirty pages.
+pub(crate) fn is_dirty(key: &FileKey) -> bool {
+    DIRTY.lock().contains_key(key)
+}
+
+/// Records that `backend` may have dirty cached pages where `len` bytes
+/// were written at `pos`. A `len` of 0 only remembers the file.
+pub fn mark_dirty(backend: &FileBackend, pos: u64, len: u64) {
+    if !matches!(backend, FileBackend::Cached(_)) {
+        return;
+    }
+    let key = match file_key(backend) {
+        Ok(key) => key,
+        Err(err) => {
+            warn!("cannot track dirty file: {:?}", err);
+            return;
+        }
+    };
+    let page = PAGE_SIZE_4K as u64;
+    let mut dirty = DIRTY.lock();
+    let file = dirty.entry(key).or_insert_with(|| DirtyFile {
+        backend: backend.clone(),
+        since: monotonic_time(),
+        pages: BTreeSet::new(),
+    });
+    let added = (pos / page..pos.saturating_add(len).div_ceil(page))
+        .filter(|&index| file.pages.insert(index))
+        .count();
+    DIRTY_PAGES.fetch_add(added, Ordering::Relaxed);
+}
+
+/// Returns the number of dirty pages.
+pub fn dirty_pages() -> usize {
+    DIRTY_PAGES.load(Ordering::Relaxed)
+}
+
+/// Returns `ratio` percent of memory, in pages.
+fn memory_ratio(ratio: &AtomicU32) -> usize {
+    let (_, total) = shrink::memory_pages();
+    total / 100 * ratio.load(Ordering::Relaxed) as usize
+}
+
+/// Removes the dirty files whose keys are `keys`.
+fn take(dirty: &mut BTreeMap<FileKey, DirtyFile>, keys: &[FileKey]) -> Vec<(FileKey, DirtyFile)> {
+    let files = keys
+        .iter()
+        .filter_map(|key| Some((*key, dirty.remove(key)?)))
+        .collect::<Vec<_>>();
+    let pages = files.iter().map(|(_, it)| it.pages.len()).sum();
+    DIRTY_PAGES.fetch_sub(pages, Ordering::Relaxed);
+    files
+}
+
+/// Remembers a file taken out by [`take`] again after its writeback failed,
+/// together with anything dirtied in the meantime.
+fn restore(key: FileKey, file: DirtyFile) {
+    let mut dirty = DIRTY.lock();
+    let Some(current) = dirty.get_mut(&key) else {
+        DIRTY_PAGES.fetch_add(file.pages.len(), Ordering::Relaxed);
+        dirty.insert(key, file);
+        return;
+    };
+    current.since = current.since.min(file.since);
+    let added = file
+        .pages
+        .into_iter()
+        .filter(|&index| current.pages.insert(index))
+        .count();
+    DIRTY_PAGES.fetch_add(added, Ordering::Relaxed);
+}
+
+fn same_fs(a: &Location, b: &Location) -> bool {
+    core::ptr::addr_eq(a.filesystem(), b.filesystem())
+}
+
+/// Writes back a single file. It stays dirty if that fails.
+pub fn sync_file(backend: &FileBackend, data_only: bool) -> LinuxResult<()> {
+    let taken = file_key(backend)
+        .ok()
+        .and_then(|key| take(&mut DIRTY.lock(), &[key]).pop());
+    let result = axfs_ng::File::new(backend.clone(), FileFlags::WRITE).sync(data_only);
+    if result.is_err()
+        && let Some((key, file)) = taken
+    {
+        restore(key, file);
+    }
+    result?;
+    Ok(())
+}
+
+/// Writes back dirty files matching `filter`. Those that fail stay dirty, and
+/// the first error is reported after every file has been tried.
+fn sync_dirty(filter: impl Fn(&DirtyFile) -> bool) -> LinuxResult<()> {
+    let files = {
+        let mut dirty = DIRTY.lock();
+        let keys = dirty
+            .iter()
+            .filter(|(_, it)| filter(it))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        take(&mut dirty, &keys)
+    };
+    write_back(files)
+}
+
+fn write_back(files: Vec<(FileKey, DirtyFile)>) -> LinuxResult<()> {
+    let mut result = Ok(());
+    for (key, file) in files {
+        if let Err(err) = axfs_ng::File::new(file.backend.clone(), FileFlags::WRITE).sync(false) {
+            warn!("writeback failed: {:?}", err);
+            restore(key, file);
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+/// Writes back everything belonging to the filesystem of `loc` and flushes
+/// the filesystem itself.
+pub fn sync_fs(loc: &Location) -> LinuxResult<()> {
+    let result = sync_dirty(|it| same_fs(it.backend.location(), loc));
+    loc.filesystem().flush()?;
+    result
+}
+
+/// Writes back all dirty data and flushes every mounted filesystem.
+pub fn sync_all() -> LinuxResult<()> {
+    let mut result = sync_dirty(|_| true);
+    let fs = FS_CONTEXT.lock();
+    for entry in mount::entries() {
+        let Ok(loc) = fs.resolve(entry.target.as_str()) else {
+            continue;
+        };
+        if let Err(err) = loc.filesystem().flush() {
+            warn!("failed to flush {}: {:?}", entry.target, err);
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+/// Writes back the oldest dirty files while dirty data exceeds
+/// `dirty_ratio`. Called by writers after dirtying pages.
+///
+/// The files written back may belong to anyone, so their errors are only
+/// logged; the writer's own write has already succeeded.
+pub fn balance_dirty() {
+    if dirty_pages() <= memory_ratio(&DIRTY_RATIO) {
+        return;
+    }
+    let background = memory_ratio(&DIRTY_BACKGROUND_RATIO);
+    while dirty_pages() > background {
+        let oldest = {
+            let mut dirty = DIRTY.lock();
+            let key = dirty
+                .iter()
+                .min_by_key(|(_, it)| it.since)
+                .map(|(key, _)| *key);
+            key.map(|key| take(&mut dirty, &[key]))
+        };
+        let Some(files) = oldest else {
+            break;
+        };
+        // A file that cannot be written back stays dirty, so trying again
+        // would only spin.
+        if write_back(files).is_err() {
+            break;
+        }
+    }
+}
+
+/// Shrinker writing back the oldest dirty files.
+///
+/// Written back pages become clean, so that the page cache shrinker of
+/// [`super::pagecache`] can let go of them. Only the pages that actually go
+/// away here count as freed: those of files still held stay cached.
+struct WritebackShrinker;
+
+impl Shrinker for WritebackShrinker {
+    fn name(&self) -> &str {
+        "dirty page cache"
+    }
+
+    fn count(&self) -> usize {
+        dirty_pages()
+    }
+
+    fn scan(&self, nr: usize) -> usize {
+        let files = {
+            let mut dirty = DIRTY.lock();
+            let mut oldest = dirty
+                .iter()
+                .map(|(key, it)| (it.since, *key, it.pages.len()))
+                .collect::<Vec<_>>();
+            oldest.sort_unstable_by_key(|(since, ..)| *since);
+            let mut pages = 0;
+            let keys = oldest
+                .into_iter()
+                .take_while(|(.., count)| {
+                    let more = pages < nr;
+                    pages += count;
+                    more
+                })
+                .map(|(_, key, _)| key)
+                .collect::<Vec<_>>();
+            take(&mut dirty, &keys)
+        };
+        let (before, _) = shrink::memory_pages();
+        let _ = write_back(files);
+        shrink::memory_pages().0.saturating_sub(before)
+    }
+}
+
+/// Registers the shrinker of dirty page cache.
+pub fn register_shrinker() {
+    shrink::register_shrinker(Arc::new(WritebackShrinker));
+}
+
+/// Writes back what has expired, or everything above the background ratio.
+fn writeback_once() {
+    let result = if dirty_pages() > memory_ratio(&DIRTY_BACKGROUND_RATIO) {
+        sync_dirty(|_| true)
+    } else {
+        let expire =
+            Duration::from_millis(DIRTY_EXPIRE_CENTISECS.load(Ordering::Relaxed) as u64 * 10);
+        let now = monotonic_time();
+        sync_dirty(|it| now.saturating_sub(it.since) >= expire)
+    };
+    if let Err(err) = result {
+        warn!("periodic writeback failed: {:?}", err);
+    }
+}
+
+/// Spawns the writeback task.
+pub fn spawn_writeback_task() {
+    axtask::spawn(
+        || {
+            loop {
+                let interval = DIRTY_WRITEBACK_CENTISECS.load(Ordering::Relaxed);
+                // Still wake up now and then to notice a new interval.
+                axtask::sleep(Duration::from_millis(
+                    if interval == 0 { 500 } else { interval } as u64 * 10,
+                ));
+                if interval != 0 {
+                    writeback_once();
+                }
+            }
+        },
+        "writeback".into(),
+    );
+}
+
+/// Handles a write to `/proc/sys/vm/drop_caches`.
+///
+/// Dirty data is written back first so that as much as possible can go.
+/// Page cache is held by open files and the ELF loader, so dropping it
+/// clears the latter.
+pub fn drop_caches(mode: u32) -> LinuxResult<()> {
+    if !(1..=4).contains(&mode) {
+        return Err(LinuxError::EINVAL);
+    }
+    if mode & 1 != 0 {
+        sync_all()?;
+        starry_core::mm::clear_elf_cache();
+    }
+    Ok(())
+}
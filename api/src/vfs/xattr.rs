@@ -0,0 +1,121 @@
+//! Extended attributes.
+//!
+//! tmpfs keeps extended attributes in its inodes. Other filesystems do not
+//! expose an extended attribute interface yet, so they refuse to store any
+//! attributes with `EOPNOTSUPP`, like a Linux filesystem without xattr
+//! support.
+
+use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
+
+use super::{
+    acl::{self, XATTR_NAME_ACL_ACCESS, XATTR_NAME_ACL_DEFAULT},
+    tmp::MemoryNode,
+};
+
+/// Maximum size of an extended attribute value.
+pub const XATTR_SIZE_MAX: usize = 65536;
+/// Maximum length of an extended attribute name.
+pub const XATTR_NAME_MAX: usize = 255;
+
+/// Extended attributes of a single node.
+pub type XattrMap = BTreeMap<String, Vec<u8>>;
+
+bitflags::bitflags! {
+    /// Flags for `setxattr`.
+    #[derive(Debug, Clone, Copy)]
+    pub struct XattrFlags: u32 {
+        /// Fail if the attribute already exists.
+        const CREATE = 1;
+        /// Fail if the attribute does not exist.
+        const REPLACE = 2;
+    }
+}
+
+/// Extended attributes, and with them ACLs, are only kept for tmpfs nodes; the
+/// ext4 backend has no xattr interface yet.
+fn with_xattrs<R>(loc: &Location, f: impl FnOnce(&mut XattrMap) -> R) -> LinuxResult<R> {
+    match loc.entry().downcast::<MemoryNode>() {
+        Ok(node) => Ok(node.with_xattrs(f)),
+        Err(_) => Err(LinuxError::EOPNOTSUPP),
+    }
+}
+
+fn check_name(name: &str) -> LinuxResult<()> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(LinuxError::ERANGE);
+    }
+    let known = ["user.", "trusted.", "security.", "system."];
+    if !known.iter().any(|prefix| name.starts_with(prefix)) {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    if name.starts_with("system.")
+        && name != XATTR_NAME_ACL_ACCESS
+        && name != XATTR_NAME_ACL_DEFAULT
+    {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    Ok(())
+}
+
+/// Reads an attribute without any namespace checks.
+pub(crate) fn get_raw(loc: &Location, name: &str) -> LinuxResult<Vec<u8>> {
+    with_xattrs(loc, |map| map.get(name).cloned())?.ok_or(LinuxError::ENODATA)
+}
+
+/// Writes an attribute without any namespace checks.
+pub(crate) fn set_raw(loc: &Location, name: &str, value: Vec<u8>) -> LinuxResult<()> {
+    with_xattrs(loc, |map| {
+        map.insert(name.into(), value);
+    })
+}
+
+/// Removes an attribute without any namespace checks.
+pub(crate) fn remove_raw(loc: &Location, name: &str) -> LinuxResult<()> {
+    with_xattrs(loc, |map| map.remove(name))?
+        .map(|_| ())
+        .ok_or(LinuxError::ENODATA)
+}
+
+/// Gets the value of an extended attribute.
+pub fn get_xattr(loc: &Location, name: &str) -> LinuxResult<Vec<u8>> {
+    check_name(name)?;
+    get_raw(loc, name)
+}
+
+/// Sets the value of an extended attribute.
+pub fn set_xattr(loc: &Location, name: &str, value: &[u8], flags: XattrFlags) -> LinuxResult<()> {
+    check_name(name)?;
+    if value.len() > XATTR_SIZE_MAX {
+        return Err(LinuxError::E2BIG);
+    }
+    let exists = with_xattrs(loc, |map| map.contains_key(name))?;
+    if flags.contains(XattrFlags::CREATE) && exists {
+        return Err(LinuxError::EEXIST);
+    }
+    if flags.contains(XattrFlags::REPLACE) && !exists {
+        return Err(LinuxError::ENODATA);
+    }
+
+    if name == XATTR_NAME_ACL_ACCESS || name == XATTR_NAME_ACL_DEFAULT {
+        acl::set_acl_xattr(loc, name, value)
+    } else {
+        set_raw(loc, name, value.to_vec())
+    }
+}
+
+/// Lists the names of all extended attributes.
+pub fn list_xattr(loc: &Location) -> LinuxResult<Vec<String>> {
+    match with_xattrs(loc, |map| map.keys().cloned().collect()) {
+        Err(LinuxError::EOPNOTSUPP) => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Removes an extended attribute.
+pub fn remove_xattr(loc: &Location, name: &str) -> LinuxResult<()> {
+    check_name(name)?;
+    remove_raw(loc, name)
+}
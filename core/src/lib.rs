@@ -15,6 +15,7 @@ pub mod futex;
 pub mod mm;
 pub mod resources;
 pub mod shm;
+pub mod shrink;
 pub mod task;
 pub mod time;
 pub mod vfs;
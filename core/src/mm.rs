@@ -1,6 +1,8 @@
 //! User address space management.
 
-use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use alloc::{
+    borrow::ToOwned, collections::vec_deque::VecDeque, string::String, sync::Arc, vec, vec::Vec,
+};
 use core::{
     ffi::CStr,
     hint::unlikely,
@@ -25,9 +27,11 @@ use kernel_guard::IrqSave;
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use ouroboros::self_referencing;
 use starry_vm::{VmError, VmIo, VmResult};
-use uluru::LRUCache;
 
-use crate::config::{USER_SPACE_BASE, USER_SPACE_SIZE};
+use crate::{
+    config::{USER_SPACE_BASE, USER_SPACE_SIZE},
+    shrink::{Shrinker, memory_pages, register_shrinker},
+};
 
 /// Creates a new empty user address space.
 pub fn new_user_aspace_empty() -> LinuxResult<AddrSpace> {
@@ -175,22 +179,45 @@ impl ElfCacheEntry {
     }
 }
 
-struct ElfLoader(LRUCache<ElfCacheEntry, 32>);
+/// Maximum number of entries in the ELF cache.
+const ELF_CACHE_SIZE: usize = 32;
+
+/// Cache of loaded ELF files, most recently used first.
+struct ElfLoader(VecDeque<ElfCacheEntry>);
 
 type LoadResult = Result<(VirtAddr, Vec<AuxEntry>), Vec<u8>>;
 
 impl ElfLoader {
     const fn new() -> Self {
-        Self(LRUCache::new())
+        Self(VecDeque::new())
+    }
+
+    /// Moves the entry of `loc` to the front, if there is one.
+    fn touch(&mut self, loc: &Location) -> bool {
+        let Some(index) = self
+            .0
+            .iter()
+            .position(|e| e.borrow_cache().location().ptr_eq(loc))
+        else {
+            return false;
+        };
+        let entry = self.0.remove(index).unwrap();
+        self.0.push_front(entry);
+        true
+    }
+
+    fn insert(&mut self, entry: ElfCacheEntry) {
+        self.0.push_front(entry);
+        self.0.truncate(ELF_CACHE_SIZE);
     }
 
     fn load(&mut self, uspace: &mut AddrSpace, path: &str) -> LinuxResult<LoadResult> {
         let loc = FS_CONTEXT.lock().resolve(path)?;
 
-        if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
+        if !self.touch(&loc) {
             match ElfCacheEntry::load(loc)? {
                 Ok(e) => {
-                    self.0.insert(e);
+                    self.insert(e);
                 }
                 Err(data) => {
                     return Ok(Err(data));
@@ -225,9 +252,9 @@ impl ElfLoader {
 
         let (elf, ldso) = if let Some(ldso) = ldso {
             let loc = FS_CONTEXT.lock().resolve(ldso)?;
-            if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
+            if !self.touch(&loc) {
                 let e = ElfCacheEntry::load(loc)?.map_err(|_| LinuxError::EINVAL)?;
-                self.0.insert(e);
+                self.insert(e);
             }
 
             let mut iter = self.0.iter();
@@ -264,6 +291,50 @@ pub fn clear_elf_cache() {
     ELF_LOADER.lock().0.clear();
 }
 
+/// Shrinker for the ELF cache.
+///
+/// Every entry keeps the page cache of its file alive, so an entry counts as
+/// the pages of the whole file. Entries are dropped least recently used
+/// first, and only the pages that actually go away with them, i.e. those of
+/// files nobody else holds, count as freed.
+struct ElfCacheShrinker;
+
+impl Shrinker for ElfCacheShrinker {
+    fn name(&self) -> &str {
+        "elf cache"
+    }
+
+    fn count(&self) -> usize {
+        ELF_LOADER
+            .lock()
+            .0
+            .iter()
+            .map(|e| {
+                let len = e.borrow_cache().location().len().unwrap_or(0);
+                (len as usize).div_ceil(PAGE_SIZE_4K)
+            })
+            .sum()
+    }
+
+    fn scan(&self, nr: usize) -> usize {
+        let (before, _) = memory_pages();
+        let mut loader = ELF_LOADER.lock();
+        let mut freed = 0;
+        while freed < nr
+            && let Some(entry) = loader.0.pop_back()
+        {
+            drop(entry);
+            freed = memory_pages().0.saturating_sub(before);
+        }
+        freed
+    }
+}
+
+/// Registers the shrinker of the ELF cache.
+pub fn register_elf_cache_shrinker() {
+    register_shrinker(Arc::new(ElfCacheShrinker));
+}
+
 /// Load the user app to the user address space.
 ///
 /// # Arguments
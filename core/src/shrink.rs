@@ -0,0 +1,105 @@
+//! Cache reclaim under memory pressure.
+//!
+//! Caches that can give memory back register a [`Shrinker`]. When free
+//! memory drops below [`LOW_WATERMARK`] percent, the reclaim task asks the
+//! shrinkers to free pages until [`HIGH_WATERMARK`] percent is free again,
+//! each in proportion to how much it holds. A task failing to get memory
+//! reclaims directly with [`reclaim`].
+
+use alloc::{sync::Arc, vec::Vec};
+use core::time::Duration;
+
+use axsync::Mutex;
+
+/// Percentage of free memory below which caches are shrunk.
+pub const LOW_WATERMARK: usize = 5;
+/// Percentage of free memory reclaim stops at.
+pub const HIGH_WATERMARK: usize = 10;
+
+/// Interval between two checks of the reclaim task.
+const RECLAIM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A cache that can give pages back.
+pub trait Shrinker: Send + Sync {
+    /// Returns the name of the cache.
+    fn name(&self) -> &str;
+
+    /// Returns the number of pages the cache could free.
+    fn count(&self) -> usize;
+
+    /// Tries to free `nr` pages, least recently used first, and returns the
+    /// number of pages freed.
+    fn scan(&self, nr: usize) -> usize;
+}
+
+static SHRINKERS: Mutex<Vec<Arc<dyn Shrinker>>> = Mutex::new(Vec::new());
+
+/// Registers a shrinker.
+pub fn register_shrinker(shrinker: Arc<dyn Shrinker>) {
+    SHRINKERS.lock().push(shrinker);
+}
+
+/// Returns the number of pages held by all registered caches.
+pub fn cached_pages() -> usize {
+    shrinkers().iter().map(|it| it.count()).sum()
+}
+
+fn shrinkers() -> Vec<Arc<dyn Shrinker>> {
+    SHRINKERS.lock().clone()
+}
+
+/// Returns the number of free pages and the total number of pages.
+pub fn memory_pages() -> (usize, usize) {
+    let allocator = axalloc::global_allocator();
+    let free = allocator.available_pages();
+    (free, free + allocator.used_pages())
+}
+
+/// Asks the caches to free `nr` pages and returns the number freed.
+pub fn reclaim(nr: usize) -> usize {
+    let shrinkers = shrinkers();
+    let counts = shrinkers.iter().map(|it| it.count()).collect::<Vec<_>>();
+    let total = counts.iter().sum::<usize>();
+    if total == 0 {
+        return 0;
+    }
+
+    let mut freed = 0;
+    for (shrinker, count) in shrinkers.iter().zip(counts) {
+        let share = (nr * count).div_ceil(total).min(count);
+        if share == 0 {
+            continue;
+        }
+        let n = shrinker.scan(share);
+        debug!(
+            "shrinker {}: freed {} of {} pages",
+            shrinker.name(),
+            n,
+            share
+        );
+        freed += n;
+    }
+    freed
+}
+
+/// Reclaims up to the high watermark if free memory is below the low one.
+pub fn reclaim_if_low() -> usize {
+    let (free, total) = memory_pages();
+    if free * 100 >= total * LOW_WATERMARK {
+        return 0;
+    }
+    reclaim((total * HIGH_WATERMARK / 100).saturating_sub(free))
+}
+
+/// Spawns the reclaim task.
+pub fn spawn_reclaim_task() {
+    axtask::spawn(
+        || {
+            loop {
+                axtask::sleep(RECLAIM_INTERVAL);
+                reclaim_if_low();
+            }
+        },
+        "reclaim".into(),
+    );
+}
@@ -190,6 +190,36 @@ impl Thread {
     }
 }
 
+/// User and group ids of a process.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    /// Real user id.
+    pub uid: u32,
+    /// Effective user id.
+    pub euid: u32,
+    /// Saved set-user-id.
+    pub suid: u32,
+    /// User id for filesystem access, follows `euid`.
+    pub fsuid: u32,
+    /// Real group id.
+    pub gid: u32,
+    /// Effective group id.
+    pub egid: u32,
+    /// Saved set-group-id.
+    pub sgid: u32,
+    /// Group id for filesystem access, follows `egid`.
+    pub fsgid: u32,
+    /// Supplementary groups.
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Returns whether the process is privileged, i.e. runs as root.
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+}
+
 /// [`Process`]-shared data.
 pub struct ProcessData {
     /// The process.
@@ -226,6 +256,9 @@ pub struct ProcessData {
 
     /// The default mask for file permissions.
     umask: AtomicU32,
+
+    /// The user and group ids.
+    cred: RwLock<Credentials>,
 }
 
 impl ProcessData {
@@ -261,6 +294,8 @@ impl ProcessData {
             futex_table: Arc::new(FutexTable::new()),
 
             umask: AtomicU32::new(0o022),
+
+            cred: RwLock::default(),
         })
     }
 
@@ -318,6 +353,16 @@ impl ProcessData {
     pub fn replace_umask(&self, umask: u32) -> u32 {
         self.umask.swap(umask, Ordering::SeqCst)
     }
+
+    /// Get the credentials.
+    pub fn cred(&self) -> Credentials {
+        self.cred.read().clone()
+    }
+
+    /// Set the credentials.
+    pub fn set_cred(&self, cred: Credentials) {
+        *self.cred.write() = cred;
+    }
 }
 
 struct FutexTables {
@@ -51,6 +51,21 @@ pub trait DeviceOps: Send + Sync {
     fn flags(&self) -> NodeFlags {
         NodeFlags::empty()
     }
+
+    /// Returns the capacity in bytes, if this is a block device.
+    fn capacity(&self) -> Option<u64> {
+        None
+    }
+
+    /// Writes data the device holds back to its storage, if it is a block
+    /// device.
+    fn flush(&self) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Returns the readahead size in bytes, if this is a block device.
+    fn readahead(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// A device node in the filesystem.
@@ -99,7 +114,10 @@ impl NodeOps for Device {
     fn filesystem(&self) -> &dyn FilesystemOps;
 
     fn sync(&self, _data_only: bool) -> VfsResult<()> {
-        Err(VfsError::EINVAL)
+        if self.ops.capacity().is_none() {
+            return Err(VfsError::EINVAL);
+        }
+        self.ops.flush()
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
@@ -93,6 +93,7 @@ run_ltp() {
     copy_file_range01
     creat01
     creat03
+    creat04
     creat05
     creat08
     dirtypipe
@@ -145,6 +146,7 @@ run_ltp() {
     execlp01
     execv01
     execve01
+    execve02
     execve03
     execve05
     execvp01
@@ -154,7 +156,12 @@ run_ltp() {
     faccessat02
     faccessat201
     faccessat202
+    fallocate01
+    fallocate02
     fallocate03
+    fallocate04
+    fallocate05
+    fallocate06
     fchdir01
     fchdir02
     fchmod01
@@ -195,6 +202,7 @@ run_ltp() {
     fork08
     fork10
     fpathconf01
+    fsopen02
     fstat02
     fstat02_64
     fstat03
@@ -204,8 +212,13 @@ run_ltp() {
     fstatfs02
     fstatfs02_64
     fsync01
+    fsync02
+    fsync03
+    fsync04
     ftruncate01
     ftruncate01_64
+    ftruncate03
+    ftruncate04
     futex_cmp_requeue02
     futex_wait01
     futex_wait02
@@ -245,6 +258,12 @@ run_ltp() {
     getrandom02
     getrandom03
     getrandom04
+    getresgid01
+    getresgid02
+    getresgid03
+    getresuid01
+    getresuid02
+    getresuid03
     getrlimit01
     getrlimit02
     getrusage01
@@ -260,6 +279,11 @@ run_ltp() {
     getuid01
     getuid03
     in6_01
+    ioctl_ficlone01
+    ioctl_ficlone02
+    ioctl_ficlone03
+    ioctl_ficlonerange01
+    ioctl_ficlonerange02
     ioctl_ns07
     ioctl04
     ioctl05
@@ -273,6 +297,7 @@ run_ltp() {
     link02
     link04
     link05
+    link06
     link08
     listen01
     llseek01
@@ -301,6 +326,9 @@ run_ltp() {
     mkdir05
     mkdir09
     mkdirat02
+    mknod07
+    mknodat01
+    mknodat02
     mlock01
     mlock03
     mlock04
@@ -321,6 +349,9 @@ run_ltp() {
     mmap18
     mmap19
     mmap20
+    mount02
+    mount03
+    mount06
     mprotect01
     mprotect02
     mprotect03
@@ -380,6 +411,8 @@ run_ltp() {
     preadv201_64
     preadv202
     preadv202_64
+    preadv203
+    preadv203_64
     pselect02
     pselect02_64
     pselect03
@@ -396,10 +429,15 @@ run_ltp() {
     pwritev01_64
     pwritev201
     pwritev201_64
+    pwritev202
+    pwritev202_64
+    pwritev203
+    pwritev203_64
     read01
     read02
     read03
     read04
+    readahead01
     readdir01
     readlink01
     readlink03
@@ -408,7 +446,9 @@ run_ltp() {
     readv01
     readv02
     realpath01
+    recvfrom01
     recvmsg01
+    recvmsg02
     rename01
     rename03
     rename08
@@ -426,6 +466,7 @@ run_ltp() {
     sched_getscheduler01
     select03
     select04
+    send01
     sendfile02
     sendfile02_64
     sendfile04
@@ -437,6 +478,7 @@ run_ltp() {
     sendfile08
     sendfile08_64
     sendmmsg02
+    sendto01
     sendto02
     setgid01
     setgid03
@@ -444,6 +486,9 @@ run_ltp() {
     setitimer02
     setpgid02
     setpgrp02
+    setregid01
+    setregid02
+    setregid04
     setresuid04
     setresuid05
     setreuid01
@@ -515,6 +560,8 @@ run_ltp() {
     symlink02
     symlink04
     syscall01
+    tee01
+    tee02
     tgkill03
     time01
     times01
@@ -545,6 +592,8 @@ run_ltp() {
     utimes01
     utsname01
     utsname04
+    vmsplice01
+    vmsplice02
     wait01
     wait02
     wait401